@@ -0,0 +1,55 @@
+//! Parse arbitrary strings with the helpers we use for arguments and
+//! for fields of the input, and check they never panic and only return
+//! values in range.
+//!
+//! randline is a binary rather than a library, so this includes the
+//! modules it needs straight from `src/`.  Run it with:
+//!
+//!     cd fuzz
+//!     cargo +nightly fuzz run parse_args
+//!
+#![no_main]
+#![allow(dead_code)]
+
+use libfuzzer_sys::fuzz_target;
+
+#[path = "../../src/confidence.rs"]
+mod confidence;
+#[path = "../../src/diagnostics.rs"]
+mod diagnostics;
+#[path = "../../src/encoding.rs"]
+mod encoding;
+#[path = "../../src/fields.rs"]
+mod fields;
+#[path = "../../src/input.rs"]
+mod input;
+#[path = "../../src/sample_size.rs"]
+mod sample_size;
+#[path = "../../src/scores.rs"]
+mod scores;
+#[path = "../../src/timestamps.rs"]
+mod timestamps;
+#[path = "../../src/weights.rs"]
+mod weights;
+
+use sample_size::SampleSize;
+
+fuzz_target!(|text: &str| {
+    match sample_size::parse_sample_size(text) {
+        Some(SampleSize::Count(k)) => assert!(k > 0),
+        Some(SampleSize::Fraction(f)) => assert!(f > 0.0 && f <= 1.0, "f = {}", f),
+        Some(SampleSize::Confidence { .. }) => panic!("k can't be a confidence level"),
+        None => (),
+    }
+
+    if let Some(p) = sample_size::parse_proportion(text) {
+        assert!(p > 0.0 && p < 1.0, "p = {}", p);
+    }
+
+    for field in 1..=3 {
+        let _ = scores::parse_score(text, field);
+        let _ = weights::parse_weight(text, field);
+    }
+
+    let _ = timestamps::parse_timestamp(text);
+});
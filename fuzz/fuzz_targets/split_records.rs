@@ -0,0 +1,75 @@
+//! Split arbitrary bytes into records, with arbitrary read options, and
+//! check it never panics -- bad input should always be a read error.
+//!
+//! randline is a binary rather than a library, so this includes the
+//! modules it needs straight from `src/`.  Run it with:
+//!
+//!     cd fuzz
+//!     cargo +nightly fuzz run split_records
+//!
+#![no_main]
+#![allow(dead_code)]
+
+use std::io::Cursor;
+
+use arbitrary::Arbitrary;
+use libfuzzer_sys::fuzz_target;
+
+#[path = "../../src/diagnostics.rs"]
+mod diagnostics;
+#[path = "../../src/encoding.rs"]
+mod encoding;
+#[path = "../../src/input.rs"]
+mod input;
+
+/// The encodings we try, besides UTF-8.  These cover single-byte,
+/// multi-byte, stateful and UTF-16 decoders.
+const ENCODINGS: &[&str] = &["latin1", "shift_jis", "iso-2022-jp", "utf-16le", "utf-16be"];
+
+#[derive(Arbitrary, Debug)]
+struct Input {
+    bytes: Vec<u8>,
+    nul_separated: bool,
+    crlf_separated: bool,
+    fixed_width: Option<u8>,
+    strip_cr: bool,
+    lossy_utf8: bool,
+    encoding: Option<u8>,
+}
+
+fuzz_target!(|input: Input| {
+    let options = input::ReadOptions {
+        nul_separated: input.nul_separated,
+        crlf_separated: input.crlf_separated,
+        // A width of 0 is rejected when we parse the arguments.
+        fixed_width: input.fixed_width.filter(|w| *w > 0).map(usize::from),
+        strip_cr: input.strip_cr,
+        lossy_utf8: input.lossy_utf8,
+        input_encoding: input
+            .encoding
+            .map(|i| encoding::for_name(ENCODINGS[usize::from(i) % ENCODINGS.len()]).unwrap()),
+        ..input::ReadOptions::default()
+    };
+
+    for record in input::split_records(Cursor::new(input.bytes), options) {
+        let Ok(record) = record else {
+            break;
+        };
+
+        // A record is never longer than the width, and never contains
+        // the separator it was split on.  With lossy decoding, each
+        // invalid byte becomes a character of its own, so a record can
+        // be longer than the width.
+        if let Some(width) = options.fixed_width {
+            if !options.lossy_utf8 {
+                assert!(record.chars().count() <= width, "record = {:?}", record);
+            }
+        } else if options.crlf_separated {
+            assert!(!record.contains("\r\n"), "record = {:?}", record);
+        } else if options.nul_separated {
+            assert!(!record.contains('\0'), "record = {:?}", record);
+        } else {
+            assert!(!record.contains('\n'), "record = {:?}", record);
+        }
+    }
+});
@@ -0,0 +1,95 @@
+use std::fs::OpenOptions;
+use std::io::Write;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Append an entry to the audit log at `path`, creating it if it
+/// doesn't exist.
+///
+/// Each entry is a JSON object on a line of its own, so the log is
+/// JSON Lines.  We write the whole line in a single call, so entries
+/// from two runs at the same time don't get mixed up.
+pub fn append(path: &str, entry: &serde_json::Value) -> std::io::Result<()> {
+    let mut file = OpenOptions::new().create(true).append(true).open(path)?;
+
+    file.write_all(format!("{}\n", entry).as_bytes())
+}
+
+/// Format a time as an RFC 3339 timestamp in UTC, to the second, e.g.
+/// `2025-01-11T12:53:20Z`.
+pub fn timestamp(time: SystemTime) -> String {
+    let secs = time
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0);
+
+    let (year, month, day) = civil_from_days((secs / 86400) as i64);
+    let secs_of_day = secs % 86400;
+
+    format!(
+        "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}Z",
+        year,
+        month,
+        day,
+        secs_of_day / 3600,
+        secs_of_day % 3600 / 60,
+        secs_of_day % 60
+    )
+}
+
+/// Turn a number of days since 1970-01-01 into a (year, month, day) date
+/// in the proleptic Gregorian calendar.
+///
+/// This is Howard Hinnant's `civil_from_days` algorithm, which works
+/// in 400-year eras starting on 1 March, so leap days come at the end.
+/// See <https://howardhinnant.github.io/date_algorithms.html>.
+fn civil_from_days(days: i64) -> (i64, u32, u32) {
+    let z = days + 719468;
+    let era = z.div_euclid(146097);
+    let day_of_era = z.rem_euclid(146097);
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let month_index = (5 * day_of_year + 2) / 153;
+    let day = (day_of_year - (153 * month_index + 2) / 5 + 1) as u32;
+    let month = if month_index < 10 {
+        month_index + 3
+    } else {
+        month_index - 9
+    } as u32;
+    let year = year_of_era + era * 400 + if month <= 2 { 1 } else { 0 };
+
+    (year, month, day)
+}
+
+#[cfg(test)]
+mod audit_tests {
+    use super::*;
+    use std::time::Duration;
+
+    fn at(secs: u64) -> String {
+        timestamp(UNIX_EPOCH + Duration::from_secs(secs))
+    }
+
+    #[test]
+    fn it_formats_timestamps() {
+        assert_eq!(at(0), "1970-01-01T00:00:00Z");
+        assert_eq!(at(1736600000), "2025-01-11T12:53:20Z");
+        assert_eq!(at(951782400), "2000-02-29T00:00:00Z");
+        assert_eq!(at(4107542399), "2100-02-28T23:59:59Z");
+    }
+
+    #[test]
+    fn it_appends_one_line_per_entry() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("audit.jsonl");
+        let path = path.to_str().unwrap();
+
+        append(path, &serde_json::json!({"seed": 1})).unwrap();
+        append(path, &serde_json::json!({"seed": 2})).unwrap();
+
+        assert_eq!(
+            std::fs::read_to_string(path).unwrap(),
+            "{\"seed\":1}\n{\"seed\":2}\n"
+        );
+    }
+}
@@ -0,0 +1,8 @@
+/// Copy some text to the system clipboard.
+///
+/// This fails if there's no clipboard we can use, e.g. if we're running
+/// over SSH or there's no display server.
+pub fn copy(text: String) -> Result<(), arboard::Error> {
+    let mut clipboard = arboard::Clipboard::new()?;
+    clipboard.set_text(text)
+}
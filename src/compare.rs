@@ -0,0 +1,89 @@
+use std::collections::{HashMap, HashSet};
+use std::hash::Hash;
+
+/// How much do a set of samples overlap?
+#[derive(Debug, PartialEq)]
+pub struct Overlap {
+    /// How many items appear in every sample -- the "stable core".
+    pub in_every_sample: usize,
+
+    /// How many distinct items appear in at least one sample.
+    pub in_any_sample: usize,
+}
+
+/// Work out how much a set of samples overlap.
+///
+/// Each item is identified by a key, e.g. the text of a line.  If a
+/// sample contains the same key more than once, it's only counted once.
+pub fn overlap<K: Eq + Hash>(samples: &[Vec<K>]) -> Overlap {
+    let mut counts: HashMap<&K, usize> = HashMap::new();
+
+    for sample in samples {
+        let keys: HashSet<&K> = sample.iter().collect();
+        for key in keys {
+            *counts.entry(key).or_insert(0) += 1;
+        }
+    }
+
+    Overlap {
+        in_every_sample: counts.values().filter(|c| **c == samples.len()).count(),
+        in_any_sample: counts.len(),
+    }
+}
+
+#[cfg(test)]
+mod overlap_tests {
+    use super::*;
+
+    #[test]
+    fn it_counts_the_overlap() {
+        let samples = vec![vec![1, 2, 3], vec![2, 3, 4], vec![3, 2, 5]];
+
+        assert_eq!(
+            overlap(&samples),
+            Overlap {
+                in_every_sample: 2,
+                in_any_sample: 5
+            }
+        );
+    }
+
+    #[test]
+    fn it_counts_identical_samples() {
+        let samples = vec![vec![1, 2], vec![2, 1]];
+
+        assert_eq!(
+            overlap(&samples),
+            Overlap {
+                in_every_sample: 2,
+                in_any_sample: 2
+            }
+        );
+    }
+
+    #[test]
+    fn it_counts_disjoint_samples() {
+        let samples = vec![vec![1, 2], vec![3, 4]];
+
+        assert_eq!(
+            overlap(&samples),
+            Overlap {
+                in_every_sample: 0,
+                in_any_sample: 4
+            }
+        );
+    }
+
+    #[test]
+    fn it_counts_a_repeated_key_once() {
+        let samples = vec![vec!["a", "a"], vec!["a", "b"]];
+
+        assert_eq!(
+            overlap(&samples),
+            Overlap {
+                in_every_sample: 1,
+                in_any_sample: 2
+            }
+        );
+    }
+}
@@ -0,0 +1,86 @@
+/// The z-scores for the confidence levels we support.
+///
+/// We don't have an inverse normal CDF to hand, so we only support the
+/// common levels, which cover nearly all survey-style sampling.
+const Z_SCORES: [(f64, f64); 6] = [
+    (0.80, 1.2816),
+    (0.90, 1.6449),
+    (0.95, 1.9600),
+    (0.98, 2.3263),
+    (0.99, 2.5758),
+    (0.999, 3.2905),
+];
+
+/// Look up the z-score for a confidence level, e.g. 0.95 => 1.96.
+///
+/// Returns `None` if it isn't one of the levels we support.
+pub fn z_score(level: f64) -> Option<f64> {
+    Z_SCORES
+        .iter()
+        .find(|(l, _)| (l - level).abs() < 1e-9)
+        .map(|(_, z)| *z)
+}
+
+/// A list of the confidence levels we support, for error messages.
+pub fn supported_levels() -> String {
+    Z_SCORES
+        .iter()
+        .map(|(l, _)| format!("{}%", l * 100.0))
+        .collect::<Vec<String>>()
+        .join(", ")
+}
+
+/// How many items do we need to sample from a population of this size,
+/// to estimate a proportion with the given margin of error?
+///
+/// This uses Cochran's formula, with the finite population correction:
+///
+///     n0 = z² p(1-p) / e²
+///     n  = n0 / (1 + (n0 - 1) / N)
+///
+/// We don't know the true proportion p, so we use p = 0.5, which gives
+/// the largest (most conservative) sample size.  The result is rounded
+/// up, and never more than the population.
+pub fn required_sample_size(population: usize, z: f64, margin: f64) -> usize {
+    if population == 0 {
+        return 0;
+    }
+
+    let n0 = z * z * 0.25 / (margin * margin);
+    let n = n0 / (1.0 + (n0 - 1.0) / population as f64);
+
+    // Round to a few decimal places first, so floating-point error
+    // doesn't push a whole number up to the next integer.
+    let n = ((n * 1e6).round() / 1e6).ceil() as usize;
+
+    n.min(population)
+}
+
+#[cfg(test)]
+mod confidence_tests {
+    use super::*;
+
+    #[test]
+    fn it_looks_up_z_scores() {
+        assert_eq!(z_score(0.95), Some(1.96));
+        assert_eq!(z_score(0.99), Some(2.5758));
+        assert_eq!(z_score(0.5), None);
+    }
+
+    // These match the published tables, e.g. a population of 1000 needs
+    // a sample of 278 for 95% confidence and a 5% margin of error.
+    #[test]
+    fn it_computes_the_sample_size() {
+        assert_eq!(required_sample_size(100, 1.96, 0.05), 80);
+        assert_eq!(required_sample_size(1000, 1.96, 0.05), 278);
+        assert_eq!(required_sample_size(10000, 1.96, 0.05), 370);
+        assert_eq!(required_sample_size(1_000_000, 1.96, 0.05), 385);
+        assert_eq!(required_sample_size(1000, 2.5758, 0.01), 944);
+    }
+
+    #[test]
+    fn it_never_picks_more_than_the_population() {
+        assert_eq!(required_sample_size(0, 1.96, 0.05), 0);
+        assert_eq!(required_sample_size(5, 3.2905, 0.001), 5);
+    }
+}
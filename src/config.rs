@@ -0,0 +1,411 @@
+use std::path::PathBuf;
+
+use crate::diagnostics;
+use crate::options::Options;
+
+/// Default options, from a `.randlinerc` file and `RANDLINE_*`
+/// environment variables.
+///
+/// The environment variables override the config file, and anything on
+/// the command line overrides both.
+#[derive(Debug, Default, PartialEq)]
+pub struct Defaults {
+    /// The subcommand to use if you don't pass one: `sample`, `shuf`
+    /// or `count`.
+    pub mode: Option<String>,
+
+    /// The sample size to use if you don't pass one, written the same
+    /// way as on the command line, e.g. `10` or `10%`.
+    pub k: Option<String>,
+
+    /// The other settings, as the name of the option and its value,
+    /// e.g. `("seed", Some("42"))`, or `("shuffle", None)` for a flag.
+    pub settings: Vec<(&'static str, Option<String>)>,
+}
+
+impl Defaults {
+    /// Apply the settings to `base`, then parse the command-line
+    /// arguments `cli_args` on top of them.
+    ///
+    /// The settings come before the command-line arguments, so an option
+    /// on the command line overrides the same setting.  We also skip any
+    /// setting which can't be used with the other options -- e.g.
+    /// `sort-output = true` doesn't apply if you pass `--shuffle` -- so
+    /// a default never turns a valid command line into an error.
+    pub fn apply(&self, mut base: Options, cli_args: &[String]) -> Options {
+        for (name, value) in &self.settings {
+            let mut with_setting = base.clone();
+            with_setting.parse_arg(format!("--{}", name), &mut value.clone().into_iter());
+
+            if with_setting.clone().parse_args(cli_args).check().is_ok() {
+                base = with_setting;
+            }
+        }
+
+        base.parse_args(cli_args)
+    }
+}
+
+/// Options which are on or off, e.g. `shuffle = true`.
+const FLAGS: &[&str] = &["shuffle", "sort-output", "strip", "strip-cr", "with-source"];
+
+/// Options which take a value, e.g. `seed = 42`.
+const OPTIONS: &[&str] = &[
+    "seed",
+    "input-format",
+    "input-delimiter",
+    "output-delimiter",
+    "output-separator",
+    "output-buffer-size",
+    "locale",
+];
+
+/// Load the defaults from the first `.randlinerc` we find, in the
+/// working directory or your home directory, and from the environment.
+///
+/// Exits with an error if the config file isn't valid TOML, or it has
+/// a setting we don't recognise.
+pub fn load() -> Defaults {
+    let mut settings = match find_config_file() {
+        Some(path) => {
+            let text = std::fs::read_to_string(&path).unwrap_or_else(|e| {
+                diagnostics::exit_with_error(format!("Unable to read {}: {}", path.display(), e))
+            });
+
+            parse_config(&text).unwrap_or_else(|e| {
+                diagnostics::exit_with_error(format!("Unable to parse {}: {}", path.display(), e))
+            })
+        }
+        None => toml::Table::new(),
+    };
+
+    for key in ["k", "mode"].iter().chain(FLAGS).chain(OPTIONS) {
+        let name = format!("RANDLINE_{}", key.to_uppercase().replace('-', "_"));
+
+        if let Ok(value) = std::env::var(&name) {
+            let value = if FLAGS.contains(key) {
+                toml::Value::Boolean(!matches!(value.as_str(), "" | "0" | "false"))
+            } else {
+                toml::Value::String(value)
+            };
+
+            settings.insert(key.to_string(), value);
+        }
+    }
+
+    to_defaults(&settings)
+        .unwrap_or_else(|e| diagnostics::exit_with_error(format!("Invalid default: {}", e)))
+}
+
+fn find_config_file() -> Option<PathBuf> {
+    let home = std::env::var_os("HOME").map(PathBuf::from);
+
+    [Some(PathBuf::from(".")), home]
+        .into_iter()
+        .flatten()
+        .map(|dir| dir.join(".randlinerc"))
+        .find(|path| path.is_file())
+}
+
+/// Parse the text of a config file, and check every setting is one
+/// we recognise.
+fn parse_config(text: &str) -> Result<toml::Table, String> {
+    let settings: toml::Table = text.parse().map_err(|e: toml::de::Error| e.to_string())?;
+
+    for key in settings.keys() {
+        let key = key.as_str();
+
+        if !(key == "k" || key == "mode" || FLAGS.contains(&key) || OPTIONS.contains(&key)) {
+            return Err(format!("unknown setting {:?}", key));
+        }
+    }
+
+    Ok(settings)
+}
+
+/// Turn the settings into a mode, k, and a list of other settings.
+fn to_defaults(settings: &toml::Table) -> Result<Defaults, String> {
+    let mut defaults = Defaults {
+        mode: settings
+            .get("mode")
+            .map(|v| to_text("mode", v))
+            .transpose()?,
+        k: settings.get("k").map(|v| to_text("k", v)).transpose()?,
+        settings: Vec::new(),
+    };
+
+    if let Some(mode) = &defaults.mode {
+        if !matches!(mode.as_str(), "sample" | "shuf" | "count") {
+            return Err(format!(
+                "mode has to be one of: sample, shuf, count; got {:?}",
+                mode
+            ));
+        }
+    }
+
+    for flag in FLAGS {
+        match settings.get(*flag) {
+            Some(toml::Value::Boolean(true)) => defaults.settings.push((flag, None)),
+            Some(toml::Value::Boolean(false)) | None => (),
+            Some(_) => return Err(format!("{} has to be true or false", flag)),
+        }
+    }
+
+    for option in OPTIONS {
+        if let Some(value) = settings.get(*option) {
+            defaults
+                .settings
+                .push((option, Some(to_text(option, value)?)));
+        }
+    }
+
+    Ok(defaults)
+}
+
+/// Get the text of a setting, as you'd pass it on the command line.
+fn to_text(key: &str, value: &toml::Value) -> Result<String, String> {
+    match value {
+        toml::Value::String(s) => Ok(s.clone()),
+        toml::Value::Integer(i) => Ok(i.to_string()),
+        toml::Value::Float(f) => Ok(f.to_string()),
+        _ => Err(format!("{} has to be a string or a number", key)),
+    }
+}
+
+#[cfg(test)]
+mod config_tests {
+    use super::*;
+    use crate::input;
+
+    fn defaults(text: &str) -> Result<Defaults, String> {
+        to_defaults(&parse_config(text)?)
+    }
+
+    #[test]
+    fn it_turns_settings_into_options() {
+        assert_eq!(
+            defaults("k = \"10%\"\nmode = \"shuf\"\nseed = 42\nshuffle = true\nstrip = false\n"),
+            Ok(Defaults {
+                mode: Some("shuf".to_string()),
+                k: Some("10%".to_string()),
+                settings: vec![("shuffle", None), ("seed", Some("42".to_string()))],
+            })
+        );
+    }
+
+    fn apply(text: &str, cli_args: &[&str]) -> Options {
+        let cli_args: Vec<String> = cli_args.iter().map(|a| a.to_string()).collect();
+        defaults(text).unwrap().apply(Options::default(), &cli_args)
+    }
+
+    #[test]
+    fn it_lets_the_command_line_override_settings() {
+        let config = "shuffle = true\nseed = 42\noutput-separator = \",\"\n";
+
+        let options = apply(config, &[]);
+        assert!(options.shuffle);
+        assert_eq!(options.seed, Some(42));
+        assert_eq!(options.output_separator.as_deref(), Some(","));
+
+        let options = apply(config, &["--seed", "7", "--sort-output"]);
+        assert!(!options.shuffle);
+        assert_eq!(options.seed, Some(7));
+        assert_eq!(options.output_separator.as_deref(), Some(","));
+
+        let options = apply(config, &["--csv"]);
+        assert!(options.shuffle);
+        assert_eq!(options.seed, Some(42));
+        assert_eq!(options.output_separator, None);
+    }
+
+    #[test]
+    fn it_only_uses_a_default_locale_when_sorting() {
+        assert!(apply("locale = \"sv\"\n", &[]).locale.is_none());
+        assert!(apply("locale = \"sv\"\n", &["--sort-output"])
+            .locale
+            .is_some());
+        assert!(apply("locale = \"sv\"\nsort-output = true\n", &[])
+            .locale
+            .is_some());
+        assert!(
+            apply("locale = \"sv\"\nsort-output = true\n", &["--shuffle"])
+                .locale
+                .is_none()
+        );
+    }
+
+    #[test]
+    fn it_only_drops_an_auto_input_format_for_options_that_need_another() {
+        assert_eq!(
+            apply("input-format = \"nul\"\n", &["--timeout", "1"]).input_format,
+            input::InputFormat::Nul
+        );
+        assert_eq!(
+            apply("input-format = \"auto\"\n", &["--timeout", "1"]).input_format,
+            input::InputFormat::Newline
+        );
+    }
+
+    // Whatever you pass on the command line, a setting in the config
+    // file never turns a valid set of options into an invalid one.
+    #[test]
+    fn no_setting_makes_the_command_line_invalid() {
+        let settings = [
+            "shuffle = true",
+            "sort-output = true",
+            "strip = true",
+            "strip-cr = true",
+            "with-source = true",
+            "seed = 42",
+            "input-format = \"nul\"",
+            "input-format = \"jsonl\"",
+            "input-format = \"auto\"",
+            "input-delimiter = \"crlf\"",
+            "output-delimiter = \"nul\"",
+            "output-separator = \",\"",
+            "output-buffer-size = 1024",
+            "locale = \"sv\"",
+            "sort-output = true\nlocale = \"sv\"",
+        ];
+
+        for cli_args in CLI_ARGS {
+            // Some options only work with a particular k.
+            for k in [None, Some("2"), Some("10%")] {
+                let cli_args: Vec<&str> = cli_args.iter().copied().chain(k).collect();
+
+                let from_cli = apply("", &cli_args);
+                if from_cli.check().is_err() {
+                    continue;
+                }
+
+                for setting in settings {
+                    assert_eq!(
+                        apply(setting, &cli_args).check(),
+                        Ok(()),
+                        "setting = {:?}, args = {:?}",
+                        setting,
+                        cli_args
+                    );
+                }
+            }
+        }
+    }
+
+    /// Every option, with a valid value if it takes one.
+    const CLI_ARGS: &[&[&str]] = &[
+        &[],
+        &["--unique"],
+        &["--approx-unique"],
+        &["--dedup-adjacent"],
+        &["--with-source"],
+        &["--per-file"],
+        &["--jsonl"],
+        &["--jsonl", "--validate"],
+        &["--count-only"],
+        &["--weighted"],
+        &["--weighted", "--fail-fast"],
+        &["--with-replacement"],
+        &["--strip"],
+        &["--csv"],
+        &["--output-json-objects"],
+        &["--shuffle"],
+        &["--sort-output"],
+        &["--stable-order"],
+        &["--explain"],
+        &["--stats"],
+        &["--print-total-bytes"],
+        &["--progress-bar"],
+        &["--strip-cr"],
+        &["--partial-on-error"],
+        &["--invert"],
+        &["--two-pass"],
+        &["--invert", "--two-pass"],
+        &["--shuffle-only"],
+        &["--k-from-input"],
+        &["--quiet-empty"],
+        &["--dedup-output"],
+        &["--line-buffered"],
+        &["--indices-only"],
+        &["--unique", "--fold-case"],
+        &["--dedup-output", "--fold-case"],
+        &["--echo-args"],
+        &["--max-runtime", "1"],
+        &["--timeout", "1"],
+        &["--group-by", "x"],
+        &["--count-matches", "x"],
+        &["--approx-unique", "--filter-size", "100"],
+        &["--file", "f"],
+        &["--exclude-file", "f"],
+        &["--include-file", "f"],
+        &["--force-include", "f"],
+        &["--weighted", "--default-weight", "1"],
+        &["--weighted", "--weight-regex", "(x)"],
+        &["--weighted", "--weight-field", "2"],
+        &["--columns", "1"],
+        &["--weighted", "--weights-file", "f"],
+        &["--balance-by", "x"],
+        &["--weighted", "--temperature", "1"],
+        &["--output-buffer-size", "1024"],
+        &["--limit-rate", "1"],
+        &["--output-separator", ";"],
+        &["--prefix", "x"],
+        &["--suffix", "x"],
+        &["--window", "1"],
+        &["--trim-to", "5"],
+        &["--bash-array", "lines"],
+        &["--seed", "1"],
+        &["--warmup", "1"],
+        &["--sort-output", "--locale", "en"],
+        &["--audit-log", "f"],
+        &["--output-count-to", "3"],
+        &["--input-encoding", "latin1"],
+        &["--output-encoding", "latin1"],
+        &["--shuffle-memory", "1"],
+        &["--resample", "2"],
+        &["--flush-marker", "---"],
+        &["--stable-by", "1"],
+        &["--entropy", "system"],
+        &["--stable-by", "1", "--hash-algo", "xxhash"],
+        &["--score-field", "1"],
+        &["--representativeness", "1"],
+        &["--score-field", "1", "--score-threshold", "0.5"],
+        &["--score-field", "1", "--score-scale", "2"],
+        &["--reservoir-impl", "algo-l"],
+        &["--score-field", "1", "--score-transform", "linear"],
+        &["--fixed-width", "5"],
+        &["--min-count", "1"],
+        &["--combine-with", "f"],
+        &["--combine-with", "f", "--combine-population", "10"],
+        &["--capacity-hint", "10"],
+        &["--assume-count", "10"],
+        &["--max-lines", "10"],
+        &["--sample-bytes", "10"],
+        &["--confidence", "95%", "--margin", "5%"],
+        &["--input-delimiter", "nul"],
+        &["--output-delimiter", "crlf"],
+        &["--crlf"],
+        &["--input-format", "jsonl"],
+        &["--input-format", "auto"],
+        &["--retry-on-empty", "1", "--file", "f"],
+        &["--compare-seeds", "2"],
+    ];
+
+    #[test]
+    fn it_allows_an_empty_config() {
+        assert_eq!(defaults(""), Ok(Defaults::default()));
+    }
+
+    #[test]
+    fn it_rejects_invalid_settings() {
+        for text in [
+            "colour = \"red\"",
+            "shuffle = \"yes\"",
+            "seed = true",
+            "mode = \"fetch\"",
+            "k = [1, 2]",
+            "not toml",
+        ] {
+            assert!(defaults(text).is_err(), "text = {:?}", text);
+        }
+    }
+}
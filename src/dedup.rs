@@ -0,0 +1,194 @@
+use bloomfilter::Bloom;
+use std::borrow::Cow;
+use std::collections::HashSet;
+
+/// How many distinct lines the approximate filter is sized for, if
+/// the user doesn't pass `--filter-size`.
+pub const DEFAULT_FILTER_SIZE: usize = 1_000_000;
+
+/// The false positive rate of the approximate filter, when it's holding
+/// as many distinct lines as it was sized for.
+///
+/// A false positive means a line we haven't seen before is treated as
+/// a duplicate, and dropped from the population.
+pub const FALSE_POSITIVE_RATE: f64 = 0.01;
+
+/// Remove duplicate lines, keeping the first occurrence of each.
+///
+/// If `fold_case` is true, lines that only differ in case are treated
+/// as duplicates, and we keep the first one with its original casing.
+///
+/// This remembers every distinct line it's seen, so memory usage grows
+/// with the number of distinct lines in the input.
+pub fn unique<T: AsRef<str>>(
+    lines: impl Iterator<Item = T>,
+    fold_case: bool,
+) -> impl Iterator<Item = T> {
+    let mut seen: HashSet<String> = HashSet::new();
+
+    lines.filter(move |ln| seen.insert(dedup_key(ln.as_ref(), fold_case).into_owned()))
+}
+
+/// Remove duplicate lines using a Bloom filter, keeping the first
+/// occurrence of each.
+///
+/// This uses a fixed amount of memory, but it's approximate -- a small
+/// fraction of distinct lines will be wrongly treated as duplicates.
+/// Duplicate lines are always removed.
+///
+/// `fold_case` works the same way as for `unique`.
+pub fn approx_unique<T: AsRef<str>>(
+    lines: impl Iterator<Item = T>,
+    filter_size: usize,
+    fold_case: bool,
+) -> impl Iterator<Item = T> {
+    let mut filter = ApproxFilter::new(filter_size);
+
+    lines.filter(move |ln| filter.is_new(&dedup_key(ln.as_ref(), fold_case)))
+}
+
+/// Collapse runs of identical lines into a single line, like `uniq`.
+///
+/// Only consecutive duplicates are removed, so this is mostly useful for
+/// sorted input.  It only remembers the previous line, so it uses a fixed
+/// amount of memory (apart from the length of that line).
+///
+/// `fold_case` works the same way as for `unique`.
+pub fn adjacent_unique<T: AsRef<str>>(
+    lines: impl Iterator<Item = T>,
+    fold_case: bool,
+) -> impl Iterator<Item = T> {
+    let mut previous: Option<String> = None;
+
+    lines.filter(move |ln| {
+        let key = dedup_key(ln.as_ref(), fold_case);
+
+        if previous.as_deref() == Some(key.as_ref()) {
+            false
+        } else {
+            previous = Some(key.into_owned());
+            true
+        }
+    })
+}
+
+/// The key we use to decide if two lines are duplicates.
+///
+/// To fold case, we lowercase the line.  This isn't full Unicode case
+/// folding (e.g. `ß` and `SS` are still different), but it handles the
+/// common cases.
+fn dedup_key(line: &str, fold_case: bool) -> Cow<'_, str> {
+    if fold_case {
+        Cow::Owned(line.to_lowercase())
+    } else {
+        Cow::Borrowed(line)
+    }
+}
+
+struct ApproxFilter {
+    bloom: Bloom<str>,
+}
+
+impl ApproxFilter {
+    fn new(filter_size: usize) -> ApproxFilter {
+        // Note: we're okay to `unwrap()` here because this only fails if
+        // `filter_size` is zero, and we check for that when parsing args.
+        ApproxFilter {
+            bloom: Bloom::new_for_fp_rate(filter_size, FALSE_POSITIVE_RATE).unwrap(),
+        }
+    }
+
+    /// Returns true if we (probably) haven't seen this line before.
+    fn is_new(&mut self, line: &str) -> bool {
+        !self.bloom.check_and_set(line)
+    }
+}
+
+#[cfg(test)]
+mod dedup_tests {
+    use super::*;
+
+    fn strings(items: &[&str]) -> Vec<String> {
+        items.iter().map(|s| s.to_string()).collect()
+    }
+
+    // Exact dedup removes every repeated line, and keeps the first
+    // occurrence of each.
+    #[test]
+    fn it_removes_duplicates() {
+        let lines = strings(&["a", "b", "a", "c", "b", "a"]);
+        let deduped: Vec<String> = unique(lines.into_iter(), false).collect();
+
+        assert_eq!(deduped, strings(&["a", "b", "c"]));
+    }
+
+    // Approximate dedup always removes repeated lines.
+    #[test]
+    fn it_removes_duplicates_approximately() {
+        let lines = strings(&["a", "b", "a", "c", "b", "a"]);
+        let deduped: Vec<String> = approx_unique(lines.into_iter(), 100, false).collect();
+
+        assert_eq!(deduped, strings(&["a", "b", "c"]));
+    }
+
+    // Approximate dedup keeps nearly all the distinct lines, when the
+    // filter is sized for the input.
+    #[test]
+    fn it_keeps_most_distinct_lines() {
+        let n = 10000;
+        let lines = (0..n).chain(0..n).map(|i| i.to_string());
+        let deduped: Vec<String> = approx_unique(lines, n, false).collect();
+
+        assert!(deduped.len() <= n);
+        assert!(
+            deduped.len() as f64 >= n as f64 * (1.0 - 2.0 * FALSE_POSITIVE_RATE),
+            "Too many distinct lines dropped: kept {} of {}",
+            deduped.len(),
+            n
+        );
+    }
+
+    // If we fold case, lines that only differ in case are duplicates,
+    // and we keep the first one with its original casing.
+    #[test]
+    fn it_removes_case_insensitive_duplicates() {
+        let lines = strings(&["Foo", "bar", "foo", "FOO", "Bar", "baz"]);
+
+        let deduped: Vec<String> = unique(lines.clone().into_iter(), true).collect();
+        assert_eq!(deduped, strings(&["Foo", "bar", "baz"]));
+
+        let deduped: Vec<String> = approx_unique(lines.into_iter(), 100, true).collect();
+        assert_eq!(deduped, strings(&["Foo", "bar", "baz"]));
+    }
+
+    // Adjacent dedup collapses runs of repeated lines, but keeps lines
+    // which are repeated later on.
+    #[test]
+    fn it_removes_adjacent_duplicates() {
+        let lines = strings(&["a", "a", "a", "b", "b", "a", "c", "c"]);
+        let deduped: Vec<String> = adjacent_unique(lines.into_iter(), false).collect();
+
+        assert_eq!(deduped, strings(&["a", "b", "a", "c"]));
+    }
+
+    #[test]
+    fn it_removes_adjacent_case_insensitive_duplicates() {
+        let lines = strings(&["Foo", "foo", "FOO", "bar", "Foo"]);
+        let deduped: Vec<String> = adjacent_unique(lines.into_iter(), true).collect();
+
+        assert_eq!(deduped, strings(&["Foo", "bar", "Foo"]));
+    }
+
+    // The filter's memory doesn't grow as we add more lines.
+    #[test]
+    fn it_uses_bounded_memory() {
+        let mut filter = ApproxFilter::new(1000);
+        let size_before = filter.bloom.as_slice().len();
+
+        for i in 0..100000 {
+            filter.is_new(&i.to_string());
+        }
+
+        assert_eq!(filter.bloom.as_slice().len(), size_before);
+    }
+}
@@ -0,0 +1,76 @@
+use std::fmt::Display;
+use std::io::IsTerminal;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::OnceLock;
+
+const RED: &str = "\x1b[31m";
+const YELLOW: &str = "\x1b[33m";
+const RESET: &str = "\x1b[0m";
+
+static USE_COLOR: AtomicBool = AtomicBool::new(false);
+
+/// Something to do before we print an error and exit, e.g. clear the
+/// progress bar off stderr.
+static BEFORE_EXIT: OnceLock<fn()> = OnceLock::new();
+
+/// Decide whether messages on stderr should be colored.
+///
+/// We only use color if stderr is a terminal, and the user hasn't
+/// turned it off with `--no-color` or by setting `NO_COLOR`
+/// (see https://no-color.org/).
+pub fn init(no_color_flag: bool) {
+    let no_color_env = std::env::var_os("NO_COLOR").is_some_and(|v| !v.is_empty());
+
+    USE_COLOR.store(
+        !no_color_flag && !no_color_env && std::io::stderr().is_terminal(),
+        Ordering::Relaxed,
+    );
+}
+
+/// Call `f` before we print an error in `exit_with_error`.  This can
+/// only be set once.
+pub fn before_exit(f: fn()) {
+    let _ = BEFORE_EXIT.set(f);
+}
+
+/// Print an error message to stderr and exit with status 1.
+pub fn exit_with_error(message: impl Display) -> ! {
+    if let Some(f) = BEFORE_EXIT.get() {
+        f();
+    }
+
+    eprintln!("{}", paint(message, RED, USE_COLOR.load(Ordering::Relaxed)));
+    std::process::exit(1)
+}
+
+/// Print a warning message to stderr.
+pub fn warning(message: impl Display) {
+    eprintln!(
+        "{}",
+        paint(message, YELLOW, USE_COLOR.load(Ordering::Relaxed))
+    );
+}
+
+/// Wrap a message in ANSI color codes, if color is enabled.
+fn paint(message: impl Display, color: &str, use_color: bool) -> String {
+    if use_color {
+        format!("{}{}{}", color, message, RESET)
+    } else {
+        message.to_string()
+    }
+}
+
+#[cfg(test)]
+mod diagnostics_tests {
+    use super::*;
+
+    #[test]
+    fn it_wraps_the_message_if_color_enabled() {
+        assert_eq!(paint("oops", RED, true), "\x1b[31moops\x1b[0m");
+    }
+
+    #[test]
+    fn it_leaves_the_message_if_color_disabled() {
+        assert_eq!(paint("oops", RED, false), "oops");
+    }
+}
@@ -0,0 +1,331 @@
+use encoding_rs::{Decoder, DecoderResult, Encoder, EncoderResult, Encoding};
+use std::io::{self, BufRead, Read, Write};
+
+/// Look up an encoding by name, e.g. `latin1`, `shift_jis` or `utf-16le`.
+///
+/// This accepts the labels from the WHATWG Encoding Standard, which are
+/// the names browsers accept, ignoring case.  Note that the standard
+/// treats `latin1` and `iso-8859-1` as `windows-1252`, which is the same
+/// except in the range 0x80–0x9F.
+pub fn for_name(name: &str) -> Option<&'static Encoding> {
+    Encoding::for_label(name.as_bytes())
+}
+
+/// A reader which decodes text in a legacy encoding, and returns it
+/// as UTF-8.
+///
+/// If the input isn't valid in that encoding, it's a read error, unless
+/// `lossy` is set, in which case we replace each invalid sequence with
+/// U+FFFD.  A byte order mark for the encoding at the start of the input
+/// is removed.
+pub struct DecodingReader<R> {
+    inner: R,
+    decoder: Decoder,
+    lossy: bool,
+
+    /// UTF-8 we've decoded but not returned yet, starting at `pos`.
+    decoded: Vec<u8>,
+    pos: usize,
+
+    /// Set once we've decoded the end of the input.
+    finished: bool,
+}
+
+impl<R: BufRead> DecodingReader<R> {
+    pub fn new(inner: R, encoding: &'static Encoding, lossy: bool) -> DecodingReader<R> {
+        DecodingReader {
+            inner,
+            decoder: encoding.new_decoder_with_bom_removal(),
+            lossy,
+            decoded: Vec::new(),
+            pos: 0,
+            finished: false,
+        }
+    }
+
+    /// Decode the next chunk of the input into `decoded`.
+    fn decode_chunk(&mut self) -> io::Result<()> {
+        let input = self.inner.fill_buf()?;
+        let last = input.is_empty();
+
+        // We make room for the whole chunk, so the decoder never stops
+        // because it's run out of space.
+        let capacity = if self.lossy {
+            self.decoder.max_utf8_buffer_length(input.len())
+        } else {
+            self.decoder
+                .max_utf8_buffer_length_without_replacement(input.len())
+        }
+        .ok_or_else(|| io::Error::other("input chunk is too big to decode"))?;
+
+        self.decoded.resize(capacity, 0);
+        self.pos = 0;
+
+        let (read, written) = if self.lossy {
+            let (_, read, written, _) = self.decoder.decode_to_utf8(input, &mut self.decoded, last);
+            (read, written)
+        } else {
+            match self
+                .decoder
+                .decode_to_utf8_without_replacement(input, &mut self.decoded, last)
+            {
+                (DecoderResult::Malformed(_, _), _, _) => {
+                    return Err(io::Error::new(
+                        io::ErrorKind::InvalidData,
+                        format!(
+                            "stream did not contain valid {}",
+                            self.decoder.encoding().name()
+                        ),
+                    ))
+                }
+                (_, read, written) => (read, written),
+            }
+        };
+
+        self.inner.consume(read);
+        self.decoded.truncate(written);
+        self.finished = last;
+
+        Ok(())
+    }
+}
+
+impl<R: BufRead> Read for DecodingReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        while self.pos == self.decoded.len() && !self.finished {
+            self.decode_chunk()?;
+        }
+
+        let n = buf.len().min(self.decoded.len() - self.pos);
+        buf[..n].copy_from_slice(&self.decoded[self.pos..self.pos + n]);
+        self.pos += n;
+
+        Ok(n)
+    }
+}
+
+/// Returns true if we can write text in this encoding.
+///
+/// `encoding_rs` can't encode the "replacement" encoding, which is only
+/// there to stop browsers decoding some unsafe encodings, but we do
+/// UTF-16 ourselves.
+pub fn can_encode(encoding: &'static Encoding) -> bool {
+    encoding.output_encoding() == encoding
+        || encoding == encoding_rs::UTF_16LE
+        || encoding == encoding_rs::UTF_16BE
+}
+
+/// A writer which takes UTF-8 and writes it in another encoding, or
+/// passes it through unchanged if the encoding is `None`.
+///
+/// If the text has a character which the encoding can't represent, it's
+/// a write error, which names the character.  We don't write a byte order
+/// mark for UTF-16.
+pub struct EncodingWriter<W> {
+    inner: W,
+    target: Option<Target>,
+
+    /// The start of a UTF-8 character which was split across two writes,
+    /// which we encode when we've seen the rest of it.
+    pending: Vec<u8>,
+}
+
+enum Target {
+    Encoder(Encoder),
+    Utf16 { big_endian: bool },
+}
+
+impl<W: Write> EncodingWriter<W> {
+    pub fn new(inner: W, encoding: Option<&'static Encoding>) -> EncodingWriter<W> {
+        let target = encoding.map(|encoding| {
+            if encoding == encoding_rs::UTF_16LE || encoding == encoding_rs::UTF_16BE {
+                Target::Utf16 {
+                    big_endian: encoding == encoding_rs::UTF_16BE,
+                }
+            } else {
+                Target::Encoder(encoding.new_encoder())
+            }
+        });
+
+        EncodingWriter {
+            inner,
+            target,
+            pending: Vec::new(),
+        }
+    }
+}
+
+/// Encode `text` and append it to `out`, or return an error if it has
+/// a character the encoder can't represent.
+///
+/// If `last` is set, we also end any shift sequence, e.g. in ISO-2022-JP,
+/// after which the encoder can't be used again.
+fn encode(encoder: &mut Encoder, text: &str, out: &mut Vec<u8>, last: bool) -> io::Result<()> {
+    let mut text = text;
+
+    loop {
+        let needed = encoder
+            .max_buffer_length_from_utf8_without_replacement(text.len())
+            .ok_or_else(|| io::Error::other("output is too big to encode"))?;
+        out.reserve(needed);
+
+        let (result, read) = encoder.encode_from_utf8_to_vec_without_replacement(text, out, last);
+        text = &text[read..];
+
+        match result {
+            EncoderResult::InputEmpty => return Ok(()),
+            EncoderResult::OutputFull => continue,
+            EncoderResult::Unmappable(c) => {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!(
+                        "can't write {:?} (U+{:04X}) in {}",
+                        c,
+                        c as u32,
+                        encoder.encoding().name()
+                    ),
+                ))
+            }
+        }
+    }
+}
+
+impl<W: Write> Write for EncodingWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let Some(target) = &mut self.target else {
+            return self.inner.write(buf);
+        };
+
+        self.pending.extend_from_slice(buf);
+
+        // Only encode complete characters, and keep the start of a split
+        // character for the next write.
+        let complete = match std::str::from_utf8(&self.pending) {
+            Ok(text) => text.len(),
+            Err(e) if e.error_len().is_none() => e.valid_up_to(),
+            Err(e) => return Err(io::Error::new(io::ErrorKind::InvalidData, e)),
+        };
+
+        let text = std::str::from_utf8(&self.pending[..complete]).unwrap();
+        let mut encoded = Vec::new();
+
+        match target {
+            Target::Encoder(encoder) => encode(encoder, text, &mut encoded, false)?,
+            Target::Utf16 { big_endian } => {
+                for unit in text.encode_utf16() {
+                    if *big_endian {
+                        encoded.extend_from_slice(&unit.to_be_bytes());
+                    } else {
+                        encoded.extend_from_slice(&unit.to_le_bytes());
+                    }
+                }
+            }
+        }
+
+        self.inner.write_all(&encoded)?;
+        self.pending.drain(..complete);
+
+        Ok(buf.len())
+    }
+
+    /// Flush the output, and for an encoding with shift sequences, go back
+    /// to the initial state, so everything we've written so far is
+    /// complete on its own.
+    fn flush(&mut self) -> io::Result<()> {
+        if let Some(Target::Encoder(encoder)) = &mut self.target {
+            let mut encoded = Vec::new();
+            encode(encoder, "", &mut encoded, true)?;
+            *encoder = encoder.encoding().new_encoder();
+            self.inner.write_all(&encoded)?;
+        }
+
+        self.inner.flush()
+    }
+}
+
+#[cfg(test)]
+mod encoding_tests {
+    use super::*;
+
+    fn decode(input: &[u8], name: &str, lossy: bool) -> io::Result<String> {
+        let mut reader = DecodingReader::new(input, for_name(name).unwrap(), lossy);
+        let mut output = String::new();
+        reader.read_to_string(&mut output)?;
+        Ok(output)
+    }
+
+    #[test]
+    fn it_decodes_legacy_encodings() {
+        assert_eq!(decode(b"caf\xe9\n", "latin1", false).unwrap(), "café\n");
+        assert_eq!(
+            decode(b"\x93\xfa\x96\x7b\n", "shift_jis", false).unwrap(),
+            "日本\n"
+        );
+        assert_eq!(
+            decode(b"\xff\xfea\x00\n\x00", "utf-16le", false).unwrap(),
+            "a\n"
+        );
+    }
+
+    #[test]
+    fn it_rejects_invalid_input_unless_lossy() {
+        assert!(decode(b"a\x81\n", "shift_jis", false).is_err());
+        assert_eq!(
+            decode(b"a\x81\n", "shift_jis", true).unwrap(),
+            "a\u{FFFD}\n"
+        );
+    }
+
+    fn encoded(text: &[&str], name: &str) -> io::Result<Vec<u8>> {
+        let mut writer = EncodingWriter::new(Vec::new(), Some(for_name(name).unwrap()));
+
+        for chunk in text {
+            writer.write_all(chunk.as_bytes())?;
+        }
+
+        writer.flush()?;
+        Ok(writer.inner)
+    }
+
+    #[test]
+    fn it_encodes_legacy_encodings() {
+        assert_eq!(encoded(&["café\n"], "latin1").unwrap(), b"caf\xe9\n");
+        assert_eq!(
+            encoded(&["日本\n"], "shift_jis").unwrap(),
+            b"\x93\xfa\x96\x7b\n"
+        );
+        assert_eq!(encoded(&["a\n"], "utf-16be").unwrap(), b"\x00a\x00\n");
+        assert_eq!(
+            encoded(&["日本"], "iso-2022-jp").unwrap(),
+            b"\x1b$BF|K\\\x1b(B"
+        );
+    }
+
+    // A character can be split across two writes, e.g. by a `BufWriter`.
+    #[test]
+    fn it_encodes_characters_split_across_writes() {
+        let mut writer = EncodingWriter::new(Vec::new(), Some(for_name("latin1").unwrap()));
+        writer.write_all(b"caf\xc3").unwrap();
+        writer.write_all(b"\xa9\n").unwrap();
+        assert_eq!(writer.inner, b"caf\xe9\n");
+    }
+
+    #[test]
+    fn it_rejects_unrepresentable_characters() {
+        let err = encoded(&["a\n", "日本\n"], "latin1").unwrap_err();
+        assert_eq!(err.to_string(), "can't write '日' (U+65E5) in windows-1252");
+    }
+
+    #[test]
+    fn it_passes_utf8_through_unchanged() {
+        let mut writer = EncodingWriter::new(Vec::new(), None);
+        writer.write_all("日本\n".as_bytes()).unwrap();
+        assert_eq!(writer.inner, "日本\n".as_bytes());
+    }
+
+    #[test]
+    fn it_rejects_unknown_encodings() {
+        assert!(for_name("latin1").is_some());
+        assert!(for_name("not-an-encoding").is_none());
+    }
+}
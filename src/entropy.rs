@@ -0,0 +1,72 @@
+use rand::rngs::OsRng;
+use rand::TryRngCore;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Where we get the seed from, if you don't pass `--seed`.
+///
+/// None of these are suitable for anything security-sensitive: the
+/// seed only goes into a `StdRng`, and `--explain` prints it.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum Entropy {
+    /// The thread-local generator from `rand`, which is seeded from the
+    /// operating system once per process.
+    #[default]
+    Default,
+
+    /// Ask the operating system for a fresh random number.  This might
+    /// block if the system hasn't gathered enough entropy yet, e.g. early
+    /// in boot, and it's an error if it isn't available.
+    System,
+
+    /// Mix the current time and the process ID.  This never blocks, but
+    /// two processes started at the same moment can get the same seed,
+    /// and anyone who knows roughly when you ran it can guess the seed.
+    Time,
+}
+
+impl Entropy {
+    /// Pick a seed from this source.
+    pub fn seed(self) -> Result<u64, String> {
+        match self {
+            Entropy::Default => Ok(rand::random()),
+            Entropy::System => OsRng
+                .try_next_u64()
+                .map_err(|e| format!("Unable to get entropy from the system: {}", e)),
+            Entropy::Time => {
+                let now = SystemTime::now()
+                    .duration_since(UNIX_EPOCH)
+                    .map_err(|e| format!("Unable to get entropy from the time: {}", e))?;
+
+                Ok(mix(
+                    now.as_nanos() as u64 ^ ((std::process::id() as u64) << 32)
+                ))
+            }
+        }
+    }
+}
+
+/// The SplitMix64 finalizer, which spreads the bits of the time across
+/// the whole seed, so nearby times give very different seeds.
+fn mix(mut x: u64) -> u64 {
+    x = (x ^ (x >> 30)).wrapping_mul(0xbf58476d1ce4e5b9);
+    x = (x ^ (x >> 27)).wrapping_mul(0x94d049bb133111eb);
+    x ^ (x >> 31)
+}
+
+#[cfg(test)]
+mod entropy_tests {
+    use super::*;
+
+    #[test]
+    fn every_source_gives_a_seed() {
+        for entropy in [Entropy::Default, Entropy::System, Entropy::Time] {
+            assert!(entropy.seed().is_ok(), "entropy = {:?}", entropy);
+        }
+    }
+
+    #[test]
+    fn nearby_times_give_different_seeds() {
+        assert_ne!(mix(1), mix(2));
+        assert_ne!(mix(1) >> 32, mix(2) >> 32);
+    }
+}
@@ -0,0 +1,117 @@
+use crate::sampling::ReservoirImpl;
+
+/// Which sampling algorithm did we use?
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Algorithm {
+    /// Uniform reservoir sampling, with the implementation we ran.
+    Reservoir(ReservoirImpl),
+    WeightedReservoir,
+    Windowed,
+    WithReplacement {
+        weighted: bool,
+    },
+}
+
+/// Describe how we picked a sample, for `--explain`.
+///
+/// `picked` is how many lines are in the sample, and `population` is how
+/// many lines we were picking from, after any filtering.
+pub fn explanation(algorithm: Algorithm, picked: usize, population: usize, seed: u64) -> String {
+    let description = match algorithm {
+        Algorithm::Reservoir(ReservoirImpl::Naive) => {
+            "reservoir sampling (random keys) -- every line has the same chance of being picked"
+        }
+        Algorithm::Reservoir(ReservoirImpl::AlgorithmL) => {
+            "reservoir sampling (Algorithm L) -- every line has the same chance of being picked"
+        }
+        Algorithm::Reservoir(ReservoirImpl::AlgorithmR) => {
+            "reservoir sampling (Algorithm R) -- every line has the same chance of being picked"
+        }
+        Algorithm::WeightedReservoir => {
+            "weighted reservoir sampling (Algorithm A-ExpJ) -- \
+             a line's chance of being picked is proportional to its weight"
+        }
+        Algorithm::Windowed => {
+            "windowed reservoir sampling -- \
+             every line in the time window has the same chance of being picked"
+        }
+        Algorithm::WithReplacement { weighted: false } => {
+            "sampling with replacement (alias method) -- \
+             every draw is independent, and every line is equally likely"
+        }
+        Algorithm::WithReplacement { weighted: true } => {
+            "sampling with replacement (alias method) -- \
+             every draw is independent, and picks a line in proportion to its weight"
+        }
+    };
+
+    // The overall inclusion probability only makes sense when every
+    // line has the same chance of being picked.
+    let probability = match algorithm {
+        Algorithm::Reservoir(_) if population > 0 => format!(
+            "Each line had a {}/{} = {:.2}% chance of being picked",
+            picked,
+            population,
+            100.0 * picked as f64 / population as f64
+        ),
+        Algorithm::WithReplacement { weighted: false } if population > 0 => format!(
+            "Each draw had a 1/{} = {:.2}% chance of picking any given line",
+            population,
+            100.0 / population as f64
+        ),
+        _ => format!(
+            "On average, a line had a {}/{} chance of being picked",
+            picked, population
+        ),
+    };
+
+    format!(
+        "Algorithm: {}\nPicked {} of {} line(s)\n{}\nSeed: {}",
+        description, picked, population, probability, seed
+    )
+}
+
+#[cfg(test)]
+mod explanation_tests {
+    use super::*;
+
+    #[test]
+    fn it_explains_a_uniform_sample() {
+        assert_eq!(
+            explanation(Algorithm::Reservoir(ReservoirImpl::Naive), 3, 12, 42),
+            "Algorithm: reservoir sampling (random keys) -- \
+             every line has the same chance of being picked\n\
+             Picked 3 of 12 line(s)\n\
+             Each line had a 3/12 = 25.00% chance of being picked\n\
+             Seed: 42"
+        );
+    }
+
+    #[test]
+    fn it_names_the_reservoir_impl() {
+        let text = explanation(Algorithm::Reservoir(ReservoirImpl::AlgorithmL), 3, 12, 42);
+        assert!(text.contains("(Algorithm L)"), "text = {:?}", text);
+
+        let text = explanation(Algorithm::Reservoir(ReservoirImpl::AlgorithmR), 3, 12, 42);
+        assert!(text.contains("(Algorithm R)"), "text = {:?}", text);
+    }
+
+    #[test]
+    fn it_explains_a_weighted_sample() {
+        let text = explanation(Algorithm::WeightedReservoir, 2, 5, 1);
+
+        assert!(text.contains("A-ExpJ"), "text = {:?}", text);
+        assert!(
+            text.contains("On average, a line had a 2/5 chance"),
+            "text = {:?}",
+            text
+        );
+    }
+
+    #[test]
+    fn it_explains_an_empty_input() {
+        let text = explanation(Algorithm::Reservoir(ReservoirImpl::Naive), 0, 0, 1);
+
+        assert!(text.contains("Picked 0 of 0 line(s)"), "text = {:?}", text);
+    }
+}
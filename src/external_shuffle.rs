@@ -0,0 +1,342 @@
+use rand::seq::SliceRandom;
+use rand::Rng;
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{self, BufReader, BufWriter, Read, Write};
+use std::path::PathBuf;
+use std::rc::Rc;
+
+use crate::input::Record;
+
+/// How many temporary files we split the input into, if it doesn't fit
+/// in memory.  If one of them is still too big, we split it again.
+const BUCKETS: usize = 16;
+
+/// Shuffle the records, using at most roughly `memory_budget` bytes of
+/// memory for the records themselves, and pass each one to `emit` in
+/// their new order.
+///
+/// If the records fit in the budget, we shuffle them in memory, the same
+/// as `--shuffle-only`.  Otherwise, we write each record to one of a few
+/// temporary files, picked at random, then shuffle the files one at
+/// a time, in a random order.  Because every record is equally likely to
+/// go in every file, this gives a uniformly random order.
+///
+/// The temporary files are in a new directory inside the system temp
+/// directory (`$TMPDIR` on Unix), and the whole directory is deleted
+/// when we're done with it -- unless the process exits first, e.g.
+/// because of a read error or a second Ctrl-C.
+pub fn shuffle<R: Rng>(
+    records: impl Iterator<Item = Record>,
+    memory_budget: usize,
+    rng: &mut R,
+    emit: &mut impl FnMut(Record) -> io::Result<()>,
+) -> io::Result<()> {
+    let mut sources = Sources::default();
+
+    shuffle_within_budget(
+        Box::new(records.map(Ok)),
+        memory_budget,
+        rng,
+        &mut sources,
+        emit,
+    )
+}
+
+/// Roughly how much memory a record uses: the text of the line, plus
+/// the `Record` itself.
+fn record_size(record: &Record) -> usize {
+    record.line.len() + std::mem::size_of::<Record>()
+}
+
+/// This takes a boxed iterator so it can call itself on the records
+/// from a temporary file, which are a different type of iterator.
+fn shuffle_within_budget<R: Rng>(
+    mut records: Box<dyn Iterator<Item = io::Result<Record>> + '_>,
+    memory_budget: usize,
+    rng: &mut R,
+    sources: &mut Sources,
+    emit: &mut impl FnMut(Record) -> io::Result<()>,
+) -> io::Result<()> {
+    let mut buffer: Vec<Record> = Vec::new();
+    let mut buffered_size = 0;
+
+    // Read records into memory until we've gone over the budget.  If
+    // that's all of them, we've got them in memory anyway, so we might
+    // as well shuffle them there -- this is what stops us splitting a
+    // single huge record forever.
+    for record in records.by_ref() {
+        let record = record?;
+        buffered_size += record_size(&record);
+        buffer.push(record);
+
+        if buffered_size > memory_budget {
+            break;
+        }
+    }
+
+    let next = records.next().transpose()?;
+
+    let Some(next) = next else {
+        buffer.shuffle(rng);
+        return buffer.into_iter().try_for_each(emit);
+    };
+
+    let dir = tempfile::Builder::new().prefix("randline-").tempdir()?;
+
+    let mut buckets: Vec<Bucket> = (0..BUCKETS)
+        .map(|i| Bucket::create(dir.path().join(i.to_string())))
+        .collect::<io::Result<_>>()?;
+
+    let everything = buffer
+        .into_iter()
+        .map(Ok)
+        .chain(Some(Ok(next)))
+        .chain(records);
+
+    for record in everything {
+        let record = record?;
+        buckets[rng.random_range(0..BUCKETS)].write(&record, sources)?;
+    }
+
+    let mut order: Vec<FinishedBucket> = buckets
+        .into_iter()
+        .map(Bucket::finish)
+        .collect::<io::Result<_>>()?;
+    order.shuffle(rng);
+
+    for bucket in order {
+        let mut reader = BufReader::new(File::open(&bucket.path)?);
+
+        if bucket.size <= memory_budget {
+            let mut records: Vec<Record> =
+                std::iter::from_fn(|| read_record(&mut reader, sources).transpose())
+                    .collect::<io::Result<_>>()?;
+            records.shuffle(rng);
+            records.into_iter().try_for_each(&mut *emit)?;
+        } else {
+            // The records in this file only use sources we've already
+            // seen, so we can read them with a copy of the list, while
+            // the recursive call uses the original to write them out.
+            let known_sources = sources.clone();
+            let records =
+                std::iter::from_fn(move || read_record(&mut reader, &known_sources).transpose());
+            shuffle_within_budget(Box::new(records), memory_budget, rng, sources, emit)?;
+        }
+    }
+
+    dir.close()
+}
+
+/// The sources of the records we've written to temporary files.
+///
+/// Rather than writing the path of the source with every record, we
+/// write its position in this list.
+#[derive(Clone, Default)]
+struct Sources {
+    ids: HashMap<Rc<str>, u32>,
+    names: Vec<Rc<str>>,
+}
+
+impl Sources {
+    fn id(&mut self, source: &Rc<str>) -> u32 {
+        if let Some(id) = self.ids.get(source) {
+            return *id;
+        }
+
+        let id = self.names.len() as u32;
+        self.ids.insert(Rc::clone(source), id);
+        self.names.push(Rc::clone(source));
+        id
+    }
+
+    fn name(&self, id: u32) -> io::Result<Rc<str>> {
+        self.names
+            .get(id as usize)
+            .cloned()
+            .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "unknown source"))
+    }
+}
+
+/// A temporary file we're writing records to.
+struct Bucket {
+    path: PathBuf,
+    writer: BufWriter<File>,
+
+    /// Roughly how much memory the records in this file would use,
+    /// as measured by `record_size`.
+    size: usize,
+}
+
+/// A temporary file we've finished writing.
+struct FinishedBucket {
+    path: PathBuf,
+    size: usize,
+}
+
+impl Bucket {
+    fn create(path: PathBuf) -> io::Result<Bucket> {
+        let writer = BufWriter::new(File::create(&path)?);
+
+        Ok(Bucket {
+            path,
+            writer,
+            size: 0,
+        })
+    }
+
+    fn write(&mut self, record: &Record, sources: &mut Sources) -> io::Result<()> {
+        self.size += record_size(record);
+        write_record(&mut self.writer, record, sources)
+    }
+
+    fn finish(mut self) -> io::Result<FinishedBucket> {
+        self.writer.flush()?;
+
+        Ok(FinishedBucket {
+            path: self.path,
+            size: self.size,
+        })
+    }
+}
+
+/// Write a record in a simple binary format:
+///
+///     source ID (u32) | line number (u64) | has weight (u8) | weight (f64)
+///         | line length (u64) | line (UTF-8)
+///
+/// All the numbers are little-endian.  The length means the line can
+/// contain any character, including newlines.
+fn write_record(out: &mut impl Write, record: &Record, sources: &mut Sources) -> io::Result<()> {
+    out.write_all(&sources.id(&record.source).to_le_bytes())?;
+    out.write_all(&(record.line_number as u64).to_le_bytes())?;
+    out.write_all(&[record.weight.is_some() as u8])?;
+    out.write_all(&record.weight.unwrap_or(0.0).to_le_bytes())?;
+    out.write_all(&(record.line.len() as u64).to_le_bytes())?;
+    out.write_all(record.line.as_bytes())
+}
+
+/// Read a record written by `write_record`, or `None` if we're at the
+/// end of the file.
+fn read_record(input: &mut impl Read, sources: &Sources) -> io::Result<Option<Record>> {
+    let mut source_id = [0; 4];
+
+    match input.read_exact(&mut source_id) {
+        Ok(()) => (),
+        Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => return Ok(None),
+        Err(e) => return Err(e),
+    }
+
+    let mut line_number = [0; 8];
+    input.read_exact(&mut line_number)?;
+
+    let mut has_weight = [0; 1];
+    input.read_exact(&mut has_weight)?;
+
+    let mut weight = [0; 8];
+    input.read_exact(&mut weight)?;
+
+    let mut length = [0; 8];
+    input.read_exact(&mut length)?;
+
+    let mut line = vec![0; u64::from_le_bytes(length) as usize];
+    input.read_exact(&mut line)?;
+
+    Ok(Some(Record {
+        source: sources.name(u32::from_le_bytes(source_id))?,
+        line_number: u64::from_le_bytes(line_number) as usize,
+        line: String::from_utf8(line).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?,
+        weight: (has_weight[0] == 1).then(|| f64::from_le_bytes(weight)),
+    }))
+}
+
+#[cfg(test)]
+mod external_shuffle_tests {
+    use super::*;
+    use rand::rngs::StdRng;
+    use rand::SeedableRng;
+
+    fn records(lines: &[&str]) -> Vec<Record> {
+        let source: Rc<str> = Rc::from("-");
+
+        lines
+            .iter()
+            .enumerate()
+            .map(|(i, line)| Record {
+                source: Rc::clone(&source),
+                line_number: i + 1,
+                line: line.to_string(),
+                weight: None,
+            })
+            .collect()
+    }
+
+    fn shuffled(records: Vec<Record>, memory_budget: usize, seed: u64) -> Vec<Record> {
+        let mut rng = StdRng::seed_from_u64(seed);
+        let mut output = Vec::new();
+
+        shuffle(records.into_iter(), memory_budget, &mut rng, &mut |r| {
+            output.push(r);
+            Ok(())
+        })
+        .unwrap();
+
+        output
+    }
+
+    fn line_numbers(records: &[Record]) -> Vec<usize> {
+        records.iter().map(|r| r.line_number).collect()
+    }
+
+    // Whether or not the records fit in memory, we get every record
+    // back exactly once, with its source, line number and weight.
+    #[test]
+    fn it_returns_every_record() {
+        let lines: Vec<String> = (0..1000)
+            .map(|i| format!("line {}\nwith a newline", i))
+            .collect();
+        let lines: Vec<&str> = lines.iter().map(|s| s.as_str()).collect();
+
+        for memory_budget in [0, 1000, 10_000, 1_000_000] {
+            let mut input = records(&lines);
+            input[5].weight = Some(2.5);
+
+            let mut output = shuffled(input.clone(), memory_budget, 1);
+            output.sort_by_key(|r| r.line_number);
+
+            assert_eq!(line_numbers(&output), line_numbers(&input));
+
+            for (a, b) in input.iter().zip(output.iter()) {
+                assert_eq!(a.line, b.line);
+                assert_eq!(a.source, b.source);
+                assert_eq!(a.weight, b.weight);
+            }
+        }
+    }
+
+    // A single record that's bigger than the budget on its own is
+    // still shuffled, rather than split forever.
+    #[test]
+    fn it_handles_records_bigger_than_the_budget() {
+        let long_line = "x".repeat(1000);
+        let output = shuffled(records(&[&long_line, &long_line, "short"]), 10, 2);
+        assert_eq!(output.len(), 3);
+    }
+
+    // Every record is equally likely to end up in each position, even
+    // when we have to go via temporary files.
+    #[test]
+    fn it_picks_a_uniformly_random_order() {
+        let mut first_counts = [0; 4];
+        let iterations = 4000;
+
+        for seed in 0..iterations {
+            let output = shuffled(records(&["a", "b", "c", "d"]), 0, seed);
+            first_counts[output[0].line_number - 1] += 1;
+        }
+
+        for count in first_counts {
+            assert!((900..=1100).contains(&count), "counts = {:?}", first_counts);
+        }
+    }
+}
@@ -0,0 +1,23 @@
+/// Get the `field`-th tab-separated field of a line (counting from 1),
+/// or `None` if the line doesn't have that field.
+///
+///     nth_field("user-1\tclick", 1)  => Some("user-1")
+///     nth_field("user-1", 2)         => None
+///
+pub fn nth_field(line: &str, field: usize) -> Option<&str> {
+    line.split('\t').nth(field.checked_sub(1)?)
+}
+
+#[cfg(test)]
+mod fields_tests {
+    use super::*;
+
+    #[test]
+    fn it_gets_the_nth_field() {
+        assert_eq!(nth_field("user-1\tclick", 1), Some("user-1"));
+        assert_eq!(nth_field("click\tuser-1", 2), Some("user-1"));
+        assert_eq!(nth_field("click\t\tuser-1", 2), Some(""));
+        assert_eq!(nth_field("user-1", 2), None);
+        assert_eq!(nth_field("user-1", 0), None);
+    }
+}
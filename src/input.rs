@@ -0,0 +1,949 @@
+use std::collections::HashSet;
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, Read};
+use std::rc::Rc;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::mpsc::{self, RecvTimeoutError};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crate::diagnostics;
+use crate::encoding::DecodingReader;
+
+/// A single line of input, and where it came from.
+#[derive(Clone)]
+pub struct Record {
+    /// The path of the file this line was read from, or `-` for stdin.
+    pub source: Rc<str>,
+
+    /// The 1-indexed position of this line within its source.
+    pub line_number: usize,
+
+    pub line: String,
+
+    /// The weight of this line, if we know it -- either because it came
+    /// from a separate weights file, or we've parsed it in weighted mode.
+    pub weight: Option<f64>,
+}
+
+impl AsRef<str> for Record {
+    fn as_ref(&self) -> &str {
+        &self.line
+    }
+}
+
+/// Options that control how we read the input.
+#[derive(Clone, Copy, Default)]
+pub struct ReadOptions {
+    /// Give up if stdin hasn't reached EOF within this time.  This only
+    /// applies to stdin.
+    pub timeout: Option<Duration>,
+
+    /// If a file is empty, keep re-opening it until it has some lines,
+    /// or until this much time has passed.  This only applies to files.
+    pub retry_on_empty: Option<Duration>,
+
+    /// Split records on NUL bytes rather than newlines.
+    pub nul_separated: bool,
+
+    /// Split records on `\r\n` only, so a bare `\n` is part of a record.
+    pub crlf_separated: bool,
+
+    /// Split the input into records of this many characters, rather than
+    /// looking for a separator.
+    pub fixed_width: Option<usize>,
+
+    /// Remove a single trailing carriage return (`\r`) from each record.
+    pub strip_cr: bool,
+
+    /// If we get an error while reading, stop reading and keep the lines
+    /// we've read so far, rather than exiting.
+    pub partial_on_error: bool,
+
+    /// Replace invalid UTF-8 with U+FFFD REPLACEMENT CHARACTER (`�`),
+    /// rather than treating it as a read error.
+    pub lossy_utf8: bool,
+
+    /// Decode the input from this encoding, rather than UTF-8.
+    pub input_encoding: Option<&'static encoding_rs::Encoding>,
+
+    /// Stop reading at this time, and keep the lines we've read so far.
+    pub deadline: Option<Instant>,
+
+    /// Add the bytes we read to the count in `bytes_read()`.  This is only
+    /// set for the input we're sampling from, not for files like
+    /// `--force-include` or `--weights-file`.
+    pub count_bytes: bool,
+}
+
+/// How the input is split into records.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum InputFormat {
+    /// One record per line.
+    Newline,
+
+    /// Records separated by NUL bytes, e.g. the output of `find -print0`.
+    Nul,
+
+    /// Records separated by Windows-style `\r\n` line endings.
+    Crlf,
+
+    /// One JSON value per line.
+    Jsonl,
+
+    /// Pick one of the other formats by looking at the start of the input.
+    Auto,
+}
+
+/// How many bytes we look at when guessing the input format.
+const SNIFF_SIZE: usize = 64 * 1024;
+
+/// How long to wait between attempts to re-read an empty file.
+const RETRY_INTERVAL: Duration = Duration::from_millis(100);
+
+/// Set if we've stopped reading after an error, so we don't start
+/// reading any more sources.
+static STOPPED_AFTER_ERROR: AtomicBool = AtomicBool::new(false);
+
+/// Set if the user has pressed Ctrl-C, so we stop reading and pick
+/// a sample from the lines we've read so far.
+static INTERRUPTED: AtomicBool = AtomicBool::new(false);
+
+/// Set if we've passed the deadline in `ReadOptions`, so we stop reading
+/// and pick a sample from the lines we've read so far.
+static PAST_DEADLINE: AtomicBool = AtomicBool::new(false);
+
+/// The number of bytes we've read from the input so far.  This is
+/// a static because stdin might be read on a background thread.
+static BYTES_READ: AtomicU64 = AtomicU64::new(0);
+
+/// The total number of bytes we've read from the input so far, including
+/// the delimiters, e.g. for `--print-total-bytes`.
+///
+/// For compressed files, this counts the bytes after decompression.  It
+/// doesn't count files like `--exclude-file` or `--force-include` which
+/// aren't part of the input, or the second pass of `--two-pass`.
+pub fn bytes_read() -> u64 {
+    BYTES_READ.load(Ordering::Relaxed)
+}
+
+/// A reader which adds the number of bytes read from it to `BYTES_READ`,
+/// if `enabled` is set.
+///
+/// We count bytes as they're consumed, not as they're buffered, so we
+/// don't count anything we read ahead but never used.
+struct CountingReader<R> {
+    inner: R,
+    enabled: bool,
+}
+
+impl<R: Read> Read for CountingReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let n = self.inner.read(buf)?;
+        if self.enabled {
+            BYTES_READ.fetch_add(n as u64, Ordering::Relaxed);
+        }
+        Ok(n)
+    }
+}
+
+impl<R: BufRead> BufRead for CountingReader<R> {
+    fn fill_buf(&mut self) -> io::Result<&[u8]> {
+        self.inner.fill_buf()
+    }
+
+    fn consume(&mut self, amt: usize) {
+        self.inner.consume(amt);
+        if self.enabled {
+            BYTES_READ.fetch_add(amt as u64, Ordering::Relaxed);
+        }
+    }
+}
+
+/// Handle Ctrl-C by stopping reading, rather than exiting straight away.
+///
+/// We stop before the next record, so if we're blocked waiting for more
+/// input, we only stop once the next record arrives or the input closes.
+/// A second Ctrl-C exits immediately, without printing a sample.
+pub fn stop_reading_on_interrupt() {
+    let result = ctrlc::set_handler(|| {
+        if INTERRUPTED.swap(true, Ordering::Relaxed) {
+            std::process::exit(130);
+        }
+
+        diagnostics::warning(
+            "Interrupted; picking a sample from the lines read so far \
+             (press Ctrl-C again to quit)",
+        );
+    });
+
+    if let Err(e) = result {
+        diagnostics::warning(format!("Unable to handle Ctrl-C: {}", e));
+    }
+}
+
+/// Stop an iterator once `flag` is set.  This checks the flag before
+/// asking for each item, so we keep every item we've already read.
+fn until_set<T>(
+    mut items: impl Iterator<Item = T>,
+    flag: &'static AtomicBool,
+) -> impl Iterator<Item = T> {
+    std::iter::from_fn(move || {
+        if flag.load(Ordering::Relaxed) {
+            None
+        } else {
+            items.next()
+        }
+    })
+}
+
+/// Stop an iterator once we've passed `deadline`, if there is one.  This
+/// checks the time before asking for each item, so we keep every item
+/// we've already read -- but if we're blocked waiting for the next item,
+/// we can't stop until it arrives.
+fn until_deadline<T>(
+    mut items: impl Iterator<Item = T>,
+    deadline: Option<Instant>,
+) -> impl Iterator<Item = T> {
+    std::iter::from_fn(move || match deadline {
+        Some(deadline) if Instant::now() >= deadline => {
+            stop_at_deadline();
+            None
+        }
+        _ => items.next(),
+    })
+}
+
+/// Note that we've passed the deadline, and warn the user the first
+/// time, so they know the sample is only from part of the input.
+fn stop_at_deadline() {
+    if !PAST_DEADLINE.swap(true, Ordering::Relaxed) {
+        diagnostics::warning("Reached --max-runtime; picking a sample from the lines read so far");
+    }
+}
+
+/// Read the lines from each of `paths` in turn, where `-` means stdin.
+/// If there are no paths, read from stdin.
+///
+/// If `partial_on_error` is set and we get a read error, we stop reading
+/// altogether -- we don't skip to the next source.
+pub fn read_records(paths: Vec<String>, options: ReadOptions) -> impl Iterator<Item = Record> {
+    let paths = if paths.is_empty() {
+        vec!["-".to_string()]
+    } else {
+        paths
+    };
+
+    let paths = paths.into_iter().take_while(|_| {
+        !(STOPPED_AFTER_ERROR.load(Ordering::Relaxed) || PAST_DEADLINE.load(Ordering::Relaxed))
+    });
+
+    paths.flat_map(move |path| {
+        let lines = until_set(read_lines(&path, options), &INTERRUPTED);
+        let lines = until_deadline(lines, options.deadline).map(move |line| {
+            if options.strip_cr {
+                strip_trailing_cr(line)
+            } else {
+                line
+            }
+        });
+        let source: Rc<str> = Rc::from(path);
+
+        lines.enumerate().map(move |(i, line)| Record {
+            source: Rc::clone(&source),
+            line_number: i + 1,
+            line,
+            weight: None,
+        })
+    })
+}
+
+/// If stdin is a regular file, e.g. `randline 5 < file.txt`, count the
+/// records in it and then rewind it, so it can be read again from the start.
+///
+/// Returns `None` if stdin isn't a file we can seek, e.g. it's a pipe, or
+/// if we can't read it -- in which case we leave the error for the usual
+/// read to report.  This has to be called before anything reads stdin.
+#[cfg(unix)]
+pub fn count_stdin_records(options: ReadOptions) -> Option<usize> {
+    use std::io::{Seek, SeekFrom};
+    use std::os::fd::AsFd;
+
+    // This is a duplicate of the stdin file descriptor, which shares its
+    // offset, so when we rewind this, we're rewinding stdin.
+    let mut file = File::from(io::stdin().as_fd().try_clone_to_owned().ok()?);
+
+    if !file.metadata().ok()?.is_file() {
+        return None;
+    }
+
+    let start = file.stream_position().ok()?;
+
+    let count = split_records(BufReader::new(file.try_clone().ok()?), options)
+        .try_fold(0, |count, record| record.ok().map(|_| count + 1));
+
+    file.seek(SeekFrom::Start(start)).ok()?;
+    count
+}
+
+#[cfg(not(unix))]
+pub fn count_stdin_records(_options: ReadOptions) -> Option<usize> {
+    None
+}
+
+/// Remove a single carriage return from the end of a line.
+///
+/// `BufRead::lines()` already removes the `\r` from a `\r\n` line ending,
+/// but not from a last line without a newline, or from NUL-delimited
+/// records.  We only look at the end of the line, so carriage returns
+/// in the middle of a line are left alone.
+fn strip_trailing_cr(mut line: String) -> String {
+    if line.ends_with('\r') {
+        line.pop();
+    }
+
+    line
+}
+
+/// Read the distinct lines of a file into a set, e.g. for `--exclude-file`
+/// or `--include-file`.  The lines are split and decoded with `options`.
+///
+/// This keeps every distinct line of the file in memory, so memory usage
+/// grows with the size of the file -- roughly the total length of the
+/// distinct lines, plus a few dozen bytes of overhead per line.
+pub fn read_line_set(path: &str, options: ReadOptions) -> HashSet<String> {
+    let reader = BufReader::new(open_reader(path));
+
+    check_lines(split_records(reader, options), path.to_string(), options).collect()
+}
+
+/// Guess the format of the input by looking at the start of the first
+/// source, without consuming any of it.
+///
+/// We can't look at a named pipe without consuming what we read, so if
+/// the first source is a FIFO, we assume it's newline-delimited.
+pub fn detect_format(paths: &[String]) -> InputFormat {
+    let path = paths.first().map(|p| p.as_str()).unwrap_or("-");
+
+    if path == "-" {
+        let mut stdin = io::stdin().lock();
+        let chunk = stdin.fill_buf();
+        return match chunk {
+            Ok(chunk) => sniff_format(chunk),
+            Err(e) => read_error("stdin", e),
+        };
+    }
+
+    if is_fifo(path) {
+        return InputFormat::Newline;
+    }
+
+    let mut reader = BufReader::with_capacity(SNIFF_SIZE, open_reader(path));
+    match reader.fill_buf() {
+        Ok(chunk) => sniff_format(chunk),
+        Err(e) => read_error(path, e),
+    }
+}
+
+#[cfg(unix)]
+fn is_fifo(path: &str) -> bool {
+    use std::os::unix::fs::FileTypeExt;
+
+    std::fs::metadata(path).is_ok_and(|m| m.file_type().is_fifo())
+}
+
+#[cfg(not(unix))]
+fn is_fifo(_path: &str) -> bool {
+    false
+}
+
+/// Guess the format of the input from a chunk at the start of it.
+///
+///   - If there are any NUL bytes, it's NUL-delimited.  NUL bytes
+///     almost never appear in text, so this is a strong signal.
+///   - If every non-blank line is a JSON object or array, it's JSON
+///     Lines.  We ignore the last line if it might have been cut off
+///     by the end of the chunk.
+///   - Otherwise, it's newline-delimited.
+///
+/// We only look for JSON objects or arrays, because a line like `123`
+/// or `true` is valid JSON but more likely to be plain text.
+fn sniff_format(chunk: &[u8]) -> InputFormat {
+    if chunk.contains(&0) {
+        return InputFormat::Nul;
+    }
+
+    let mut lines: Vec<&[u8]> = chunk.split(|b| *b == b'\n').collect();
+
+    if lines.len() > 1 {
+        lines.pop();
+    }
+
+    let mut lines = lines
+        .into_iter()
+        .map(|ln| String::from_utf8_lossy(ln))
+        .filter(|ln| !ln.trim().is_empty())
+        .peekable();
+
+    if lines.peek().is_none() {
+        return InputFormat::Newline;
+    }
+
+    let is_json = lines.all(|ln| {
+        let ln = ln.trim();
+        (ln.starts_with('{') || ln.starts_with('['))
+            && serde_json::from_str::<serde_json::Value>(ln).is_ok()
+    });
+
+    if is_json {
+        InputFormat::Jsonl
+    } else {
+        InputFormat::Newline
+    }
+}
+
+fn read_lines(path: &str, options: ReadOptions) -> Box<dyn Iterator<Item = String>> {
+    if path == "-" {
+        return match (options.timeout, options.deadline) {
+            (None, None) => check_lines(
+                split_records(
+                    CountingReader {
+                        inner: io::stdin().lock(),
+                        enabled: options.count_bytes,
+                    },
+                    options,
+                ),
+                "stdin".to_string(),
+                options,
+            ),
+            (timeout, _) => Box::new(stdin_lines_with_timeout(timeout, options)),
+        };
+    }
+
+    match options.retry_on_empty {
+        None => file_lines(path, options),
+        Some(retry_for) => file_lines_with_retry(path, retry_for, options),
+    }
+}
+
+/// Split a reader into records, on newlines, `\r\n` or NUL bytes, or
+/// into fixed-width records.
+///
+/// If the input is in another encoding, we decode it to UTF-8 first,
+/// so we can split it the same way.
+pub fn split_records(
+    reader: impl BufRead + 'static,
+    options: ReadOptions,
+) -> Box<dyn Iterator<Item = io::Result<String>>> {
+    match options.input_encoding {
+        Some(encoding) => split_utf8_records(
+            BufReader::new(DecodingReader::new(reader, encoding, options.lossy_utf8)),
+            options,
+        ),
+        None => split_utf8_records(reader, options),
+    }
+}
+
+fn split_utf8_records(
+    reader: impl BufRead + 'static,
+    options: ReadOptions,
+) -> Box<dyn Iterator<Item = io::Result<String>>> {
+    let lossy = options.lossy_utf8;
+
+    if let Some(width) = options.fixed_width {
+        Box::new(fixed_width_records(reader, width, lossy))
+    } else if options.crlf_separated {
+        Box::new(crlf_records(reader, lossy))
+    } else if options.nul_separated {
+        Box::new(
+            reader
+                .split(b'\0')
+                .map(move |record| record.and_then(|bytes| decode(bytes, lossy))),
+        )
+    } else if lossy {
+        // This matches `BufRead::lines()`, which removes the `\n` or
+        // `\r\n` at the end of each line, but it can't do lossy decoding.
+        Box::new(reader.split(b'\n').map(|record| {
+            record.and_then(|mut bytes| {
+                if bytes.ends_with(b"\r") {
+                    bytes.pop();
+                }
+
+                decode(bytes, true)
+            })
+        }))
+    } else {
+        Box::new(reader.lines())
+    }
+}
+
+/// Turn the bytes of a record into a string.
+///
+/// If the bytes aren't valid UTF-8, it's an error, unless `lossy` is set,
+/// in which case we replace each invalid sequence with U+FFFD.
+fn decode(bytes: Vec<u8>, lossy: bool) -> io::Result<String> {
+    match String::from_utf8(bytes) {
+        Ok(s) => Ok(s),
+        Err(e) if lossy => Ok(String::from_utf8_lossy(e.as_bytes()).into_owned()),
+        Err(e) => Err(io::Error::new(io::ErrorKind::InvalidData, e)),
+    }
+}
+
+/// Split a reader into records which end with `\r\n`.
+///
+/// A bare `\n` without a `\r` before it is part of the record, e.g.
+/// a line break inside a cell of a spreadsheet exported on Windows.
+/// The last record doesn't need a line ending; if it ends with a bare
+/// `\n`, that's ignored, like the last line of a file.
+fn crlf_records(reader: impl BufRead, lossy: bool) -> impl Iterator<Item = io::Result<String>> {
+    let mut pieces = reader.split(b'\n');
+
+    std::iter::from_fn(move || {
+        let mut record: Option<Vec<u8>> = None;
+
+        loop {
+            match pieces.next() {
+                Some(Ok(piece)) => {
+                    match record.as_mut() {
+                        Some(record) => {
+                            record.push(b'\n');
+                            record.extend(piece);
+                        }
+                        None => record = Some(piece),
+                    }
+
+                    if record.as_ref().unwrap().ends_with(b"\r") {
+                        break;
+                    }
+                }
+                Some(Err(e)) => return Some(Err(e)),
+                None => break,
+            }
+        }
+
+        record.map(|mut bytes| {
+            if bytes.ends_with(b"\r") {
+                bytes.pop();
+            }
+
+            decode(bytes, lossy)
+        })
+    })
+}
+
+/// Split a reader into records of `width` characters each.
+///
+/// We count characters, not bytes, so a multi-byte character is never
+/// split across two records.  Newlines are ordinary characters, except
+/// that one newline (or `\r\n`) at the very end of the input is ignored.
+/// If the input doesn't divide evenly, the last record is shorter.
+fn fixed_width_records(
+    reader: impl BufRead,
+    width: usize,
+    lossy: bool,
+) -> impl Iterator<Item = io::Result<String>> {
+    let mut bytes = reader.bytes();
+
+    // The first byte of the next record, if we've already read it.
+    let mut pending: Option<u8> = None;
+
+    std::iter::from_fn(move || {
+        let mut record = Vec::new();
+        let mut chars = 0;
+        let mut at_eof = false;
+
+        loop {
+            let b = match pending.take().map(Ok).or_else(|| bytes.next()) {
+                Some(Ok(b)) => b,
+                Some(Err(e)) => return Some(Err(e)),
+                None => {
+                    at_eof = true;
+                    break;
+                }
+            };
+
+            // Every byte except a UTF-8 continuation byte starts
+            // a new character.
+            if b & 0xC0 != 0x80 {
+                if chars == width {
+                    pending = Some(b);
+                    break;
+                }
+                chars += 1;
+            }
+
+            record.push(b);
+        }
+
+        if at_eof && record.ends_with(b"\n") {
+            record.pop();
+            if record.ends_with(b"\r") {
+                record.pop();
+            }
+        }
+
+        if record.is_empty() {
+            return None;
+        }
+
+        Some(decode(record, lossy))
+    })
+}
+
+fn file_lines(path: &str, options: ReadOptions) -> Box<dyn Iterator<Item = String>> {
+    let reader = CountingReader {
+        inner: BufReader::new(open_reader(path)),
+        enabled: options.count_bytes,
+    };
+
+    check_lines(split_records(reader, options), path.to_string(), options)
+}
+
+/// Unwrap the lines we've read from `source`, and handle any read errors.
+fn check_lines(
+    lines: Box<dyn Iterator<Item = io::Result<String>>>,
+    source: String,
+    options: ReadOptions,
+) -> Box<dyn Iterator<Item = String>> {
+    Box::new(lines.map_while(move |line| check_line(line, &source, options)))
+}
+
+/// Unwrap a line we've read from `source`.
+///
+/// If there was a read error, we either exit, or if `partial_on_error`
+/// is set, we print a warning and return `None` so the caller stops
+/// reading.
+fn check_line(line: io::Result<String>, source: &str, options: ReadOptions) -> Option<String> {
+    match line {
+        Ok(ln) => Some(ln),
+        Err(e) if options.partial_on_error => {
+            diagnostics::warning(format!(
+                "Unable to read from {}: {}; using the lines read so far",
+                source, e
+            ));
+            STOPPED_AFTER_ERROR.store(true, Ordering::Relaxed);
+            None
+        }
+        Err(e) => read_error(source, e),
+    }
+}
+
+/// Read the lines from a file, but if it's empty, wait and re-open it
+/// until it has at least one line.
+///
+/// If the file is still empty after `retry_for`, we give up with
+/// a warning, and treat it as empty.
+fn file_lines_with_retry(
+    path: &str,
+    retry_for: Duration,
+    options: ReadOptions,
+) -> Box<dyn Iterator<Item = String>> {
+    let deadline = Instant::now() + retry_for;
+
+    loop {
+        let mut lines = file_lines(path, options);
+
+        if let Some(first_line) = lines.next() {
+            return Box::new(std::iter::once(first_line).chain(lines));
+        }
+
+        if Instant::now() >= deadline {
+            diagnostics::warning(format!(
+                "{} was still empty after {} seconds",
+                path,
+                retry_for.as_secs_f64()
+            ));
+            return Box::new(std::iter::empty());
+        }
+
+        thread::sleep(RETRY_INTERVAL);
+    }
+}
+
+/// Open a file for reading, and decompress it if it's compressed.
+///
+/// We decide if a file is compressed by looking at its extension:
+/// `.gz` is gzip, `.zst` is Zstandard, and `.bz2` is bzip2.  Anything
+/// else is read as-is.
+fn open_reader(path: &str) -> Box<dyn Read> {
+    let file = open_file(path);
+
+    if path.ends_with(".gz") {
+        Box::new(flate2::read::MultiGzDecoder::new(file))
+    } else if path.ends_with(".zst") {
+        match zstd::Decoder::new(file) {
+            Ok(decoder) => Box::new(decoder),
+            Err(e) => diagnostics::exit_with_error(format!("Unable to open {}: {}", path, e)),
+        }
+    } else if path.ends_with(".bz2") {
+        Box::new(bzip2::read::MultiBzDecoder::new(file))
+    } else {
+        Box::new(file)
+    }
+}
+
+/// Returns true if `open_reader` decompresses this file.
+pub fn is_compressed(path: &str) -> bool {
+    path.ends_with(".gz") || path.ends_with(".zst") || path.ends_with(".bz2")
+}
+
+/// Open a file for reading.
+///
+/// This also works for named pipes (FIFOs).  Opening a FIFO blocks until
+/// something opens the other end for writing, and reads block until the
+/// writer sends more data, so a slow writer just means we wait -- it's
+/// not an error.  We reach EOF when the writer closes the pipe.
+fn open_file(path: &str) -> File {
+    // Look at the file type before we open it.  We can't read lines
+    // from a directory, and on some platforms opening one "succeeds"
+    // and we'd get a confusing error on the first read.
+    //
+    // Note: `metadata()` doesn't block on a FIFO, even if there's no
+    // writer yet.
+    match std::fs::metadata(path) {
+        Ok(metadata) if metadata.is_dir() => {
+            diagnostics::exit_with_error(format!("Unable to open {}: is a directory", path))
+        }
+        Ok(_) => (),
+        Err(e) => diagnostics::exit_with_error(format!("Unable to open {}: {}", path, e)),
+    }
+
+    match File::open(path) {
+        Ok(f) => f,
+        Err(e) => diagnostics::exit_with_error(format!("Unable to open {}: {}", path, e)),
+    }
+}
+
+fn read_error(source: &str, e: io::Error) -> ! {
+    diagnostics::exit_with_error(format!("Unable to read from {}: {:?}", source, e))
+}
+
+/// Read lines from stdin, but give up if we haven't reached EOF
+/// before `timeout` has elapsed, or stop at the deadline in `options`.
+///
+/// A blocking read on stdin can't be interrupted, so the reading happens
+/// on a background thread which passes lines back over a channel.  If we
+/// hit the timeout, we exit the process, which takes the reader thread
+/// down with it.  If we hit the deadline, we stop reading, and leave the
+/// reader thread blocked until the process exits.
+fn stdin_lines_with_timeout(
+    timeout: Option<Duration>,
+    options: ReadOptions,
+) -> impl Iterator<Item = String> {
+    let timeout_at = timeout.map(|t| Instant::now() + t);
+    let (sender, receiver) = mpsc::channel();
+
+    thread::spawn(move || {
+        let stdin = CountingReader {
+            inner: io::stdin().lock(),
+            enabled: options.count_bytes,
+        };
+
+        for line in split_records(stdin, options) {
+            if sender.send(line).is_err() {
+                break;
+            }
+        }
+    });
+
+    std::iter::from_fn(move || {
+        let wait_until = match (timeout_at, options.deadline) {
+            (Some(t), Some(d)) => t.min(d),
+            (Some(t), None) => t,
+            (None, Some(d)) => d,
+            (None, None) => unreachable!(),
+        };
+
+        let remaining = wait_until.saturating_duration_since(Instant::now());
+
+        match receiver.recv_timeout(remaining) {
+            Ok(line) => check_line(line, "stdin", options),
+            Err(RecvTimeoutError::Disconnected) => None,
+            Err(RecvTimeoutError::Timeout) if Some(wait_until) == options.deadline => {
+                stop_at_deadline();
+                None
+            }
+            Err(RecvTimeoutError::Timeout) => diagnostics::exit_with_error(format!(
+                "Timed out waiting for stdin after {} seconds",
+                timeout.unwrap().as_secs_f64()
+            )),
+        }
+    })
+}
+
+#[cfg(test)]
+mod strip_trailing_cr_tests {
+    use super::*;
+
+    #[test]
+    fn it_strips_one_trailing_carriage_return() {
+        assert_eq!(strip_trailing_cr("abc\r".to_string()), "abc");
+        assert_eq!(strip_trailing_cr("abc\r\r".to_string()), "abc\r");
+        assert_eq!(strip_trailing_cr("abc".to_string()), "abc");
+        assert_eq!(strip_trailing_cr("\r".to_string()), "");
+    }
+
+    #[test]
+    fn it_leaves_mid_line_carriage_returns() {
+        assert_eq!(strip_trailing_cr("a\rb".to_string()), "a\rb");
+        assert_eq!(strip_trailing_cr("a\rb\r".to_string()), "a\rb");
+    }
+}
+
+#[cfg(test)]
+mod until_set_tests {
+    use super::*;
+
+    // Once the flag is set, we stop -- but we keep the items we read
+    // before that, including the one we read when the flag was set.
+    #[test]
+    fn it_stops_once_the_flag_is_set() {
+        static FLAG: AtomicBool = AtomicBool::new(false);
+
+        let items = (1..=10).inspect(|i| {
+            if *i == 3 {
+                FLAG.store(true, Ordering::Relaxed);
+            }
+        });
+
+        assert_eq!(until_set(items, &FLAG).collect::<Vec<_>>(), vec![1, 2, 3]);
+    }
+
+    #[test]
+    fn it_reads_everything_if_the_flag_is_never_set() {
+        static FLAG: AtomicBool = AtomicBool::new(false);
+
+        assert_eq!(until_set(1..=3, &FLAG).collect::<Vec<_>>(), vec![1, 2, 3]);
+    }
+}
+
+#[cfg(test)]
+mod fixed_width_records_tests {
+    use super::*;
+
+    fn records(input: &str, width: usize) -> Vec<String> {
+        fixed_width_records(io::Cursor::new(input.to_string()), width, false)
+            .map(|r| r.unwrap())
+            .collect()
+    }
+
+    #[test]
+    fn it_splits_the_input_into_fixed_width_records() {
+        assert_eq!(records("aaabbbccc", 3), vec!["aaa", "bbb", "ccc"]);
+        assert_eq!(records("", 3), Vec::<String>::new());
+    }
+
+    #[test]
+    fn it_keeps_a_trailing_short_record() {
+        assert_eq!(records("aaabbbcc", 3), vec!["aaa", "bbb", "cc"]);
+    }
+
+    #[test]
+    fn it_counts_characters_not_bytes() {
+        assert_eq!(records("éaébüüü", 2), vec!["éa", "éb", "üü", "ü"]);
+    }
+
+    #[test]
+    fn it_ignores_a_final_newline() {
+        assert_eq!(records("aaabbb\n", 3), vec!["aaa", "bbb"]);
+        assert_eq!(records("aaabbb\r\n", 3), vec!["aaa", "bbb"]);
+        assert_eq!(records("aaa\nbb\n", 3), vec!["aaa", "\nbb"]);
+    }
+}
+
+#[cfg(test)]
+mod crlf_records_tests {
+    use super::*;
+
+    fn records(input: &str) -> Vec<String> {
+        crlf_records(io::Cursor::new(input.to_string()), false)
+            .map(|r| r.unwrap())
+            .collect()
+    }
+
+    #[test]
+    fn it_splits_on_crlf() {
+        assert_eq!(records("a\r\nb\r\nc\r\n"), vec!["a", "b", "c"]);
+        assert_eq!(records("a\r\nb"), vec!["a", "b"]);
+        assert_eq!(records(""), Vec::<String>::new());
+    }
+
+    #[test]
+    fn it_keeps_a_bare_newline_in_the_record() {
+        assert_eq!(records("a\nb\r\nc\r\n"), vec!["a\nb", "c"]);
+        assert_eq!(records("a\n\nb\r\n"), vec!["a\n\nb"]);
+    }
+
+    #[test]
+    fn it_ignores_a_final_bare_newline() {
+        assert_eq!(records("a\r\nb\n"), vec!["a", "b"]);
+    }
+
+    #[test]
+    fn it_keeps_empty_records() {
+        assert_eq!(records("a\r\n\r\nb\r\n"), vec!["a", "", "b"]);
+    }
+}
+
+#[cfg(test)]
+mod decode_tests {
+    use super::*;
+
+    #[test]
+    fn it_decodes_valid_utf8() {
+        assert_eq!(decode(b"caf\xc3\xa9".to_vec(), false).unwrap(), "café");
+        assert_eq!(decode(b"caf\xc3\xa9".to_vec(), true).unwrap(), "café");
+    }
+
+    #[test]
+    fn it_rejects_invalid_utf8_unless_lossy() {
+        assert!(decode(b"a\xffb".to_vec(), false).is_err());
+        assert_eq!(decode(b"a\xffb".to_vec(), true).unwrap(), "a\u{FFFD}b");
+    }
+}
+
+#[cfg(test)]
+mod sniff_format_tests {
+    use super::*;
+
+    #[test]
+    fn it_detects_nul_delimited_input() {
+        assert_eq!(sniff_format(b"one\0two\0three\0"), InputFormat::Nul);
+        assert_eq!(sniff_format(b"a\nb\0c"), InputFormat::Nul);
+    }
+
+    #[test]
+    fn it_detects_json_lines() {
+        assert_eq!(
+            sniff_format(b"{\"a\": 1}\n\n[1, 2]\n{\"b\": 2}\n"),
+            InputFormat::Jsonl
+        );
+    }
+
+    // If the chunk ends partway through a line, we ignore that line.
+    #[test]
+    fn it_ignores_a_partial_last_line() {
+        assert_eq!(
+            sniff_format(b"{\"a\": 1}\n{\"b\": 2}\n{\"c\": "),
+            InputFormat::Jsonl
+        );
+    }
+
+    #[test]
+    fn it_detects_newline_delimited_input() {
+        for chunk in [
+            &b"one\ntwo\nthree\n"[..],
+            b"{\"a\": 1}\nnot json\n",
+            b"123\ntrue\n",
+            b"",
+            b"\n\n",
+        ] {
+            assert_eq!(
+                sniff_format(chunk),
+                InputFormat::Newline,
+                "chunk = {:?}",
+                String::from_utf8_lossy(chunk)
+            );
+        }
+    }
+}
@@ -0,0 +1,146 @@
+use crossterm::event::{self, Event, KeyCode, KeyEvent, KeyEventKind, KeyModifiers};
+use crossterm::{cursor, execute, terminal};
+use std::io::Write;
+
+/// Show a sample in the terminal, and let the user press a key to
+/// pick a new sample:
+///
+///   r or space = pick a new sample
+///   q or Esc   = quit
+///
+/// The caller should have read all the input already, so `pick_sample`
+/// can resample it in memory.
+pub fn run(pick_sample: impl FnMut() -> Vec<String>) -> std::io::Result<()> {
+    let mut stdout = std::io::stdout();
+
+    terminal::enable_raw_mode()?;
+    execute!(stdout, terminal::EnterAlternateScreen)?;
+
+    let result = event_loop(&mut stdout, Preview::new(pick_sample));
+
+    execute!(stdout, terminal::LeaveAlternateScreen)?;
+    terminal::disable_raw_mode()?;
+
+    result
+}
+
+fn event_loop<F: FnMut() -> Vec<String>>(
+    out: &mut impl Write,
+    mut preview: Preview<F>,
+) -> std::io::Result<()> {
+    loop {
+        execute!(
+            out,
+            terminal::Clear(terminal::ClearType::All),
+            cursor::MoveTo(0, 0)
+        )?;
+        write!(out, "{}", preview.render())?;
+        out.flush()?;
+
+        if let Event::Key(key) = event::read()? {
+            match action_for(key) {
+                Action::Resample => preview.resample(),
+                Action::Quit => return Ok(()),
+                Action::Ignore => (),
+            }
+        }
+    }
+}
+
+#[derive(Debug, PartialEq)]
+enum Action {
+    Resample,
+    Quit,
+    Ignore,
+}
+
+fn action_for(key: KeyEvent) -> Action {
+    // On Windows we get events for both pressing and releasing a key,
+    // so only react to the first.
+    if key.kind != KeyEventKind::Press {
+        return Action::Ignore;
+    }
+
+    match key.code {
+        KeyCode::Char('r') | KeyCode::Char(' ') => Action::Resample,
+        KeyCode::Char('q') | KeyCode::Esc => Action::Quit,
+        KeyCode::Char('c') if key.modifiers.contains(KeyModifiers::CONTROL) => Action::Quit,
+        _ => Action::Ignore,
+    }
+}
+
+/// The current sample, and how many samples we've picked so far.
+struct Preview<F: FnMut() -> Vec<String>> {
+    pick_sample: F,
+    sample: Vec<String>,
+    round: usize,
+}
+
+impl<F: FnMut() -> Vec<String>> Preview<F> {
+    fn new(mut pick_sample: F) -> Preview<F> {
+        let sample = pick_sample();
+
+        Preview {
+            pick_sample,
+            sample,
+            round: 1,
+        }
+    }
+
+    fn resample(&mut self) {
+        self.sample = (self.pick_sample)();
+        self.round += 1;
+    }
+
+    /// Render the sample for the terminal.  We're in raw mode, so we
+    /// need explicit carriage returns.
+    fn render(&self) -> String {
+        let mut text = self.sample.join("\r\n");
+        text.push_str(&format!(
+            "\r\n\r\n[sample #{}] r/space: resample, q: quit",
+            self.round
+        ));
+        text
+    }
+}
+
+#[cfg(test)]
+mod interactive_tests {
+    use super::*;
+    use std::cell::Cell;
+
+    // Every time we resample, we call the sampler again.
+    #[test]
+    fn it_calls_the_sampler_on_resample() {
+        let calls = Cell::new(0);
+
+        let mut preview = Preview::new(|| {
+            calls.set(calls.get() + 1);
+            vec![format!("sample {}", calls.get())]
+        });
+
+        assert_eq!(calls.get(), 1);
+        assert_eq!(preview.sample, vec!["sample 1"]);
+
+        preview.resample();
+
+        assert_eq!(calls.get(), 2);
+        assert_eq!(preview.sample, vec!["sample 2"]);
+        assert!(preview.render().contains("[sample #2]"));
+    }
+
+    #[test]
+    fn it_maps_keys_to_actions() {
+        let key = |code| KeyEvent::new(code, KeyModifiers::NONE);
+
+        assert_eq!(action_for(key(KeyCode::Char('r'))), Action::Resample);
+        assert_eq!(action_for(key(KeyCode::Char(' '))), Action::Resample);
+        assert_eq!(action_for(key(KeyCode::Char('q'))), Action::Quit);
+        assert_eq!(action_for(key(KeyCode::Esc)), Action::Quit);
+        assert_eq!(action_for(key(KeyCode::Char('x'))), Action::Ignore);
+        assert_eq!(
+            action_for(KeyEvent::new(KeyCode::Char('c'), KeyModifiers::CONTROL)),
+            Action::Quit
+        );
+    }
+}
@@ -0,0 +1,71 @@
+use std::cell::Cell;
+use std::rc::Rc;
+
+use crate::diagnostics;
+use crate::input::Record;
+
+/// Check that every record is a valid JSON value.
+///
+/// If `skip_invalid` is true, malformed records are dropped from the
+/// population, and `skipped` counts how many we dropped.  Otherwise,
+/// we exit with an error on the first malformed record.
+///
+/// Blank lines are always ignored.
+pub fn check_records(
+    records: impl Iterator<Item = Record>,
+    skip_invalid: bool,
+    skipped: Rc<Cell<usize>>,
+) -> impl Iterator<Item = Record> {
+    records.filter(move |record| {
+        if record.line.trim().is_empty() {
+            return false;
+        }
+
+        match serde_json::from_str::<serde_json::Value>(&record.line) {
+            Ok(_) => true,
+            Err(_) if skip_invalid => {
+                skipped.set(skipped.get() + 1);
+                false
+            }
+            Err(e) => diagnostics::exit_with_error(format!(
+                "Invalid JSON on line {} of {}: {}",
+                record.line_number, record.source, e
+            )),
+        }
+    })
+}
+
+#[cfg(test)]
+mod jsonl_tests {
+    use super::*;
+
+    fn records(lines: &[&str]) -> Vec<Record> {
+        let source: Rc<str> = Rc::from("-");
+
+        lines
+            .iter()
+            .enumerate()
+            .map(|(i, ln)| Record {
+                source: Rc::clone(&source),
+                line_number: i + 1,
+                line: ln.to_string(),
+                weight: None,
+            })
+            .collect()
+    }
+
+    // Malformed records are dropped and counted, and blank lines
+    // are ignored.
+    #[test]
+    fn it_skips_invalid_records() {
+        let skipped = Rc::new(Cell::new(0));
+        let input = records(&[r#"{"a": 1}"#, "{not json", "", "[1, 2]", r#""s"#]);
+
+        let valid: Vec<String> = check_records(input.into_iter(), true, Rc::clone(&skipped))
+            .map(|r| r.line)
+            .collect();
+
+        assert_eq!(valid, vec![r#"{"a": 1}"#, "[1, 2]"]);
+        assert_eq!(skipped.get(), 2);
+    }
+}
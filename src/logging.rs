@@ -0,0 +1,41 @@
+/// Set up logging with `tracing`, if randline was built with the
+/// `tracing` feature.
+///
+/// Use the `RUST_LOG` environment variable to choose what gets logged,
+/// e.g. `RUST_LOG=randline=info`.  Logs go to stderr, so they don't
+/// get mixed up with the sample.
+#[cfg(feature = "tracing")]
+pub fn init() {
+    use tracing_subscriber::fmt::format::FmtSpan;
+
+    tracing_subscriber::fmt()
+        .with_env_filter(tracing_subscriber::EnvFilter::from_default_env())
+        .with_span_events(FmtSpan::CLOSE)
+        .with_writer(std::io::stderr)
+        .init();
+}
+
+#[cfg(not(feature = "tracing"))]
+pub fn init() {}
+
+/// Enter a tracing span that lasts until the end of the current block.
+/// When the span closes, we log how long it took.
+///
+/// This compiles to nothing if the `tracing` feature is disabled.
+macro_rules! enter_span {
+    ($name:expr) => {
+        #[cfg(feature = "tracing")]
+        let _span = tracing::info_span!($name).entered();
+    };
+}
+
+/// Log an event with `tracing`.
+///
+/// This compiles to nothing if the `tracing` feature is disabled, so
+/// it can refer to variables that only exist with the feature.
+macro_rules! log_event {
+    ($($arg:tt)*) => {
+        #[cfg(feature = "tracing")]
+        tracing::info!($($arg)*);
+    };
+}
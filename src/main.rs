@@ -1,161 +1,5918 @@
 #![deny(warnings)]
 
-use std::io::BufRead;
+use icu_collator::options::CollatorOptions;
+use icu_collator::Collator;
+use rand::rngs::StdRng;
+use rand::seq::SliceRandom;
+use rand::{RngCore, SeedableRng};
+use std::borrow::Cow;
+use std::cell::{Cell, RefCell};
+use std::collections::HashSet;
+use std::io::BufWriter;
 use std::iter::Iterator;
+use std::rc::Rc;
+use std::time::Instant;
 
+use crate::input::Record;
+use crate::options::Options;
+use crate::sample_size::{parse_sample_size, SampleSize};
+
+#[macro_use]
+mod logging;
+
+mod audit;
+#[cfg(feature = "clipboard")]
+mod clipboard;
+mod compare;
+mod confidence;
+mod config;
+mod dedup;
+mod diagnostics;
+mod encoding;
+mod entropy;
+mod explain;
+mod external_shuffle;
+mod fields;
+mod input;
+#[cfg(feature = "interactive")]
+mod interactive;
+mod jsonl;
+mod options;
+mod output;
+mod progress;
+mod sample_size;
 mod sampling;
+mod scores;
+mod stable;
+mod stats;
+mod timestamps;
+mod weights;
+
+fn main() {
+    let started = Instant::now();
+
+    logging::init();
+
+    // Read the user's command line arguments (if any)
+    //
+    //   0 arguments  = get a single random line
+    //   1 argument k = get that number of lines, or that fraction of
+    //                  the input if k is a percentage (`10%`) or
+    //                  a number less than 1 (`0.1`)
+    //  >1 arguments  = error
+    //
+    // Options (like `--timeout`) can appear anywhere, and don't count
+    // towards the positional arguments.
+    //
+    // The first argument can be a subcommand, which says what to do
+    // with the input:
+    //
+    //   randline sample [k] = pick a sample of k lines
+    //   randline shuf       = print every line in a random order,
+    //                         the same as `--shuffle-only`
+    //   randline count      = count the lines, the same as `--count-only`
+    //
+    // If there's no subcommand, we use the mode from the config file,
+    // or pick a sample, so `randline [k]` still works.  All the options
+    // work with every subcommand.
+    let mut cli_args: Vec<String> = std::env::args().skip(1).collect();
+
+    let subcommand = match cli_args.first().map(|arg| arg.as_str()) {
+        Some("sample" | "shuf" | "count") => Some(cli_args.remove(0)),
+        _ => None,
+    };
+
+    // The options which come before the command-line arguments: the
+    // subcommand, and any defaults from the config file.
+    let mut base = Options::default();
+
+    match subcommand.as_deref() {
+        Some("shuf") => base.shuffle_only = true,
+        Some("count") => base.count_only = true,
+        _ => (),
+    }
+
+    let from_cli = base.clone().parse_args(&cli_args);
+
+    // We can only tell if `--no-color` is an option rather than the value
+    // of another option once we've parsed the arguments, so any errors
+    // in the arguments themselves are never colored.
+    diagnostics::init(from_cli.no_color);
+
+    let defaults = config::load();
+
+    // A default mode from the config file only applies if you haven't
+    // chosen what to do on the command line, with a subcommand or by
+    // choosing the sample size.
+    if subcommand.is_none()
+        && from_cli.k.is_none()
+        && from_cli.confidence_level.is_none()
+        && from_cli.margin.is_none()
+        && from_cli.sample_bytes.is_none()
+        && from_cli.score_field.is_none()
+        && !from_cli.k_from_input
+        && !from_cli.shuffle_only
+        && !from_cli.count_only
+    {
+        match defaults.mode.as_deref() {
+            Some("shuf") => base.shuffle_only = true,
+            Some("count") => base.count_only = true,
+            _ => (),
+        }
+    }
+
+    // A default k from the config file only applies if you haven't
+    // chosen the sample size some other way.
+    if let Some(default_k) = &defaults.k {
+        if from_cli.k.is_none()
+            && from_cli.confidence_level.is_none()
+            && from_cli.margin.is_none()
+            && from_cli.sample_bytes.is_none()
+            && from_cli.score_field.is_none()
+            && !from_cli.k_from_input
+        {
+            base.k = match parse_sample_size(default_k) {
+                Some(parsed_k) => Some(parsed_k),
+                None => diagnostics::exit_with_error(format!(
+                    "Invalid default: k has to be a sample size, e.g. 10 or 10%; got {:?}",
+                    default_k
+                )),
+            };
+        }
+    }
+
+    // The other defaults only apply if they can be used with the options
+    // on the command line -- see `Defaults::apply`.
+    let mut options = defaults.apply(base, &cli_args);
+    options
+        .check()
+        .unwrap_or_else(|e| diagnostics::exit_with_error(e));
+
+    // If we're guessing the format, we look at the start of the input
+    // before we start reading it properly, then check the options again
+    // now we know what the format is.
+    let sniffed_format = options.input_format == input::InputFormat::Auto;
+
+    if sniffed_format {
+        options.input_format = input::detect_format(&options.files);
+        options
+            .check()
+            .unwrap_or_else(|e| diagnostics::exit_with_error(e));
+    }
+
+    if options.two_pass {
+        for path in &options.files {
+            if !std::fs::metadata(path).is_ok_and(|m| m.is_file()) {
+                diagnostics::exit_with_error(format!(
+                    "--two-pass reads the input twice, so {} has to be a file",
+                    path
+                ));
+            }
+        }
+    }
+
+    let k = options
+        .sample_size()
+        .unwrap_or_else(|e| diagnostics::exit_with_error(e));
+
+    #[cfg(feature = "clipboard")]
+    let clipboard = options.clipboard;
+
+    let Options {
+        k: _,
+        timeout,
+        group_by,
+        unique,
+        approx_unique,
+        dedup_adjacent,
+        filter_size,
+        files,
+        with_source,
+        mut jsonl,
+        validate,
+        count_only,
+        weighted,
+        default_weight,
+        weight_regex,
+        weight_field,
+        columns,
+        weights_file,
+        balance_by,
+        temperature,
+        fail_fast,
+        interactive,
+        clipboard: _,
+        limit_rate,
+        line_buffered,
+        stable_by,
+        hash_algorithm,
+        output_buffer_size,
+        assume_count,
+        output_separator,
+        output_delimiter,
+        prefix,
+        suffix,
+        csv,
+        output_json_objects,
+        bash_array,
+        seed,
+        warmup,
+        entropy,
+        shuffle,
+        input_format,
+        explain,
+        confidence_level: _,
+        margin: _,
+        strip_cr,
+        sample_bytes,
+        partial_on_error,
+        lossy_utf8,
+        input_encoding,
+        output_encoding,
+        max_lines,
+        invert,
+        two_pass,
+        shuffle_only,
+        shuffle_memory,
+        score_field,
+        resample,
+        flush_marker,
+        dedup_output,
+        indices_only,
+        combine_with,
+        combine_population,
+        representativeness,
+        sort_output,
+        stable_order,
+        locale,
+        echo_args,
+        fixed_width,
+        min_count,
+        quiet_empty,
+        capacity_hint,
+        stats,
+        print_total_bytes,
+        progress_bar,
+        output_count_to,
+        audit_log,
+        reservoir_impl,
+        count_matches,
+        per_file,
+        k_from_input,
+        score_options,
+        window,
+        with_replacement,
+        strip,
+        trim_to,
+        fold_case,
+        retry_on_empty,
+        no_color: _,
+        max_runtime,
+        compare_seeds,
+        exclude_files,
+        include_files,
+        force_include_files,
+    } = options;
+
+    if input_format == input::InputFormat::Jsonl {
+        jsonl = true;
+    }
+    // If you pass `--echo-args`, print the arguments we got and what we
+    // made of them, to help debug shell quoting.  This is deliberately
+    // undocumented -- it's a debugging aid, not part of the interface.
+    if echo_args {
+        // We build this in a few pieces, because one `json!` with every
+        // option is more than the macro can expand with the default
+        // recursion limit.
+        let mut config = serde_json::Map::new();
+        for group in [
+            // What we read
+            serde_json::json!({
+                "args": std::env::args().skip(1).collect::<Vec<String>>(),
+                "k": if k_from_input { serde_json::Value::Null } else { k.to_json() },
+                "k_from_input": k_from_input,
+                "files": files,
+                "input_format": format!("{:?}", input_format),
+                "fixed_width": fixed_width,
+                "lossy_utf8": lossy_utf8,
+                "input_encoding": input_encoding.map(|e| e.name()),
+                "max_lines": max_lines,
+                "max_runtime": max_runtime.map(|runtime| runtime.as_secs_f64()),
+                "print_total_bytes": print_total_bytes,
+                "progress_bar": progress_bar,
+                "assume_count": assume_count,
+                "min_count": min_count,
+                "quiet_empty": quiet_empty,
+                "capacity_hint": capacity_hint,
+                "combine_with": combine_with,
+                "force_include_files": force_include_files,
+                "combine_population": combine_population,
+                "jsonl": jsonl,
+            }),
+            // How we pick the sample
+            serde_json::json!({
+                "reservoir_impl": format!("{:?}", reservoir_impl),
+                "stable_by": stable_by,
+                "hash_algo": hash_algorithm.map(|a| format!("{:?}", a)),
+                "seed": seed,
+                "warmup": warmup,
+                "entropy": entropy.map(|e| format!("{:?}", e)),
+                "weighted": weighted,
+                "weight_field": weight_field,
+                "balance_by": balance_by.as_ref().map(|p| p.as_str()),
+                "temperature": temperature,
+                "with_replacement": with_replacement,
+                "window": window,
+                "unique": unique,
+                "approx_unique": approx_unique,
+                "dedup_adjacent": dedup_adjacent,
+                "sample_bytes": sample_bytes,
+                "per_file": per_file,
+                "resample": resample,
+                "representativeness": representativeness,
+                "invert": invert,
+                "two_pass": two_pass,
+                "stable_order": stable_order,
+                "shuffle_only": shuffle_only,
+                "shuffle_memory": shuffle_memory,
+            }),
+            // How we print it
+            serde_json::json!({
+                "output_encoding": output_encoding.map(|e| e.name()),
+                "flush_marker": flush_marker,
+                "dedup_output": dedup_output,
+                "line_buffered": line_buffered,
+                "indices_only": indices_only,
+                "shuffle": shuffle,
+                "audit_log": audit_log,
+                "sort_output": sort_output,
+                "locale": locale.as_ref().map(|l| l.to_string()),
+                "count_only": count_only,
+                "csv": csv,
+                "output_json_objects": output_json_objects,
+                "bash_array": bash_array,
+                "output_separator": output_separator,
+                "output_delimiter": output_delimiter,
+                "prefix": prefix,
+                "suffix": suffix,
+                "trim_to": trim_to,
+                "columns": columns,
+            }),
+        ] {
+            if let serde_json::Value::Object(fields) = group {
+                config.extend(fields);
+            }
+        }
+
+        eprintln!("{}", serde_json::Value::Object(config));
+    }
+
+    // If the user presses Ctrl-C, stop reading and pick a sample from
+    // what we've got, so you can sample a live stream until you're bored.
+    input::stop_reading_on_interrupt();
+
+    let read_options = input::ReadOptions {
+        timeout,
+        retry_on_empty,
+        nul_separated: input_format == input::InputFormat::Nul,
+        crlf_separated: input_format == input::InputFormat::Crlf,
+        fixed_width,
+        strip_cr,
+        partial_on_error,
+        lossy_utf8,
+        input_encoding,
+        deadline: max_runtime.map(|runtime| started + runtime),
+        count_bytes: true,
+    };
+
+    // Files like `--exclude-file` and `--weights-file` are split into
+    // lines as usual, but they're in the same encoding as the input.
+    let other_file_options = input::ReadOptions {
+        lossy_utf8,
+        input_encoding,
+        ..input::ReadOptions::default()
+    };
+
+    // If we need to know how many records there are before we can sample,
+    // and stdin is really a file (`randline 10% < file.txt`), we count the
+    // records and rewind stdin, rather than holding every record in memory.
+    //
+    // This only works if every record goes into the population, so we
+    // skip it if we're filtering the input, or we might stop reading
+    // early because of `--max-runtime`.  We also can't do it if we've
+    // already started reading stdin to guess the format.
+    let counted_population = if (invert
+        || matches!(k, SampleSize::Fraction(_) | SampleSize::Confidence { .. }))
+        && files.iter().all(|f| f == "-")
+        && !sniffed_format
+        && !(unique || approx_unique || dedup_adjacent || validate || k_from_input)
+        && exclude_files.is_empty()
+        && include_files.is_empty()
+        && !(compare_seeds.is_some() || sample_bytes.is_some() || score_field.is_some())
+        && !(stable_by.is_some() || shuffle_only || interactive || count_only)
+        && assume_count.is_none()
+        && max_runtime.is_none()
+    {
+        input::count_stdin_records(read_options).map(|count| {
+            log_event!(
+                records = count,
+                "counted the records in stdin, and rewound it"
+            );
+            count.min(max_lines.unwrap_or(usize::MAX))
+        })
+    } else {
+        None
+    };
+
+    // Remember where the input came from for `--audit-log`, before we
+    // hand the paths to the reader.
+    let input_sources = if files.is_empty() {
+        vec!["-".to_string()]
+    } else {
+        files.clone()
+    };
+
+    let progress = if progress_bar {
+        progress::start(&files)
+    } else {
+        None
+    };
+
+    let mut records = input::read_records(files, read_options);
+
+    // If the input says how many lines to pick, take k from its first
+    // line, and sample from the rest.
+    let k = match k_from_input {
+        true => match records.next() {
+            Some(r) => match r.line.trim().parse::<usize>() {
+                Ok(n) if n > 0 => SampleSize::Count(n),
+                _ => diagnostics::exit_with_error(format!(
+                    "--k-from-input expects the first line to be a positive integer, got {:?}",
+                    r.line
+                )),
+            },
+            None if quiet_empty => return,
+            None => diagnostics::exit_with_error(
+                "--k-from-input expects the first line to be k, but the input is empty",
+            ),
+        },
+        false => k,
+    };
+
+    // If the weights are in a separate file, pair each line with its
+    // weight now, before any filtering changes the positions.
+    let records: Box<dyn Iterator<Item = Record>> = match &weights_file {
+        Some(path) => Box::new(weights::attach_weights(
+            records,
+            input::read_records(vec![path.clone()], other_file_options),
+            path.clone(),
+            fail_fast,
+        )),
+        None => Box::new(records),
+    };
+
+    // If there's a cap on how much input we read, stop after that many
+    // lines.  This counts every line we read, before any filtering, so
+    // it bounds how long we spend reading a huge or endless input.
+    let records = records.take(max_lines.unwrap_or(usize::MAX));
+
+    // If we're counting matches, check every line we read against the
+    // pattern.  This doesn't change which lines go into the sample.
+    let match_count = Rc::new(Cell::new(0));
+
+    let records: Box<dyn Iterator<Item = Record>> = match &count_matches {
+        Some(pattern) => {
+            let pattern = pattern.clone();
+            let match_count = Rc::clone(&match_count);
+
+            Box::new(records.inspect(move |r| {
+                if pattern.is_match(&r.line) {
+                    match_count.set(match_count.get() + 1);
+                }
+            }))
+        }
+        None => Box::new(records),
+    };
+
+    // Count how many lines we read, before any filtering, so we can log
+    // it and check it against `--min-count`.
+    let lines_read = Rc::new(Cell::new(0));
+
+    let records = {
+        let lines_read = Rc::clone(&lines_read);
+        records.inspect(move |_| lines_read.set(lines_read.get() + 1))
+    };
+
+    // If you pass `--min-count`, it's an error if the input is smaller
+    // than that, whatever k is.  We only know how big the input is once
+    // we've read all of it, so this runs after sampling.
+    let check_min_count = || {
+        if let Some(min_count) = min_count {
+            if lines_read.get() < min_count {
+                diagnostics::exit_with_error(format!(
+                    "The input has {} line(s), but --min-count is {}",
+                    lines_read.get(),
+                    min_count
+                ));
+            }
+        }
+    };
+
+    // If you pass `--quiet-empty`, an empty input isn't an error, and we
+    // don't print anything at all -- not even a count, a report, or an
+    // empty JSON array -- so you can tell it apart from a real sample.
+    let is_quiet_empty = || quiet_empty && lines_read.get() == 0;
+
+    // Print the counts of what we read, for `--output-count-to`,
+    // `--count-matches` and `--print-total-bytes`.  We call this once
+    // we've read all the input, whichever way we sample it.
+    let print_counts = || {
+        // If you pass `--output-count-to`, we write the number of lines we
+        // read to that file descriptor, so it doesn't get mixed up with the
+        // sample on stdout or any messages on stderr.
+        #[cfg(unix)]
+        if let Some(fd) = output_count_to {
+            if let Err(e) = output::write_count_to_fd(fd, lines_read.get()) {
+                diagnostics::exit_with_error(format!(
+                    "Unable to write the count to file descriptor {}: {}",
+                    fd, e
+                ));
+            }
+        }
+
+        if let Some(pattern) = &count_matches {
+            eprintln!("{} line(s) matched {}", match_count.get(), pattern);
+        }
+
+        if print_total_bytes {
+            eprintln!(
+                "Read {} line(s), {} byte(s)",
+                lines_read.get(),
+                input::bytes_read()
+            );
+        }
+    };
+
+    // If we're reading JSON Lines, check every record is valid JSON
+    // before it goes into the sample.
+    let skipped_records = Rc::new(Cell::new(0));
+
+    let records: Box<dyn Iterator<Item = Record>> = if jsonl {
+        Box::new(jsonl::check_records(
+            records,
+            validate,
+            Rc::clone(&skipped_records),
+        ))
+    } else {
+        Box::new(records)
+    };
+
+    // Remove any lines which appear in an exclude file, so we only sample
+    // lines that aren't in the reference set.
+    let records: Box<dyn Iterator<Item = Record>> = if exclude_files.is_empty() {
+        records
+    } else {
+        let excluded: HashSet<String> = exclude_files
+            .iter()
+            .flat_map(|path| input::read_line_set(path, other_file_options))
+            .collect();
+
+        Box::new(records.filter(move |r| !excluded.contains(&r.line)))
+    };
+
+    // Only keep lines which appear in an include file, so we only sample
+    // lines that are in the reference set.
+    let records: Box<dyn Iterator<Item = Record>> = if include_files.is_empty() {
+        records
+    } else {
+        let included: HashSet<String> = include_files
+            .iter()
+            .flat_map(|path| input::read_line_set(path, other_file_options))
+            .collect();
+
+        Box::new(records.filter(move |r| included.contains(&r.line)))
+    };
+
+    // Remove duplicate lines before we sample, so every distinct line
+    // has an equal chance of being picked.
+    let records: Box<dyn Iterator<Item = Record>> = if unique {
+        Box::new(dedup::unique(records, fold_case))
+    } else if approx_unique {
+        Box::new(dedup::approx_unique(records, filter_size, fold_case))
+    } else if dedup_adjacent {
+        Box::new(dedup::adjacent_unique(records, fold_case))
+    } else {
+        Box::new(records)
+    };
+
+    // If you pass `--balance-by`, each line's weight depends on how many
+    // other lines have the same key, so we have to read everything and
+    // count the keys before we can weigh any of them.
+    let records: Box<dyn Iterator<Item = Record>> = match &balance_by {
+        Some(pattern) => {
+            let mut records: Vec<Record> = {
+                enter_span!("read");
+                records.collect()
+            };
+
+            let weights =
+                weights::balance_weights(records.iter().map(|r| r.line.as_str()), pattern);
+
+            for (record, weight) in records.iter_mut().zip(weights) {
+                record.weight = Some(weight);
+            }
+
+            Box::new(records.into_iter())
+        }
+        None => records,
+    };
+
+    // If we only want the line count, we can skip sampling entirely.
+    // This counts the lines after any filtering (e.g. `--unique`), so
+    // it's the size of the population we'd be sampling from.
+    if count_only {
+        let count = records.count();
+
+        if is_quiet_empty() {
+            return;
+        }
+
+        check_min_count();
+        progress::clear();
+        println!("{}", count);
+        print_counts();
+
+        return;
+    }
+
+    // In weighted mode, each line's weight is its first tab-separated
+    // field (or the field picked by `--weight-field`), the first capture
+    // group of `--weight-regex`, or the matching line of `--weights-file`.
+    // Lines without a valid weight get the default weight, if there is
+    // one, or it's an error.
+    //
+    // With `--fail-fast`, the default weight is only for lines which
+    // don't have a weight at all; a malformed weight is always an error.
+    let parse_weight = |line: &str| match &weight_regex {
+        Some(pattern) => weights::parse_weight_with_regex(line, pattern),
+        None => weights::parse_weight(line, weight_field.unwrap_or(1)),
+    };
+
+    let weight_of = |record: &Record| {
+        let weight = if weights_file.is_some() || balance_by.is_some() {
+            record.weight
+        } else {
+            parse_weight(&record.line)
+        };
+
+        if weight.is_none() && fail_fast && weights_file.is_none() {
+            if let Some(text) = weights::weight_text(
+                &record.line,
+                weight_regex.as_ref(),
+                weight_field.unwrap_or(1),
+            ) {
+                diagnostics::exit_with_error(format!(
+                    "Invalid weight {:?} on line {} of {}",
+                    text, record.line_number, record.source
+                ));
+            }
+        }
+
+        match weight {
+            Some(w) => w,
+            None => match default_weight {
+                Some(w) => w,
+                None => diagnostics::exit_with_error(format!(
+                    "Missing or invalid weight on line {} of {}",
+                    record.line_number, record.source
+                )),
+            },
+        }
+    };
+
+    // Pair each record with its weight, and keep the weight on the
+    // record, so we can print it with `--output-json-objects`.
+    //
+    // We keep track of the biggest weight as we go, so we can check
+    // there was something to pick once we've sampled.
+    let weighted_lines = Cell::new(0);
+    let max_weight = Cell::new(0.0_f64);
+
+    let weigh = |mut record: Record| {
+        let weight = weight_of(&record);
+        record.weight = Some(weight);
+        weighted_lines.set(weighted_lines.get() + 1);
+        max_weight.set(max_weight.get().max(weight));
+        (weight, record)
+    };
+
+    // If every line has weight 0, the sampling algorithms have nothing
+    // to choose between, so any sample would be meaningless.
+    //
+    // This doesn't apply with `--temperature`, because the softmax turns
+    // any weights into positive ones.
+    let check_total_weight = || {
+        if weighted_lines.get() == 0 || temperature.is_some() {
+            return;
+        }
+
+        if max_weight.get() == 0.0 {
+            diagnostics::exit_with_error(format!(
+                "Every line has weight 0, so there's nothing to pick from {} line(s)",
+                weighted_lines.get()
+            ));
+        }
+    };
+
+    // In windowed mode, each line starts with a Unix timestamp.
+    let timestamp_of = |record: &Record| match timestamps::parse_timestamp(&record.line) {
+        Some(t) => t,
+        None => diagnostics::exit_with_error(format!(
+            "Missing or invalid timestamp on line {} of {}",
+            record.line_number, record.source
+        )),
+    };
+
+    let sample_records =
+        |records: Box<dyn Iterator<Item = Record>>, k: usize, mut rng: &mut dyn RngCore| {
+            let capacity = capacity_hint.unwrap_or(k);
+
+            if with_replacement {
+                // Sampling with replacement means we need every line in
+                // memory, so we can pick it again.  If we're not weighted,
+                // every line gets the same weight.
+                let items: Vec<(f64, Record)> = records
+                    .map(|r| if weighted { weigh(r) } else { (1.0, r) })
+                    .collect();
+
+                let items = match temperature {
+                    Some(t) => weights::softmax(items, t),
+                    None => items,
+                };
+
+                sampling::weighted_sample_with_replacement(&items, k, &mut rng)
+            } else if let Some(window) = window {
+                sampling::windowed_reservoir_sample(
+                    records.map(|r| (timestamp_of(&r), r)),
+                    k,
+                    window,
+                    &mut rng,
+                )
+            } else if let Some(t) = temperature {
+                // The softmax depends on every weight, so we need all the
+                // lines in memory before we can scale any of them.
+                let items: Vec<(f64, Record)> = records.map(weigh).collect();
+                let items = weights::softmax(items, t).into_iter();
+
+                if stable_order {
+                    sampling::weighted_reservoir_sample_expj_in_key_order(items, k, k, &mut rng)
+                } else {
+                    sampling::weighted_reservoir_sample(items, k, &mut rng)
+                }
+            } else if weighted && stable_order {
+                sampling::weighted_reservoir_sample_expj_in_key_order(
+                    records.map(weigh),
+                    k,
+                    capacity,
+                    &mut rng,
+                )
+            } else if weighted {
+                sampling::weighted_reservoir_sample_expj_with_capacity(
+                    records.map(weigh),
+                    k,
+                    capacity,
+                    &mut rng,
+                )
+            } else if stable_order {
+                sampling::reservoir_sample_in_key_order(records, k, capacity, &mut rng)
+            } else {
+                reservoir_impl.sample_with_capacity(records, k, capacity, &mut rng)
+            }
+        };
+
+    // Note: we only strip whitespace when we print the line, so it
+    // doesn't affect which lines get picked.
+    //
+    // The prefix and suffix go around the whole line, including the
+    // source, but not around separators or the markers between groups.
+    //
+    // `--columns` counts the fields of the line as it was read, so it
+    // can pick or drop the weight field like any other.
+    let format_record = |record: &Record| {
+        let line = match &columns {
+            Some(columns) => Cow::Owned(output::select_columns(&record.line, columns)),
+            None => Cow::Borrowed(record.line.as_str()),
+        };
+
+        let line = if strip { line.trim() } else { &line };
+
+        let line = match trim_to {
+            Some(n) => output::trim_to(line, n),
+            None => Cow::Borrowed(line),
+        };
+
+        if with_source {
+            format!("{}{}\t{}{}", prefix, record.source, line, suffix)
+        } else {
+            format!("{}{}{}", prefix, line, suffix)
+        }
+    };
+
+    // Everything we print on stdout goes through the output encoding,
+    // if there is one.
+    let open_stdout = || {
+        BufWriter::with_capacity(
+            output_buffer_size,
+            encoding::EncodingWriter::new(std::io::stdout().lock(), output_encoding),
+        )
+    };
+
+    // We use separate random number generators for picking the sample
+    // and shuffling it, so each is reproducible on its own -- e.g. adding
+    // `--shuffle` doesn't change which lines are picked with a given seed.
+    //
+    // Both are derived from a single seed, and always in the same order,
+    // whether or not we're going to use them.  If the user doesn't pick
+    // a seed, we pick one at random, so we can print it with `--explain`.
+    // You can choose where that randomness comes from with `--entropy`.
+    //
+    // `--stable-by` doesn't use either generator -- it hashes the seed
+    // directly, and the seed defaults to 0, so you get the same selection
+    // every time unless you ask for a different one.
+    let stable_seed = seed.unwrap_or(0);
+    let seed = seed.unwrap_or_else(|| {
+        entropy
+            .unwrap_or_default()
+            .seed()
+            .unwrap_or_else(|e| diagnostics::exit_with_error(e))
+    });
+    let mut seed_rng = StdRng::seed_from_u64(seed);
+    let mut sample_rng = StdRng::from_rng(&mut seed_rng);
+    let mut shuffle_rng = StdRng::from_rng(&mut seed_rng);
+
+    // If you pass `--warmup`, we throw away that many random numbers
+    // before we start sampling, so you can see how much the sample
+    // depends on the state of the generator.
+    for _ in 0..warmup {
+        sample_rng.next_u64();
+    }
+
+    // In interactive mode, we read all the input into memory, so we can
+    // keep picking new samples until the user is happy.
+    #[cfg(feature = "interactive")]
+    if interactive {
+        let records: Vec<Record> = records.collect();
+        check_min_count();
+
+        let k = k.resolve(records.len());
+
+        let pick_sample = || {
+            let mut sample =
+                sample_records(Box::new(records.clone().into_iter()), k, &mut sample_rng);
+
+            if shuffle {
+                sample.shuffle(&mut shuffle_rng);
+            }
+
+            sample.iter().map(format_record).collect()
+        };
+
+        if let Err(e) = interactive::run(pick_sample) {
+            diagnostics::exit_with_error(format!("Unable to run interactive mode: {}", e));
+        }
+
+        return;
+    }
+
+    // Count how many lines we're sampling from, after any filtering, so
+    // we can print it with `--explain`.
+    let population = Rc::new(Cell::new(0));
+
+    let records: Box<dyn Iterator<Item = Record>> = {
+        let population = Rc::clone(&population);
+        Box::new(records.inspect(move |_| population.set(population.get() + 1)))
+    };
+
+    // If you pass `--representativeness`, we keep a running mean and
+    // variance of that field across the population, so we can compare
+    // the sample to it.  Lines where the field isn't a number are left
+    // out of both.
+    let population_stats = Rc::new(RefCell::new(stats::RunningStats::default()));
+
+    let records: Box<dyn Iterator<Item = Record>> = match representativeness {
+        Some(field) => {
+            let population_stats = Rc::clone(&population_stats);
+
+            Box::new(records.inspect(move |r| {
+                if let Some(x) = scores::parse_score(&r.line, field) {
+                    population_stats.borrow_mut().push(x);
+                }
+            }))
+        }
+        None => records,
+    };
+
+    // With `--sort-output`, samples are sorted in byte order unless you
+    // pass a locale, because collation is much slower.
+    let collator = locale.as_ref().map(|locale| {
+        Collator::try_new(locale.into(), CollatorOptions::default()).unwrap_or_else(|e| {
+            diagnostics::exit_with_error(format!(
+                "Unable to sort lines for locale {}: {}",
+                locale, e
+            ))
+        })
+    });
+
+    let sort_sample = |sample: &mut [Record]| match &collator {
+        Some(collator) => sample.sort_by(|a, b| collator.compare(&a.line, &b.line)),
+        None => sample.sort_by(|a, b| a.line.cmp(&b.line)),
+    };
+
+    // If the input was NUL-delimited, the lines might contain newlines,
+    // so we use NUL bytes to separate the output as well.  Likewise, if
+    // the input was split on `\r\n`, the output uses `\r\n`, so it
+    // round-trips.  You can pick a different delimiter with
+    // `--output-delimiter`.
+    let (separator, terminator) = match (output_separator.as_deref(), output_delimiter) {
+        (Some(separator), _) => (separator, "\n"),
+        (None, Some(delimiter)) => (delimiter, delimiter),
+        (None, None) if input_format == input::InputFormat::Nul => ("\0", "\0"),
+        (None, None) if input_format == input::InputFormat::Crlf => ("\r\n", "\r\n"),
+        (None, None) => ("\n", "\n"),
+    };
+
+    // If we're resampling, we fill n reservoirs in a single pass, and
+    // print each sample with a `---` line in between.
+    if let Some(n) = resample {
+        // Note: we checked that k is a count when we parsed the args.
+        let SampleSize::Count(k) = k else {
+            unreachable!()
+        };
+
+        let mut samples = {
+            enter_span!("sample");
+            sampling::multi_reservoir_sample(records, k, n, &mut sample_rng)
+        };
+
+        for sample in samples.iter_mut() {
+            if shuffle {
+                sample.shuffle(&mut shuffle_rng);
+            } else if sort_output {
+                sort_sample(sample);
+            }
+        }
+
+        progress::clear();
+
+        if is_quiet_empty() {
+            return;
+        }
+
+        check_min_count();
+        print_counts();
+
+        let lines: Vec<String> = samples
+            .iter()
+            .enumerate()
+            .flat_map(|(i, sample)| {
+                let marker = if i > 0 { Some("---".to_string()) } else { None };
+                marker.into_iter().chain(sample.iter().map(format_record))
+            })
+            .collect();
+
+        enter_span!("write");
+        let mut stdout = open_stdout();
+
+        let result = if line_buffered {
+            output::write_lines_one_at_a_time(&mut stdout, &lines, separator, terminator, None)
+        } else {
+            output::write_lines(&mut stdout, &lines, separator, terminator)
+        };
+
+        if let Err(e) = result {
+            diagnostics::exit_with_error(format!("Unable to write to stdout: {}", e));
+        }
+
+        return;
+    }
+
+    // If there's a flush marker, we treat the input as a series of
+    // batches, each ending with the marker.  We sample k lines from each
+    // batch, and print them as soon as we see the marker, followed by
+    // the marker itself, so this works on an endless stream.
+    if let Some(marker) = &flush_marker {
+        // Note: we checked that k is a count when we parsed the args.
+        let SampleSize::Count(k) = k else {
+            unreachable!()
+        };
+
+        let mut records = records.peekable();
+        let mut stdout = open_stdout();
+
+        while records.peek().is_some() {
+            let mut saw_marker = false;
+
+            // Each batch is sampled separately, so it needs a line with
+            // a non-zero weight of its own.
+            weighted_lines.set(0);
+            max_weight.set(0.0);
+
+            let batch: Vec<Record> = std::iter::from_fn(|| match records.next() {
+                Some(r) if r.line == *marker => {
+                    saw_marker = true;
+                    None
+                }
+                r => r,
+            })
+            .collect();
+
+            let mut sample = sample_records(Box::new(batch.into_iter()), k, &mut sample_rng);
+            check_total_weight();
+
+            if shuffle {
+                sample.shuffle(&mut shuffle_rng);
+            }
+
+            let lines: Vec<String> = sample
+                .iter()
+                .map(format_record)
+                .chain(saw_marker.then(|| marker.clone()))
+                .collect();
+
+            let result = if line_buffered {
+                output::write_lines_one_at_a_time(&mut stdout, &lines, separator, terminator, None)
+            } else {
+                output::write_lines(&mut stdout, &lines, separator, terminator)
+            };
+
+            if let Err(e) = result {
+                diagnostics::exit_with_error(format!("Unable to write to stdout: {}", e));
+            }
+        }
+
+        check_min_count();
+        print_counts();
+        return;
+    }
+
+    // If you pass `--shuffle-memory`, we shuffle the input without
+    // holding it all in memory, by spilling it to temporary files if it's
+    // too big, and we print each line as soon as we know where it goes.
+    if let Some(memory_budget) = shuffle_memory {
+        let mut stream = output::LineStream::new(
+            open_stdout(),
+            separator,
+            terminator,
+            limit_rate.map(output::RateLimiter::new),
+            line_buffered,
+        );
+
+        // We don't print anything until we've read all the input, so we
+        // can check its size and print the counts before the first line.
+        let mut started = false;
+
+        let result = {
+            enter_span!("sample");
+            external_shuffle::shuffle(records, memory_budget, &mut shuffle_rng, &mut |record| {
+                if !started {
+                    started = true;
+                    progress::clear();
+                    check_min_count();
+                    print_counts();
+                }
+
+                stream.push(format_record(&record))
+            })
+        };
+
+        let result = result.and_then(|()| stream.finish());
+
+        if let Err(e) = result {
+            diagnostics::exit_with_error(format!("Unable to shuffle the input: {}", e));
+        }
+
+        progress::clear();
+
+        if !started && !is_quiet_empty() {
+            check_min_count();
+            print_counts();
+        }
+
+        return;
+    }
+
+    // If you pass `--two-pass`, we invert the sample without holding the
+    // input in memory.  On the first pass, we pick the positions of the
+    // sample; on the second, we re-open the files and print every line
+    // that isn't at one of those positions, as soon as we read it.
+    //
+    // This picks the same lines as `--invert` without `--two-pass`, but
+    // the files mustn't change between the two passes.
+    if two_pass {
+        let picked: HashSet<usize> = {
+            enter_span!("sample");
+            let picked = match k {
+                SampleSize::Count(k) => sampling::reservoir_sample(
+                    records.enumerate().map(|(i, _)| i),
+                    k,
+                    &mut sample_rng,
+                ),
+                _ => {
+                    let population = records.count();
+                    let k = k.resolve(population);
+                    sampling::reservoir_sample(0..population, k, &mut sample_rng)
+                }
+            };
+            picked.into_iter().collect()
+        };
+
+        if is_quiet_empty() {
+            return;
+        }
+
+        check_min_count();
+        print_counts();
+
+        enter_span!("write");
+        let mut stream = output::LineStream::new(
+            open_stdout(),
+            separator,
+            terminator,
+            limit_rate.map(output::RateLimiter::new),
+            line_buffered,
+        );
+
+        // We've already counted the bytes in the first pass.
+        let read_options = input::ReadOptions {
+            count_bytes: false,
+            ..read_options
+        };
+
+        let second_pass = input::read_records(input_sources, read_options)
+            .take(max_lines.unwrap_or(usize::MAX))
+            .enumerate()
+            .filter(|(i, _)| !picked.contains(i));
+
+        let result = second_pass
+            .map(|(_, r)| format_record(&r))
+            .try_for_each(|line| stream.push(line))
+            .and_then(|()| stream.finish());
+
+        if let Err(e) = result {
+            diagnostics::exit_with_error(format!("Unable to write to stdout: {}", e));
+        }
+
+        return;
+    }
+
+    // Note: we usually read the input as we sample it, so the "sample"
+    // span includes the time spent reading.
+    let sample = match k {
+        // In compare-seeds mode, we read all the input into memory, and
+        // take a sample with each of the seeds s, s+1, ..., s+n-1, where
+        // s is the seed we'd use anyway, so we can see how much they
+        // overlap.  Then we print the first sample as usual -- it's the
+        // same sample you'd get from that seed without `--compare-seeds`.
+        _ if compare_seeds.is_some() => {
+            let n = compare_seeds.unwrap();
+            let records: Vec<Record> = records.collect();
+            let k = k.resolve(records.len());
+
+            if is_quiet_empty() {
+                return;
+            }
+
+            let mut samples: Vec<Vec<Record>> = vec![sample_records(
+                Box::new(records.clone().into_iter()),
+                k,
+                &mut sample_rng,
+            )];
+
+            for i in 1..n {
+                let mut seed_rng = StdRng::seed_from_u64(seed.wrapping_add(i));
+                let mut rng = StdRng::from_rng(&mut seed_rng);
+                for _ in 0..warmup {
+                    rng.next_u64();
+                }
+
+                samples.push(sample_records(
+                    Box::new(records.clone().into_iter()),
+                    k,
+                    &mut rng,
+                ));
+            }
+
+            // We compare lines by their text, so a line that's repeated
+            // in the input is one line, wherever it was picked from.
+            let keys: Vec<Vec<&str>> = samples
+                .iter()
+                .map(|sample| sample.iter().map(|r| r.line.as_str()).collect())
+                .collect();
+
+            let overlap = compare::overlap(&keys);
+
+            progress::clear();
+            eprintln!(
+                "Compared {} samples of {} line(s) from {} line(s): \
+                 {} line(s) in every sample, {} line(s) in at least one sample",
+                n,
+                k,
+                records.len(),
+                overlap.in_every_sample,
+                overlap.in_any_sample
+            );
+
+            samples.swap_remove(0)
+        }
+
+        // If we're filling a byte budget, we read all the input into
+        // memory, shuffle it, and keep lines until the budget runs out.
+        _ if sample_bytes.is_some() => {
+            let records: Vec<Record> = {
+                enter_span!("read");
+                records.collect()
+            };
+
+            // We count each line as we'll print it, e.g. with the source
+            // from `--with-source`, and the separator that comes after it.
+            enter_span!("sample");
+            sampling::byte_budget_sample(
+                records,
+                sample_bytes.unwrap(),
+                |r| format_record(r).len(),
+                (separator.len(), terminator.len()),
+                &mut sample_rng,
+            )
+        }
+
+        // If we're keeping lines based on a score, we decide whether to
+        // keep each line independently, so we don't need to buffer.
+        _ if score_field.is_some() => {
+            let field = score_field.unwrap();
+
+            let score_of = |record: &Record| match scores::parse_score(&record.line, field) {
+                Some(score) => score,
+                None => diagnostics::exit_with_error(format!(
+                    "Missing or invalid score on line {} of {}",
+                    record.line_number, record.source
+                )),
+            };
+
+            enter_span!("sample");
+            sampling::bernoulli_sample(
+                records.map(|r| (scores::keep_probability(score_of(&r), score_options), r)),
+                &mut sample_rng,
+            )
+        }
+
+        // If we're picking by ID, each line is kept if its ID is, so all
+        // the lines with the same ID get the same decision.  This doesn't
+        // use the random number generator -- it's all in the hash.
+        SampleSize::Fraction(fraction) if stable_by.is_some() => {
+            let field = stable_by.unwrap();
+            let algorithm = hash_algorithm.unwrap_or(stable::HashAlgorithm::Fnv);
+
+            enter_span!("sample");
+            records
+                .filter(|r| match fields::nth_field(&r.line, field) {
+                    Some(id) => stable::is_selected(id, stable_seed, fraction, algorithm),
+                    None => diagnostics::exit_with_error(format!(
+                        "Missing field {} on line {} of {}",
+                        field, r.line_number, r.source
+                    )),
+                })
+                .collect()
+        }
+
+        // If we're sampling each file separately, we keep a reservoir
+        // for one file at a time, and start a new one when the source
+        // changes.  The samples are printed in the order of the files.
+        SampleSize::Count(k) if per_file => {
+            enter_span!("sample");
+            let mut records = records.peekable();
+            let mut sample: Vec<Record> = Vec::new();
+
+            while let Some(first) = records.peek() {
+                let source = Rc::clone(&first.source);
+                let this_file =
+                    std::iter::from_fn(|| records.next_if(|r| Rc::ptr_eq(&r.source, &source)));
+
+                if weighted {
+                    sample.extend(sampling::weighted_reservoir_sample_expj_with_capacity(
+                        this_file.map(weigh),
+                        k,
+                        capacity_hint.unwrap_or(k),
+                        &mut sample_rng,
+                    ));
+                } else {
+                    sample.extend(reservoir_impl.sample_with_capacity(
+                        this_file,
+                        k,
+                        capacity_hint.unwrap_or(k),
+                        &mut sample_rng,
+                    ));
+                }
+            }
+
+            sample
+        }
+
+        // If we're combining with an earlier sample, we sample the new
+        // input as usual, then merge the two samples, weighting each one
+        // by the size of the population it came from.  This assumes the
+        // earlier sample is a uniform sample of at least k lines (or all
+        // of them) from a population that doesn't overlap this one.
+        SampleSize::Count(k) if combine_with.is_some() => {
+            let path = combine_with.clone().unwrap();
+            let prior_population = combine_population.unwrap();
+
+            let prior: Vec<Record> = input::read_records(
+                vec![path.clone()],
+                input::ReadOptions {
+                    nul_separated: read_options.nul_separated,
+                    crlf_separated: read_options.crlf_separated,
+                    fixed_width,
+                    strip_cr,
+                    lossy_utf8,
+                    input_encoding,
+                    ..input::ReadOptions::default()
+                },
+            )
+            .collect();
+
+            if prior.len() > prior_population {
+                diagnostics::exit_with_error(format!(
+                    "{} has {} line(s), which is more than --combine-population ({})",
+                    path,
+                    prior.len(),
+                    prior_population
+                ));
+            }
+
+            if prior.len() < k.min(prior_population) {
+                diagnostics::exit_with_error(format!(
+                    "{} has {} line(s), but it needs at least {} to combine with a sample of {}",
+                    path,
+                    prior.len(),
+                    k.min(prior_population),
+                    k
+                ));
+            }
+
+            enter_span!("sample");
+            let sample = reservoir_impl.sample_with_capacity(
+                records,
+                k,
+                capacity_hint.unwrap_or(k),
+                &mut sample_rng,
+            );
+
+            sampling::combine_samples(
+                prior,
+                prior_population,
+                sample,
+                population.get(),
+                k,
+                &mut sample_rng,
+            )
+        }
+
+        // If we're only shuffling, we print every line in a random order,
+        // like `shuf`, and ignore k.  This uses the shuffle RNG, so it's
+        // reproducible with `--seed`.
+        _ if shuffle_only => {
+            let mut records: Vec<Record> = {
+                enter_span!("read");
+                records.collect()
+            };
+
+            enter_span!("sample");
+            records.shuffle(&mut shuffle_rng);
+            records
+        }
+
+        // If we're inverting the sample and we already know how many
+        // records there are, we pick which positions to keep first, then
+        // keep every other record as we read it, so we don't need to
+        // buffer the input.  This gives the same result as picking from
+        // a buffer.
+        _ if invert && counted_population.is_some() => {
+            let population = counted_population.unwrap();
+
+            enter_span!("sample");
+            let k = k.resolve(population);
+            let picked: HashSet<usize> =
+                sampling::reservoir_sample(0..population, k, &mut sample_rng)
+                    .into_iter()
+                    .collect();
+
+            records
+                .enumerate()
+                .filter(|(i, _)| !picked.contains(i))
+                .map(|(_, r)| r)
+                .collect()
+        }
+
+        // Otherwise, we print every line that *wasn't* picked, so we need
+        // every line in memory until we know which ones those are.
+        _ if invert => {
+            let records: Vec<Record> = {
+                enter_span!("read");
+                records.collect()
+            };
+
+            enter_span!("sample");
+            let k = k.resolve(records.len());
+            let (_, rejected) =
+                sampling::reservoir_sample_with_rejects(records.into_iter(), k, &mut sample_rng);
+            rejected
+        }
+
+        // If there are lines we have to include, we take them out of the
+        // input so they can't be picked twice, then fill the rest of the
+        // sample at random.  The forced lines come first, in the order of
+        // the files, and they're included even if they aren't in the input.
+        SampleSize::Count(k) if !force_include_files.is_empty() => {
+            let mut seen: HashSet<String> = HashSet::new();
+            let forced: Vec<Record> = input::read_records(
+                force_include_files.clone(),
+                input::ReadOptions {
+                    nul_separated: read_options.nul_separated,
+                    crlf_separated: read_options.crlf_separated,
+                    fixed_width,
+                    strip_cr,
+                    lossy_utf8,
+                    input_encoding,
+                    ..input::ReadOptions::default()
+                },
+            )
+            .filter(|r| seen.insert(r.line.clone()))
+            .collect();
+
+            if forced.len() > k {
+                diagnostics::exit_with_error(format!(
+                    "--force-include has {} distinct line(s), which is more than the sample size ({})",
+                    forced.len(),
+                    k
+                ));
+            }
+
+            enter_span!("sample");
+            let rest = Box::new(records.filter(move |r| !seen.contains(&r.line)));
+            let mut sample = forced;
+            let k = k - sample.len();
+            sample.extend(sample_records(rest, k, &mut sample_rng));
+            sample
+        }
+
+        SampleSize::Count(k) => {
+            enter_span!("sample");
+            sample_records(records, k, &mut sample_rng)
+        }
+
+        // If we're picking a fraction of the input, or enough lines for
+        // a given confidence level, we need to know how big the input is
+        // before we know how many lines to pick.  If we've already counted
+        // the input, or you've told us how big it is, we can sample as we
+        // read it ...
+        SampleSize::Fraction(_) | SampleSize::Confidence { .. }
+            if assume_count.or(counted_population).is_some() =>
+        {
+            enter_span!("sample");
+            let k = k.resolve(assume_count.or(counted_population).unwrap());
+            sample_records(records, k, &mut sample_rng)
+        }
+
+        // ... otherwise, we have to read everything into memory first.
+        SampleSize::Fraction(_) | SampleSize::Confidence { .. } => {
+            let records: Vec<Record> = {
+                enter_span!("read");
+                records.collect()
+            };
+
+            enter_span!("sample");
+            let k = k.resolve(records.len());
+            sample_records(Box::new(records.into_iter()), k, &mut sample_rng)
+        }
+    };
+
+    // We've finished reading the input, so clear the progress bar before
+    // we print anything else.
+    drop(progress);
+
+    if is_quiet_empty() {
+        return;
+    }
+
+    check_total_weight();
+    check_min_count();
+
+    if explain {
+        let algorithm = if with_replacement {
+            explain::Algorithm::WithReplacement { weighted }
+        } else if window.is_some() {
+            explain::Algorithm::Windowed
+        } else if weighted {
+            explain::Algorithm::WeightedReservoir
+        } else {
+            explain::Algorithm::Reservoir(reservoir_impl)
+        };
+
+        eprintln!(
+            "{}",
+            explain::explanation(algorithm, sample.len(), population.get(), seed)
+        );
+    }
+
+    // Compare the mean of the field in the sample to the mean across the
+    // population, so you can tell if this draw happened to be skewed.
+    // If the sample is random, the z-score is within +/-1.96 about 95%
+    // of the time.
+    if let Some(field) = representativeness {
+        let population_stats = population_stats.borrow();
+        let mut sample_stats = stats::RunningStats::default();
+
+        for x in sample
+            .iter()
+            .filter_map(|r| scores::parse_score(&r.line, field))
+        {
+            sample_stats.push(x);
+        }
+
+        let z_score = stats::z_score(&population_stats, &sample_stats, with_replacement);
+
+        eprintln!(
+            "Sample mean of field {} is {:.3} ({} line(s)); population mean is {:.3} ({} line(s)); {}",
+            field,
+            sample_stats.mean(),
+            sample_stats.count(),
+            population_stats.mean(),
+            population_stats.count(),
+            match z_score {
+                Some(z) if z.abs() <= 1.96 => {
+                    format!("z-score is {:.2}, within the 95% range", z)
+                }
+                Some(z) => format!("z-score is {:.2}, outside the 95% range", z),
+                None => "z-score is undefined".to_string(),
+            }
+        );
+    }
+
+    // If you pass `--dedup-output`, we remove repeated lines from the
+    // sample, keeping the first of each.  Unlike `--unique`, this doesn't
+    // change which lines we pick from, so the sample can be smaller
+    // than k -- e.g. if we picked the same line twice with replacement.
+    let sample: Vec<Record> = if dedup_output {
+        dedup::unique(sample.into_iter(), fold_case).collect()
+    } else {
+        sample
+    };
+
+    // If we're shuffling, put the sample in a random order.  This only
+    // changes the order we print the sample, not which lines are selected.
+    let sample = if shuffle {
+        let mut sample = sample;
+        sample.shuffle(&mut shuffle_rng);
+        sample
+    } else {
+        sample
+    };
+
+    // If we're sorting the output, put the sample in lexicographic order,
+    // so it's easy to diff the output of different runs.
+    let sample = if sort_output {
+        let mut sample = sample;
+        sort_sample(&mut sample);
+        sample
+    } else {
+        sample
+    };
+
+    log_event!(
+        lines_read = lines_read.get(),
+        sample_size = sample.len(),
+        "finished sampling"
+    );
+
+    if stats {
+        let report = stats::report(
+            lines_read.get(),
+            sample.iter().map(|r| r.line.as_str()),
+            started.elapsed(),
+        );
+        eprintln!("{}", report);
+    }
+
+    print_counts();
+
+    if skipped_records.get() > 0 {
+        diagnostics::warning(format!(
+            "Skipped {} malformed JSON record(s)",
+            skipped_records.get()
+        ));
+    }
+
+    // If you pass `--audit-log`, we append a record of this run to the
+    // log before we print the sample, so there's no sample without
+    // a record of how it was picked.
+    if let Some(path) = &audit_log {
+        let entry = serde_json::json!({
+            "timestamp": audit::timestamp(std::time::SystemTime::now()),
+            "seed": seed,
+            "k": k.to_json(),
+            "sources": input_sources,
+            "lines_read": lines_read.get(),
+            "output_count": sample.len(),
+        });
+
+        if let Err(e) = audit::append(path, &entry) {
+            diagnostics::exit_with_error(format!(
+                "Unable to write to the audit log {}: {}",
+                path, e
+            ));
+        }
+    }
+
+    // If you pass `--output-json-objects`, we print the sample as a JSON
+    // array, with the source, line number and weight of every line.
+    if output_json_objects {
+        enter_span!("write");
+        let mut stdout = open_stdout();
+
+        if let Err(e) = output::write_json_objects(&mut stdout, &sample) {
+            diagnostics::exit_with_error(format!("Unable to write to stdout: {}", e));
+        }
+
+        return;
+    }
+
+    let lines: Vec<String> = match group_by {
+        // If you pass `--indices-only`, we print the line number of each
+        // line in the sample rather than the line itself, in input order,
+        // so you can use them to slice the input later.
+        _ if indices_only => {
+            let mut indices: Vec<usize> = sample.iter().map(|r| r.line_number).collect();
+            indices.sort_unstable();
+            indices.iter().map(|i| i.to_string()).collect()
+        }
+
+        None => sample.iter().map(format_record).collect(),
+
+        // If we're grouping the output, put a blank line between each
+        // group.  This only changes the order we print the sample,
+        // not which lines are selected.
+        Some(pattern) => output::group_by_key(sample, &pattern)
+            .iter()
+            .enumerate()
+            .flat_map(|(i, group)| {
+                let blank_line = if i > 0 { Some(String::new()) } else { None };
+                blank_line
+                    .into_iter()
+                    .chain(group.iter().map(format_record))
+            })
+            .collect(),
+    };
+
+    enter_span!("write");
+
+    // If you pass `--bash-array`, we print the sample as a Bash array
+    // assignment, with every line quoted, so you can `eval` it.
+    if let Some(name) = &bash_array {
+        let mut stdout = open_stdout();
+
+        if let Err(e) = output::write_bash_array(&mut stdout, name, &lines) {
+            diagnostics::exit_with_error(format!("Unable to write to stdout: {}", e));
+        }
+
+        return;
+    }
+
+    // If you pass `--clipboard`, we copy the output to the clipboard
+    // rather than printing it -- unless we can't use the clipboard, in
+    // which case we print it as usual.
+    #[cfg(feature = "clipboard")]
+    if clipboard {
+        let mut buffer: Vec<u8> = Vec::new();
+
+        if let Err(e) = output::write_sample(&mut buffer, &lines, csv, separator, terminator) {
+            diagnostics::exit_with_error(format!("Unable to write the sample: {}", e));
+        }
+
+        match clipboard::copy(String::from_utf8_lossy(&buffer).into_owned()) {
+            Ok(()) => return,
+            Err(e) => diagnostics::warning(format!(
+                "Unable to copy to the clipboard: {}; printing the sample instead",
+                e
+            )),
+        }
+    }
+
+    let mut stdout = open_stdout();
+
+    // If you pass `--limit-rate`, we write the lines one at a time,
+    // so a slow reader downstream can keep up.  If you pass
+    // `--line-buffered`, we write them one at a time as fast as we can,
+    // so the reader sees each line as soon as it's written.
+    let result = match limit_rate {
+        Some(rate) => output::write_lines_one_at_a_time(
+            &mut stdout,
+            &lines,
+            separator,
+            terminator,
+            Some(&mut output::RateLimiter::new(rate)),
+        )
+        .map_err(|e| e.into()),
+        None if line_buffered => {
+            output::write_lines_one_at_a_time(&mut stdout, &lines, separator, terminator, None)
+                .map_err(|e| e.into())
+        }
+        None => output::write_sample(&mut stdout, &lines, csv, separator, terminator),
+    };
+
+    if let Err(e) = result {
+        diagnostics::exit_with_error(format!("Unable to write to stdout: {}", e));
+    }
+}
+
+#[cfg(test)]
+mod cli_tests {
+    use assert_cmd::Command;
+    use std::sync::OnceLock;
+
+    /// Run `randline` in an empty directory, without `$HOME` or any
+    /// `RANDLINE_*` variables, so the tests don't pick up a config file
+    /// or defaults from whoever is running them.
+    fn randline() -> Command {
+        static EMPTY_DIR: OnceLock<tempfile::TempDir> = OnceLock::new();
+
+        let mut cmd = Command::cargo_bin("randline").unwrap();
+        cmd.current_dir(
+            EMPTY_DIR
+                .get_or_init(|| tempfile::tempdir().unwrap())
+                .path(),
+        )
+        .env_remove("HOME");
+
+        for (name, _) in std::env::vars_os() {
+            if name.to_string_lossy().starts_with("RANDLINE_") {
+                cmd.env_remove(name);
+            }
+        }
+
+        cmd
+    }
+
+    // Note: for the purposes of the CLI tests, I trust that the reservoir
+    // sampling code works correctly -- that's tested separately.  I'm just
+    // checking the CLI parses the options correctly, so all the input lines
+    // are the same for easy assertions.
+
+    // If you call `randline` without any arguments, it picks a single line.
+    #[test]
+    fn it_selects_a_single_line_if_no_arg() {
+        randline()
+            .write_stdin("a\na\na\na\na\na\n")
+            .assert()
+            .success()
+            .stdout("a\n")
+            .stderr("");
+    }
+
+    // If there's a `.randlinerc` in the working directory, it sets
+    // the default k, which you can override with `RANDLINE_K` or on
+    // the command line.
+    #[test]
+    fn it_reads_defaults_from_a_config_file() {
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::write(dir.path().join(".randlinerc"), "k = 2\n").unwrap();
+
+        let run = |args: &[&str], env_k: Option<&str>| {
+            let mut cmd = randline();
+            cmd.current_dir(dir.path()).env("HOME", dir.path());
+
+            if let Some(k) = env_k {
+                cmd.env("RANDLINE_K", k);
+            }
+
+            cmd.args(args)
+                .write_stdin("a\na\na\na\na\n")
+                .assert()
+                .success()
+                .stderr("")
+                .get_output()
+                .stdout
+                .clone()
+        };
+
+        assert_eq!(run(&[], None), b"a\na\n");
+        assert_eq!(run(&[], Some("3")), b"a\na\na\n");
+        assert_eq!(run(&["4"], Some("3")), b"a\na\na\na\n");
+    }
+
+    // A config file with a setting we don't know is an error.
+    #[test]
+    fn it_fails_if_the_config_file_is_invalid() {
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::write(dir.path().join(".randlinerc"), "colour = \"red\"\n").unwrap();
+
+        randline()
+            .current_dir(dir.path())
+            .env("HOME", dir.path())
+            .assert()
+            .failure()
+            .code(1)
+            .stdout("")
+            .stderr("Unable to parse ./.randlinerc: unknown setting \"colour\"\n");
+    }
+
+    // Options on the command line override the config file, including
+    // options which can't be used with a setting from the config file.
+    #[test]
+    fn it_lets_the_command_line_override_the_config_file() {
+        let run = |config: &str, args: &[&str]| {
+            let dir = tempfile::tempdir().unwrap();
+            std::fs::write(dir.path().join(".randlinerc"), config).unwrap();
+
+            randline()
+                .current_dir(dir.path())
+                .env("HOME", dir.path())
+                .args(args)
+                .write_stdin("c\na\nb\n")
+                .assert()
+                .success()
+                .stderr("")
+                .get_output()
+                .stdout
+                .clone()
+        };
+
+        assert_eq!(
+            run("shuffle = true\n", &["--sort-output", "3"]),
+            b"a\nb\nc\n"
+        );
+        assert_eq!(
+            run(
+                "output-separator = \";\"\n",
+                &["--csv", "--sort-output", "3"]
+            ),
+            b"a\nb\nc\n"
+        );
+        assert_eq!(run("mode = \"count\"\n", &["1"]).len(), 2);
+        assert_eq!(run("mode = \"count\"\n", &[]), b"3\n");
+
+        // `--two-pass` can't be used with JSON Lines, so we ignore the
+        // input format from the config file.
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::write(dir.path().join(".randlinerc"), "input-format = \"jsonl\"\n").unwrap();
+        std::fs::write(dir.path().join("lines.txt"), "a\na\na\n").unwrap();
+
+        randline()
+            .current_dir(dir.path())
+            .env("HOME", dir.path())
+            .args(["--invert", "--two-pass", "--file", "lines.txt", "2"])
+            .assert()
+            .success()
+            .stdout("a\n")
+            .stderr("");
+    }
+
+    // If you pass an argument `k` and there are more lines than `k`,
+    // it selects a subset of them.
+    #[test]
+    fn it_selects_k_lines_if_more_lines_than_k() {
+        randline()
+            .arg("2")
+            .write_stdin("a\na\na\na\na\na\n")
+            .assert()
+            .success()
+            .stdout("a\na\n")
+            .stderr("");
+    }
+
+    // If you pass an argument `k` and there are that number of lines,
+    // it selects all of them.
+    #[test]
+    fn it_selects_k_lines_if_equal_lines_to_k() {
+        randline()
+            .arg("2")
+            .write_stdin("a\na\n")
+            .assert()
+            .success()
+            .stdout("a\na\n")
+            .stderr("");
+    }
+
+    // If you pass an argument `k` and there are less lines than `k`,
+    // it selects all of them.
+    #[test]
+    fn it_selects_k_lines_if_less_lines_than_k() {
+        randline()
+            .arg("5")
+            .write_stdin("a\na\n")
+            .assert()
+            .success()
+            .stdout("a\na\n")
+            .stderr("");
+    }
+
+    // Passing a non-integer argument is an error.
+    #[test]
+    fn it_fails_if_non_integer_argument() {
+        randline()
+            .arg("XXX")
+            .assert()
+            .failure()
+            .code(1)
+            .stdout("")
+            .stderr("Usage: randline [sample | shuf | count] [options] [k]\n");
+    }
+
+    // Passing k=0 is an error.
+    #[test]
+    fn it_fails_if_k_equals_zero() {
+        randline()
+            .arg("0")
+            .assert()
+            .failure()
+            .code(1)
+            .stdout("")
+            .stderr("Usage: randline [sample | shuf | count] [options] [k]\n");
+    }
+
+    // Passing k<0 is an error.
+    #[test]
+    fn it_fails_if_k_negative() {
+        randline()
+            .arg("-1")
+            .assert()
+            .failure()
+            .code(1)
+            .stdout("")
+            .stderr("Usage: randline [sample | shuf | count] [options] [k]\n");
+    }
+
+    // Passing more than one argument is an error.
+    #[test]
+    fn it_fails_if_too_many_args() {
+        randline()
+            .args(["1", "2", "3"])
+            .assert()
+            .failure()
+            .code(1)
+            .stdout("")
+            .stderr("Usage: randline [sample | shuf | count] [options] [k]\n");
+    }
+
+    // If the input arrives before the timeout, the timeout has no effect.
+    #[test]
+    fn it_reads_stdin_within_the_timeout() {
+        randline()
+            .args(["--timeout", "10", "2"])
+            .write_stdin("a\na\na\n")
+            .assert()
+            .success()
+            .stdout("a\na\n")
+            .stderr("");
+    }
+
+    // If stdin doesn't reach EOF before the timeout, it gives up
+    // with an error.
+    //
+    // This simulates a slow producer by writing a single line, then
+    // holding stdin open without closing it.
+    #[test]
+    fn it_fails_if_stdin_exceeds_the_timeout() {
+        use std::io::Write;
+        use std::process::Stdio;
+
+        let mut child = std::process::Command::new(assert_cmd::cargo::cargo_bin("randline"))
+            .env_remove("HOME")
+            .args(["--timeout", "0.5"])
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn()
+            .unwrap();
+
+        let mut stdin = child.stdin.take().unwrap();
+        stdin.write_all(b"a\n").unwrap();
+
+        let output = child.wait_with_output().unwrap();
+        drop(stdin);
+
+        assert_eq!(output.status.code(), Some(1));
+        assert_eq!(output.stdout, b"");
+        assert_eq!(
+            String::from_utf8(output.stderr).unwrap(),
+            "Timed out waiting for stdin after 0.5 seconds\n"
+        );
+    }
+
+    // If you pass `--max-runtime`, we stop reading when we run out of
+    // time, and print a sample from the lines read so far, even though
+    // stdin is still open.
+    #[test]
+    fn it_prints_the_sample_so_far_at_the_max_runtime() {
+        use std::io::Write;
+        use std::process::Stdio;
+        use std::time::{Duration, Instant};
+
+        let started = Instant::now();
+
+        let mut child = std::process::Command::new(assert_cmd::cargo::cargo_bin("randline"))
+            .env_remove("HOME")
+            .args(["--max-runtime", "0.5", "5"])
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn()
+            .unwrap();
+
+        let mut stdin = child.stdin.take().unwrap();
+        stdin.write_all(b"a\n").unwrap();
+
+        let output = child.wait_with_output().unwrap();
+        drop(stdin);
+
+        // There's plenty of tolerance on the upper bound, so this isn't
+        // flaky on a slow machine.
+        let elapsed = started.elapsed();
+        assert!(elapsed >= Duration::from_millis(500), "{:?}", elapsed);
+        assert!(elapsed < Duration::from_secs(10), "{:?}", elapsed);
+
+        assert_eq!(output.status.code(), Some(0));
+        assert_eq!(output.stdout, b"a\n");
+        assert_eq!(
+            String::from_utf8(output.stderr).unwrap(),
+            "Reached --max-runtime; picking a sample from the lines read so far\n"
+        );
+    }
+
+    // `--max-runtime` also stops an endless stream that never pauses.
+    #[test]
+    fn it_stops_an_endless_stream_at_the_max_runtime() {
+        use std::io::Write;
+        use std::process::Stdio;
+
+        let mut child = std::process::Command::new(assert_cmd::cargo::cargo_bin("randline"))
+            .env_remove("HOME")
+            .args(["--max-runtime", "0.5", "3"])
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn()
+            .unwrap();
+
+        // Keep writing until randline stops reading and closes stdin.
+        let mut stdin = child.stdin.take().unwrap();
+        let writer = std::thread::spawn(move || while stdin.write_all(b"y\n").is_ok() {});
+
+        let output = child.wait_with_output().unwrap();
+        writer.join().unwrap();
+
+        assert_eq!(output.status.code(), Some(0));
+        assert_eq!(output.stdout, b"y\ny\ny\n");
+    }
+
+    // If the input ends before the max runtime, it has no effect.
+    #[test]
+    fn it_reads_everything_within_the_max_runtime() {
+        randline()
+            .args(["--max-runtime", "10", "--sort-output", "5"])
+            .write_stdin("b\na\n")
+            .assert()
+            .success()
+            .stdout("a\nb\n")
+            .stderr("");
+    }
+
+    // If you press Ctrl-C, we stop reading and print a sample from the
+    // lines read so far, even though stdin is still open.
+    //
+    // We're blocked waiting for the next line when the signal arrives,
+    // so we write one more line to wake the reader up.
+    //
+    // Rather than sleeping for a fixed time, we wait until randline is
+    // blocked reading stdin, which means it's read every line so far.
+    // We check with `/proc/<pid>/syscall`, so this only runs on Linux.
+    #[test]
+    #[cfg(target_os = "linux")]
+    fn it_prints_the_sample_so_far_on_ctrl_c() {
+        use std::io::Write;
+        use std::process::Stdio;
+        use std::time::{Duration, Instant};
+
+        let mut child = std::process::Command::new(assert_cmd::cargo::cargo_bin("randline"))
+            .env_remove("HOME")
+            .args(["--sort-output", "10"])
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn()
+            .unwrap();
+
+        let mut stdin = child.stdin.take().unwrap();
+        stdin.write_all(b"a\nb\nc\n").unwrap();
+
+        // A blocked `read(0, ...)` shows up as syscall 0 on fd 0x0.
+        let syscall = format!("/proc/{}/syscall", child.id());
+        let deadline = Instant::now() + Duration::from_secs(10);
+        while !std::fs::read_to_string(&syscall)
+            .unwrap()
+            .starts_with("0 0x0 ")
+        {
+            assert!(Instant::now() < deadline, "randline never read stdin");
+            std::thread::sleep(Duration::from_millis(10));
+        }
+
+        let status = std::process::Command::new("kill")
+            .args(["-INT", &child.id().to_string()])
+            .status()
+            .unwrap();
+        assert!(status.success());
+
+        // The warning is printed after the handler sets the flag, so once
+        // we see it, the next line we write will be the last one read.
+        let mut stderr = std::io::BufReader::new(child.stderr.take().unwrap());
+        let mut warning = String::new();
+        std::io::BufRead::read_line(&mut stderr, &mut warning).unwrap();
+        assert!(
+            warning.starts_with("Interrupted; picking a sample from the lines read so far"),
+            "warning = {:?}",
+            warning
+        );
+
+        stdin.write_all(b"d\n").unwrap();
+
+        let output = child.wait_with_output().unwrap();
+        drop(stdin);
+
+        assert!(output.status.success());
+        assert_eq!(output.stdout, b"a\nb\nc\nd\n");
+    }
+
+    // The timeout has to be a positive number of seconds.
+    #[test]
+    fn it_fails_if_timeout_is_invalid() {
+        for timeout in ["XXX", "0", "-1"] {
+            randline()
+                .args(["--timeout", timeout])
+                .assert()
+                .failure()
+                .code(1)
+                .stdout("")
+                .stderr("--timeout expects a positive number of seconds\n");
+        }
+    }
+
+    // Error messages don't include color codes when stderr is redirected,
+    // whether or not `--no-color` is passed.
+    #[test]
+    fn it_doesnt_color_errors_if_stderr_redirected() {
+        for args in [vec!["XXX"], vec!["--no-color", "XXX"]] {
+            randline()
+                .args(args)
+                .assert()
+                .failure()
+                .code(1)
+                .stdout("")
+                .stderr("Usage: randline [sample | shuf | count] [options] [k]\n");
+        }
+    }
+
+    // Passing `--no-color` doesn't affect the sample.
+    #[test]
+    fn it_accepts_no_color_flag() {
+        randline()
+            .args(["--no-color", "2"])
+            .write_stdin("a\na\na\n")
+            .assert()
+            .success()
+            .stdout("a\na\n")
+            .stderr("");
+    }
+
+    // If you pass `--group-by`, lines with the same key are printed
+    // next to each other, with a blank line between groups.
+    #[test]
+    fn it_groups_output_by_key() {
+        let output = randline()
+            .args(["--group-by", "^[a-z]", "6"])
+            .write_stdin("a1\nb1\na2\nb2\na3\nb3\n")
+            .output()
+            .unwrap();
+
+        assert!(output.status.success());
+
+        let stdout = String::from_utf8(output.stdout).unwrap();
+        let mut groups: Vec<&str> = stdout.trim_end().split("\n\n").collect();
+        groups.sort();
+
+        assert_eq!(groups.len(), 2);
+        assert!(groups[0].lines().all(|ln| ln.starts_with('a')));
+        assert!(groups[1].lines().all(|ln| ln.starts_with('b')));
+        assert_eq!(groups[0].lines().count(), 3);
+        assert_eq!(groups[1].lines().count(), 3);
+    }
+
+    // Passing an invalid regex to `--group-by` is an error.
+    #[test]
+    fn it_fails_if_group_by_regex_is_invalid() {
+        let output = randline().args(["--group-by", "("]).output().unwrap();
+
+        assert_eq!(output.status.code(), Some(1));
+        assert_eq!(output.stdout, b"");
+        assert!(String::from_utf8(output.stderr)
+            .unwrap()
+            .starts_with("Invalid regex for --group-by:"));
+    }
+
+    // If you pass `--unique`, repeated lines are only counted once.
+    #[test]
+    fn it_removes_duplicates_with_unique() {
+        for flag in ["--unique", "--approx-unique"] {
+            randline()
+                .args([flag, "5"])
+                .write_stdin("a\na\na\na\na\na\n")
+                .assert()
+                .success()
+                .stdout("a\n")
+                .stderr("");
+        }
+    }
+
+    // The filter size for `--approx-unique` has to be a positive integer.
+    #[test]
+    fn it_fails_if_filter_size_is_invalid() {
+        randline()
+            .args(["--approx-unique", "--filter-size", "0"])
+            .assert()
+            .failure()
+            .code(1)
+            .stdout("")
+            .stderr("--filter-size expects a positive integer\n");
+    }
+
+    // If you pass `--dedup-adjacent`, runs of repeated lines are
+    // collapsed into one line, like `uniq`.
+    #[test]
+    fn it_removes_adjacent_duplicates() {
+        randline()
+            .args(["--dedup-adjacent", "--count-only"])
+            .write_stdin("a\na\na\nb\nb\na\nc\n")
+            .assert()
+            .success()
+            .stdout("4\n")
+            .stderr("");
+    }
+
+    // If you pass `--file`, it reads from that file instead of stdin.
+    // You can pass `--file` more than once, and `-` means stdin.
+    #[test]
+    fn it_reads_from_multiple_files() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("a.txt");
+        std::fs::write(&path, "a\na\n").unwrap();
+
+        randline()
+            .args(["--file", path.to_str().unwrap(), "--file", "-", "4"])
+            .write_stdin("a\na\n")
+            .assert()
+            .success()
+            .stdout("a\na\na\na\n")
+            .stderr("");
+    }
+
+    // If you pass `--exclude-file`, lines in that file are never picked.
+    #[test]
+    fn it_never_picks_excluded_lines() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("exclude.txt");
+        std::fs::write(&path, "b\nd\n").unwrap();
+
+        randline()
+            .args(["--exclude-file", path.to_str().unwrap(), "10"])
+            .write_stdin("a\nb\na\nd\nb\n")
+            .assert()
+            .success()
+            .stdout("a\na\n")
+            .stderr("");
+    }
+
+    // If you pass `--include-file`, only lines in that file can be picked.
+    #[test]
+    fn it_only_picks_included_lines() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("include.txt");
+        std::fs::write(&path, "b\nd\nz\n").unwrap();
+
+        randline()
+            .args(["--include-file", path.to_str().unwrap(), "10"])
+            .write_stdin("a\nb\na\nb\nc\nb\n")
+            .assert()
+            .success()
+            .stdout("b\nb\nb\n")
+            .stderr("");
+    }
+
+    // If you pass `--force-include`, the lines in that file are always
+    // in the sample, and the rest of the sample is picked at random
+    // from the other lines.
+    #[test]
+    fn it_always_includes_forced_lines() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("force.txt");
+        std::fs::write(&path, "c\ne\n").unwrap();
+
+        for seed in 0..20 {
+            let output = randline()
+                .args(["--force-include", path.to_str().unwrap()])
+                .args(["--seed", &seed.to_string(), "3"])
+                .write_stdin("a\nb\nc\nd\ne\nf\ng\n")
+                .output()
+                .unwrap();
+
+            assert!(output.status.success());
+
+            let stdout = String::from_utf8(output.stdout).unwrap();
+            let lines: Vec<&str> = stdout.lines().collect();
+            assert_eq!(lines.len(), 3, "stdout = {:?}", stdout);
+            assert_eq!(&lines[..2], &["c", "e"], "stdout = {:?}", stdout);
+            assert!(
+                ["a", "b", "d", "f", "g"].contains(&lines[2]),
+                "stdout = {:?}",
+                stdout
+            );
+        }
+    }
+
+    // It's an error if there are more forced lines than fit in the sample.
+    #[test]
+    fn it_fails_if_there_are_too_many_forced_lines() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("force.txt");
+        std::fs::write(&path, "a\nb\na\nc\n").unwrap();
+
+        randline()
+            .args(["--force-include", path.to_str().unwrap(), "2"])
+            .write_stdin("a\nb\nc\n")
+            .assert()
+            .failure()
+            .code(1)
+            .stdout("")
+            .stderr(
+                "--force-include has 3 distinct line(s), which is more than the sample size (2)\n",
+            );
+    }
+
+    // Files ending in `.gz`, `.zst` or `.bz2` are decompressed as
+    // they're read.
+    #[test]
+    fn it_reads_compressed_files() {
+        use std::io::Write;
+
+        let dir = tempfile::tempdir().unwrap();
+        let text = b"a\na\n";
+
+        let gz_path = dir.path().join("lines.txt.gz");
+        let mut gz = flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::default());
+        gz.write_all(text).unwrap();
+        std::fs::write(&gz_path, gz.finish().unwrap()).unwrap();
+
+        let zst_path = dir.path().join("lines.txt.zst");
+        std::fs::write(&zst_path, zstd::encode_all(&text[..], 0).unwrap()).unwrap();
+
+        let bz2_path = dir.path().join("lines.txt.bz2");
+        let mut bz2 = bzip2::write::BzEncoder::new(Vec::new(), bzip2::Compression::default());
+        bz2.write_all(text).unwrap();
+        std::fs::write(&bz2_path, bz2.finish().unwrap()).unwrap();
+
+        for path in [gz_path, zst_path, bz2_path] {
+            randline()
+                .args(["--file", path.to_str().unwrap(), "5"])
+                .assert()
+                .success()
+                .stdout("a\na\n")
+                .stderr("");
+        }
+    }
+
+    // If a compressed file is corrupt, it's a read error.
+    #[test]
+    fn it_fails_if_compressed_file_is_corrupt() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("lines.txt.gz");
+        std::fs::write(&path, "this isn't gzip\n").unwrap();
+        let path = path.to_str().unwrap();
+
+        let output = randline().args(["--file", path]).output().unwrap();
+
+        assert_eq!(output.status.code(), Some(1));
+        assert_eq!(output.stdout, b"");
+        assert!(String::from_utf8(output.stderr)
+            .unwrap()
+            .starts_with(&format!("Unable to read from {}: ", path)));
+    }
+
+    // If you pass `--per-file`, we pick k lines from each file, rather
+    // than k lines in total, so a small file gets as many lines in the
+    // sample as a big file.
+    #[test]
+    fn it_samples_each_file_separately() {
+        let dir = tempfile::tempdir().unwrap();
+        let big = dir.path().join("big.txt");
+        let small = dir.path().join("small.txt");
+        std::fs::write(&big, "big\n".repeat(1000)).unwrap();
+        std::fs::write(&small, "small\n".repeat(3)).unwrap();
+
+        randline()
+            .args([
+                "--per-file",
+                "--file",
+                big.to_str().unwrap(),
+                "--file",
+                small.to_str().unwrap(),
+                "2",
+            ])
+            .assert()
+            .success()
+            .stdout("big\nbig\nsmall\nsmall\n")
+            .stderr("");
+    }
+
+    // If you pass `--with-source`, each line is prefixed with the path
+    // of the file it came from, or `-` for stdin.
+    #[test]
+    fn it_tags_lines_with_their_source() {
+        let dir = tempfile::tempdir().unwrap();
+        let path1 = dir.path().join("1.txt");
+        let path2 = dir.path().join("2.txt");
+        std::fs::write(&path1, "one\none\n").unwrap();
+        std::fs::write(&path2, "two\ntwo\n").unwrap();
+
+        let path1 = path1.to_str().unwrap();
+        let path2 = path2.to_str().unwrap();
+
+        let output = randline()
+            .args([
+                "--with-source",
+                "--file",
+                path1,
+                "--file",
+                path2,
+                "--file",
+                "-",
+            ])
+            .arg("10")
+            .write_stdin("three\n")
+            .output()
+            .unwrap();
+
+        assert!(output.status.success());
+
+        let mut lines: Vec<String> = String::from_utf8(output.stdout)
+            .unwrap()
+            .lines()
+            .map(|ln| ln.to_string())
+            .collect();
+        lines.sort();
+
+        let mut expected = vec![
+            format!("{}\tone", path1),
+            format!("{}\tone", path1),
+            format!("{}\ttwo", path2),
+            format!("{}\ttwo", path2),
+            "-\tthree".to_string(),
+        ];
+        expected.sort();
+
+        assert_eq!(lines, expected);
+    }
+
+    // If a file doesn't exist, it fails with an error.
+    #[test]
+    fn it_fails_if_file_doesnt_exist() {
+        randline()
+            .args(["--file", "doesnotexist.txt"])
+            .assert()
+            .failure()
+            .code(1)
+            .stdout("")
+            .stderr("Unable to open doesnotexist.txt: No such file or directory (os error 2)\n");
+    }
+
+    // If you pass a number less than 1, it picks that fraction of
+    // the input.
+    #[test]
+    fn it_selects_a_fraction_of_lines() {
+        for arg in ["0.1", "10%"] {
+            randline()
+                .arg(arg)
+                .write_stdin("a\n".repeat(20))
+                .assert()
+                .success()
+                .stdout("a\na\n")
+                .stderr("");
+        }
+    }
+
+    // Passing `1.0` means "one line", not "the whole input".
+    #[test]
+    fn it_treats_one_as_a_count() {
+        randline()
+            .arg("1.0")
+            .write_stdin("a\na\na\n")
+            .assert()
+            .success()
+            .stdout("a\n")
+            .stderr("");
+    }
+
+    // Passing a fraction of zero is an error.
+    #[test]
+    fn it_fails_if_fraction_is_zero() {
+        randline()
+            .arg("0.0")
+            .assert()
+            .failure()
+            .code(1)
+            .stdout("")
+            .stderr("Usage: randline [sample | shuf | count] [options] [k]\n");
+    }
+
+    // If you pass `--jsonl`, every line has to be valid JSON.
+    #[test]
+    fn it_fails_on_invalid_jsonl() {
+        randline()
+            .args(["--jsonl", "5"])
+            .write_stdin("{\"a\": 1}\n{not json\n")
+            .assert()
+            .failure()
+            .code(1)
+            .stdout("")
+            .stderr("Invalid JSON on line 2 of -: key must be a string at line 1 column 2\n");
+    }
+
+    // If you pass `--jsonl --validate`, invalid lines are skipped
+    // with a warning.
+    #[test]
+    fn it_skips_invalid_jsonl_with_validate() {
+        randline()
+            .args(["--jsonl", "--validate", "5"])
+            .write_stdin("{\"a\": 1}\n{not json\n{\"a\": 1}\n")
+            .assert()
+            .success()
+            .stdout("{\"a\": 1}\n{\"a\": 1}\n")
+            .stderr("Skipped 1 malformed JSON record(s)\n");
+    }
+
+    // If every line is valid JSON, they're all eligible for the sample.
+    #[test]
+    fn it_samples_valid_jsonl() {
+        randline()
+            .args(["--jsonl", "--validate", "2"])
+            .write_stdin("[1]\n[1]\n[1]\n")
+            .assert()
+            .success()
+            .stdout("[1]\n[1]\n")
+            .stderr("");
+    }
+
+    // If you pass `--count-only`, it prints the number of lines
+    // rather than a sample.
+    #[test]
+    fn it_prints_the_line_count() {
+        randline()
+            .args(["--count-only", "2"])
+            .write_stdin("a\nb\nc\nd\ne\n")
+            .assert()
+            .success()
+            .stdout("5\n")
+            .stderr("");
+    }
+
+    // The count is the number of lines after filtering, i.e. the number
+    // of lines we'd be sampling from.
+    #[test]
+    fn it_counts_lines_after_filtering() {
+        randline()
+            .args(["--count-only", "--unique"])
+            .write_stdin("a\nb\na\nb\nc\n")
+            .assert()
+            .success()
+            .stdout("3\n")
+            .stderr("");
+    }
+
+    // In weighted mode, the weight is the first tab-separated field, and
+    // lines with weight 0 are never picked if there are enough others.
+    #[test]
+    fn it_selects_weighted_lines() {
+        randline()
+            .args(["--weighted", "2"])
+            .write_stdin("1\ta\n0\tb\n1\ta\n0\tb\n")
+            .assert()
+            .success()
+            .stdout("1\ta\n1\ta\n")
+            .stderr("");
+    }
+
+    // In weighted mode, a line without a weight is an error.
+    #[test]
+    fn it_fails_if_weight_missing() {
+        randline()
+            .args(["--weighted", "2"])
+            .write_stdin("1\ta\nb\n")
+            .assert()
+            .failure()
+            .code(1)
+            .stdout("")
+            .stderr("Missing or invalid weight on line 2 of -\n");
+    }
+
+    // If every line has weight 0, there's nothing to pick, so it's an
+    // error rather than an arbitrary sample.
+    #[test]
+    fn it_fails_if_every_weight_is_zero() {
+        for args in [
+            vec!["--weighted", "2"],
+            vec!["--weighted", "--with-replacement", "2"],
+            vec!["--weighted", "--per-file", "2"],
+        ] {
+            randline()
+                .args(&args)
+                .write_stdin("0\ta\n0\tb\n0\tc\n")
+                .assert()
+                .failure()
+                .code(1)
+                .stdout("")
+                .stderr("Every line has weight 0, so there's nothing to pick from 3 line(s)\n");
+        }
+    }
+
+    // Big weights are fine, even if their total is more than the
+    // biggest float.
+    #[test]
+    fn it_allows_weights_whose_total_overflows() {
+        randline()
+            .args(["--weighted", "--sort-output", "2"])
+            .write_stdin("1e308\ta\n1e308\tb\n")
+            .assert()
+            .success()
+            .stdout("1e308\ta\n1e308\tb\n")
+            .stderr("");
+    }
+
+    // With `--temperature`, zero weights are fine, because the softmax
+    // gives every line a positive weight.
+    #[test]
+    fn it_allows_zero_weights_with_temperature() {
+        randline()
+            .args(["--weighted", "--temperature", "1", "--sort-output", "3"])
+            .write_stdin("0\ta\n0\tb\n0\tc\n")
+            .assert()
+            .success()
+            .stdout("0\ta\n0\tb\n0\tc\n")
+            .stderr("");
+    }
+
+    // If you pass `--default-weight`, lines without a weight get the
+    // default instead.
+    //
+    // Here the lines with explicit weights have weight 0, so we can only
+    // pick the lines which get the default weight.
+    #[test]
+    fn it_uses_the_default_weight_for_missing_weights() {
+        randline()
+            .args(["--weighted", "--default-weight", "1", "2"])
+            .write_stdin("0\ta\nb\n0\ta\nb\n0\ta\n")
+            .assert()
+            .success()
+            .stdout("b\nb\n")
+            .stderr("");
+    }
+
+    // If you pass `--weight-regex`, the weight is the first capture
+    // group, wherever it is in the line.
+    #[test]
+    fn it_selects_lines_weighted_by_regex() {
+        let output = randline()
+            .args(["--weight-regex", "took ([0-9.]+)ms", "2"])
+            .write_stdin(
+                "GET /a took 0ms\nGET /b took 1.5ms (ok)\nGET /c took 0ms\nGET /d took 2ms\n",
+            )
+            .output()
+            .unwrap();
+
+        assert!(output.status.success());
+
+        let stdout = String::from_utf8(output.stdout).unwrap();
+        let mut lines: Vec<&str> = stdout.lines().collect();
+        lines.sort();
+
+        assert_eq!(lines, vec!["GET /b took 1.5ms (ok)", "GET /d took 2ms"]);
+    }
+
+    // Lines that don't match `--weight-regex` get the default weight.
+    #[test]
+    fn it_uses_the_default_weight_if_regex_doesnt_match() {
+        randline()
+            .args(["--weight-regex", "w=([0-9]+)", "--default-weight", "1", "1"])
+            .write_stdin("a w=0\nb\nc w=0\n")
+            .assert()
+            .success()
+            .stdout("b\n")
+            .stderr("");
+    }
+
+    // If you pass `--weight-field` and `--columns`, both count the fields
+    // of the input line, so you can weight by one column and only print
+    // the others.
+    #[test]
+    fn it_weights_by_one_column_and_prints_others() {
+        randline()
+            .args(["--weight-field", "2", "--columns", "3,1", "1"])
+            .write_stdin("apple\t0\tred\nbanana\t5\tyellow\ncherry\t0\tdark red\n")
+            .assert()
+            .success()
+            .stdout("yellow\tbanana\n")
+            .stderr("");
+    }
+
+    // `--columns` works without weights too, and it can include the
+    // weight field.
+    #[test]
+    fn it_only_prints_the_selected_columns() {
+        randline()
+            .args(["--weighted", "--columns", "1,2", "--sort-output", "2"])
+            .write_stdin("1\tapple\tred\n2\tbanana\tyellow\n")
+            .assert()
+            .success()
+            .stdout("1\tapple\n2\tbanana\n")
+            .stderr("");
+    }
+
+    #[test]
+    fn it_fails_if_the_columns_are_invalid() {
+        randline()
+            .args(["--columns", "0,2", "2"])
+            .assert()
+            .failure()
+            .code(1)
+            .stdout("")
+            .stderr("--columns expects a comma-separated list of field numbers, like 2,3\n");
+    }
+
+    // The regex for `--weight-regex` needs a capture group.
+    #[test]
+    fn it_fails_if_weight_regex_has_no_capture_group() {
+        randline()
+            .args(["--weight-regex", "[0-9]+"])
+            .assert()
+            .failure()
+            .code(1)
+            .stdout("")
+            .stderr("--weight-regex needs a capture group for the weight\n");
+    }
+
+    // If you pass `--weights-file`, each line's weight is the matching
+    // line of that file.
+    #[test]
+    fn it_reads_weights_from_a_separate_file() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("weights.txt");
+        std::fs::write(&path, "0\n1\n0\n1\n").unwrap();
+
+        randline()
+            .args(["--weights-file", path.to_str().unwrap(), "2"])
+            .write_stdin("a\nb\nc\nb\n")
+            .assert()
+            .success()
+            .stdout("b\nb\n")
+            .stderr("");
+    }
+
+    // The weights file has to have one weight for every line of input.
+    #[test]
+    fn it_fails_if_weights_file_is_a_different_length() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("weights.txt");
+        std::fs::write(&path, "1\n1\n").unwrap();
+        let path = path.to_str().unwrap();
+
+        randline()
+            .args(["--weights-file", path])
+            .write_stdin("a\nb\nc\n")
+            .assert()
+            .failure()
+            .code(1)
+            .stdout("")
+            .stderr(format!(
+                "The weights file {} has 2 line(s), but the input has more\n",
+                path
+            ));
+
+        randline()
+            .args(["--weights-file", path])
+            .write_stdin("a\n")
+            .assert()
+            .failure()
+            .code(1)
+            .stdout("")
+            .stderr(format!(
+                "The input has 1 line(s), but the weights file {} has more\n",
+                path
+            ));
+    }
+
+    // If you pass `--fail-fast`, a malformed weight is an error even if
+    // there's a default weight, and we report the line it's on.  Lines
+    // with no weight still get the default.
+    #[test]
+    fn it_fails_fast_on_a_malformed_weight() {
+        randline()
+            .args(["--weighted", "--default-weight", "1", "--fail-fast", "5"])
+            .write_stdin("1\ta\n\tb\n2\tc\nx\td\n3\te\n")
+            .assert()
+            .failure()
+            .code(1)
+            .stdout("")
+            .stderr("Invalid weight \"x\" on line 4 of -\n");
+    }
+
+    // `--fail-fast` also applies to a separate weights file.
+    #[test]
+    fn it_fails_fast_on_a_malformed_weight_in_a_weights_file() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("weights.txt");
+        std::fs::write(&path, "1\n\n-2\n").unwrap();
+        let path = path.to_str().unwrap();
+
+        randline()
+            .args([
+                "--weights-file",
+                path,
+                "--default-weight",
+                "1",
+                "--fail-fast",
+            ])
+            .write_stdin("a\nb\nc\n")
+            .assert()
+            .failure()
+            .code(1)
+            .stdout("")
+            .stderr(format!("Invalid weight \"-2\" on line 3 of {}\n", path));
+    }
+
+    // The default weight has to be a positive number.
+    #[test]
+    fn it_fails_if_default_weight_is_invalid() {
+        for weight in ["0", "-1", "NaN", "inf", "XXX"] {
+            randline()
+                .args(["--weighted", "--default-weight", weight])
+                .assert()
+                .failure()
+                .code(1)
+                .stdout("")
+                .stderr("--default-weight expects a positive number\n");
+        }
+    }
+
+    // If you pass `--temperature`, the weights go through a softmax, so
+    // a low temperature almost always picks the line with the highest
+    // weight, even if the other lines have a non-zero weight.
+    #[test]
+    fn it_picks_the_heaviest_lines_at_low_temperature() {
+        for _ in 0..10 {
+            randline()
+                .args(["--weighted", "--temperature", "0.01", "--sort-output", "2"])
+                .write_stdin("1\tlow\n5\thigh\n2\tlow\n5\thigh\n3\tlow\n")
+                .assert()
+                .success()
+                .stdout("5\thigh\n5\thigh\n")
+                .stderr("");
+        }
+    }
+
+    #[test]
+    fn it_fails_if_temperature_is_invalid() {
+        for t in ["0", "-1", "inf", "XXX"] {
+            randline()
+                .args(["--weighted", "--temperature", t])
+                .assert()
+                .failure()
+                .code(1)
+                .stdout("")
+                .stderr("--temperature expects a positive number\n");
+        }
+    }
+
+    // If randline was built without the `interactive` feature, passing
+    // `--interactive` is an error.
+    #[cfg(not(feature = "interactive"))]
+    #[test]
+    fn it_fails_if_interactive_not_available() {
+        randline()
+            .arg("--interactive")
+            .assert()
+            .failure()
+            .code(1)
+            .stdout("")
+            .stderr(
+                "--interactive isn't available; rebuild randline with `--features interactive`\n",
+            );
+    }
+
+    // If you pass `--output-separator`, the sampled lines are joined
+    // with that string, and the output ends with a newline.
+    #[test]
+    fn it_joins_lines_with_the_output_separator() {
+        randline()
+            .args(["--output-separator", ", ", "3"])
+            .write_stdin("a\na\na\na\n")
+            .assert()
+            .success()
+            .stdout("a, a, a\n")
+            .stderr("");
+    }
+
+    // If you pass `--prefix` and `--suffix`, they go around every line,
+    // but not around the separators between them.
+    #[test]
+    fn it_adds_a_prefix_and_suffix_to_every_line() {
+        randline()
+            .args(["--prefix", "rm '", "--suffix", "'", "2"])
+            .write_stdin("a\na\n")
+            .assert()
+            .success()
+            .stdout("rm 'a'\nrm 'a'\n")
+            .stderr("");
+
+        randline()
+            .args([
+                "--prefix",
+                "<",
+                "--suffix",
+                ">",
+                "--output-separator",
+                ", ",
+                "3",
+            ])
+            .write_stdin("a\na\na\n")
+            .assert()
+            .success()
+            .stdout("<a>, <a>, <a>\n")
+            .stderr("");
+    }
+
+    // If the sample is empty, there's no output at all -- not even
+    // a trailing newline.
+    #[test]
+    fn it_prints_nothing_for_an_empty_sample() {
+        randline()
+            .args(["--output-separator", ", ", "3"])
+            .write_stdin("")
+            .assert()
+            .success()
+            .stdout("")
+            .stderr("");
+    }
+
+    // An empty input is an empty sample, whichever way we're reading
+    // and sampling it.
+    #[test]
+    fn it_prints_nothing_for_empty_input_in_every_mode() {
+        for args in [
+            vec!["3"],
+            vec!["--weighted", "3"],
+            vec!["--weighted", "--with-replacement", "3"],
+            vec!["--with-replacement", "3"],
+            vec!["--input-format", "nul", "3"],
+            vec!["--input-format", "jsonl", "3"],
+            vec!["--input-delimiter", "nul", "--output-delimiter", "nul", "3"],
+            vec!["10%"],
+            vec!["--sample-bytes", "100"],
+            vec!["--invert", "3"],
+            vec!["shuf"],
+        ] {
+            randline()
+                .args(&args)
+                .write_stdin("")
+                .assert()
+                .success()
+                .stdout("")
+                .stderr("");
+        }
+    }
+
+    // If you pass `--quiet-empty`, an empty input prints nothing at all,
+    // even in the modes which would usually print something, and it
+    // isn't an error.
+    #[test]
+    fn it_is_quiet_for_empty_input() {
+        for args in [
+            vec!["--count-only"],
+            vec!["--output-json-objects", "3"],
+            vec!["--resample", "2", "3"],
+            vec!["--explain", "3"],
+            vec!["--stats", "3"],
+            vec!["--weighted", "--explain", "3"],
+            vec!["--k-from-input"],
+        ] {
+            randline()
+                .arg("--quiet-empty")
+                .args(&args)
+                .write_stdin("")
+                .assert()
+                .success()
+                .stdout("")
+                .stderr("");
+        }
+    }
+
+    // An empty input is an error with `--min-count`, so you can't ask
+    // for it to be quiet as well.
+    #[test]
+    fn it_fails_if_you_pass_quiet_empty_and_min_count() {
+        randline()
+            .args(["--quiet-empty", "--min-count", "5", "3"])
+            .write_stdin("")
+            .assert()
+            .failure()
+            .code(1)
+            .stdout("")
+            .stderr("--quiet-empty can't be used with --min-count\n");
+    }
+
+    // `--quiet-empty` only applies to empty input; a single blank line
+    // is still a line.
+    #[test]
+    fn it_is_not_quiet_for_a_blank_line() {
+        randline()
+            .args(["--quiet-empty", "--count-only"])
+            .write_stdin("\n")
+            .assert()
+            .success()
+            .stdout("1\n")
+            .stderr("");
+    }
+
+    // If you pass `--window`, only lines from the last N seconds can
+    // be picked.
+    #[test]
+    fn it_only_picks_lines_in_the_window() {
+        let input: String = (0..100).map(|t| format!("{} line\n", t)).collect();
+
+        let output = randline()
+            .args(["--window", "3", "50"])
+            .write_stdin(input)
+            .output()
+            .unwrap();
+
+        assert!(output.status.success());
+
+        let mut lines: Vec<String> = String::from_utf8(output.stdout)
+            .unwrap()
+            .lines()
+            .map(|ln| ln.to_string())
+            .collect();
+        lines.sort();
+
+        assert_eq!(lines, vec!["96 line", "97 line", "98 line", "99 line"]);
+    }
+
+    // In windowed mode, a line without a timestamp is an error.
+    #[test]
+    fn it_fails_if_timestamp_missing() {
+        randline()
+            .args(["--window", "10"])
+            .write_stdin("1 a\nb\n")
+            .assert()
+            .failure()
+            .code(1)
+            .stdout("")
+            .stderr("Missing or invalid timestamp on line 2 of -\n");
+    }
+
+    // If you pass `--with-replacement`, it can pick the same line more
+    // than once, so it picks k lines even if there are fewer in the input.
+    #[test]
+    fn it_selects_k_lines_with_replacement() {
+        randline()
+            .args(["--with-replacement", "5"])
+            .write_stdin("a\na\n")
+            .assert()
+            .success()
+            .stdout("a\na\na\na\na\n")
+            .stderr("");
+    }
+
+    // If you pass `--weighted --with-replacement`, lines with weight 0
+    // are never picked.
+    #[test]
+    fn it_selects_weighted_lines_with_replacement() {
+        randline()
+            .args(["--weighted", "--with-replacement", "5"])
+            .write_stdin("0\tb\n1\ta\n0\tb\n")
+            .assert()
+            .success()
+            .stdout("1\ta\n1\ta\n1\ta\n1\ta\n1\ta\n")
+            .stderr("");
+    }
+
+    // If you pass `--strip`, leading and trailing whitespace is removed
+    // from each sampled line.
+    #[test]
+    fn it_strips_whitespace_from_output() {
+        randline()
+            .args(["--strip", "3"])
+            .write_stdin("  a  \n\ta\t\na   \n")
+            .assert()
+            .success()
+            .stdout("a\na\na\n")
+            .stderr("");
+    }
+
+    // If you pass `--trim-to`, long lines are cut short with an ellipsis,
+    // counting characters rather than bytes.
+    #[test]
+    fn it_trims_long_lines_in_the_output() {
+        randline()
+            .args(["--trim-to", "8", "--sort-output", "3"])
+            .write_stdin("short\nthis line is far too long\ncrème brûlée au caramel\n")
+            .assert()
+            .success()
+            .stdout("crème b…\nshort\nthis li…\n")
+            .stderr("");
+    }
+
+    // If a file is a directory, it fails with an error.
+    #[test]
+    fn it_fails_if_file_is_a_directory() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().to_str().unwrap();
+
+        randline()
+            .args(["--file", path])
+            .assert()
+            .failure()
+            .code(1)
+            .stdout("")
+            .stderr(format!("Unable to open {}: is a directory\n", path));
+    }
+
+    // It can read from a named pipe (FIFO), and waits for a slow writer
+    // to finish rather than treating it as an error.
+    #[cfg(unix)]
+    #[test]
+    fn it_reads_from_a_fifo() {
+        use std::io::Write;
+        use std::time::Duration;
+
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("pipe");
+
+        let status = std::process::Command::new("mkfifo")
+            .arg(&path)
+            .status()
+            .unwrap();
+        assert!(status.success());
+
+        let writer_path = path.clone();
+        let writer = std::thread::spawn(move || {
+            let mut fifo = std::fs::OpenOptions::new()
+                .write(true)
+                .open(writer_path)
+                .unwrap();
+
+            for _ in 0..3 {
+                fifo.write_all(b"a\n").unwrap();
+                fifo.flush().unwrap();
+                std::thread::sleep(Duration::from_millis(200));
+            }
+        });
+
+        randline()
+            .args(["--file", path.to_str().unwrap(), "5"])
+            .assert()
+            .success()
+            .stdout("a\na\na\n")
+            .stderr("");
+
+        writer.join().unwrap();
+    }
+
+    // If you pass `--fold-case`, lines that only differ in case are
+    // treated as duplicates.
+    #[test]
+    fn it_removes_case_insensitive_duplicates() {
+        for flag in ["--unique", "--approx-unique"] {
+            let output = randline()
+                .args([flag, "--fold-case", "5"])
+                .write_stdin("Foo\nfoo\nFOO\nbar\nBAR\n")
+                .output()
+                .unwrap();
+
+            assert!(output.status.success());
+
+            let mut lines: Vec<String> = String::from_utf8(output.stdout)
+                .unwrap()
+                .lines()
+                .map(|ln| ln.to_string())
+                .collect();
+            lines.sort();
+
+            assert_eq!(lines, vec!["Foo", "bar"]);
+        }
+    }
+
+    // If you pass `--retry-on-empty`, it waits for an empty file to
+    // get some content.
+    #[test]
+    fn it_retries_an_empty_file() {
+        use std::time::Duration;
+
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("later.txt");
+        std::fs::write(&path, "").unwrap();
+
+        let writer_path = path.clone();
+        let writer = std::thread::spawn(move || {
+            std::thread::sleep(Duration::from_millis(300));
+            std::fs::write(writer_path, "a\na\n").unwrap();
+        });
+
+        randline()
+            .args([
+                "--retry-on-empty",
+                "10",
+                "--file",
+                path.to_str().unwrap(),
+                "5",
+            ])
+            .assert()
+            .success()
+            .stdout("a\na\n")
+            .stderr("");
+
+        writer.join().unwrap();
+    }
+
+    // If the file is still empty when we run out of time, it gives up
+    // with a warning.
+    #[test]
+    fn it_gives_up_retrying_an_empty_file() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("empty.txt");
+        std::fs::write(&path, "").unwrap();
+        let path = path.to_str().unwrap();
+
+        randline()
+            .args(["--retry-on-empty", "0.3", "--file", path])
+            .assert()
+            .success()
+            .stdout("")
+            .stderr(format!("{} was still empty after 0.3 seconds\n", path));
+    }
+
+    // `--retry-on-empty` only applies to files, so it's an error if
+    // we're reading from stdin.
+    #[test]
+    fn it_fails_if_retry_on_empty_is_used_with_stdin() {
+        for args in [
+            &["--retry-on-empty", "1"][..],
+            &["--retry-on-empty", "1", "--file", "-"],
+        ] {
+            randline()
+                .args(args)
+                .write_stdin("a\n")
+                .assert()
+                .failure()
+                .code(1)
+                .stdout("")
+                .stderr(
+                    "--retry-on-empty only applies to files, so it can't be used with stdin; \
+                     use --file\n",
+                );
+        }
+    }
+
+    // If you pass `--assume-count`, we work out k from that rather than
+    // the actual size of the input: 50% of 10 lines is 5 lines, even
+    // though there are really 100 lines.
+    #[test]
+    fn it_computes_k_from_the_assumed_count() {
+        let input: String = (1..=100).map(|i| format!("{}\n", i)).collect();
+
+        let output = randline()
+            .args(["--assume-count", "10", "50%"])
+            .write_stdin(input)
+            .output()
+            .unwrap();
+
+        assert!(output.status.success());
+        assert_eq!(String::from_utf8(output.stdout).unwrap().lines().count(), 5);
+    }
+
+    #[test]
+    fn it_fails_if_assume_count_is_used_with_a_count() {
+        randline()
+            .args(["--assume-count", "10", "5"])
+            .write_stdin("a\n")
+            .assert()
+            .failure()
+            .stderr("--assume-count can only be used when k is a fraction, or with --confidence\n");
+    }
+
+    // Run randline with stdin redirected from a file, like
+    // `randline … < file`, rather than piped in.
+    fn run_with_stdin_file(args: &[&str], contents: &str) -> std::process::Output {
+        let mut file = tempfile::NamedTempFile::new().unwrap();
+        std::io::Write::write_all(&mut file, contents.as_bytes()).unwrap();
+
+        std::process::Command::new(assert_cmd::cargo::cargo_bin("randline"))
+            .env_remove("HOME")
+            .args(args)
+            .stdin(std::fs::File::open(file.path()).unwrap())
+            .output()
+            .unwrap()
+    }
+
+    // If stdin is a file, we count the records and rewind it rather than
+    // buffering -- and we get the same sample as if it was piped in.
+    #[test]
+    fn it_samples_a_fraction_of_a_redirected_file() {
+        let input: String = (1..=20).map(|i| format!("{}\n", i)).collect();
+
+        for args in [["--seed", "1", "25%"], ["--seed", "1", "--invert"]] {
+            let redirected = run_with_stdin_file(&args, &input);
+
+            let piped = randline()
+                .args(args)
+                .write_stdin(input.clone())
+                .output()
+                .unwrap();
+
+            assert!(redirected.status.success());
+            assert_eq!(redirected.stdout, piped.stdout, "args = {:?}", args);
+        }
+
+        let output = run_with_stdin_file(&["25%"], &input);
+        assert_eq!(String::from_utf8(output.stdout).unwrap().lines().count(), 5);
+
+        let output = run_with_stdin_file(&["--invert", "5"], &input);
+        assert_eq!(
+            String::from_utf8(output.stdout).unwrap().lines().count(),
+            15
+        );
+    }
+
+    // If something has already read part of stdin, e.g. `(head -n 1;
+    // randline 50%) < file`, we rewind it to where we started rather
+    // than the start of the file.
+    #[cfg(unix)]
+    #[test]
+    fn it_rewinds_a_redirected_file_to_where_it_started() {
+        use std::io::{Seek, SeekFrom};
+
+        let mut file = tempfile::NamedTempFile::new().unwrap();
+        std::io::Write::write_all(&mut file, b"header\na\nb\nc\nd\n").unwrap();
+
+        for args in [["50%"], ["--invert"]] {
+            let mut stdin = std::fs::File::open(file.path()).unwrap();
+            stdin.seek(SeekFrom::Start(7)).unwrap();
+
+            let output = std::process::Command::new(assert_cmd::cargo::cargo_bin("randline"))
+                .env_remove("HOME")
+                .args(args)
+                .stdin(stdin)
+                .output()
+                .unwrap();
+
+            assert!(output.status.success());
+
+            let stdout = String::from_utf8(output.stdout).unwrap();
+            let expected_count = if args == ["50%"] { 2 } else { 3 };
+            assert_eq!(stdout.lines().count(), expected_count, "args = {:?}", args);
+            assert!(!stdout.contains("header"), "stdout = {:?}", stdout);
+        }
+    }
+
+    // If randline is built with `tracing`, it logs when it counts the
+    // records in stdin and rewinds it.
+    #[cfg(feature = "tracing")]
+    #[cfg(unix)]
+    #[test]
+    fn it_rewinds_a_redirected_file() {
+        let mut file = tempfile::NamedTempFile::new().unwrap();
+        std::io::Write::write_all(&mut file, b"a\nb\nc\nd\n").unwrap();
+
+        let output = std::process::Command::new(assert_cmd::cargo::cargo_bin("randline"))
+            .env_remove("HOME")
+            .env("RUST_LOG", "randline=info")
+            .env("NO_COLOR", "1")
+            .arg("50%")
+            .stdin(std::fs::File::open(file.path()).unwrap())
+            .output()
+            .unwrap();
+
+        assert!(output.status.success());
+
+        let stderr = String::from_utf8(output.stderr).unwrap();
+        assert!(
+            stderr.contains("counted the records in stdin, and rewound it")
+                && stderr.contains("records=4"),
+            "stderr = {:?}",
+            stderr
+        );
+    }
+
+    // If randline is built with `tracing`, the sample still goes to
+    // stdout, and the logs go to stderr.
+    //
+    // Note: like the other CLI tests, this runs the binary in `target`,
+    // so build it with `cargo build --features tracing` first.
+    #[cfg(feature = "tracing")]
+    #[test]
+    fn it_logs_with_tracing() {
+        let output = randline()
+            .env("RUST_LOG", "randline=info")
+            .env("NO_COLOR", "1")
+            .arg("2")
+            .write_stdin("a\na\na\n")
+            .output()
+            .unwrap();
+
+        assert!(output.status.success());
+        assert_eq!(output.stdout, b"a\na\n");
+
+        let stderr = String::from_utf8(output.stderr).unwrap();
+        assert!(stderr.contains("lines_read=3"), "stderr = {:?}", stderr);
+        assert!(stderr.contains("sample_size=2"), "stderr = {:?}", stderr);
+        assert!(stderr.contains("write"), "stderr = {:?}", stderr);
+    }
+
+    // If you pass `--bash-array`, the sample is printed as a Bash array
+    // assignment, with every line in single quotes.
+    #[test]
+    fn it_prints_a_bash_array() {
+        randline()
+            .args(["--bash-array", "lines", "--sort-output", "3"])
+            .write_stdin("it's a test\n$HOME\nplain\n")
+            .assert()
+            .success()
+            .stdout("lines=( '$HOME' 'it'\\''s a test' 'plain' )\n")
+            .stderr("");
+    }
+
+    // The output of `--bash-array` can be `eval`'d, and the array has
+    // exactly the lines of the sample.
+    #[cfg(unix)]
+    #[test]
+    fn it_prints_a_bash_array_that_can_be_evaluated() {
+        let dir = tempfile::tempdir().unwrap();
+        let input_path = dir.path().join("input.txt");
+        std::fs::write(&input_path, "it's a \"test\"\n$(echo oops) `x` \\n\n").unwrap();
+
+        let output = std::process::Command::new("bash")
+            .env_remove("HOME")
+            .arg("-c")
+            .arg(format!(
+                "eval \"$({} --bash-array lines --sort-output --file {} 2)\" && \
+                 printf '<%s>\\n' \"${{lines[@]}}\"",
+                assert_cmd::cargo::cargo_bin("randline").display(),
+                input_path.display()
+            ))
+            .output()
+            .unwrap();
+
+        assert!(output.status.success(), "output = {:?}", output);
+        assert_eq!(
+            String::from_utf8(output.stdout).unwrap(),
+            "<$(echo oops) `x` \\n>\n<it's a \"test\">\n"
+        );
+    }
+
+    // The name of the array has to be a valid shell variable name.
+    #[test]
+    fn it_fails_if_the_bash_array_name_is_invalid() {
+        randline()
+            .args(["--bash-array", "my-lines", "2"])
+            .assert()
+            .failure()
+            .code(1)
+            .stdout("")
+            .stderr("--bash-array expects a variable name, like `lines`\n");
+    }
+
+    // If you pass `--output-count-to`, the number of lines read goes to
+    // that file descriptor, and the sample goes to stdout as usual.
+    #[cfg(unix)]
+    #[test]
+    fn it_writes_the_count_to_a_file_descriptor() {
+        let dir = tempfile::tempdir().unwrap();
+        let sample_path = dir.path().join("sample.txt");
+
+        // Point fd 3 at the shell's stdout, which is a pipe we read from,
+        // and send randline's stdout to a file.
+        let output = std::process::Command::new("sh")
+            .env_remove("HOME")
+            .arg("-c")
+            .arg(format!(
+                "printf 'a\\na\\na\\na\\na\\n' | {} --output-count-to 3 2 3>&1 >{}",
+                assert_cmd::cargo::cargo_bin("randline").display(),
+                sample_path.display()
+            ))
+            .output()
+            .unwrap();
+
+        assert!(output.status.success());
+        assert_eq!(output.stdout, b"5\n");
+        assert_eq!(output.stderr, b"");
+        assert_eq!(std::fs::read_to_string(sample_path).unwrap(), "a\na\n");
+    }
+
+    // With `--flush-marker`, we write the count once we've read all the
+    // batches, including the markers.
+    #[cfg(unix)]
+    #[test]
+    fn it_writes_the_count_to_a_file_descriptor_with_flush_marker() {
+        let dir = tempfile::tempdir().unwrap();
+        let sample_path = dir.path().join("sample.txt");
+
+        let output = std::process::Command::new("sh")
+            .env_remove("HOME")
+            .arg("-c")
+            .arg(format!(
+                "printf 'a\\n---\\nb\\n' | {} --flush-marker --- --output-count-to 3 1 3>&1 >{}",
+                assert_cmd::cargo::cargo_bin("randline").display(),
+                sample_path.display()
+            ))
+            .output()
+            .unwrap();
+
+        assert!(output.status.success());
+        assert_eq!(output.stdout, b"3\n");
+        assert_eq!(output.stderr, b"");
+        assert_eq!(std::fs::read_to_string(sample_path).unwrap(), "a\n---\nb\n");
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn it_fails_if_the_count_file_descriptor_is_not_open() {
+        randline()
+            .args(["--output-count-to", "999"])
+            .write_stdin("a\n")
+            .assert()
+            .failure()
+            .code(1)
+            .stderr(
+                "Unable to write the count to file descriptor 999: \
+                 Bad file descriptor (os error 9)\n",
+            );
+    }
+
+    // If you pass `--combine-with`, we merge an earlier sample with a
+    // sample of the new input.  If k covers both populations, we keep
+    // every line from both.
+    #[test]
+    fn it_combines_with_an_earlier_sample() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("stage1.txt");
+        std::fs::write(&path, "a\nb\n").unwrap();
+
+        randline()
+            .args(["--combine-with", path.to_str().unwrap()])
+            .args(["--combine-population", "2", "--sort-output", "5"])
+            .write_stdin("c\nd\n")
+            .assert()
+            .success()
+            .stdout("a\nb\nc\nd\n")
+            .stderr("");
+    }
+
+    // Sampling in two stages and combining them picks every line about
+    // as often as sampling the whole input in a single pass.
+    #[test]
+    fn it_combines_stages_like_a_single_pass() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("stage1.txt");
+
+        let stage1_input: String = (0..4).map(|i| format!("{}\n", i)).collect();
+        let stage2_input: String = (4..12).map(|i| format!("{}\n", i)).collect();
+
+        let mut counts = [0; 12];
+        let iterations = 300;
+
+        for _ in 0..iterations {
+            let stage1 = randline()
+                .arg("3")
+                .write_stdin(stage1_input.clone())
+                .assert()
+                .success()
+                .get_output()
+                .stdout
+                .clone();
+            std::fs::write(&path, stage1).unwrap();
+
+            let stage2 = randline()
+                .args(["--combine-with", path.to_str().unwrap()])
+                .args(["--combine-population", "4", "3"])
+                .write_stdin(stage2_input.clone())
+                .assert()
+                .success()
+                .get_output()
+                .stdout
+                .clone();
+
+            let stage2 = String::from_utf8(stage2).unwrap();
+            assert_eq!(stage2.lines().count(), 3);
+
+            for line in stage2.lines() {
+                counts[line.parse::<usize>().unwrap()] += 1;
+            }
+        }
+
+        // A single pass picks each of the 12 lines with probability 3/12.
+        let expected = iterations as f64 * 3.0 / 12.0;
+
+        for (line, count) in counts.iter().enumerate() {
+            let ratio = *count as f64 / expected;
+            assert!(
+                ratio > 0.6 && ratio < 1.4,
+                "line {} was picked {} times, expected about {}",
+                line,
+                count,
+                expected
+            );
+        }
+    }
+
+    // The earlier sample has to have enough lines to combine with.
+    #[test]
+    fn it_fails_if_the_earlier_sample_is_too_small() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("stage1.txt");
+        std::fs::write(&path, "a\n").unwrap();
+        let path = path.to_str().unwrap();
+
+        randline()
+            .args(["--combine-with", path, "--combine-population", "100", "3"])
+            .write_stdin("c\nd\n")
+            .assert()
+            .failure()
+            .code(1)
+            .stdout("")
+            .stderr(format!(
+                "{} has 1 line(s), but it needs at least 3 to combine with a sample of 3\n",
+                path
+            ));
+    }
+
+    // If you pass `--indices-only`, we print the line numbers of the
+    // sample, which point at the same lines we'd pick without it.
+    #[test]
+    fn it_prints_the_indices_of_the_sample() {
+        let input: String = (1..=100).map(|i| format!("line-{}\n", i)).collect();
+
+        let run = |args: &[&str]| {
+            let output = randline()
+                .args(args)
+                .write_stdin(input.clone())
+                .assert()
+                .success()
+                .get_output()
+                .stdout
+                .clone();
+
+            String::from_utf8(output).unwrap()
+        };
+
+        let lines = run(&["--seed", "42", "--sort-output", "10"]);
+        let indices = run(&["--seed", "42", "--indices-only", "10"]);
+
+        let indices: Vec<usize> = indices.lines().map(|i| i.parse().unwrap()).collect();
+        assert!(indices.is_sorted());
+
+        let mut expected: Vec<&str> = lines.lines().collect();
+        let mut actual: Vec<String> = indices.iter().map(|i| format!("line-{}", i)).collect();
+        expected.sort();
+        actual.sort();
+        assert_eq!(actual, expected);
+    }
+
+    // The indices count every line of the input, including lines which
+    // were filtered out before sampling.
+    #[test]
+    fn it_prints_indices_in_the_original_input() {
+        randline()
+            .args(["--indices-only", "--unique", "5"])
+            .write_stdin("a\na\nb\na\nc\n")
+            .assert()
+            .success()
+            .stdout("1\n3\n5\n")
+            .stderr("");
+    }
+
+    // If you pass `--dedup-output`, a line picked more than once with
+    // replacement is only printed once.
+    #[test]
+    fn it_removes_duplicates_from_the_output() {
+        randline()
+            .args(["--with-replacement", "--dedup-output", "5"])
+            .write_stdin("a\n")
+            .assert()
+            .success()
+            .stdout("a\n")
+            .stderr("");
+
+        let output = randline()
+            .args(["--with-replacement", "--dedup-output", "100"])
+            .write_stdin("a\nb\nc\n")
+            .assert()
+            .success()
+            .get_output()
+            .stdout
+            .clone();
+
+        let mut lines: Vec<&str> = std::str::from_utf8(&output).unwrap().lines().collect();
+        lines.sort();
+        assert_eq!(lines, vec!["a", "b", "c"]);
+    }
+
+    // `--dedup-output` compares the lines that were picked, so identical
+    // lines in the input are collapsed in the sample too.
+    #[test]
+    fn it_removes_duplicate_input_lines_from_the_output() {
+        randline()
+            .args(["--dedup-output", "3"])
+            .write_stdin("a\na\na\n")
+            .assert()
+            .success()
+            .stdout("a\n")
+            .stderr("");
+    }
+
+    // With `--fold-case`, `--dedup-output` treats lines that only differ
+    // in case as the same line.
+    #[test]
+    fn it_folds_case_when_removing_duplicates_from_the_output() {
+        let output = randline()
+            .args(["--dedup-output", "--fold-case", "3"])
+            .write_stdin("a\nA\na\n")
+            .output()
+            .unwrap();
+
+        assert!(output.status.success());
+        assert_eq!(
+            String::from_utf8(output.stdout).unwrap().to_lowercase(),
+            "a\n"
+        );
+    }
+
+    // `--fold-case` only makes sense with an option that removes
+    // duplicates.
+    #[test]
+    fn it_fails_if_you_pass_fold_case_without_removing_duplicates() {
+        randline()
+            .args(["--fold-case"])
+            .assert()
+            .failure()
+            .code(1)
+            .stdout("")
+            .stderr(
+                "--fold-case can only be used with --unique, --approx-unique, \
+                 --dedup-adjacent or --dedup-output\n",
+            );
+    }
+
+    // If you pass `--capacity-hint`, it only changes how much memory we
+    // allocate for the reservoir, so we pick the same sample.
+    #[test]
+    fn it_picks_the_same_sample_with_a_capacity_hint() {
+        let input: String = (1..=1000).map(|i| format!("{}\n", i)).collect();
+
+        let sample = |args: &[&str]| {
+            randline()
+                .args(args)
+                .write_stdin(input.clone())
+                .assert()
+                .success()
+                .get_output()
+                .stdout
+                .clone()
+        };
+
+        for extra in [vec![], vec!["--weighted", "--default-weight", "1"]] {
+            let expected = sample(&[&extra[..], &["--seed", "42", "10"]].concat());
+
+            for hint in ["0", "1", "10", "100000", "18446744073709551615"] {
+                assert_eq!(
+                    sample(
+                        &[&extra[..], &["--seed", "42", "--capacity-hint", hint, "10"]].concat()
+                    ),
+                    expected
+                );
+            }
+        }
+    }
+
+    // If you pass `--flush-marker`, we sample k lines from each batch
+    // of input, and print the marker after each batch's sample.
+    #[test]
+    fn it_samples_each_batch_separately() {
+        randline()
+            .args(["--flush-marker", "---FLUSH---", "2"])
+            .write_stdin("a\na\na\na\n---FLUSH---\nb\nb\nb\n---FLUSH---\nc\n")
+            .assert()
+            .success()
+            .stdout("a\na\n---FLUSH---\nb\nb\n---FLUSH---\nc\n")
+            .stderr("");
+    }
+
+    // The batches use the same delimiter as any other output.
+    #[test]
+    fn it_uses_the_output_delimiter_with_flush_marker() {
+        randline()
+            .args(["--flush-marker", "FLUSH", "--input-format", "nul", "1"])
+            .write_stdin("a\nb\0FLUSH\0c\0")
+            .assert()
+            .success()
+            .stdout("a\nb\0FLUSH\0c\0")
+            .stderr("");
+
+        randline()
+            .args(["--flush-marker", "FLUSH", "--output-delimiter", "crlf", "1"])
+            .write_stdin("a\nFLUSH\nb\n")
+            .assert()
+            .success()
+            .stdout("a\r\nFLUSH\r\nb\r\n")
+            .stderr("");
+    }
+
+    // Each batch's sample is printed as soon as we see the marker, even
+    // if the input hasn't finished yet.
+    #[test]
+    fn it_prints_each_batch_when_it_sees_the_marker() {
+        use std::io::{BufRead, BufReader, Write};
+        use std::process::Stdio;
+
+        let mut child = std::process::Command::new(assert_cmd::cargo::cargo_bin("randline"))
+            .env_remove("HOME")
+            .args(["--flush-marker", "FLUSH", "1"])
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .spawn()
+            .unwrap();
+
+        let mut stdin = child.stdin.take().unwrap();
+        stdin.write_all(b"a\na\nFLUSH\n").unwrap();
+        stdin.flush().unwrap();
+
+        let mut stdout = BufReader::new(child.stdout.take().unwrap());
+        let mut line = String::new();
+
+        stdout.read_line(&mut line).unwrap();
+        assert_eq!(line, "a\n");
+
+        drop(stdin);
+        assert!(child.wait().unwrap().success());
+    }
+
+    // If you pass `--compare-seeds`, it reports how much the samples
+    // from different seeds overlap.
+    //
+    // If k is the same as the number of lines, every sample contains
+    // every line, so they overlap completely.
+    #[test]
+    fn it_compares_samples_from_different_seeds() {
+        randline()
+            .args(["--compare-seeds", "5", "3"])
+            .write_stdin("a\nb\nc\n")
+            .assert()
+            .success()
+            .stderr(
+                "Compared 5 samples of 3 line(s) from 3 line(s): \
+                 3 line(s) in every sample, 3 line(s) in at least one sample\n",
+            );
+    }
+
+    // If the samples are small compared to the input, they're unlikely
+    // to overlap completely.
+    #[test]
+    fn it_counts_partial_overlap_between_seeds() {
+        let input: String = (1..=1000).map(|i| format!("{}\n", i)).collect();
+
+        let output = randline()
+            .args(["--compare-seeds", "10", "5"])
+            .write_stdin(input)
+            .output()
+            .unwrap();
+
+        assert!(output.status.success());
+
+        let stderr = String::from_utf8(output.stderr).unwrap();
+        assert!(
+            stderr.starts_with("Compared 10 samples of 5 line(s) from 1000 line(s): "),
+            "stderr = {:?}",
+            stderr
+        );
+        assert!(
+            !stderr.contains(" 5 line(s) in every sample"),
+            "stderr = {:?}",
+            stderr
+        );
+    }
+
+    // The samples are compared by the text of each line, so a repeated
+    // line is only counted once.
+    #[test]
+    fn it_compares_repeated_lines_by_their_text() {
+        randline()
+            .args(["--compare-seeds", "5", "2"])
+            .write_stdin("a\na\na\na\n")
+            .assert()
+            .success()
+            .stdout("a\na\n")
+            .stderr(
+                "Compared 5 samples of 2 line(s) from 4 line(s): \
+                 1 line(s) in every sample, 1 line(s) in at least one sample\n",
+            );
+    }
+
+    // The first sample uses the seed from `--seed`, so it's the same as
+    // the sample you'd get without `--compare-seeds`.
+    #[test]
+    fn it_compares_seeds_starting_from_the_seed() {
+        let input: String = (1..=100).map(|i| format!("{}\n", i)).collect();
+
+        let expected = randline()
+            .args(["--seed", "7", "5"])
+            .write_stdin(input.clone())
+            .output()
+            .unwrap();
+
+        randline()
+            .args(["--seed", "7", "--compare-seeds", "3", "5"])
+            .write_stdin(input)
+            .assert()
+            .success()
+            .stdout(expected.stdout);
+    }
+
+    // If you pass `--csv`, the sample is written as a single-column CSV,
+    // with lines quoted where necessary.
+    #[test]
+    fn it_writes_the_sample_as_csv() {
+        randline()
+            .args(["--csv", "2"])
+            .write_stdin("a, \"b\"\na, \"b\"\n")
+            .assert()
+            .success()
+            .stdout("\"a, \"\"b\"\"\"\n\"a, \"\"b\"\"\"\n")
+            .stderr("");
+    }
+
+    // If you pass `--output-json-objects`, the sample is a JSON array of
+    // objects, which say where each line came from and its weight.
+    #[test]
+    fn it_writes_the_sample_as_json_objects() {
+        let output = randline()
+            .args(["--output-json-objects", "--weighted", "--sort-output", "2"])
+            .write_stdin("2\ta\n0\tb\n0.5\tc\n")
+            .output()
+            .unwrap();
+
+        assert!(output.status.success());
+        assert_eq!(output.stderr, b"");
+
+        let sample: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+
+        assert_eq!(
+            sample,
+            serde_json::json!([
+                {"line": "0.5\tc", "source": "-", "line_number": 3, "weight": 0.5},
+                {"line": "2\ta", "source": "-", "line_number": 1, "weight": 2.0},
+            ])
+        );
+    }
+
+    // If we're not weighted, the weight is `null`, and an empty sample
+    // is an empty array.
+    #[test]
+    fn it_writes_json_objects_without_weights() {
+        randline()
+            .args(["--output-json-objects", "1"])
+            .write_stdin("a\n")
+            .assert()
+            .success()
+            .stdout("[{\"line\":\"a\",\"line_number\":1,\"source\":\"-\",\"weight\":null}]\n")
+            .stderr("");
+
+        randline()
+            .args(["--output-json-objects", "1"])
+            .write_stdin("")
+            .assert()
+            .success()
+            .stdout("[]\n")
+            .stderr("");
+    }
+
+    // `--csv` chooses its own separators, so it can't be combined with
+    // `--output-separator`.
+    #[test]
+    fn it_fails_if_csv_and_output_separator() {
+        randline()
+            .args(["--csv", "--output-separator", ";"])
+            .assert()
+            .failure()
+            .code(1)
+            .stdout("")
+            .stderr("--csv can't be used with --output-separator\n");
+    }
+
+    // If you pass the same `--seed`, you get the same sample.
+    #[test]
+    fn it_is_reproducible_with_a_seed() {
+        let input: String = (1..=1000).map(|i| format!("{}\n", i)).collect();
+
+        let sample = |args: &[&str]| {
+            let output = randline()
+                .args(args)
+                .write_stdin(input.clone())
+                .output()
+                .unwrap();
+
+            assert!(output.status.success());
+            String::from_utf8(output.stdout).unwrap()
+        };
+
+        assert_eq!(
+            sample(&["--seed", "42", "10"]),
+            sample(&["--seed", "42", "10"])
+        );
+        assert_eq!(
+            sample(&["--seed", "42", "--weighted", "--default-weight", "1", "10"]),
+            sample(&["--seed", "42", "--weighted", "--default-weight", "1", "10"])
+        );
+    }
+
+    // If you pass `--representativeness`, we compare the mean of a field
+    // in the sample to the mean across the whole input.
+    #[test]
+    fn it_reports_the_representativeness_of_the_sample() {
+        let input: String = (1..=1000).map(|i| format!("{}\tline-{}\n", i, i)).collect();
+
+        // A uniform sample of the whole input has the population mean.
+        randline()
+            .args(["--representativeness", "1", "--sort-output", "1000"])
+            .write_stdin(input.clone())
+            .assert()
+            .success()
+            .stderr(
+                "Sample mean of field 1 is 500.500 (1000 line(s)); \
+                 population mean is 500.500 (1000 line(s)); z-score is undefined\n",
+            );
+
+        // A sample weighted by the field is skewed towards big values,
+        // so it's well outside the range we'd expect by chance.
+        let output = randline()
+            .args(["--representativeness", "1", "--weighted", "100"])
+            .write_stdin(input)
+            .assert()
+            .success()
+            .get_output()
+            .clone();
+
+        let stderr = String::from_utf8(output.stderr).unwrap();
+        assert!(
+            stderr.contains("population mean is 500.500 (1000 line(s))"),
+            "stderr = {:?}",
+            stderr
+        );
+        assert!(
+            stderr.ends_with("outside the 95% range\n"),
+            "stderr = {:?}",
+            stderr
+        );
+    }
+
+    // If you pass `--line-buffered`, we flush after every line, which
+    // doesn't change what we print.
+    #[test]
+    fn it_prints_the_same_output_when_line_buffered() {
+        for args in [
+            vec!["--seed", "1", "3"],
+            vec!["--seed", "1", "--output-separator", ", ", "3"],
+            vec!["--seed", "1", "--resample", "2", "3"],
+            vec!["--seed", "1", "--flush-marker", "--", "3"],
+            vec!["shuf", "--seed", "1"],
+        ] {
+            let input = "a\nb\nc\nd\ne\n--\nf\ng\n";
+
+            let expected = randline()
+                .args(&args)
+                .write_stdin(input)
+                .assert()
+                .success()
+                .get_output()
+                .stdout
+                .clone();
+
+            randline()
+                .args(&args)
+                .arg("--line-buffered")
+                .write_stdin(input)
+                .assert()
+                .success()
+                .stdout(expected)
+                .stderr("");
+        }
+    }
+
+    // Every source of entropy picks a valid sample.
+    #[test]
+    fn it_picks_a_sample_with_every_entropy_source() {
+        for entropy in ["default", "system", "time"] {
+            let output = randline()
+                .args(["--entropy", entropy, "--explain", "2"])
+                .write_stdin("a\nb\nc\n")
+                .assert()
+                .success()
+                .get_output()
+                .clone();
+
+            let stdout = String::from_utf8(output.stdout).unwrap();
+            let lines: std::collections::HashSet<&str> = stdout.lines().collect();
+            assert_eq!(lines.len(), 2, "entropy = {}", entropy);
+            assert!(lines.is_subset(&std::collections::HashSet::from(["a", "b", "c"])));
+
+            let stderr = String::from_utf8(output.stderr).unwrap();
+            assert!(stderr.contains("Seed: "), "stderr = {:?}", stderr);
+        }
+    }
+
+    // The entropy is only for picking a seed, so you can't pass a seed
+    // as well.
+    #[test]
+    fn it_fails_if_you_pass_entropy_and_a_seed() {
+        randline()
+            .args(["--entropy", "time", "--seed", "1", "2"])
+            .assert()
+            .failure()
+            .code(1)
+            .stdout("")
+            .stderr("--entropy can't be used with --seed\n");
+    }
+
+    // If you pass `--warmup`, we skip some random numbers before we start
+    // sampling, so the same seed picks a different sample.
+    #[test]
+    fn it_picks_a_different_sample_after_warmup() {
+        let input: String = (1..=1000).map(|i| format!("{}\n", i)).collect();
+
+        let sample = |args: &[&str]| {
+            let output = randline()
+                .args(args)
+                .write_stdin(input.clone())
+                .output()
+                .unwrap();
+
+            assert!(output.status.success());
+            String::from_utf8(output.stdout).unwrap()
+        };
+
+        assert_eq!(
+            sample(&["--seed", "42", "10"]),
+            sample(&["--seed", "42", "--warmup", "0", "10"])
+        );
+        assert_eq!(
+            sample(&["--seed", "42", "--warmup", "5", "10"]),
+            sample(&["--seed", "42", "--warmup", "5", "10"])
+        );
+        assert_ne!(
+            sample(&["--seed", "42", "10"]),
+            sample(&["--seed", "42", "--warmup", "5", "10"])
+        );
+    }
+
+    // The warmup has to be a whole number, and not so big that it takes
+    // a long time to throw the random numbers away.
+    #[test]
+    fn it_fails_if_warmup_is_invalid() {
+        for warmup in ["XXX", "-1", "1.5", "1000001", "18446744073709551615"] {
+            randline()
+                .args(["--warmup", warmup, "1"])
+                .write_stdin("a\n")
+                .assert()
+                .failure()
+                .code(1)
+                .stdout("")
+                .stderr("--warmup expects an integer between 0 and 1000000\n");
+        }
+    }
+
+    // Adding `--shuffle` changes the order of the sample, but not which
+    // lines are picked, because the shuffle uses its own random numbers.
+    #[test]
+    fn it_picks_the_same_lines_with_and_without_shuffle() {
+        let input: String = (1..=1000).map(|i| format!("{}\t{}\n", i, i)).collect();
+
+        let sorted_sample = |args: &[&str]| {
+            let output = randline()
+                .args(args)
+                .write_stdin(input.clone())
+                .output()
+                .unwrap();
+
+            assert!(output.status.success());
+
+            let mut lines: Vec<String> = String::from_utf8(output.stdout)
+                .unwrap()
+                .lines()
+                .map(|ln| ln.to_string())
+                .collect();
+            lines.sort();
+            lines
+        };
+
+        for args in [
+            vec!["--seed", "7", "20"],
+            vec!["--seed", "7", "--weighted", "20"],
+        ] {
+            let mut shuffled_args = args.clone();
+            shuffled_args.insert(0, "--shuffle");
+
+            assert_eq!(
+                sorted_sample(&args),
+                sorted_sample(&shuffled_args),
+                "args = {:?}",
+                args
+            );
+        }
+    }
+
+    // The seed has to be a non-negative integer.
+    #[test]
+    fn it_fails_if_seed_is_invalid() {
+        for seed in ["-1", "1.5", "XXX"] {
+            randline()
+                .args(["--seed", seed])
+                .assert()
+                .failure()
+                .code(1)
+                .stdout("")
+                .stderr("--seed expects a non-negative integer\n");
+        }
+    }
+
+    // If you pass `--input-format nul`, records are separated by NUL
+    // bytes, and the sample is printed the same way.
+    #[test]
+    fn it_reads_nul_delimited_input() {
+        randline()
+            .args(["--input-format", "nul", "3"])
+            .write_stdin("a\nb\0a\nb\0")
+            .assert()
+            .success()
+            .stdout("a\nb\0a\nb\0")
+            .stderr("");
+    }
+
+    // The input and output delimiters are independent, so you can read
+    // NUL-delimited records and print them on separate lines, or the
+    // other way round.
+    #[test]
+    fn it_uses_different_input_and_output_delimiters() {
+        randline()
+            .args([
+                "--input-delimiter",
+                "nul",
+                "--output-delimiter",
+                "newline",
+                "3",
+            ])
+            .write_stdin("a\0a\0a\0")
+            .assert()
+            .success()
+            .stdout("a\na\na\n")
+            .stderr("");
+
+        randline()
+            .args(["--output-delimiter", "nul", "3"])
+            .write_stdin("a\na\na\n")
+            .assert()
+            .success()
+            .stdout("a\0a\0a\0")
+            .stderr("");
+    }
+
+    #[test]
+    fn it_fails_if_output_delimiter_is_invalid() {
+        randline()
+            .args(["--output-delimiter", "tab"])
+            .assert()
+            .failure()
+            .code(1)
+            .stdout("")
+            .stderr("--output-delimiter expects one of: newline, nul, crlf\n");
+    }
+
+    // If you pass `--crlf`, records are split on `\r\n`, so there are
+    // no stray carriage returns in the sample, and the output uses
+    // `\r\n` as well, so it round-trips.
+    #[test]
+    fn it_round_trips_crlf_input() {
+        randline()
+            .args(["--crlf", "--sort-output", "5"])
+            .write_stdin("c\r\na\r\nb\r\n")
+            .assert()
+            .success()
+            .stdout("a\r\nb\r\nc\r\n")
+            .stderr("");
+    }
+
+    // With `--crlf`, a bare newline is part of the record, like a line
+    // break inside a spreadsheet cell.
+    #[test]
+    fn it_keeps_bare_newlines_in_crlf_records() {
+        randline()
+            .args(["--crlf", "--count-only"])
+            .write_stdin("a\nb\r\nc\r\n")
+            .assert()
+            .success()
+            .stdout("2\n")
+            .stderr("");
+
+        randline()
+            .args(["--input-delimiter", "crlf", "--output-delimiter", "newline"])
+            .args(["--sort-output", "5"])
+            .write_stdin("b\r\na\nb\r\n")
+            .assert()
+            .success()
+            .stdout("a\nb\nb\n")
+            .stderr("");
+    }
+
+    // You can print `\r\n` line endings whatever the input was.
+    #[test]
+    fn it_prints_crlf_line_endings() {
+        randline()
+            .args(["--output-delimiter", "crlf", "3"])
+            .write_stdin("a\na\n")
+            .assert()
+            .success()
+            .stdout("a\r\na\r\n")
+            .stderr("");
+    }
+
+    // If you pass `--fixed-width`, the input is split into records of
+    // that many characters, and the last record can be shorter.
+    #[test]
+    fn it_reads_fixed_width_records() {
+        randline()
+            .args(["--fixed-width", "4", "--sort-output", "10"])
+            .write_stdin("0003000100020004xy\n")
+            .assert()
+            .success()
+            .stdout("0001\n0002\n0003\n0004\nxy\n")
+            .stderr("");
+    }
+
+    #[test]
+    fn it_fails_if_you_pass_fixed_width_with_input_format() {
+        randline()
+            .args(["--fixed-width", "4", "--input-format", "nul"])
+            .write_stdin("aaaa")
+            .assert()
+            .failure()
+            .stderr("--fixed-width can't be used with --input-format or --jsonl\n");
+    }
+
+    // If you pass `--input-format auto`, we detect each format from the
+    // start of the input.
+    #[test]
+    fn it_detects_the_input_format() {
+        // NUL-delimited
+        randline()
+            .args(["--input-format", "auto", "3"])
+            .write_stdin("x y\0x y\0")
+            .assert()
+            .success()
+            .stdout("x y\0x y\0")
+            .stderr("");
+
+        // JSON Lines -- blank lines are skipped, and --validate is allowed
+        randline()
+            .args(["--input-format", "auto", "--validate", "3"])
+            .write_stdin("{\"a\": 1}\n\n{\"a\": 1}\n")
+            .assert()
+            .success()
+            .stdout("{\"a\": 1}\n{\"a\": 1}\n")
+            .stderr("");
+
+        // Newline-delimited
+        randline()
+            .args(["--input-format", "auto", "3"])
+            .write_stdin("x y\nx y\n")
+            .assert()
+            .success()
+            .stdout("x y\nx y\n")
+            .stderr("");
+    }
+
+    #[test]
+    fn it_fails_if_input_format_is_invalid() {
+        randline()
+            .args(["--input-format", "csv"])
+            .assert()
+            .failure()
+            .code(1)
+            .stdout("")
+            .stderr("--input-format expects one of: newline, nul, jsonl, auto\n");
+    }
+
+    // If you pass `--explain`, we describe how the sample was picked,
+    // including how many lines we picked from how many.
+    #[test]
+    fn it_explains_the_sample() {
+        randline()
+            .args(["--explain", "--seed", "5", "--unique", "2"])
+            .write_stdin("a\nb\nc\nd\na\n")
+            .assert()
+            .success()
+            .stderr(
+                "Algorithm: reservoir sampling (random keys) -- \
+                 every line has the same chance of being picked\n\
+                 Picked 2 of 4 line(s)\n\
+                 Each line had a 2/4 = 50.00% chance of being picked\n\
+                 Seed: 5\n",
+            );
+    }
+
+    // If you pass `--confidence` and `--margin`, k is the sample size
+    // you need for that confidence level and margin of error.
+    //
+    // For a population of 100, that's 80 lines at 95% confidence and
+    // a 5% margin of error.
+    #[test]
+    fn it_picks_enough_lines_for_the_confidence_level() {
+        let input: String = (1..=100).map(|i| format!("{}\n", i)).collect();
+
+        for args in [
+            ["--confidence", "95%", "--margin", "5%"],
+            ["--confidence", "0.95", "--margin", "0.05"],
+        ] {
+            let output = randline()
+                .args(args)
+                .write_stdin(input.clone())
+                .output()
+                .unwrap();
+
+            assert!(output.status.success());
+            assert_eq!(
+                String::from_utf8(output.stdout).unwrap().lines().count(),
+                80
+            );
+        }
+    }
+
+    #[test]
+    fn it_fails_if_confidence_without_margin() {
+        randline()
+            .args(["--confidence", "95%"])
+            .assert()
+            .failure()
+            .code(1)
+            .stdout("")
+            .stderr("--confidence and --margin have to be used together\n");
+    }
+
+    #[test]
+    fn it_fails_if_confidence_level_is_unsupported() {
+        randline()
+            .args(["--confidence", "42%", "--margin", "5%"])
+            .assert()
+            .failure()
+            .code(1)
+            .stdout("")
+            .stderr("--confidence has to be one of: 80%, 90%, 95%, 98%, 99%, 99.9%\n");
+    }
+
+    // Windows-style line endings are always removed.
+    #[test]
+    fn it_removes_crlf_line_endings() {
+        randline()
+            .args(["2"])
+            .write_stdin("a\r\na\r\n")
+            .assert()
+            .success()
+            .stdout("a\na\n")
+            .stderr("");
+    }
+
+    // If you pass `--strip-cr`, we also remove a carriage return at the
+    // end of the last line, or one left over from `\r\r\n`.  Carriage
+    // returns in the middle of a line are kept.
+    #[test]
+    fn it_strips_trailing_carriage_returns() {
+        randline()
+            .args(["2"])
+            .write_stdin("a\rb\r\r\na\rb\r")
+            .assert()
+            .success()
+            .stdout("a\rb\r\na\rb\r\n")
+            .stderr("");
+
+        randline()
+            .args(["--strip-cr", "2"])
+            .write_stdin("a\rb\r\r\na\rb\r")
+            .assert()
+            .success()
+            .stdout("a\rb\na\rb\n")
+            .stderr("");
+    }
+
+    // If you pass `--sample-bytes`, the output fits in that many bytes.
+    #[test]
+    fn it_stays_within_the_byte_budget() {
+        let input: String = (1..=1000).map(|i| format!("{}\n", i)).collect();
+
+        for budget in ["1", "10", "100", "1000"] {
+            let output = randline()
+                .args(["--sample-bytes", budget])
+                .write_stdin(input.clone())
+                .output()
+                .unwrap();
+
+            assert!(output.status.success());
+            assert!(
+                output.stdout.len() <= budget.parse().unwrap(),
+                "output = {:?}, budget = {}",
+                String::from_utf8(output.stdout),
+                budget
+            );
+        }
+    }
+
+    // The budget counts the whole of each line as we print it, including
+    // the source from `--with-source` and the output separator.
+    #[test]
+    fn it_counts_the_formatted_lines_in_the_byte_budget() {
+        // "-\ta\n" is 4 bytes, so it fits in 4 but not in 3.
+        randline()
+            .args(["--sample-bytes", "4", "--with-source"])
+            .write_stdin("a\n")
+            .assert()
+            .success()
+            .stdout("-\ta\n")
+            .stderr("");
+
+        randline()
+            .args(["--sample-bytes", "3", "--with-source"])
+            .write_stdin("a\n")
+            .assert()
+            .success()
+            .stdout("")
+            .stderr("");
+
+        // "ab, ab\n" is 7 bytes, so the second line doesn't fit in 6.
+        randline()
+            .args(["--sample-bytes", "6", "--output-separator", ", "])
+            .write_stdin("ab\nab\n")
+            .assert()
+            .success()
+            .stdout("ab\n")
+            .stderr("");
+    }
+
+    // Output formats that add quoting or line numbers can't be used with
+    // a byte budget, because we don't count what they add.
+    #[test]
+    fn it_fails_if_sample_bytes_is_used_with_another_output_format() {
+        for flag in [
+            vec!["--csv"],
+            vec!["--output-json-objects"],
+            vec!["--bash-array", "lines"],
+            vec!["--group-by", "x"],
+            vec!["--indices-only"],
+        ] {
+            randline()
+                .args(["--sample-bytes", "100"])
+                .args(&flag)
+                .write_stdin("a\n")
+                .assert()
+                .failure()
+                .code(1)
+                .stdout("")
+                .stderr(
+                    "--sample-bytes can't be used with --csv, --output-json-objects, \
+                     --bash-array, --group-by or --indices-only\n",
+                );
+        }
+    }
+
+    // If every line fits in the budget, we print every line.
+    #[test]
+    fn it_prints_every_line_if_they_fit_in_the_byte_budget() {
+        randline()
+            .args(["--sample-bytes", "6"])
+            .write_stdin("ab\nab\n")
+            .assert()
+            .success()
+            .stdout("ab\nab\n")
+            .stderr("");
+    }
+
+    // If there's a read error partway through the input, we exit with
+    // an error and print nothing.  Invalid UTF-8 is a read error.
+    #[test]
+    fn it_fails_on_a_read_error() {
+        let output = randline()
+            .args(["5"])
+            .write_stdin(b"a\na\n\xff\nb\n".to_vec())
+            .output()
+            .unwrap();
+
+        assert_eq!(output.status.code(), Some(1));
+        assert_eq!(output.stdout, b"");
+        assert!(String::from_utf8(output.stderr)
+            .unwrap()
+            .starts_with("Unable to read from stdin: "));
+    }
+
+    // If you pass `--partial-on-error`, we sample from the lines we read
+    // before the error, and print a warning.
+    #[test]
+    fn it_samples_the_lines_before_a_read_error() {
+        randline()
+            .args(["--partial-on-error", "5"])
+            .write_stdin(b"a\na\n\xff\nb\n".to_vec())
+            .assert()
+            .success()
+            .stdout("a\na\n")
+            .stderr(
+                "Unable to read from stdin: stream did not contain valid UTF-8; \
+                 using the lines read so far\n",
+            );
+    }
+
+    // If you pass `--lossy-utf8`, invalid UTF-8 is replaced with U+FFFD
+    // rather than being a read error, so we sample every line.
+    #[test]
+    fn it_replaces_invalid_utf8_with_lossy_utf8() {
+        randline()
+            .args(["--lossy-utf8", "--sort-output", "5"])
+            .write_stdin(b"a\nb\xffc\r\n\xe2\x82\n".to_vec())
+            .assert()
+            .success()
+            .stdout("a\nb\u{FFFD}c\n\u{FFFD}\n")
+            .stderr("");
+    }
+
+    // If you pass `--input-encoding`, we decode the input from that
+    // encoding, and print the sample as UTF-8.
+    #[test]
+    fn it_reads_input_in_another_encoding() {
+        randline()
+            .args(["--input-encoding", "latin1", "--sort-output", "5"])
+            .write_stdin(b"caf\xe9\nna\xefve\n".to_vec())
+            .assert()
+            .success()
+            .stdout("café\nnaïve\n");
+
+        randline()
+            .args(["--input-encoding", "klingon", "5"])
+            .assert()
+            .failure()
+            .stderr("--input-encoding doesn't know the encoding \"klingon\"\n");
+    }
+
+    // Files like `--exclude-file` are read in the same encoding as
+    // the input, so their lines match.
+    #[test]
+    fn it_reads_other_files_in_the_input_encoding() {
+        let dir = tempfile::tempdir().unwrap();
+        let exclude = dir.path().join("exclude.txt");
+        let include = dir.path().join("include.txt");
+        std::fs::write(&exclude, b"caf\xe9\n").unwrap();
+        std::fs::write(&include, b"caf\xe9\nna\xefve\n").unwrap();
+
+        randline()
+            .args(["--input-encoding", "latin1", "--sort-output", "5"])
+            .args(["--exclude-file", exclude.to_str().unwrap()])
+            .args(["--include-file", include.to_str().unwrap()])
+            .write_stdin(b"caf\xe9\nna\xefve\nplain\n".to_vec())
+            .assert()
+            .success()
+            .stdout("naïve\n");
+    }
+
+    // If you pass `--output-encoding`, we print the sample in that
+    // encoding, and reading it back with `--input-encoding` gets the
+    // original lines.
+    #[test]
+    fn it_writes_output_in_another_encoding() {
+        for (name, input) in [
+            ("latin1", "café\nnaïve\n"),
+            ("shift_jis", "日本\n東京\n"),
+            ("utf-16le", "café\n日本\n"),
+        ] {
+            let encoded = randline()
+                .args(["--output-encoding", name, "--sort-output", "5"])
+                .write_stdin(input)
+                .output()
+                .unwrap();
+
+            assert!(encoded.status.success(), "name = {}", name);
+            assert_ne!(encoded.stdout, input.as_bytes(), "name = {}", name);
+
+            randline()
+                .args(["--input-encoding", name, "--sort-output", "5"])
+                .write_stdin(encoded.stdout)
+                .assert()
+                .success()
+                .stdout(input);
+        }
+    }
+
+    // If a line has a character the output encoding can't represent,
+    // it's an error.
+    #[test]
+    fn it_fails_if_the_output_encoding_cant_represent_a_line() {
+        randline()
+            .args(["--output-encoding", "latin1", "5"])
+            .write_stdin("日本\n")
+            .assert()
+            .failure()
+            .stderr("Unable to write to stdout: can't write '日' (U+65E5) in windows-1252\n");
+    }
+
+    // `--lossy-utf8` works with every way of splitting the input.
+    #[test]
+    fn it_replaces_invalid_utf8_in_every_input_format() {
+        for (args, input, expected) in [
+            (
+                vec!["--input-format", "nul"],
+                b"\xffa\0b\0".to_vec(),
+                "b\0\u{FFFD}a\0",
+            ),
+            (
+                vec!["--crlf"],
+                b"\xffa\r\nb\r\n".to_vec(),
+                "b\r\n\u{FFFD}a\r\n",
+            ),
+            (
+                vec!["--fixed-width", "2"],
+                b"\xffab".to_vec(),
+                "b\n\u{FFFD}a\n",
+            ),
+        ] {
+            randline()
+                .args(["--lossy-utf8", "--sort-output"])
+                .args(&args)
+                .arg("5")
+                .write_stdin(input)
+                .assert()
+                .success()
+                .stdout(expected.to_string())
+                .stderr("");
+        }
+    }
+
+    // If you pass `--audit-log`, every run appends a JSON line saying
+    // when it ran, how the sample was picked and how big it was.
+    #[test]
+    fn it_appends_to_the_audit_log() {
+        let dir = tempfile::tempdir().unwrap();
+        let input_path = dir.path().join("input.txt");
+        let log_path = dir.path().join("audit.jsonl");
+        std::fs::write(&input_path, "a\nb\nc\nd\n").unwrap();
+
+        for _ in 0..2 {
+            randline()
+                .args(["--audit-log", log_path.to_str().unwrap()])
+                .args(["--file", input_path.to_str().unwrap(), "--seed", "42", "3"])
+                .assert()
+                .success()
+                .stderr("");
+        }
+
+        let log = std::fs::read_to_string(&log_path).unwrap();
+        let entries: Vec<serde_json::Value> = log
+            .lines()
+            .map(|ln| serde_json::from_str(ln).unwrap())
+            .collect();
+
+        assert_eq!(entries.len(), 2);
+
+        for entry in entries {
+            assert_eq!(entry["seed"], 42);
+            assert_eq!(entry["k"], 3);
+            assert_eq!(
+                entry["sources"],
+                serde_json::json!([input_path.to_str().unwrap()])
+            );
+            assert_eq!(entry["lines_read"], 4);
+            assert_eq!(entry["output_count"], 3);
+
+            let timestamp = entry["timestamp"].as_str().unwrap();
+            assert_eq!(timestamp.len(), 20, "timestamp = {:?}", timestamp);
+            assert!(timestamp.ends_with('Z'), "timestamp = {:?}", timestamp);
+        }
+    }
+
+    // If we can't write to the audit log, we don't print the sample.
+    #[test]
+    fn it_fails_if_it_cant_write_the_audit_log() {
+        let dir = tempfile::tempdir().unwrap();
+
+        let output = randline()
+            .args(["--audit-log", dir.path().to_str().unwrap(), "2"])
+            .write_stdin("a\nb\n")
+            .output()
+            .unwrap();
+
+        assert_eq!(output.status.code(), Some(1));
+        assert_eq!(output.stdout, b"");
+        assert!(String::from_utf8(output.stderr)
+            .unwrap()
+            .starts_with("Unable to write to the audit log "));
+    }
+
+    // If you pass `--max-lines`, we only read that many lines of input.
+    #[test]
+    fn it_only_reads_up_to_max_lines() {
+        let input: String = (1..=100).map(|i| format!("{}\n", i)).collect();
+
+        let output = randline()
+            .args(["--max-lines", "10", "100"])
+            .write_stdin(input)
+            .output()
+            .unwrap();
+
+        assert!(output.status.success());
+
+        let mut lines: Vec<usize> = String::from_utf8(output.stdout)
+            .unwrap()
+            .lines()
+            .map(|ln| ln.parse().unwrap())
+            .collect();
+        lines.sort();
+
+        assert_eq!(lines, (1..=10).collect::<Vec<usize>>());
+    }
+
+    // If you pass `--k-from-input`, the first line is k, and it isn't
+    // part of the sample.
+    #[test]
+    fn it_reads_k_from_the_first_line() {
+        let output = randline()
+            .args(["--k-from-input", "--sort-output"])
+            .write_stdin("3\na\nb\nc\nd\ne\n")
+            .output()
+            .unwrap();
+
+        assert!(output.status.success());
+
+        let stdout = String::from_utf8(output.stdout).unwrap();
+        let lines: Vec<&str> = stdout.lines().collect();
+
+        assert_eq!(lines.len(), 3);
+        assert!(!lines.contains(&"3"), "stdout = {:?}", stdout);
+    }
+
+    #[test]
+    fn it_fails_if_the_first_line_is_not_k() {
+        randline()
+            .arg("--k-from-input")
+            .write_stdin("")
+            .assert()
+            .failure()
+            .stderr("--k-from-input expects the first line to be k, but the input is empty\n");
+
+        for (input, first_line) in [("0\na\n", "0"), ("three\na\n", "three"), ("-1\na\n", "-1")] {
+            randline()
+                .arg("--k-from-input")
+                .write_stdin(input)
+                .assert()
+                .failure()
+                .stderr(format!(
+                    "--k-from-input expects the first line to be a positive integer, got {:?}\n",
+                    first_line
+                ));
+        }
+    }
+
+    #[test]
+    fn it_fails_if_you_pass_k_and_k_from_input() {
+        randline()
+            .args(["--k-from-input", "3"])
+            .write_stdin("3\na\n")
+            .assert()
+            .failure()
+            .stderr(
+                "--k-from-input can't be used with k, --confidence, --margin, --sample-bytes, \
+                 --score-field or --shuffle-only\n",
+            );
+    }
+
+    // If you pass `--min-count` and the input is smaller than that, it's
+    // an error, and we don't print a sample.
+    #[test]
+    fn it_fails_if_the_input_is_smaller_than_min_count() {
+        randline()
+            .args(["--min-count", "5", "2"])
+            .write_stdin("a\nb\nc\n")
+            .assert()
+            .failure()
+            .stdout("")
+            .stderr("The input has 3 line(s), but --min-count is 5\n");
+    }
+
+    // The minimum is checked against every line we read, before any
+    // filtering, and it doesn't depend on k.
+    #[test]
+    fn it_counts_every_line_for_min_count() {
+        randline()
+            .args(["--min-count", "3", "--unique", "5"])
+            .write_stdin("a\na\na\n")
+            .assert()
+            .success()
+            .stdout("a\n")
+            .stderr("");
+    }
+
+    // If you pass `--stats`, we print a JSON report to stderr, and the
+    // sample is printed as usual.
+    #[test]
+    fn it_prints_stats_as_json() {
+        let output = randline()
+            .args(["--stats", "--unique", "2"])
+            .write_stdin("aaa\naaa\naaa\naaa\n")
+            .output()
+            .unwrap();
+
+        assert!(output.status.success());
+        assert_eq!(output.stdout, b"aaa\n");
+
+        let stderr = String::from_utf8(output.stderr).unwrap();
+        let report: serde_json::Value = serde_json::from_str(&stderr).unwrap();
+
+        assert_eq!(report["lines_read"], 4);
+        assert_eq!(report["sample_size"], 1);
+        assert_eq!(report["mean_line_length"], 3.0);
+    }
+
+    // Every `--reservoir-impl` picks a sample of the right size.
+    #[test]
+    fn it_samples_with_every_reservoir_impl() {
+        for name in ["naive", "algo-l", "algo-r"] {
+            randline()
+                .args(["--reservoir-impl", name, "--sort-output", "3"])
+                .write_stdin("a\na\na\na\na\n")
+                .assert()
+                .success()
+                .stdout("a\na\na\n")
+                .stderr("");
+        }
+    }
+
+    // If randline was built without the clipboard feature, `--clipboard`
+    // is an error, and we don't print anything.
+    #[test]
+    #[cfg(not(feature = "clipboard"))]
+    fn it_fails_if_clipboard_is_not_available() {
+        randline()
+            .args(["--clipboard", "2"])
+            .write_stdin("a\na\n")
+            .assert()
+            .failure()
+            .stdout("")
+            .stderr("--clipboard isn't available; rebuild randline with `--features clipboard`\n");
+    }
+
+    // If there's no clipboard we can use (e.g. there's no display server
+    // in CI), we print a warning and fall back to printing the sample.
+    #[test]
+    #[cfg(feature = "clipboard")]
+    fn it_prints_the_sample_if_there_is_no_clipboard() {
+        let output = randline()
+            .args(["--clipboard", "2"])
+            .env_remove("DISPLAY")
+            .env_remove("WAYLAND_DISPLAY")
+            .write_stdin("a\na\n")
+            .output()
+            .unwrap();
+
+        assert!(output.status.success());
+        assert_eq!(output.stdout, b"a\na\n");
+        assert!(String::from_utf8(output.stderr)
+            .unwrap()
+            .starts_with("Unable to copy to the clipboard:"));
+    }
+
+    // If you pass `--limit-rate`, we write no more than that many lines
+    // per second.  At 20 lines per second, 6 lines take at least 0.25s,
+    // because the first line is written straight away.
+    #[test]
+    fn it_limits_the_output_rate() {
+        let started = std::time::Instant::now();
+
+        randline()
+            .args(["--shuffle-only", "--limit-rate", "20"])
+            .write_stdin("a\na\na\na\na\na\n")
+            .assert()
+            .success()
+            .stdout("a\na\na\na\na\na\n")
+            .stderr("");
+
+        assert!(
+            started.elapsed() >= std::time::Duration::from_millis(250),
+            "elapsed = {:?}",
+            started.elapsed()
+        );
+    }
+
+    #[test]
+    fn it_fails_if_you_pass_limit_rate_without_shuffle_only() {
+        randline()
+            .args(["--limit-rate", "20", "2"])
+            .write_stdin("a\n")
+            .assert()
+            .failure()
+            .stderr("--limit-rate can only be used with --shuffle-only\n");
+    }
+
+    // If you pass `--stable-by`, every line with the same ID gets the same
+    // decision, whatever order the lines are in.
+    #[test]
+    fn it_picks_the_same_ids_across_shuffled_inputs() {
+        let lines: Vec<String> = (0..200)
+            .flat_map(|user| (0..3).map(move |event| format!("user-{}\tevent-{}", user, event)))
+            .collect();
+
+        let selected_users = |input: String| -> Vec<String> {
+            let output = randline()
+                .args(["--stable-by", "1", "--seed", "7", "30%"])
+                .write_stdin(input)
+                .output()
+                .unwrap();
+
+            assert!(output.status.success());
+
+            let stdout = String::from_utf8(output.stdout).unwrap();
+            let mut users: Vec<String> = stdout
+                .lines()
+                .map(|ln| ln.split('\t').next().unwrap().to_string())
+                .collect();
+            users.sort();
+            users
+        };
+
+        let forwards = selected_users(lines.iter().map(|ln| format!("{}\n", ln)).collect());
+        let backwards = selected_users(lines.iter().rev().map(|ln| format!("{}\n", ln)).collect());
+
+        assert_eq!(forwards, backwards);
+
+        // Every selected user has all 3 of their lines picked.
+        let mut distinct = forwards.clone();
+        distinct.dedup();
+        assert_eq!(forwards.len(), distinct.len() * 3);
+        assert!(!distinct.is_empty() && distinct.len() < 200);
+    }
+
+    // Each `--hash-algo` always picks the same IDs -- if these change,
+    // people can't reproduce an old selection.  FNV is the default.
+    #[test]
+    fn it_picks_the_same_ids_with_each_hash_algorithm() {
+        let input: String = (0..10).map(|i| format!("user-{}\n", i)).collect();
+
+        for (args, expected) in [
+            (vec![], "user-1\nuser-3\nuser-7\nuser-8\nuser-9\n"),
+            (
+                vec!["--hash-algo", "fnv"],
+                "user-1\nuser-3\nuser-7\nuser-8\nuser-9\n",
+            ),
+            (
+                vec!["--hash-algo", "xxhash"],
+                "user-0\nuser-2\nuser-4\nuser-5\nuser-6\nuser-7\nuser-9\n",
+            ),
+            (
+                vec!["--hash-algo", "sha256"],
+                "user-3\nuser-4\nuser-5\nuser-6\nuser-8\nuser-9\n",
+            ),
+        ] {
+            randline()
+                .args(["--stable-by", "1", "50%"])
+                .args(args)
+                .write_stdin(input.clone())
+                .assert()
+                .success()
+                .stdout(expected)
+                .stderr("");
+        }
+    }
+
+    #[test]
+    fn it_fails_if_stable_by_is_used_with_a_count() {
+        randline()
+            .args(["--stable-by", "1", "5"])
+            .write_stdin("a\n")
+            .assert()
+            .failure()
+            .stderr("--stable-by can only be used when k is a fraction, e.g. 10%\n");
+    }
+
+    // The output is the same whatever size the output buffer is, including
+    // buffers which are smaller than a single line.
+    #[test]
+    fn it_writes_the_same_output_with_any_buffer_size() {
+        let mut lines: Vec<String> = (1..=1000).map(|i| format!("line {}\n", i)).collect();
+        let input = lines.concat();
+
+        lines.sort();
+        let expected = lines.concat();
+
+        for size in ["1", "7", "4096", "1048576"] {
+            randline()
+                .args(["--output-buffer-size", size, "--sort-output", "1000"])
+                .write_stdin(input.clone())
+                .assert()
+                .success()
+                .stdout(expected.clone())
+                .stderr("");
+        }
+    }
+
+    #[test]
+    fn it_fails_if_output_buffer_size_is_invalid() {
+        for size in ["0", "-1", "XXX"] {
+            randline()
+                .args(["--output-buffer-size", size])
+                .write_stdin("a\n")
+                .assert()
+                .failure()
+                .stderr("--output-buffer-size expects a positive integer\n");
+        }
+    }
+
+    // If you pass `--invert`, we print the lines that weren't picked.
+    #[test]
+    fn it_prints_the_lines_that_werent_picked() {
+        let input: String = (1..=10).map(|i| format!("{}\n", i)).collect();
 
-fn main() {
-    // Read the user's command line arguments (if any)
-    //
-    //   0 arguments  = get a single random line
-    //   1 argument k = get that number of lines
-    //  >1 arguments  = error
-    //
-    let args: Vec<_> = std::env::args().collect();
+        let output = randline()
+            .args(["--invert", "3"])
+            .write_stdin(input)
+            .output()
+            .unwrap();
 
-    let k = match args.len() {
-        1 => 1,
-        2 => match args[1].parse::<usize>() {
-            Ok(parsed_k) if parsed_k > 0 => parsed_k,
-            _ => {
-                eprintln!("Usage: randline [k]");
-                std::process::exit(1)
-            }
-        },
-        _ => {
-            eprintln!("Usage: randline [k]");
-            std::process::exit(1)
+        assert!(output.status.success());
+
+        let lines: Vec<usize> = String::from_utf8(output.stdout)
+            .unwrap()
+            .lines()
+            .map(|ln| ln.parse().unwrap())
+            .collect();
+
+        // The unpicked lines are printed in their original order
+        assert_eq!(lines.len(), 7);
+        assert!(lines.windows(2).all(|w| w[0] < w[1]), "lines = {:?}", lines);
+    }
+
+    // If you pass `--stable-order`, we print the same lines as without it,
+    // but in order of the random weights they were given, so the order is
+    // the same every time for a given seed.
+    #[test]
+    fn it_prints_the_sample_in_a_stable_order() {
+        let input: String = (1..=100).map(|i| format!("{}\n", i)).collect();
+
+        let run = |args: &[&str]| -> Vec<String> {
+            let output = randline()
+                .args(args)
+                .write_stdin(input.clone())
+                .output()
+                .unwrap();
+
+            assert!(output.status.success());
+            String::from_utf8(output.stdout)
+                .unwrap()
+                .lines()
+                .map(|ln| ln.to_string())
+                .collect()
+        };
+
+        let ordered = run(&["--seed", "1", "--stable-order", "10"]);
+        assert_eq!(
+            ordered,
+            ["42", "52", "95", "14", "37", "4", "5", "76", "85", "86"]
+        );
+        assert_eq!(ordered, run(&["--seed", "1", "--stable-order", "10"]));
+
+        let mut unordered = run(&["--seed", "1", "10"]);
+        let mut sorted = ordered.clone();
+        unordered.sort();
+        sorted.sort();
+        assert_eq!(sorted, unordered);
+    }
+
+    // `--stable-order` only works with the samplers that give each line
+    // a weight, so it's an error with any other way of picking lines.
+    #[test]
+    fn it_fails_if_stable_order_cant_be_used() {
+        for args in [
+            vec!["--per-file", "2"],
+            vec!["--resample", "2", "2"],
+            vec![
+                "--combine-with",
+                "Cargo.toml",
+                "--combine-population",
+                "10",
+                "2",
+            ],
+        ] {
+            randline()
+                .arg("--stable-order")
+                .args(&args)
+                .write_stdin("a\nb\nc\n")
+                .assert()
+                .failure()
+                .code(1)
+                .stdout("")
+                .stderr(
+                    "--stable-order can't be used with --shuffle, --shuffle-only, \
+                     --sort-output, --invert, --with-replacement, --window, --sample-bytes, \
+                     --score-field, --stable-by, --per-file, --resample, --combine-with \
+                     or --reservoir-impl\n",
+                );
         }
-    };
+    }
 
-    let lines = std::io::stdin().lock().lines().map(|line| match line {
-        Ok(ln) => ln,
-        Err(e) => {
-            eprintln!("Unable to read from stdin: {:?}", e);
-            std::process::exit(1)
+    // The progress bar is only drawn if stderr is a terminal, so if
+    // it's redirected, you just get the sample.
+    #[test]
+    fn it_hides_the_progress_bar_if_stderr_is_redirected() {
+        let mut file = tempfile::NamedTempFile::new().unwrap();
+        std::io::Write::write_all(&mut file, b"a\nb\nc\n").unwrap();
+
+        for args in [
+            vec!["--progress-bar", "--file", file.path().to_str().unwrap()],
+            vec!["--progress-bar"],
+        ] {
+            randline()
+                .args(args)
+                .args(["--sort-output", "5"])
+                .write_stdin("a\nb\nc\n")
+                .assert()
+                .success()
+                .stdout("a\nb\nc\n")
+                .stderr("");
         }
-    });
+    }
+
+    // `--flush-marker` prints each batch as soon as it's read, so there's
+    // no point where we've finished reading and can clear the bar.
+    #[test]
+    fn it_fails_if_you_pass_progress_bar_and_flush_marker() {
+        randline()
+            .args(["--progress-bar", "--flush-marker", "FLUSH", "1"])
+            .write_stdin("a\nFLUSH\n")
+            .assert()
+            .failure()
+            .code(1)
+            .stdout("")
+            .stderr(
+                "--progress-bar can't be used with --interactive, --two-pass or --flush-marker\n",
+            );
+    }
+
+    // If you pass `--balance-by`, lines with a rare key are more likely
+    // to be picked than lines with a common key.  Here 10% of the lines
+    // are "rare", but each key has the same total weight, so they should
+    // be about half the sample.
+    #[test]
+    fn it_oversamples_rare_keys_with_balance_by() {
+        let input: String = (1..=90)
+            .map(|i| format!("common {}\n", i))
+            .chain((1..=10).map(|i| format!("rare {}\n", i)))
+            .collect();
+
+        let mut rare_count = 0;
+
+        for seed in 0..20 {
+            let output = randline()
+                .args(["--balance-by", r"^(\w+)", "--seed", &seed.to_string(), "10"])
+                .write_stdin(input.clone())
+                .output()
+                .unwrap();
+
+            assert!(output.status.success());
 
-    let sample = sampling::reservoir_sample(lines, k);
+            let stdout = String::from_utf8(output.stdout).unwrap();
+            assert_eq!(stdout.lines().count(), 10);
+            rare_count += stdout.lines().filter(|ln| ln.starts_with("rare")).count();
+        }
 
-    for line in sample {
-        println!("{}", line);
+        // Without balancing, we'd expect about 20 of the 200 lines
+        // to be rare.
+        assert!(rare_count > 60, "rare_count = {}", rare_count);
     }
-}
 
-#[cfg(test)]
-mod cli_tests {
-    use assert_cmd::Command;
+    // If you pass `--two-pass`, we invert the sample of a file by reading
+    // it twice, rather than holding it in memory.  We print the same lines
+    // as the buffered version, in their original order.
+    #[test]
+    fn it_inverts_a_file_in_two_passes() {
+        let input: String = (1..=100).map(|i| format!("{}\n", i)).collect();
 
-    // Note: for the purposes of the CLI tests, I trust that the reservoir
-    // sampling code works correctly -- that's tested separately.  I'm just
-    // checking the CLI parses the options correctly, so all the input lines
-    // are the same for easy assertions.
+        let mut file = tempfile::NamedTempFile::new().unwrap();
+        std::io::Write::write_all(&mut file, input.as_bytes()).unwrap();
+        let path = file.path().to_str().unwrap();
 
-    // If you call `randline` without any arguments, it picks a single line.
+        for k in ["10", "25%"] {
+            let two_pass = randline()
+                .args(["--seed", "1", "--invert", "--two-pass", "--file", path, k])
+                .output()
+                .unwrap();
+
+            let buffered = randline()
+                .args(["--seed", "1", "--invert", k])
+                .write_stdin(input.clone())
+                .output()
+                .unwrap();
+
+            assert!(two_pass.status.success());
+            assert_eq!(two_pass.stdout, buffered.stdout, "k = {}", k);
+
+            let lines: Vec<usize> = String::from_utf8(two_pass.stdout)
+                .unwrap()
+                .lines()
+                .map(|ln| ln.parse().unwrap())
+                .collect();
+
+            assert_eq!(lines.len(), if k == "10" { 90 } else { 75 });
+            assert!(lines.windows(2).all(|w| w[0] < w[1]), "lines = {:?}", lines);
+        }
+    }
+
+    // `--two-pass` has to read the input twice, so it can't read stdin.
     #[test]
-    fn it_selects_a_single_line_if_no_arg() {
-        Command::cargo_bin("randline")
-            .unwrap()
-            .write_stdin("a\na\na\na\na\na\n")
+    fn it_needs_a_file_for_two_passes() {
+        randline()
+            .args(["--invert", "--two-pass", "3"])
+            .write_stdin("a\nb\nc\nd\n")
             .assert()
-            .success()
-            .stdout("a\n")
-            .stderr("");
+            .failure()
+            .stderr("--two-pass reads the input twice, so it can't read from stdin; use --file\n");
+
+        randline()
+            .args(["--two-pass", "--file", "Cargo.toml", "3"])
+            .assert()
+            .failure()
+            .stderr("--two-pass can only be used with --invert\n");
     }
 
-    // If you pass an argument `k` and there are more lines than `k`,
-    // it selects a subset of them.
+    // The counts of what we read are printed after the first pass, before
+    // we print any lines.
     #[test]
-    fn it_selects_k_lines_if_more_lines_than_k() {
-        Command::cargo_bin("randline")
-            .unwrap()
-            .arg("2")
-            .write_stdin("a\na\na\na\na\na\n")
+    fn it_prints_counts_in_two_pass_mode() {
+        let mut file = tempfile::NamedTempFile::new().unwrap();
+        std::io::Write::write_all(&mut file, b"a\nb\na\n").unwrap();
+        let path = file.path().to_str().unwrap();
+
+        randline()
+            .args(["--invert", "--two-pass", "--file", path])
+            .args(["--count-matches", "a", "--print-total-bytes", "1"])
             .assert()
             .success()
-            .stdout("a\na\n")
-            .stderr("");
+            .stderr("2 line(s) matched a\nRead 3 line(s), 6 byte(s)\n");
+
+        randline()
+            .args(["--invert", "--two-pass", "--stats", "--file", path, "1"])
+            .assert()
+            .failure()
+            .stderr(
+                "--two-pass can't be used with options that filter the input, like --unique, \
+                 or options that need the whole output, like --shuffle, --sort-output \
+                 or --stats\n",
+            );
     }
 
-    // If you pass an argument `k` and there are that number of lines,
-    // it selects all of them.
+    // If you pass `--shuffle-only`, every line is printed exactly once,
+    // in a random order, and k is ignored.  With a seed, the order is
+    // the same every time.
     #[test]
-    fn it_selects_k_lines_if_equal_lines_to_k() {
-        Command::cargo_bin("randline")
-            .unwrap()
-            .arg("2")
-            .write_stdin("a\na\n")
-            .assert()
-            .success()
-            .stdout("a\na\n")
-            .stderr("");
+    fn it_shuffles_every_line() {
+        let input: String = (1..=100).map(|i| format!("{}\n", i)).collect();
+
+        let shuffled = || {
+            let output = randline()
+                .args(["--shuffle-only", "--seed", "1", "5"])
+                .write_stdin(input.clone())
+                .output()
+                .unwrap();
+
+            assert!(output.status.success());
+
+            String::from_utf8(output.stdout)
+                .unwrap()
+                .lines()
+                .map(|ln| ln.parse().unwrap())
+                .collect::<Vec<usize>>()
+        };
+
+        let first = shuffled();
+        assert_eq!(first, shuffled());
+
+        let mut sorted = first.clone();
+        sorted.sort();
+        assert_eq!(sorted, (1..=100).collect::<Vec<usize>>());
+        assert_ne!(first, sorted);
     }
 
-    // If you pass an argument `k` and there are less lines than `k`,
-    // it selects all of them.
+    // If you pass `--shuffle-memory` and the input is bigger than that,
+    // it's shuffled via temporary files, which are deleted afterwards.
+    // Every line is still printed exactly once, and the order is the
+    // same every time with a seed.
     #[test]
-    fn it_selects_k_lines_if_less_lines_than_k() {
-        Command::cargo_bin("randline")
-            .unwrap()
-            .arg("5")
-            .write_stdin("a\na\n")
+    fn it_shuffles_input_bigger_than_the_memory_budget() {
+        let input: String = (1..=2000).map(|i| format!("{}\n", i)).collect();
+        let tmp = tempfile::tempdir().unwrap();
+
+        let shuffled = || {
+            let output = randline()
+                .args(["--shuffle-only", "--shuffle-memory", "4096", "--seed", "1"])
+                .env("TMPDIR", tmp.path())
+                .env("TMP", tmp.path())
+                .env("TEMP", tmp.path())
+                .write_stdin(input.clone())
+                .output()
+                .unwrap();
+
+            assert!(output.status.success(), "output = {:?}", output);
+            assert_eq!(output.stderr, b"");
+
+            String::from_utf8(output.stdout)
+                .unwrap()
+                .lines()
+                .map(|ln| ln.parse().unwrap())
+                .collect::<Vec<usize>>()
+        };
+
+        let first = shuffled();
+        assert_eq!(first, shuffled());
+
+        let mut sorted = first.clone();
+        sorted.sort();
+        assert_eq!(sorted, (1..=2000).collect::<Vec<usize>>());
+        assert_ne!(first, sorted);
+
+        assert_eq!(std::fs::read_dir(tmp.path()).unwrap().count(), 0);
+    }
+
+    // The output of `--shuffle-memory` uses the same delimiters as
+    // any other output.
+    #[test]
+    fn it_uses_the_output_separator_with_shuffle_memory() {
+        randline()
+            .args(["--shuffle-only", "--shuffle-memory", "1"])
+            .args(["--output-separator", ","])
+            .write_stdin("a\na\na\n")
             .assert()
             .success()
-            .stdout("a\na\n")
+            .stdout("a,a,a\n")
             .stderr("");
     }
 
-    // Passing a non-integer argument is an error.
     #[test]
-    fn it_fails_if_non_integer_argument() {
-        Command::cargo_bin("randline")
-            .unwrap()
-            .arg("XXX")
+    fn it_fails_if_you_pass_shuffle_memory_without_shuffle_only() {
+        randline()
+            .args(["--shuffle-memory", "1000", "5"])
             .assert()
             .failure()
             .code(1)
             .stdout("")
-            .stderr("Usage: randline [k]\n");
+            .stderr("--shuffle-memory can only be used with --shuffle-only\n");
     }
 
-    // Passing k=0 is an error.
+    // We check `--min-count` and print the counts once we've read the
+    // input, before we print any lines.
     #[test]
-    fn it_fails_if_k_equals_zero() {
-        Command::cargo_bin("randline")
-            .unwrap()
-            .arg("0")
+    fn it_checks_the_input_before_printing_with_shuffle_memory() {
+        randline()
+            .args([
+                "--shuffle-only",
+                "--shuffle-memory",
+                "1",
+                "--min-count",
+                "5",
+            ])
+            .write_stdin("a\na\na\n")
             .assert()
             .failure()
             .code(1)
             .stdout("")
-            .stderr("Usage: randline [k]\n");
+            .stderr("The input has 3 line(s), but --min-count is 5\n");
+
+        randline()
+            .args(["--shuffle-only", "--shuffle-memory", "1"])
+            .args(["--count-matches", "a", "--print-total-bytes"])
+            .write_stdin("a\nb\na\n")
+            .assert()
+            .success()
+            .stderr("2 line(s) matched a\nRead 3 line(s), 6 byte(s)\n");
     }
 
-    // Passing k<0 is an error.
+    // If you pass `--score-field`, each line is kept with a probability
+    // based on its score.  Here the linear transform means lines scoring
+    // below the threshold are never kept, and lines scoring above
+    // `threshold + scale` are always kept.
     #[test]
-    fn it_fails_if_k_negative() {
-        Command::cargo_bin("randline")
-            .unwrap()
-            .arg("-1")
+    fn it_keeps_lines_based_on_their_score() {
+        randline()
+            .args([
+                "--score-field",
+                "2",
+                "--score-transform",
+                "linear",
+                "--score-threshold",
+                "0.5",
+                "--score-scale",
+                "0.25",
+            ])
+            .write_stdin("a\t0.1\nb\t0.9\nc\t0.5\nb\t0.75\n")
+            .assert()
+            .success()
+            .stdout("b\t0.9\nb\t0.75\n")
+            .stderr("");
+    }
+
+    #[test]
+    fn it_fails_if_score_is_missing() {
+        randline()
+            .args(["--score-field", "2"])
+            .write_stdin("a\t0.1\nb\n")
             .assert()
             .failure()
             .code(1)
             .stdout("")
-            .stderr("Usage: randline [k]\n");
+            .stderr("Missing or invalid score on line 2 of -\n");
     }
 
-    // Passing more than one argument is an error.
+    // If you pass `--resample`, we print n samples of k lines, with
+    // a `---` line between each sample.
     #[test]
-    fn it_fails_if_too_many_args() {
-        Command::cargo_bin("randline")
+    fn it_prints_several_independent_samples() {
+        let input: String = (1..=100).map(|i| format!("{}\n", i)).collect();
+
+        let output = randline()
+            .args(["--resample", "4", "3"])
+            .write_stdin(input)
+            .output()
+            .unwrap();
+
+        assert!(output.status.success());
+
+        let stdout = String::from_utf8(output.stdout).unwrap();
+        let samples: Vec<&str> = stdout.split("---\n").collect();
+
+        assert_eq!(samples.len(), 4);
+
+        for sample in samples {
+            assert_eq!(sample.lines().count(), 3, "sample = {:?}", sample);
+        }
+    }
+
+    // The samples are separated with the same delimiter as any other
+    // output, e.g. NUL bytes if the input was NUL-delimited.
+    #[test]
+    fn it_uses_the_output_delimiter_when_resampling() {
+        randline()
+            .args(["--resample", "2", "--input-format", "nul", "1"])
+            .write_stdin("a\nb\0")
+            .assert()
+            .success()
+            .stdout("a\nb\0---\0a\nb\0")
+            .stderr("");
+
+        randline()
+            .args(["--resample", "2", "--output-delimiter", "crlf", "1"])
+            .write_stdin("a\n")
+            .assert()
+            .success()
+            .stdout("a\r\n---\r\na\r\n")
+            .stderr("");
+    }
+
+    // With `--sort-output`, each sample is sorted on its own.
+    #[test]
+    fn it_sorts_each_sample_when_resampling() {
+        let output = randline()
+            .args(["--resample", "5", "--sort-output", "3"])
+            .write_stdin("d\nc\nb\na\ne\n")
+            .output()
+            .unwrap();
+
+        assert!(output.status.success());
+
+        let stdout = String::from_utf8(output.stdout).unwrap();
+        let samples: Vec<&str> = stdout.split("---\n").collect();
+        assert_eq!(samples.len(), 5);
+
+        for sample in samples {
+            let lines: Vec<&str> = sample.lines().collect();
+            let mut sorted = lines.clone();
+            sorted.sort();
+            assert_eq!(lines, sorted, "sample = {:?}", sample);
+        }
+    }
+
+    // With `--shuffle`, each sample is shuffled on its own, so the same
+    // seed picks the same lines in a different order.
+    #[test]
+    fn it_shuffles_each_sample_when_resampling() {
+        let input: String = (1..=100).map(|i| format!("{}\n", i)).collect();
+
+        let resample = |args: &[&str]| {
+            let output = randline()
+                .args(["--seed", "1", "--resample", "3"])
+                .args(args)
+                .write_stdin(input.clone())
+                .output()
+                .unwrap();
+
+            assert!(output.status.success());
+            String::from_utf8(output.stdout).unwrap()
+        };
+
+        let unshuffled = resample(&["10"]);
+        let shuffled = resample(&["--shuffle", "10"]);
+        assert_ne!(unshuffled, shuffled);
+
+        let sorted_samples = |stdout: &str| -> Vec<Vec<String>> {
+            stdout
+                .split("---\n")
+                .map(|sample| {
+                    let mut lines: Vec<String> = sample.lines().map(String::from).collect();
+                    lines.sort();
+                    lines
+                })
+                .collect()
+        };
+        assert_eq!(sorted_samples(&unshuffled), sorted_samples(&shuffled));
+    }
+
+    // With `--resample`, we still print the number of matching lines.
+    #[test]
+    fn it_counts_matching_lines_when_resampling() {
+        randline()
+            .args(["--resample", "2", "--count-matches", "a", "3"])
+            .write_stdin("a\nb\nab\n")
+            .assert()
+            .success()
+            .stderr("2 line(s) matched a\n");
+    }
+
+    // If you pass `--sort-output`, the sample is printed in sorted order.
+    #[test]
+    fn it_sorts_the_output() {
+        randline()
+            .args(["--sort-output", "5"])
+            .write_stdin("pear\napple\nfig\nbanana\napple\n")
+            .assert()
+            .success()
+            .stdout("apple\napple\nbanana\nfig\npear\n")
+            .stderr("");
+    }
+
+    // If you pass `--locale`, the sample is sorted using that locale's
+    // collation, rather than byte order -- e.g. an accented "é" sorts
+    // next to "e", rather than after "z".
+    #[test]
+    fn it_sorts_the_output_with_a_locale() {
+        let input = "zebra\néclair\nEagle\napple\n";
+
+        randline()
+            .args(["--sort-output", "4"])
+            .write_stdin(input)
+            .assert()
+            .success()
+            .stdout("Eagle\napple\nzebra\néclair\n")
+            .stderr("");
+
+        randline()
+            .args(["--sort-output", "--locale", "en", "4"])
+            .write_stdin(input)
+            .assert()
+            .success()
+            .stdout("apple\nEagle\néclair\nzebra\n")
+            .stderr("");
+    }
+
+    #[test]
+    fn it_fails_if_you_pass_locale_without_sort_output() {
+        randline()
+            .args(["--locale", "en", "4"])
+            .write_stdin("a\nb\n")
+            .assert()
+            .failure()
+            .stderr("--locale can only be used with --sort-output\n");
+    }
+
+    // If you pass `--count-matches`, we print how many lines of input
+    // matched the pattern to stderr, and the sample is unaffected.
+    #[test]
+    fn it_counts_matching_lines() {
+        randline()
+            .args(["--count-matches", "^ERROR", "--sort-output", "4"])
+            .write_stdin("ERROR a\nERROR a\nINFO ERROR\nERROR a\n")
+            .assert()
+            .success()
+            .stdout("ERROR a\nERROR a\nERROR a\nINFO ERROR\n")
+            .stderr("3 line(s) matched ^ERROR\n");
+    }
+
+    // With `--count-only`, we still print the number of matching lines.
+    #[test]
+    fn it_counts_matching_lines_with_count_only() {
+        randline()
+            .args(["--count-only", "--count-matches", "a"])
+            .write_stdin("a\nb\nab\n")
+            .assert()
+            .success()
+            .stdout("3\n")
+            .stderr("2 line(s) matched a\n");
+    }
+
+    // If you pass `--print-total-bytes`, we print how many lines and
+    // bytes we read to stderr, including the delimiters.
+    #[test]
+    fn it_prints_the_total_bytes_read() {
+        randline()
+            .args(["--print-total-bytes", "--sort-output", "3"])
+            .write_stdin("apple\nbanana\ncherry\n")
+            .assert()
+            .success()
+            .stdout("apple\nbanana\ncherry\n")
+            .stderr("Read 3 line(s), 20 byte(s)\n");
+    }
+
+    // The byte count is for every input, and only counts the delimiters
+    // that are actually there.
+    #[test]
+    fn it_prints_the_total_bytes_read_from_files() {
+        let dir = tempfile::tempdir().unwrap();
+        let first = dir.path().join("first.txt");
+        let second = dir.path().join("second.txt");
+        std::fs::write(&first, "a\nbb\n").unwrap();
+        std::fs::write(&second, "ccc").unwrap();
+
+        randline()
+            .args(["count", "--print-total-bytes"])
+            .args(["--file", first.to_str().unwrap()])
+            .args(["--file", second.to_str().unwrap()])
+            .assert()
+            .success()
+            .stdout("3\n")
+            .stderr("Read 3 line(s), 8 byte(s)\n");
+    }
+
+    // The byte count is only for the input we're sampling from, not other
+    // files like `--force-include` or `--weights-file`.
+    #[test]
+    fn it_doesnt_count_bytes_from_other_files() {
+        let dir = tempfile::tempdir().unwrap();
+        let forced = dir.path().join("forced.txt");
+        let weights = dir.path().join("weights.txt");
+        std::fs::write(&forced, "forced line").unwrap();
+        std::fs::write(&weights, "1\n1\n").unwrap();
+
+        randline()
+            .args(["--print-total-bytes", "--sort-output", "3"])
+            .args(["--force-include", forced.to_str().unwrap()])
+            .write_stdin("a\nb\n")
+            .assert()
+            .success()
+            .stdout("a\nb\nforced line\n")
+            .stderr("Read 2 line(s), 4 byte(s)\n");
+
+        randline()
+            .args(["--print-total-bytes", "--sort-output", "--weighted", "2"])
+            .args(["--weights-file", weights.to_str().unwrap()])
+            .write_stdin("a\nb\n")
+            .assert()
+            .success()
+            .stdout("a\nb\n")
+            .stderr("Read 2 line(s), 4 byte(s)\n");
+    }
+
+    // With `--resample`, the input is only read once, so we only count
+    // its bytes once.
+    #[test]
+    fn it_prints_the_total_bytes_read_when_resampling() {
+        randline()
+            .args(["--print-total-bytes", "--resample", "3", "2"])
+            .write_stdin("apple\nbanana\ncherry\n")
+            .assert()
+            .success()
+            .stderr("Read 3 line(s), 20 byte(s)\n");
+    }
+
+    // `randline sample [k]` is the same as `randline [k]`.
+    #[test]
+    fn it_picks_a_sample_with_the_sample_subcommand() {
+        for args in [vec!["sample", "2"], vec!["2"]] {
+            randline()
+                .args(&args)
+                .write_stdin("a\na\na\n")
+                .assert()
+                .success()
+                .stdout("a\na\n")
+                .stderr("");
+        }
+    }
+
+    // `randline shuf` prints every line in a random order.
+    #[test]
+    fn it_shuffles_with_the_shuf_subcommand() {
+        let output = randline()
+            .args(["shuf", "--seed", "3"])
+            .write_stdin("1\n2\n3\n4\n5\n")
+            .output()
+            .unwrap();
+
+        assert!(output.status.success());
+
+        let mut lines: Vec<String> = String::from_utf8(output.stdout)
             .unwrap()
-            .args(&["1", "2", "3"])
+            .lines()
+            .map(|ln| ln.to_string())
+            .collect();
+        lines.sort();
+
+        assert_eq!(lines, vec!["1", "2", "3", "4", "5"]);
+    }
+
+    // `randline count` prints the number of lines.
+    #[test]
+    fn it_counts_with_the_count_subcommand() {
+        randline()
+            .args(["count", "--unique"])
+            .write_stdin("a\nb\na\n")
+            .assert()
+            .success()
+            .stdout("2\n")
+            .stderr("");
+    }
+
+    // If you pass `--echo-args`, we print the arguments and the resolved
+    // configuration as JSON to stderr, and then pick a sample as normal.
+    #[test]
+    fn it_echoes_the_args_and_resolved_k() {
+        let output = randline()
+            .args(["sample", "--echo-args", "--seed", "1", "3"])
+            .write_stdin("a\na\na\n")
+            .output()
+            .unwrap();
+
+        assert!(output.status.success());
+        assert_eq!(output.stdout, b"a\na\na\n");
+
+        let stderr = String::from_utf8(output.stderr).unwrap();
+        let config: serde_json::Value = serde_json::from_str(&stderr).unwrap();
+
+        assert_eq!(
+            config["args"],
+            serde_json::json!(["sample", "--echo-args", "--seed", "1", "3"])
+        );
+        assert_eq!(config["k"], serde_json::json!(3));
+        assert_eq!(config["seed"], serde_json::json!(1));
+    }
+
+    #[test]
+    fn it_echoes_a_fractional_k() {
+        let output = randline()
+            .args(["--echo-args", "10%"])
+            .write_stdin("a\n")
+            .output()
+            .unwrap();
+
+        let stderr = String::from_utf8(output.stderr).unwrap();
+        let config: serde_json::Value = serde_json::from_str(&stderr).unwrap();
+
+        assert_eq!(config["k"], serde_json::json!({ "fraction": 0.1 }));
+    }
+
+    // A subcommand has to be the first argument.
+    #[test]
+    fn it_fails_if_subcommand_isnt_first() {
+        randline()
+            .args(["2", "sample"])
             .assert()
             .failure()
             .code(1)
             .stdout("")
-            .stderr("Usage: randline [k]\n");
+            .stderr("Usage: randline [sample | shuf | count] [options] [k]\n");
     }
 }
@@ -0,0 +1,1465 @@
+use icu_locale_core::Locale;
+use regex::Regex;
+use std::time::Duration;
+
+use crate::sample_size::{parse_proportion, parse_sample_size, SampleSize};
+use crate::{
+    confidence, dedup, diagnostics, encoding, entropy, input, output, sampling, scores, stable,
+};
+
+/// The options we've parsed from the command line and the config file,
+/// before we start reading the input.
+///
+/// Most of these are the option with the same name, e.g. `seed` is the
+/// value of `--seed`.
+#[derive(Clone)]
+pub struct Options {
+    pub k: Option<SampleSize>,
+    pub timeout: Option<Duration>,
+    pub group_by: Option<Regex>,
+    pub unique: bool,
+    pub approx_unique: bool,
+    pub dedup_adjacent: bool,
+    pub filter_size: usize,
+    pub files: Vec<String>,
+    pub with_source: bool,
+    pub jsonl: bool,
+    pub validate: bool,
+    pub count_only: bool,
+    pub weighted: bool,
+    pub default_weight: Option<f64>,
+    pub weight_regex: Option<Regex>,
+    pub weight_field: Option<usize>,
+    pub columns: Option<Vec<usize>>,
+    pub weights_file: Option<String>,
+    pub balance_by: Option<Regex>,
+    pub temperature: Option<f64>,
+    pub fail_fast: bool,
+    pub interactive: bool,
+    pub clipboard: bool,
+    pub limit_rate: Option<f64>,
+    pub line_buffered: bool,
+    pub stable_by: Option<usize>,
+    pub hash_algorithm: Option<stable::HashAlgorithm>,
+    pub output_buffer_size: usize,
+    pub assume_count: Option<usize>,
+    pub output_separator: Option<String>,
+    pub output_delimiter: Option<&'static str>,
+    pub prefix: String,
+    pub suffix: String,
+    pub csv: bool,
+    pub output_json_objects: bool,
+    pub bash_array: Option<String>,
+    pub seed: Option<u64>,
+    pub warmup: u64,
+    pub entropy: Option<entropy::Entropy>,
+    pub shuffle: bool,
+    pub input_format: input::InputFormat,
+    pub explain: bool,
+    pub confidence_level: Option<f64>,
+    pub margin: Option<f64>,
+    pub strip_cr: bool,
+    pub sample_bytes: Option<usize>,
+    pub partial_on_error: bool,
+    pub lossy_utf8: bool,
+    pub input_encoding: Option<&'static encoding_rs::Encoding>,
+    pub output_encoding: Option<&'static encoding_rs::Encoding>,
+    pub max_lines: Option<usize>,
+    pub invert: bool,
+    pub two_pass: bool,
+    pub shuffle_only: bool,
+    pub shuffle_memory: Option<usize>,
+    pub score_field: Option<usize>,
+    pub resample: Option<usize>,
+    pub flush_marker: Option<String>,
+    pub dedup_output: bool,
+    pub indices_only: bool,
+    pub combine_with: Option<String>,
+    pub combine_population: Option<usize>,
+    pub representativeness: Option<usize>,
+    pub sort_output: bool,
+    pub stable_order: bool,
+    pub locale: Option<Locale>,
+    pub echo_args: bool,
+    pub fixed_width: Option<usize>,
+    pub min_count: Option<usize>,
+    pub quiet_empty: bool,
+    pub capacity_hint: Option<usize>,
+    pub stats: bool,
+    pub print_total_bytes: bool,
+    pub progress_bar: bool,
+    pub output_count_to: Option<i32>,
+    pub audit_log: Option<String>,
+    pub reservoir_impl: sampling::ReservoirImpl,
+    pub count_matches: Option<Regex>,
+    pub per_file: bool,
+    pub k_from_input: bool,
+    pub score_options: scores::ScoreOptions,
+    pub window: Option<f64>,
+    pub with_replacement: bool,
+    pub strip: bool,
+    pub trim_to: Option<usize>,
+    pub fold_case: bool,
+    pub retry_on_empty: Option<Duration>,
+    pub no_color: bool,
+    pub max_runtime: Option<Duration>,
+    pub compare_seeds: Option<u64>,
+    pub exclude_files: Vec<String>,
+    pub include_files: Vec<String>,
+    pub force_include_files: Vec<String>,
+}
+
+impl Default for Options {
+    fn default() -> Self {
+        Options {
+            k: None,
+            timeout: None,
+            group_by: None,
+            unique: false,
+            approx_unique: false,
+            dedup_adjacent: false,
+            filter_size: dedup::DEFAULT_FILTER_SIZE,
+            files: Vec::new(),
+            with_source: false,
+            jsonl: false,
+            validate: false,
+            count_only: false,
+            weighted: false,
+            default_weight: None,
+            weight_regex: None,
+            weight_field: None,
+            columns: None,
+            weights_file: None,
+            balance_by: None,
+            temperature: None,
+            fail_fast: false,
+            interactive: false,
+            clipboard: false,
+            limit_rate: None,
+            line_buffered: false,
+            stable_by: None,
+            hash_algorithm: None,
+            output_buffer_size: output::DEFAULT_BUFFER_SIZE,
+            assume_count: None,
+            output_separator: None,
+            output_delimiter: None,
+            prefix: String::new(),
+            suffix: String::new(),
+            csv: false,
+            output_json_objects: false,
+            bash_array: None,
+            seed: None,
+            warmup: 0,
+            entropy: None,
+            shuffle: false,
+            input_format: input::InputFormat::Newline,
+            explain: false,
+            confidence_level: None,
+            margin: None,
+            strip_cr: false,
+            sample_bytes: None,
+            partial_on_error: false,
+            lossy_utf8: false,
+            input_encoding: None,
+            output_encoding: None,
+            max_lines: None,
+            invert: false,
+            two_pass: false,
+            shuffle_only: false,
+            shuffle_memory: None,
+            score_field: None,
+            resample: None,
+            flush_marker: None,
+            dedup_output: false,
+            indices_only: false,
+            combine_with: None,
+            combine_population: None,
+            representativeness: None,
+            sort_output: false,
+            stable_order: false,
+            locale: None,
+            echo_args: false,
+            fixed_width: None,
+            min_count: None,
+            quiet_empty: false,
+            capacity_hint: None,
+            stats: false,
+            print_total_bytes: false,
+            progress_bar: false,
+            output_count_to: None,
+            audit_log: None,
+            reservoir_impl: sampling::ReservoirImpl::Naive,
+            count_matches: None,
+            per_file: false,
+            k_from_input: false,
+            score_options: scores::ScoreOptions {
+                transform: scores::ScoreTransform::Sigmoid,
+                threshold: 0.0,
+                scale: 1.0,
+            },
+            window: None,
+            with_replacement: false,
+            strip: false,
+            trim_to: None,
+            fold_case: false,
+            retry_on_empty: None,
+            no_color: false,
+            max_runtime: None,
+            compare_seeds: None,
+            exclude_files: Vec::new(),
+            include_files: Vec::new(),
+            force_include_files: Vec::new(),
+        }
+    }
+}
+
+impl Options {
+    /// Parse a list of arguments on top of these options.  If an option
+    /// appears more than once, the last one wins.
+    pub fn parse_args(mut self, args: &[String]) -> Options {
+        let mut args = args.iter().cloned();
+
+        while let Some(arg) = args.next() {
+            self.parse_arg(arg, &mut args);
+        }
+
+        self
+    }
+
+    /// Parse a single argument: an option, which takes its value from
+    /// `args` if it has one, or the sample size.
+    ///
+    /// Exits with an error if the argument isn't one we recognise, or
+    /// the option doesn't have a valid value.
+    pub fn parse_arg(&mut self, arg: String, args: &mut impl Iterator<Item = String>) {
+        match arg.as_str() {
+            "--max-runtime" => {
+                self.max_runtime = match args.next().map(|t| t.parse::<f64>()) {
+                    Some(Ok(secs)) if secs > 0.0 && secs.is_finite() => {
+                        Some(Duration::from_secs_f64(secs))
+                    }
+                    _ => diagnostics::exit_with_error(
+                        "--max-runtime expects a positive number of seconds",
+                    ),
+                }
+            }
+            "--timeout" => {
+                self.timeout = match args.next().map(|t| t.parse::<f64>()) {
+                    Some(Ok(secs)) if secs > 0.0 && secs.is_finite() => {
+                        Some(Duration::from_secs_f64(secs))
+                    }
+                    _ => diagnostics::exit_with_error(
+                        "--timeout expects a positive number of seconds",
+                    ),
+                }
+            }
+            "--group-by" => {
+                self.group_by = match args.next().map(|p| Regex::new(&p)) {
+                    Some(Ok(pattern)) => Some(pattern),
+                    Some(Err(e)) => {
+                        diagnostics::exit_with_error(format!("Invalid regex for --group-by: {}", e))
+                    }
+                    None => diagnostics::exit_with_error("--group-by expects a regex"),
+                }
+            }
+            "--count-matches" => {
+                self.count_matches = match args.next().map(|p| Regex::new(&p)) {
+                    Some(Ok(pattern)) => Some(pattern),
+                    Some(Err(e)) => diagnostics::exit_with_error(format!(
+                        "Invalid regex for --count-matches: {}",
+                        e
+                    )),
+                    None => diagnostics::exit_with_error("--count-matches expects a regex"),
+                }
+            }
+            "--unique" => self.unique = true,
+            "--approx-unique" => self.approx_unique = true,
+            "--dedup-adjacent" => self.dedup_adjacent = true,
+            "--filter-size" => {
+                self.filter_size = match args.next().map(|n| n.parse::<usize>()) {
+                    Some(Ok(n)) if n > 0 => n,
+                    _ => diagnostics::exit_with_error("--filter-size expects a positive integer"),
+                }
+            }
+            "--file" => match args.next() {
+                Some(path) => self.files.push(path),
+                None => diagnostics::exit_with_error("--file expects a path"),
+            },
+            "--with-source" => self.with_source = true,
+            "--per-file" => self.per_file = true,
+            "--exclude-file" => match args.next() {
+                Some(path) => self.exclude_files.push(path),
+                None => diagnostics::exit_with_error("--exclude-file expects a path"),
+            },
+            "--include-file" => match args.next() {
+                Some(path) => self.include_files.push(path),
+                None => diagnostics::exit_with_error("--include-file expects a path"),
+            },
+            "--force-include" => match args.next() {
+                Some(path) => self.force_include_files.push(path),
+                None => diagnostics::exit_with_error("--force-include expects a path"),
+            },
+            "--jsonl" => self.jsonl = true,
+            "--validate" => self.validate = true,
+            "--count-only" => self.count_only = true,
+            "--weighted" => self.weighted = true,
+            "--default-weight" => {
+                self.default_weight = match args.next().map(|w| w.parse::<f64>()) {
+                    Some(Ok(w)) if w > 0.0 && w.is_finite() => Some(w),
+                    _ => diagnostics::exit_with_error("--default-weight expects a positive number"),
+                }
+            }
+            "--weight-regex" => {
+                self.weight_regex = match args.next().map(|p| Regex::new(&p)) {
+                    Some(Ok(pattern)) if pattern.captures_len() > 1 => Some(pattern),
+                    Some(Ok(_)) => diagnostics::exit_with_error(
+                        "--weight-regex needs a capture group for the weight",
+                    ),
+                    Some(Err(e)) => diagnostics::exit_with_error(format!(
+                        "Invalid regex for --weight-regex: {}",
+                        e
+                    )),
+                    None => diagnostics::exit_with_error("--weight-regex expects a regex"),
+                };
+                self.weighted = true;
+            }
+            "--weight-field" => {
+                self.weight_field = match args.next().map(|n| n.parse::<usize>()) {
+                    Some(Ok(n)) if n > 0 => Some(n),
+                    _ => diagnostics::exit_with_error(
+                        "--weight-field expects a field number, counting from 1",
+                    ),
+                };
+                self.weighted = true;
+            }
+            "--columns" => {
+                self.columns = match args.next().as_deref().and_then(output::parse_columns) {
+                    Some(columns) => Some(columns),
+                    None => diagnostics::exit_with_error(
+                        "--columns expects a comma-separated list of field numbers, like 2,3",
+                    ),
+                }
+            }
+            "--weights-file" => {
+                self.weights_file = match args.next() {
+                    Some(path) => Some(path),
+                    None => diagnostics::exit_with_error("--weights-file expects a path"),
+                };
+                self.weighted = true;
+            }
+            "--balance-by" => {
+                self.balance_by = match args.next().map(|p| Regex::new(&p)) {
+                    Some(Ok(pattern)) => Some(pattern),
+                    Some(Err(e)) => diagnostics::exit_with_error(format!(
+                        "Invalid regex for --balance-by: {}",
+                        e
+                    )),
+                    None => diagnostics::exit_with_error("--balance-by expects a regex"),
+                };
+                self.weighted = true;
+            }
+            "--temperature" => {
+                self.temperature = match args.next().map(|t| t.parse::<f64>()) {
+                    Some(Ok(t)) if t > 0.0 && t.is_finite() => Some(t),
+                    _ => diagnostics::exit_with_error("--temperature expects a positive number"),
+                }
+            }
+            "--fail-fast" => self.fail_fast = true,
+            "--interactive" => self.interactive = true,
+            "--clipboard" => self.clipboard = true,
+            "--output-buffer-size" => {
+                self.output_buffer_size = match args.next().map(|n| n.parse::<usize>()) {
+                    Some(Ok(n)) if n > 0 => n,
+                    _ => diagnostics::exit_with_error(
+                        "--output-buffer-size expects a positive integer",
+                    ),
+                }
+            }
+            "--limit-rate" => {
+                self.limit_rate = match args.next().map(|r| r.parse::<f64>()) {
+                    Some(Ok(r)) if r.is_finite() && r > 0.0 => Some(r),
+                    _ => diagnostics::exit_with_error("--limit-rate expects a positive number"),
+                }
+            }
+            "--output-separator" => match args.next() {
+                Some(separator) => self.output_separator = Some(separator),
+                None => diagnostics::exit_with_error("--output-separator expects a string"),
+            },
+            "--prefix" => match args.next() {
+                Some(p) => self.prefix = p,
+                None => diagnostics::exit_with_error("--prefix expects a string"),
+            },
+            "--suffix" => match args.next() {
+                Some(s) => self.suffix = s,
+                None => diagnostics::exit_with_error("--suffix expects a string"),
+            },
+            "--window" => {
+                self.window = match args.next().map(|w| w.parse::<f64>()) {
+                    Some(Ok(secs)) if secs >= 0.0 && secs.is_finite() => Some(secs),
+                    _ => diagnostics::exit_with_error(
+                        "--window expects a non-negative number of seconds",
+                    ),
+                }
+            }
+            "--with-replacement" => self.with_replacement = true,
+            "--strip" => self.strip = true,
+            "--trim-to" => {
+                self.trim_to = match args.next().map(|n| n.parse::<usize>()) {
+                    Some(Ok(n)) if n > 0 => Some(n),
+                    _ => diagnostics::exit_with_error("--trim-to expects a positive integer"),
+                }
+            }
+            "--csv" => self.csv = true,
+            "--output-json-objects" => self.output_json_objects = true,
+            "--bash-array" => {
+                self.bash_array = match args.next() {
+                    Some(name) if output::is_shell_variable_name(&name) => Some(name),
+                    _ => diagnostics::exit_with_error(
+                        "--bash-array expects a variable name, like `lines`",
+                    ),
+                }
+            }
+            "--seed" => {
+                self.seed = match args.next().map(|s| s.parse::<u64>()) {
+                    Some(Ok(s)) => Some(s),
+                    _ => diagnostics::exit_with_error("--seed expects a non-negative integer"),
+                }
+            }
+            // We throw away the random numbers one at a time, so we cap
+            // the warmup to keep it quick.
+            "--warmup" => {
+                self.warmup = match args.next().map(|n| n.parse::<u64>()) {
+                    Some(Ok(n)) if n <= 1_000_000 => n,
+                    _ => diagnostics::exit_with_error(
+                        "--warmup expects an integer between 0 and 1000000",
+                    ),
+                }
+            }
+            "--shuffle" => self.shuffle = true,
+            "--sort-output" => self.sort_output = true,
+            "--stable-order" => self.stable_order = true,
+            "--locale" => {
+                self.locale = match args.next().map(|l| l.parse::<Locale>()) {
+                    Some(Ok(l)) => Some(l),
+                    _ => diagnostics::exit_with_error("--locale expects a locale, e.g. en or sv"),
+                }
+            }
+            "--explain" => self.explain = true,
+            "--stats" => self.stats = true,
+            "--print-total-bytes" => self.print_total_bytes = true,
+            "--progress-bar" => self.progress_bar = true,
+            "--audit-log" => match args.next() {
+                Some(path) => self.audit_log = Some(path),
+                None => diagnostics::exit_with_error("--audit-log expects a path"),
+            },
+            "--output-count-to" => {
+                self.output_count_to = match args.next().map(|fd| fd.parse::<i32>()) {
+                    Some(Ok(fd)) if fd >= 0 => Some(fd),
+                    _ => diagnostics::exit_with_error(
+                        "--output-count-to expects a file descriptor number",
+                    ),
+                }
+            }
+            "--strip-cr" => self.strip_cr = true,
+            "--partial-on-error" => self.partial_on_error = true,
+            "--lossy-utf8" => self.lossy_utf8 = true,
+            "--input-encoding" => {
+                self.input_encoding = match args.next() {
+                    Some(name) => match encoding::for_name(&name) {
+                        Some(encoding) => Some(encoding),
+                        None => diagnostics::exit_with_error(format!(
+                            "--input-encoding doesn't know the encoding {:?}",
+                            name
+                        )),
+                    },
+                    None => diagnostics::exit_with_error("--input-encoding expects an encoding"),
+                }
+            }
+            "--output-encoding" => {
+                self.output_encoding = match args.next() {
+                    Some(name) => match encoding::for_name(&name) {
+                        Some(encoding) if encoding::can_encode(encoding) => Some(encoding),
+                        Some(encoding) => diagnostics::exit_with_error(format!(
+                            "--output-encoding can't write {}",
+                            encoding.name()
+                        )),
+                        None => diagnostics::exit_with_error(format!(
+                            "--output-encoding doesn't know the encoding {:?}",
+                            name
+                        )),
+                    },
+                    None => diagnostics::exit_with_error("--output-encoding expects an encoding"),
+                }
+            }
+            "--invert" => self.invert = true,
+            "--two-pass" => self.two_pass = true,
+            "--shuffle-only" => self.shuffle_only = true,
+            "--shuffle-memory" => {
+                self.shuffle_memory = match args.next().map(|n| n.parse::<usize>()) {
+                    Some(Ok(n)) if n > 0 => Some(n),
+                    _ => diagnostics::exit_with_error(
+                        "--shuffle-memory expects a positive number of bytes",
+                    ),
+                }
+            }
+            "--k-from-input" => self.k_from_input = true,
+            "--resample" => {
+                self.resample = match args.next().map(|n| n.parse::<usize>()) {
+                    Some(Ok(n)) if n > 0 => Some(n),
+                    _ => diagnostics::exit_with_error("--resample expects a positive integer"),
+                }
+            }
+            "--flush-marker" => match args.next() {
+                Some(marker) => self.flush_marker = Some(marker),
+                None => diagnostics::exit_with_error("--flush-marker expects a string"),
+            },
+            "--stable-by" => {
+                self.stable_by = match args.next().map(|n| n.parse::<usize>()) {
+                    Some(Ok(n)) if n > 0 => Some(n),
+                    _ => diagnostics::exit_with_error("--stable-by expects a positive integer"),
+                }
+            }
+            "--entropy" => {
+                self.entropy = match args.next().as_deref() {
+                    Some("default") => Some(entropy::Entropy::Default),
+                    Some("system") => Some(entropy::Entropy::System),
+                    Some("time") => Some(entropy::Entropy::Time),
+                    _ => diagnostics::exit_with_error(
+                        "--entropy expects one of: default, system, time",
+                    ),
+                }
+            }
+            "--hash-algo" => {
+                self.hash_algorithm = match args.next().as_deref() {
+                    Some("fnv") => Some(stable::HashAlgorithm::Fnv),
+                    Some("xxhash") => Some(stable::HashAlgorithm::XxHash),
+                    Some("sha256") => Some(stable::HashAlgorithm::Sha256),
+                    _ => diagnostics::exit_with_error(
+                        "--hash-algo expects one of: fnv, xxhash, sha256",
+                    ),
+                }
+            }
+            "--score-field" => {
+                self.score_field = match args.next().map(|n| n.parse::<usize>()) {
+                    Some(Ok(n)) if n > 0 => Some(n),
+                    _ => diagnostics::exit_with_error("--score-field expects a positive integer"),
+                }
+            }
+            "--representativeness" => {
+                self.representativeness = match args.next().map(|n| n.parse::<usize>()) {
+                    Some(Ok(n)) if n > 0 => Some(n),
+                    _ => diagnostics::exit_with_error(
+                        "--representativeness expects a positive integer",
+                    ),
+                }
+            }
+            "--score-threshold" => {
+                self.score_options.threshold = match args.next().map(|t| t.parse::<f64>()) {
+                    Some(Ok(t)) if t.is_finite() => t,
+                    _ => diagnostics::exit_with_error("--score-threshold expects a number"),
+                }
+            }
+            "--score-scale" => {
+                self.score_options.scale = match args.next().map(|s| s.parse::<f64>()) {
+                    Some(Ok(s)) if s.is_finite() && s > 0.0 => s,
+                    _ => diagnostics::exit_with_error("--score-scale expects a positive number"),
+                }
+            }
+            // This is deliberately undocumented -- it's for benchmarking
+            // and checking the different algorithms agree.
+            "--reservoir-impl" => {
+                self.reservoir_impl = match args.next().as_deref() {
+                    Some("naive") => sampling::ReservoirImpl::Naive,
+                    Some("algo-l") => sampling::ReservoirImpl::AlgorithmL,
+                    Some("algo-r") => sampling::ReservoirImpl::AlgorithmR,
+                    _ => diagnostics::exit_with_error(
+                        "--reservoir-impl expects one of: naive, algo-l, algo-r",
+                    ),
+                }
+            }
+            "--score-transform" => {
+                self.score_options.transform = match args.next().as_deref() {
+                    Some("sigmoid") => scores::ScoreTransform::Sigmoid,
+                    Some("linear") => scores::ScoreTransform::Linear,
+                    _ => diagnostics::exit_with_error(
+                        "--score-transform expects one of: sigmoid, linear",
+                    ),
+                }
+            }
+            "--fixed-width" => {
+                self.fixed_width = match args.next().map(|n| n.parse::<usize>()) {
+                    Some(Ok(n)) if n > 0 => Some(n),
+                    _ => diagnostics::exit_with_error("--fixed-width expects a positive integer"),
+                }
+            }
+            "--min-count" => {
+                self.min_count = match args.next().map(|n| n.parse::<usize>()) {
+                    Some(Ok(n)) if n > 0 => Some(n),
+                    _ => diagnostics::exit_with_error("--min-count expects a positive integer"),
+                }
+            }
+            "--quiet-empty" => self.quiet_empty = true,
+            "--dedup-output" => self.dedup_output = true,
+            "--line-buffered" => self.line_buffered = true,
+            "--indices-only" => self.indices_only = true,
+            "--combine-with" => match args.next() {
+                Some(path) => self.combine_with = Some(path),
+                None => diagnostics::exit_with_error("--combine-with expects a path"),
+            },
+            "--combine-population" => {
+                self.combine_population = match args.next().map(|n| n.parse::<usize>()) {
+                    Some(Ok(n)) => Some(n),
+                    _ => diagnostics::exit_with_error(
+                        "--combine-population expects a non-negative integer",
+                    ),
+                }
+            }
+            "--capacity-hint" => {
+                self.capacity_hint = match args.next().map(|n| n.parse::<usize>()) {
+                    Some(Ok(n)) => Some(n),
+                    _ => diagnostics::exit_with_error(
+                        "--capacity-hint expects a non-negative integer",
+                    ),
+                }
+            }
+            "--assume-count" => {
+                self.assume_count = match args.next().map(|n| n.parse::<usize>()) {
+                    Some(Ok(n)) => Some(n),
+                    _ => diagnostics::exit_with_error(
+                        "--assume-count expects a non-negative integer",
+                    ),
+                }
+            }
+            "--max-lines" => {
+                self.max_lines = match args.next().map(|n| n.parse::<usize>()) {
+                    Some(Ok(n)) if n > 0 => Some(n),
+                    _ => diagnostics::exit_with_error("--max-lines expects a positive integer"),
+                }
+            }
+            "--sample-bytes" => {
+                self.sample_bytes = match args.next().map(|n| n.parse::<usize>()) {
+                    Some(Ok(n)) if n > 0 => Some(n),
+                    _ => diagnostics::exit_with_error("--sample-bytes expects a positive integer"),
+                }
+            }
+            "--confidence" => {
+                self.confidence_level = match args.next().as_deref().and_then(parse_proportion) {
+                    Some(level) => Some(level),
+                    None => {
+                        diagnostics::exit_with_error("--confidence expects a percentage, e.g. 95%")
+                    }
+                }
+            }
+            "--margin" => {
+                self.margin = match args.next().as_deref().and_then(parse_proportion) {
+                    Some(m) => Some(m),
+                    None => diagnostics::exit_with_error("--margin expects a percentage, e.g. 5%"),
+                }
+            }
+            "--input-delimiter" => {
+                self.input_format = match args.next().as_deref() {
+                    Some("newline") => input::InputFormat::Newline,
+                    Some("nul") => input::InputFormat::Nul,
+                    Some("crlf") => input::InputFormat::Crlf,
+                    _ => diagnostics::exit_with_error(
+                        "--input-delimiter expects one of: newline, nul, crlf",
+                    ),
+                }
+            }
+            "--output-delimiter" => {
+                self.output_delimiter = match args.next().as_deref() {
+                    Some("newline") => Some("\n"),
+                    Some("nul") => Some("\0"),
+                    Some("crlf") => Some("\r\n"),
+                    _ => diagnostics::exit_with_error(
+                        "--output-delimiter expects one of: newline, nul, crlf",
+                    ),
+                }
+            }
+            "--crlf" => self.input_format = input::InputFormat::Crlf,
+            "--input-format" => {
+                self.input_format = match args.next().as_deref() {
+                    Some("newline") => input::InputFormat::Newline,
+                    Some("nul") => input::InputFormat::Nul,
+                    Some("jsonl") => input::InputFormat::Jsonl,
+                    Some("auto") => input::InputFormat::Auto,
+                    _ => diagnostics::exit_with_error(
+                        "--input-format expects one of: newline, nul, jsonl, auto",
+                    ),
+                }
+            }
+            "--fold-case" => self.fold_case = true,
+            "--retry-on-empty" => {
+                self.retry_on_empty = match args.next().map(|t| t.parse::<f64>()) {
+                    Some(Ok(secs)) if secs > 0.0 && secs.is_finite() => {
+                        Some(Duration::from_secs_f64(secs))
+                    }
+                    _ => diagnostics::exit_with_error(
+                        "--retry-on-empty expects a positive number of seconds",
+                    ),
+                }
+            }
+            "--compare-seeds" => {
+                self.compare_seeds = match args.next().map(|n| n.parse::<u64>()) {
+                    Some(Ok(n)) if n > 0 => Some(n),
+                    _ => diagnostics::exit_with_error("--compare-seeds expects a positive integer"),
+                }
+            }
+            "--no-color" => self.no_color = true,
+            "--echo-args" => self.echo_args = true,
+            _ if self.k.is_none() => match parse_sample_size(&arg) {
+                Some(parsed_k) => self.k = Some(parsed_k),
+                None => usage_error(),
+            },
+            _ => usage_error(),
+        }
+    }
+
+    /// The sample size, from k or from `--confidence` and `--margin`.
+    ///
+    /// If you pass `--confidence` and `--margin`, we work out k from
+    /// the size of the input, rather than taking it as an argument.
+    pub fn sample_size(&self) -> Result<SampleSize, String> {
+        match (self.k.clone(), self.confidence_level, self.margin) {
+            (k, None, None) => Ok(k.unwrap_or(SampleSize::Count(1))),
+            (None, Some(level), Some(margin)) => match confidence::z_score(level) {
+                Some(z) => Ok(SampleSize::Confidence { z, margin }),
+                None => Err(format!(
+                    "--confidence has to be one of: {}",
+                    confidence::supported_levels()
+                )),
+            },
+            (Some(_), _, _) => Err("k can't be used with --confidence or --margin".into()),
+            _ => Err("--confidence and --margin have to be used together".into()),
+        }
+    }
+
+    /// Check the options can be used together, or return an error which
+    /// says why not.
+    ///
+    /// This doesn't look at the input, so if you pass `--input-format
+    /// auto`, check again once you know the format.
+    pub fn check(&self) -> Result<(), String> {
+        if self.score_field.is_some() && (self.k.is_some() || self.confidence_level.is_some()) {
+            return Err("--score-field can't be used with k or --confidence".into());
+        }
+
+        if self.score_field.is_some()
+            && (self.weighted
+                || self.window.is_some()
+                || self.with_replacement
+                || self.sample_bytes.is_some()
+                || self.invert
+                || self.shuffle_only
+                || self.interactive
+                || self.compare_seeds.is_some())
+        {
+            return Err(
+                "--score-field can't be used with --weighted, --window, --with-replacement, \
+                 --sample-bytes, --invert, --shuffle-only, --interactive or --compare-seeds"
+                    .into(),
+            );
+        }
+
+        if self.resample.is_some()
+            && (self.weighted
+                || self.window.is_some()
+                || self.with_replacement
+                || self.sample_bytes.is_some()
+                || self.invert
+                || self.shuffle_only
+                || self.score_field.is_some()
+                || self.interactive
+                || self.compare_seeds.is_some()
+                || self.group_by.is_some()
+                || self.csv
+                || self.output_separator.is_some()
+                || self.explain)
+        {
+            return Err(
+                "--resample can only be used with uniform sampling and the default output".into(),
+            );
+        }
+
+        if self.resample.is_some() && !matches!(self.k, Some(SampleSize::Count(_)) | None) {
+            return Err("--resample can only be used when k is a count".into());
+        }
+
+        if self.sample_bytes.is_some() && (self.k.is_some() || self.confidence_level.is_some()) {
+            return Err("--sample-bytes can't be used with k or --confidence".into());
+        }
+
+        if self.sample_bytes.is_some()
+            && (self.weighted
+                || self.window.is_some()
+                || self.with_replacement
+                || self.interactive
+                || self.compare_seeds.is_some())
+        {
+            return Err(
+                "--sample-bytes can't be used with --weighted, --window, --with-replacement, \
+                 --interactive or --compare-seeds"
+                    .into(),
+            );
+        }
+
+        // The byte budget counts each line as we'd print it, so it can't
+        // account for the quoting, blank lines and line numbers these add.
+        if self.sample_bytes.is_some()
+            && (self.csv
+                || self.output_json_objects
+                || self.bash_array.is_some()
+                || self.group_by.is_some()
+                || self.indices_only)
+        {
+            return Err(
+                "--sample-bytes can't be used with --csv, --output-json-objects, --bash-array, \
+                 --group-by or --indices-only"
+                    .into(),
+            );
+        }
+
+        if self.k_from_input
+            && (self.k.is_some()
+                || self.confidence_level.is_some()
+                || self.margin.is_some()
+                || self.sample_bytes.is_some()
+                || self.score_field.is_some()
+                || self.shuffle_only)
+        {
+            return Err(
+                "--k-from-input can't be used with k, --confidence, --margin, --sample-bytes, \
+                 --score-field or --shuffle-only"
+                    .into(),
+            );
+        }
+
+        let k = self.sample_size()?;
+
+        if self.unique && self.approx_unique {
+            return Err("--unique and --approx-unique can't be used together".into());
+        }
+
+        if self.dedup_adjacent && (self.unique || self.approx_unique) {
+            return Err("--dedup-adjacent can't be used with --unique or --approx-unique".into());
+        }
+
+        if self.fold_case
+            && !self.unique
+            && !self.approx_unique
+            && !self.dedup_adjacent
+            && !self.dedup_output
+        {
+            return Err(
+                "--fold-case can only be used with --unique, --approx-unique, \
+                 --dedup-adjacent or --dedup-output"
+                    .into(),
+            );
+        }
+
+        if self.fixed_width.is_some()
+            && (self.input_format != input::InputFormat::Newline || self.jsonl)
+        {
+            return Err("--fixed-width can't be used with --input-format or --jsonl".into());
+        }
+
+        // We guess the format from the raw bytes, which doesn't work if
+        // they're in an encoding like UTF-16.
+        if self.input_format == input::InputFormat::Auto && self.input_encoding.is_some() {
+            return Err("--input-format auto can't be used with --input-encoding".into());
+        }
+
+        // We can only show how far through the input we are if we read it
+        // once, and we don't want the bar drawn over interactive mode, or
+        // over the batches that `--flush-marker` prints as it reads.
+        if self.progress_bar && (self.interactive || self.two_pass || self.flush_marker.is_some()) {
+            return Err(
+                "--progress-bar can't be used with --interactive, --two-pass or --flush-marker"
+                    .into(),
+            );
+        }
+
+        if self.output_encoding.is_some() && (self.count_only || self.interactive || self.clipboard)
+        {
+            return Err(
+                "--output-encoding can't be used with --count-only, --interactive or --clipboard"
+                    .into(),
+            );
+        }
+
+        if self.input_format == input::InputFormat::Auto && self.timeout.is_some() {
+            return Err("--input-format auto can't be used with --timeout".into());
+        }
+
+        // If you pass `--input-format jsonl`, that's the same as `--jsonl`.
+        let jsonl = self.jsonl || self.input_format == input::InputFormat::Jsonl;
+
+        // If we're guessing the format, we don't know if it's JSON Lines
+        // until we've detected it, when we check again.
+        if self.validate && !jsonl && self.input_format != input::InputFormat::Auto {
+            return Err("--validate can only be used with --jsonl".into());
+        }
+
+        if self.interactive && !cfg!(feature = "interactive") {
+            return Err(
+                "--interactive isn't available; rebuild randline with `--features interactive`"
+                    .into(),
+            );
+        }
+
+        if self.clipboard && !cfg!(feature = "clipboard") {
+            return Err(
+                "--clipboard isn't available; rebuild randline with `--features clipboard`".into(),
+            );
+        }
+
+        if self.stable_by.is_some() && !matches!(k, SampleSize::Fraction(_)) {
+            return Err("--stable-by can only be used when k is a fraction, e.g. 10%".into());
+        }
+
+        if self.stable_by.is_some()
+            && (self.weighted
+                || self.window.is_some()
+                || self.with_replacement
+                || self.invert
+                || self.shuffle_only
+                || self.per_file
+                || self.interactive
+                || self.compare_seeds.is_some()
+                || self.explain)
+        {
+            return Err(
+                "--stable-by can't be used with --weighted, --window, --with-replacement, \
+                 --invert, --shuffle-only, --per-file, --interactive, --compare-seeds or --explain"
+                    .into(),
+            );
+        }
+
+        if self.hash_algorithm.is_some() && self.stable_by.is_none() {
+            return Err("--hash-algo can only be used with --stable-by".into());
+        }
+
+        if self.assume_count.is_some()
+            && !matches!(k, SampleSize::Fraction(_) | SampleSize::Confidence { .. })
+        {
+            return Err(
+                "--assume-count can only be used when k is a fraction, or with --confidence".into(),
+            );
+        }
+
+        if self.assume_count.is_some()
+            && (self.invert || self.stable_by.is_some() || self.shuffle_only)
+        {
+            return Err(
+                "--assume-count can't be used with --invert, --stable-by or --shuffle-only".into(),
+            );
+        }
+
+        if self.limit_rate.is_some() && !self.shuffle_only {
+            return Err("--limit-rate can only be used with --shuffle-only".into());
+        }
+
+        if self.limit_rate.is_some() && (self.csv || self.clipboard) {
+            return Err("--limit-rate can't be used with --csv or --clipboard".into());
+        }
+
+        if self.line_buffered && (self.csv || self.clipboard || self.output_json_objects) {
+            return Err(
+                "--line-buffered can't be used with --csv, --clipboard or --output-json-objects"
+                    .into(),
+            );
+        }
+
+        if self.clipboard && (self.interactive || self.count_only || self.resample.is_some()) {
+            return Err(
+                "--clipboard can't be used with --interactive, --count-only or --resample".into(),
+            );
+        }
+
+        if self.csv && self.output_separator.is_some() {
+            return Err("--csv can't be used with --output-separator".into());
+        }
+
+        if self.output_delimiter.is_some() && (self.csv || self.output_separator.is_some()) {
+            return Err("--output-delimiter can't be used with --csv or --output-separator".into());
+        }
+
+        if self.output_json_objects
+            && (self.csv
+                || self.output_separator.is_some()
+                || self.output_delimiter.is_some()
+                || self.group_by.is_some()
+                || !self.prefix.is_empty()
+                || !self.suffix.is_empty()
+                || self.strip
+                || self.trim_to.is_some()
+                || self.columns.is_some()
+                || self.limit_rate.is_some()
+                || self.clipboard
+                || self.resample.is_some()
+                || self.interactive
+                || self.count_only)
+        {
+            return Err(
+                "--output-json-objects can't be used with --csv, --output-separator, \
+                 --output-delimiter, --group-by, --prefix, --suffix, --strip, --trim-to, \
+                 --columns, --limit-rate, --clipboard, --resample, --interactive or --count-only"
+                    .into(),
+            );
+        }
+
+        if self.bash_array.is_some()
+            && (self.csv
+                || self.output_json_objects
+                || self.output_separator.is_some()
+                || self.output_delimiter.is_some()
+                || self.group_by.is_some()
+                || self.limit_rate.is_some()
+                || self.line_buffered
+                || self.clipboard
+                || self.resample.is_some()
+                || self.flush_marker.is_some()
+                || self.interactive
+                || self.count_only)
+        {
+            return Err(
+                "--bash-array can't be used with --csv, --output-json-objects, \
+                 --output-separator, --output-delimiter, --group-by, --limit-rate, \
+                 --line-buffered, --clipboard, --resample, --flush-marker, \
+                 --interactive or --count-only"
+                    .into(),
+            );
+        }
+
+        if self.explain && self.interactive {
+            return Err("--explain can't be used with --interactive".into());
+        }
+
+        if self.stats && (self.count_only || self.interactive || self.resample.is_some()) {
+            return Err(
+                "--stats can't be used with --count-only, --interactive or --resample".into(),
+            );
+        }
+
+        if self.output_count_to.is_some() && !cfg!(unix) {
+            return Err("--output-count-to is only available on Unix".into());
+        }
+
+        if self.output_count_to.is_some()
+            && (self.count_only || self.interactive || self.resample.is_some())
+        {
+            return Err(
+                "--output-count-to can't be used with --count-only, --interactive or --resample"
+                    .into(),
+            );
+        }
+
+        if self.explain && self.compare_seeds.is_some() {
+            return Err("--explain can't be used with --compare-seeds".into());
+        }
+
+        if self.csv && self.group_by.is_some() {
+            return Err("--csv can't be used with --group-by".into());
+        }
+
+        if self.invert
+            && (self.weighted
+                || self.window.is_some()
+                || self.with_replacement
+                || self.sample_bytes.is_some()
+                || self.interactive
+                || self.compare_seeds.is_some())
+        {
+            return Err(
+                "--invert can't be used with --weighted, --window, --with-replacement, \
+                 --sample-bytes, --interactive or --compare-seeds"
+                    .into(),
+            );
+        }
+
+        if self.shuffle_only
+            && (self.weighted
+                || self.window.is_some()
+                || self.with_replacement
+                || self.sample_bytes.is_some()
+                || self.invert
+                || self.interactive
+                || self.compare_seeds.is_some())
+        {
+            return Err(
+                "--shuffle-only can't be used with --weighted, --window, --with-replacement, \
+                 --sample-bytes, --invert, --interactive or --compare-seeds"
+                    .into(),
+            );
+        }
+
+        if self.two_pass && !self.invert {
+            return Err("--two-pass can only be used with --invert".into());
+        }
+
+        // In two-pass mode, the second pass has to see exactly the same lines
+        // as the first, so we can't filter the input.  We also print lines as
+        // we read them, so we can't do anything that needs the whole output.
+        if self.two_pass
+            && (self.unique
+                || self.approx_unique
+                || self.dedup_adjacent
+                || !self.exclude_files.is_empty()
+                || !self.include_files.is_empty()
+                || self.k_from_input
+                || jsonl
+                || self.validate
+                || self.max_runtime.is_some()
+                || self.stable_by.is_some()
+                || self.score_field.is_some()
+                || self.per_file
+                || self.shuffle
+                || self.sort_output
+                || self.dedup_output
+                || self.group_by.is_some()
+                || self.csv
+                || self.output_json_objects
+                || self.bash_array.is_some()
+                || self.clipboard
+                || self.indices_only
+                || self.explain
+                || self.stats
+                || self.representativeness.is_some()
+                || self.audit_log.is_some())
+        {
+            return Err(
+                "--two-pass can't be used with options that filter the input, like --unique, \
+                 or options that need the whole output, like --shuffle, --sort-output \
+                 or --stats"
+                    .into(),
+            );
+        }
+
+        if self.two_pass && (self.files.is_empty() || self.files.iter().any(|f| f == "-")) {
+            return Err(
+                "--two-pass reads the input twice, so it can't read from stdin; use --file".into(),
+            );
+        }
+
+        if self.retry_on_empty.is_some() && self.files.iter().all(|f| f == "-") {
+            return Err(
+                "--retry-on-empty only applies to files, so it can't be used with stdin; use --file"
+                    .into(),
+            );
+        }
+
+        if self.per_file
+            && (self.window.is_some()
+                || self.with_replacement
+                || self.sample_bytes.is_some()
+                || self.invert
+                || self.shuffle_only
+                || self.score_field.is_some()
+                || self.resample.is_some()
+                || self.interactive
+                || self.compare_seeds.is_some())
+        {
+            return Err("--per-file can only be used with uniform or --weighted sampling".into());
+        }
+
+        if self.per_file && !matches!(k, SampleSize::Count(_)) {
+            return Err("--per-file can only be used when k is a count".into());
+        }
+
+        if self.flush_marker.is_some()
+            && (self.sample_bytes.is_some()
+                || self.invert
+                || self.shuffle_only
+                || self.score_field.is_some()
+                || self.stable_by.is_some()
+                || self.per_file
+                || self.resample.is_some()
+                || self.interactive
+                || self.compare_seeds.is_some()
+                || self.count_only
+                || self.explain
+                || self.stats
+                || self.sort_output
+                || self.group_by.is_some()
+                || self.csv
+                || self.output_json_objects)
+        {
+            return Err(
+                "--flush-marker can only be used with uniform, --weighted, --with-replacement \
+                 or --window sampling and the default output"
+                    .into(),
+            );
+        }
+
+        if self.flush_marker.is_some() && !matches!(k, SampleSize::Count(_)) {
+            return Err("--flush-marker can only be used when k is a count".into());
+        }
+
+        if self.dedup_output
+            && (self.resample.is_some()
+                || self.flush_marker.is_some()
+                || self.interactive
+                || self.count_only)
+        {
+            return Err(
+                "--dedup-output can't be used with --resample, --flush-marker, --interactive \
+                 or --count-only"
+                    .into(),
+            );
+        }
+
+        if self.indices_only
+            && (self.shuffle
+                || self.sort_output
+                || self.group_by.is_some()
+                || self.with_source
+                || self.csv
+                || self.output_json_objects
+                || self.resample.is_some()
+                || self.flush_marker.is_some()
+                || self.interactive
+                || self.count_only)
+        {
+            return Err(
+                "--indices-only can't be used with --shuffle, --sort-output, --group-by, \
+                 --with-source, --csv, --output-json-objects, --resample, --flush-marker, \
+                 --interactive or --count-only"
+                    .into(),
+            );
+        }
+
+        if self.indices_only && self.files.len() > 1 {
+            return Err("--indices-only can only be used with a single input".into());
+        }
+
+        // `--min-count` says an empty input is an error, and `--quiet-empty`
+        // says it isn't, so they can't both apply.
+        if self.quiet_empty && self.min_count.is_some() {
+            return Err("--quiet-empty can't be used with --min-count".into());
+        }
+
+        if self.entropy.is_some() && self.seed.is_some() {
+            return Err("--entropy can't be used with --seed".into());
+        }
+
+        if self.representativeness.is_some()
+            && (self.resample.is_some()
+                || self.flush_marker.is_some()
+                || self.interactive
+                || self.count_only)
+        {
+            return Err(
+                "--representativeness can't be used with --resample, --flush-marker, \
+                 --interactive or --count-only"
+                    .into(),
+            );
+        }
+
+        if self.combine_with.is_some() != self.combine_population.is_some() {
+            return Err("--combine-with and --combine-population go together".into());
+        }
+
+        if self.combine_with.is_some()
+            && (self.weighted
+                || self.with_replacement
+                || self.window.is_some()
+                || self.sample_bytes.is_some()
+                || self.score_field.is_some()
+                || self.stable_by.is_some()
+                || self.per_file
+                || self.invert
+                || self.shuffle_only
+                || self.compare_seeds.is_some()
+                || self.resample.is_some()
+                || self.flush_marker.is_some()
+                || self.interactive
+                || self.count_only)
+        {
+            return Err(
+                "--combine-with can only be used with uniform sampling without replacement".into(),
+            );
+        }
+
+        if self.combine_with.is_some() && !matches!(k, SampleSize::Count(_)) {
+            return Err("--combine-with can only be used when k is a count".into());
+        }
+
+        if !self.force_include_files.is_empty()
+            && (self.sample_bytes.is_some()
+                || self.score_field.is_some()
+                || self.stable_by.is_some()
+                || self.per_file
+                || self.combine_with.is_some()
+                || self.invert
+                || self.shuffle_only
+                || self.compare_seeds.is_some()
+                || self.resample.is_some()
+                || self.flush_marker.is_some()
+                || self.indices_only
+                || self.interactive
+                || self.count_only)
+        {
+            return Err(
+                "--force-include can't be used with --sample-bytes, --score-field, --stable-by, \
+                 --per-file, --combine-with, --invert, --shuffle-only, --compare-seeds, \
+                 --resample, --flush-marker, --indices-only, --interactive or --count-only"
+                    .into(),
+            );
+        }
+
+        if !self.force_include_files.is_empty() && !matches!(k, SampleSize::Count(_)) {
+            return Err("--force-include can only be used when k is a count".into());
+        }
+
+        if self.audit_log.is_some()
+            && (self.count_only
+                || self.resample.is_some()
+                || self.flush_marker.is_some()
+                || self.shuffle_memory.is_some()
+                || self.interactive)
+        {
+            return Err(
+                "--audit-log can't be used with --count-only, --resample, --flush-marker, \
+                 --shuffle-memory or --interactive"
+                    .into(),
+            );
+        }
+
+        if self.shuffle_memory.is_some() && !self.shuffle_only {
+            return Err("--shuffle-memory can only be used with --shuffle-only".into());
+        }
+
+        if self.shuffle_memory.is_some()
+            && (self.csv
+                || self.output_json_objects
+                || self.bash_array.is_some()
+                || self.group_by.is_some()
+                || self.clipboard
+                || self.dedup_output
+                || self.indices_only
+                || self.explain
+                || self.stats
+                || self.representativeness.is_some())
+        {
+            return Err(
+                "--shuffle-memory can't be used with --csv, --output-json-objects, --bash-array, \
+                 --group-by, --clipboard, --dedup-output, --indices-only, --explain, --stats \
+                 or --representativeness"
+                    .into(),
+            );
+        }
+
+        if self.sort_output && (self.shuffle || self.shuffle_only) {
+            return Err("--sort-output can't be used with --shuffle or --shuffle-only".into());
+        }
+
+        // The order comes from the random weights we give each line, so we
+        // need a sampler that gives every line in the sample a weight.
+        if self.stable_order
+            && (self.shuffle
+                || self.shuffle_only
+                || self.sort_output
+                || self.invert
+                || self.with_replacement
+                || self.window.is_some()
+                || self.sample_bytes.is_some()
+                || self.score_field.is_some()
+                || self.stable_by.is_some()
+                || self.per_file
+                || self.resample.is_some()
+                || self.combine_with.is_some()
+                || self.reservoir_impl != sampling::ReservoirImpl::Naive)
+        {
+            return Err(
+                "--stable-order can't be used with --shuffle, --shuffle-only, --sort-output, \
+                 --invert, --with-replacement, --window, --sample-bytes, --score-field, \
+                 --stable-by, --per-file, --resample, --combine-with or --reservoir-impl"
+                    .into(),
+            );
+        }
+
+        if self.locale.is_some() && !self.sort_output {
+            return Err("--locale can only be used with --sort-output".into());
+        }
+
+        if self.weights_file.is_some() && self.weight_regex.is_some() {
+            return Err("--weights-file can't be used with --weight-regex".into());
+        }
+
+        if self.weight_field.is_some()
+            && (self.weights_file.is_some() || self.weight_regex.is_some())
+        {
+            return Err(
+                "--weight-field can't be used with --weights-file or --weight-regex".into(),
+            );
+        }
+
+        if self.balance_by.is_some()
+            && (self.weights_file.is_some()
+                || self.weight_regex.is_some()
+                || self.weight_field.is_some()
+                || self.flush_marker.is_some())
+        {
+            return Err(
+                "--balance-by can't be used with --weights-file, --weight-regex, --weight-field \
+                 or --flush-marker"
+                    .into(),
+            );
+        }
+
+        if self.fail_fast && !self.weighted {
+            return Err("--fail-fast can only be used with --weighted".into());
+        }
+
+        if self.default_weight.is_some() && !self.weighted {
+            return Err("--default-weight can only be used with --weighted".into());
+        }
+
+        if self.temperature.is_some() && !self.weighted {
+            return Err("--temperature can only be used with --weighted".into());
+        }
+
+        if self.temperature.is_some() && self.per_file {
+            return Err("--temperature can't be used with --per-file".into());
+        }
+
+        if self.window.is_some() && self.weighted {
+            return Err("--window can't be used with --weighted".into());
+        }
+
+        if self.window.is_some() && self.with_replacement {
+            return Err("--window can't be used with --with-replacement".into());
+        }
+
+        if self.window.is_some() && !matches!(k, SampleSize::Count(_)) {
+            return Err("--window can only be used when k is a count".into());
+        }
+
+        Ok(())
+    }
+}
+
+fn usage_error() -> ! {
+    diagnostics::exit_with_error("Usage: randline [sample | shuf | count] [options] [k]")
+}
+
+#[cfg(test)]
+mod options_tests {
+    use super::*;
+
+    fn parse(args: &[&str]) -> Options {
+        let args: Vec<String> = args.iter().map(|a| a.to_string()).collect();
+        Options::default().parse_args(&args)
+    }
+
+    // `--no-color` only turns off color if it's an option, not if it's
+    // the value of another option.
+    #[test]
+    fn it_only_reads_no_color_as_an_option() {
+        assert!(parse(&["--no-color"]).no_color);
+
+        let options = parse(&["--prefix", "--no-color"]);
+        assert!(!options.no_color);
+        assert_eq!(options.prefix, "--no-color");
+    }
+}
@@ -0,0 +1,688 @@
+use regex::Regex;
+use std::borrow::Cow;
+use std::io::Write;
+use std::time::{Duration, Instant};
+
+use crate::fields::nth_field;
+use crate::input::Record;
+
+/// How big the buffer for writing to stdout is, unless you pass
+/// `--output-buffer-size`.  This is bigger than the default for
+/// `BufWriter` (8 KiB), because samples are often large.
+pub const DEFAULT_BUFFER_SIZE: usize = 64 * 1024;
+
+/// Group the lines in a sample by a key extracted with `pattern`.
+///
+/// The key is the first capture group, if the pattern has one, or
+/// the whole match otherwise.  Groups are returned in the order their
+/// key first appears in the sample, and lines keep their relative order
+/// within each group.
+///
+/// Lines which don't match the pattern are put in a group of their own,
+/// which always comes last.
+///
+///     group_by_key(vec!["a1", "b1", "a2", "c"], Regex::new("[a-z]\d"))
+///     => [["a1", "a2"], ["b1"], ["c"]]
+///
+pub fn group_by_key<T: AsRef<str>>(lines: Vec<T>, pattern: &Regex) -> Vec<Vec<T>> {
+    let mut groups: Vec<(String, Vec<T>)> = Vec::new();
+    let mut unmatched: Vec<T> = Vec::new();
+
+    for line in lines {
+        let key = pattern
+            .captures(line.as_ref())
+            .and_then(|c| c.get(1).or_else(|| c.get(0)))
+            .map(|m| m.as_str().to_string());
+
+        match key {
+            Some(key) => match groups.iter_mut().find(|(k, _)| *k == key) {
+                Some((_, group)) => group.push(line),
+                None => groups.push((key, vec![line])),
+            },
+            None => unmatched.push(line),
+        }
+    }
+
+    let mut result: Vec<Vec<T>> = groups.into_iter().map(|(_, g)| g).collect();
+
+    if !unmatched.is_empty() {
+        result.push(unmatched);
+    }
+
+    result
+}
+
+/// Write the lines of the sample to `out`, separated by `separator`.
+///
+/// If there are any lines, the output ends with `terminator`.  When the
+/// separator and terminator are both `\n`, that means every line ends
+/// with a newline.
+pub fn write_lines(
+    out: &mut impl Write,
+    lines: &[String],
+    separator: &str,
+    terminator: &str,
+) -> std::io::Result<()> {
+    if lines.is_empty() {
+        return Ok(());
+    }
+
+    write!(out, "{}{}", lines.join(separator), terminator)?;
+    out.flush()
+}
+
+/// Shorten `line` to at most `n` characters, for `--trim-to`.
+///
+/// If the line is longer than that, we keep the first `n - 1` characters
+/// and add an ellipsis (`…`), so it's exactly `n` characters long and you
+/// can tell it's been cut.  We count characters rather than bytes, so we
+/// never split a multi-byte character.
+pub fn trim_to(line: &str, n: usize) -> Cow<'_, str> {
+    match line.char_indices().nth(n) {
+        None => Cow::Borrowed(line),
+        Some(_) => {
+            let end = line.char_indices().nth(n - 1).map_or(0, |(i, _)| i);
+            Cow::Owned(format!("{}…", &line[..end]))
+        }
+    }
+}
+
+/// Parse a list of field numbers for `--columns`, like `2,3`.
+///
+/// Fields are counted from 1, and you can list a field more than once,
+/// or out of order.  Returns `None` if the list is empty, or any of the
+/// numbers aren't positive integers.
+pub fn parse_columns(list: &str) -> Option<Vec<usize>> {
+    list.split(',')
+        .map(|n| match n.trim().parse::<usize>() {
+            Ok(n) if n > 0 => Some(n),
+            _ => None,
+        })
+        .collect()
+}
+
+/// Pick the tab-separated fields of `line` listed in `columns`, counting
+/// from 1, and join them with tabs, in the order they're listed.
+///
+/// Like `cut`, fields the line doesn't have are left out, rather than
+/// being an error.
+///
+///     select_columns("1\tapple\tred", &[3, 2])  => "red\tapple"
+///     select_columns("1\tapple", &[2, 3])       => "apple"
+///
+pub fn select_columns(line: &str, columns: &[usize]) -> String {
+    columns
+        .iter()
+        .filter_map(|&n| nth_field(line, n))
+        .collect::<Vec<&str>>()
+        .join("\t")
+}
+
+/// Write the lines of the sample to `out` as a single-column CSV.
+///
+/// Lines which contain a comma, a quote or a newline are quoted, and any
+/// quotes inside them are doubled, as described in RFC 4180.  There's no
+/// header row.
+pub fn write_csv(out: &mut impl Write, lines: &[String]) -> csv::Result<()> {
+    let mut writer = csv::Writer::from_writer(out);
+
+    for line in lines {
+        writer.write_record([line])?;
+    }
+
+    writer.flush()?;
+    Ok(())
+}
+
+/// Write the lines of the sample to `out`, either as a CSV or with
+/// `write_lines`.
+pub fn write_sample(
+    out: &mut impl Write,
+    lines: &[String],
+    csv: bool,
+    separator: &str,
+    terminator: &str,
+) -> Result<(), Box<dyn std::error::Error>> {
+    if csv {
+        write_csv(out, lines)?;
+    } else {
+        write_lines(out, lines, separator, terminator)?;
+    }
+
+    Ok(())
+}
+
+/// Write the sample to `out` as a JSON array, with an object for each
+/// record that says where it came from, and its weight if we know it:
+///
+///     [{"line": "apple", "line_number": 1, "source": "fruit.txt", "weight": 2.5}]
+///
+/// The array is on a single line, followed by a newline.  If the sample
+/// is empty, it's an empty array.
+pub fn write_json_objects(out: &mut impl Write, records: &[Record]) -> std::io::Result<()> {
+    let objects: Vec<serde_json::Value> = records
+        .iter()
+        .map(|r| {
+            serde_json::json!({
+                "line": r.line,
+                "source": r.source.as_ref(),
+                "line_number": r.line_number,
+                "weight": r.weight,
+            })
+        })
+        .collect();
+
+    serde_json::to_writer(&mut *out, &objects)?;
+    writeln!(out)?;
+    out.flush()
+}
+
+/// Write the lines of the sample to `out` as a Bash array assignment,
+/// so you can `eval` it in a script:
+///
+///     name=( 'apple' 'it'\''s' )
+///
+/// Every line is in single quotes, so nothing inside it is expanded.
+/// The assignment is on a single line, followed by a newline, unless
+/// the lines themselves contain newlines.
+pub fn write_bash_array(out: &mut impl Write, name: &str, lines: &[String]) -> std::io::Result<()> {
+    write!(out, "{}=(", name)?;
+
+    for line in lines {
+        write!(out, " {}", shell_quote(line))?;
+    }
+
+    writeln!(out, " )")?;
+    out.flush()
+}
+
+/// Quote a string for a POSIX shell, by putting it in single quotes.
+///
+/// Nothing is special inside single quotes, not even a backslash, so
+/// the only thing we have to escape is a single quote, which we write
+/// as `'\''`: close the quotes, an escaped quote, then open them again.
+pub fn shell_quote(s: &str) -> String {
+    format!("'{}'", s.replace('\'', "'\\''"))
+}
+
+/// Returns true if `name` can be used as the name of a shell variable:
+/// letters, digits and underscores, not starting with a digit.
+pub fn is_shell_variable_name(name: &str) -> bool {
+    let mut chars = name.chars();
+
+    match chars.next() {
+        Some(c) if c.is_ascii_alphabetic() || c == '_' => {
+            chars.all(|c| c.is_ascii_alphanumeric() || c == '_')
+        }
+        _ => false,
+    }
+}
+
+/// A token bucket which limits how many lines per second we write.
+///
+/// The bucket holds at most one token, so there are no bursts -- lines
+/// are spaced out evenly, apart from the first, which is written straight
+/// away.
+pub struct RateLimiter {
+    rate: f64,
+    tokens: f64,
+    last_refill: Instant,
+}
+
+impl RateLimiter {
+    /// Create a limiter which allows `rate` lines per second.
+    pub fn new(rate: f64) -> RateLimiter {
+        RateLimiter {
+            rate,
+            tokens: 1.0,
+            last_refill: Instant::now(),
+        }
+    }
+
+    /// Take a token, and return how long we have to wait before we can
+    /// use it.  Tokens refill at `rate` per second, based on `now`.
+    fn take_token(&mut self, now: Instant) -> Duration {
+        let elapsed = now.saturating_duration_since(self.last_refill);
+        self.tokens = (self.tokens + elapsed.as_secs_f64() * self.rate).min(1.0);
+        self.last_refill = now;
+
+        self.tokens -= 1.0;
+
+        if self.tokens >= 0.0 {
+            Duration::ZERO
+        } else {
+            Duration::from_secs_f64(-self.tokens / self.rate)
+        }
+    }
+
+    /// Wait until we're allowed to write another line.
+    pub fn wait(&mut self) {
+        std::thread::sleep(self.take_token(Instant::now()));
+    }
+}
+
+/// Write the lines of the sample to `out` like `write_lines`, but one
+/// at a time, and no faster than `limiter` allows, if there is one.
+/// We flush after every line, so the reader gets each line as soon as
+/// it's written.
+pub fn write_lines_one_at_a_time(
+    out: &mut impl Write,
+    lines: &[String],
+    separator: &str,
+    terminator: &str,
+    mut limiter: Option<&mut RateLimiter>,
+) -> std::io::Result<()> {
+    for (i, line) in lines.iter().enumerate() {
+        if let Some(limiter) = limiter.as_mut() {
+            limiter.wait();
+        }
+
+        let end = if i == lines.len() - 1 {
+            terminator
+        } else {
+            separator
+        };
+
+        write!(out, "{}{}", line, end)?;
+        out.flush()?;
+    }
+
+    Ok(())
+}
+
+/// Write lines as we get them, rather than waiting for the whole sample,
+/// for output that we don't want to hold in memory.
+///
+/// The output is the same as `write_lines`: we hold back each line until
+/// we know if there's another one after it, so we know whether to follow
+/// it with the separator or the terminator.
+pub struct LineStream<W: Write> {
+    out: W,
+    separator: String,
+    terminator: String,
+    pending: Option<String>,
+    limiter: Option<RateLimiter>,
+    flush_every_line: bool,
+}
+
+impl<W: Write> LineStream<W> {
+    /// If there's a `limiter`, or `flush_every_line` is set, we flush
+    /// after every line, like `write_lines_one_at_a_time`.
+    pub fn new(
+        out: W,
+        separator: &str,
+        terminator: &str,
+        limiter: Option<RateLimiter>,
+        flush_every_line: bool,
+    ) -> LineStream<W> {
+        LineStream {
+            out,
+            separator: separator.to_string(),
+            terminator: terminator.to_string(),
+            pending: None,
+            flush_every_line: flush_every_line || limiter.is_some(),
+            limiter,
+        }
+    }
+
+    pub fn push(&mut self, line: String) -> std::io::Result<()> {
+        match self.pending.replace(line) {
+            Some(previous) => self.write_line(&previous, false),
+            None => Ok(()),
+        }
+    }
+
+    /// Write the last line, if there is one, and flush the output.
+    pub fn finish(mut self) -> std::io::Result<()> {
+        if let Some(last) = self.pending.take() {
+            self.write_line(&last, true)?;
+        }
+
+        self.out.flush()
+    }
+
+    fn write_line(&mut self, line: &str, is_last: bool) -> std::io::Result<()> {
+        if let Some(limiter) = self.limiter.as_mut() {
+            limiter.wait();
+        }
+
+        let end = if is_last {
+            &self.terminator
+        } else {
+            &self.separator
+        };
+
+        write!(self.out, "{}{}", line, end)?;
+
+        if self.flush_every_line {
+            self.out.flush()?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Write `count` and a newline to the file descriptor `fd`, which the
+/// caller should have opened for us, e.g. with `3>count.txt` in the shell.
+///
+/// We don't close the descriptor afterwards, because it might be one
+/// we're still using, like stdout.
+#[cfg(unix)]
+pub fn write_count_to_fd(fd: i32, count: usize) -> std::io::Result<()> {
+    use std::mem::ManuallyDrop;
+    use std::os::fd::FromRawFd;
+
+    // SAFETY: we never close the descriptor, and if it isn't open, the
+    // write fails with `EBADF`, which we return as an error.
+    let mut file = ManuallyDrop::new(unsafe { std::fs::File::from_raw_fd(fd) });
+
+    writeln!(file, "{}", count)?;
+    file.flush()
+}
+
+#[cfg(test)]
+mod rate_limiter_tests {
+    use super::*;
+
+    // The first line can be written straight away, and then each line
+    // has to wait 1/rate seconds after the one before.
+    #[test]
+    fn it_spaces_out_the_tokens() {
+        let start = Instant::now();
+        let mut limiter = RateLimiter {
+            rate: 10.0,
+            tokens: 1.0,
+            last_refill: start,
+        };
+
+        assert_eq!(limiter.take_token(start), Duration::ZERO);
+        assert_eq!(limiter.take_token(start), Duration::from_millis(100));
+    }
+
+    // If we've been idle, the bucket refills -- but only up to one token,
+    // so there's no burst of lines afterwards.
+    #[test]
+    fn it_refills_up_to_one_token() {
+        let start = Instant::now();
+        let mut limiter = RateLimiter {
+            rate: 10.0,
+            tokens: 0.0,
+            last_refill: start,
+        };
+
+        let later = start + Duration::from_secs(5);
+
+        assert_eq!(limiter.take_token(later), Duration::ZERO);
+        assert_eq!(limiter.take_token(later), Duration::from_millis(100));
+    }
+}
+
+#[cfg(test)]
+mod group_by_key_tests {
+    use super::*;
+
+    fn strings(items: &[&str]) -> Vec<String> {
+        items.iter().map(|s| s.to_string()).collect()
+    }
+
+    // Lines with the same key end up in the same group, in the order
+    // the keys were first seen.
+    #[test]
+    fn it_groups_lines_with_the_same_key() {
+        let lines = strings(&["a 1", "b 2", "a 3", "c 4", "b 5"]);
+        let groups = group_by_key(lines, &Regex::new("^[a-z]").unwrap());
+
+        assert_eq!(
+            groups,
+            vec![
+                strings(&["a 1", "a 3"]),
+                strings(&["b 2", "b 5"]),
+                strings(&["c 4"]),
+            ]
+        );
+    }
+
+    // If the pattern has a capture group, that's used as the key rather
+    // than the whole match.
+    #[test]
+    fn it_uses_the_first_capture_group_as_key() {
+        let lines = strings(&["x=1 a", "y=2 b", "z=1 c"]);
+        let groups = group_by_key(lines, &Regex::new("=(\\d)").unwrap());
+
+        assert_eq!(
+            groups,
+            vec![strings(&["x=1 a", "z=1 c"]), strings(&["y=2 b"])]
+        );
+    }
+
+    // Lines that don't match the pattern are grouped together at the end.
+    #[test]
+    fn it_puts_unmatched_lines_last() {
+        let lines = strings(&["nope", "a 1", "also nope"]);
+        let groups = group_by_key(lines, &Regex::new("^[a-z] ").unwrap());
+
+        assert_eq!(
+            groups,
+            vec![strings(&["a 1"]), strings(&["nope", "also nope"])]
+        );
+    }
+}
+
+#[cfg(test)]
+mod write_lines_tests {
+    use super::*;
+
+    fn written(lines: &[&str], separator: &str) -> String {
+        let lines: Vec<String> = lines.iter().map(|s| s.to_string()).collect();
+        let mut out: Vec<u8> = Vec::new();
+        write_lines(&mut out, &lines, separator, "\n").unwrap();
+        String::from_utf8(out).unwrap()
+    }
+
+    #[test]
+    fn it_ends_every_line_with_the_default_separator() {
+        assert_eq!(written(&["a", "b", "c"], "\n"), "a\nb\nc\n");
+    }
+
+    #[test]
+    fn it_joins_lines_with_a_custom_separator() {
+        assert_eq!(written(&["a", "b", "c"], ", "), "a, b, c\n");
+    }
+
+    #[test]
+    fn it_writes_nothing_for_an_empty_sample() {
+        assert_eq!(written(&[], ", "), "");
+    }
+}
+
+#[cfg(test)]
+mod write_lines_one_at_a_time_tests {
+    use super::*;
+
+    /// A writer which records what had been written every time it
+    /// was flushed.
+    #[derive(Default)]
+    struct FlushRecorder {
+        buffer: Vec<u8>,
+        flushed: Vec<String>,
+    }
+
+    impl Write for FlushRecorder {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.buffer.write(buf)
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            self.flushed
+                .push(String::from_utf8(self.buffer.clone()).unwrap());
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn it_flushes_after_every_line() {
+        let lines: Vec<String> = vec!["a".to_string(), "b".to_string(), "c".to_string()];
+        let mut out = FlushRecorder::default();
+
+        write_lines_one_at_a_time(&mut out, &lines, "\n", "\n", None).unwrap();
+
+        assert_eq!(out.flushed, vec!["a\n", "a\nb\n", "a\nb\nc\n"]);
+    }
+}
+
+#[cfg(test)]
+mod write_csv_tests {
+    use super::*;
+
+    fn written(lines: &[&str]) -> String {
+        let lines: Vec<String> = lines.iter().map(|s| s.to_string()).collect();
+        let mut out: Vec<u8> = Vec::new();
+        write_csv(&mut out, &lines).unwrap();
+        String::from_utf8(out).unwrap()
+    }
+
+    #[test]
+    fn it_writes_one_line_per_row() {
+        assert_eq!(written(&["a", "b c"]), "a\nb c\n");
+    }
+
+    #[test]
+    fn it_quotes_commas_quotes_and_newlines() {
+        assert_eq!(
+            written(&["a,b", "say \"hi\"", "one\ntwo"]),
+            "\"a,b\"\n\"say \"\"hi\"\"\"\n\"one\ntwo\"\n"
+        );
+    }
+}
+
+#[cfg(test)]
+mod write_bash_array_tests {
+    use super::*;
+
+    fn written(name: &str, lines: &[&str]) -> String {
+        let lines: Vec<String> = lines.iter().map(|s| s.to_string()).collect();
+        let mut out: Vec<u8> = Vec::new();
+        write_bash_array(&mut out, name, &lines).unwrap();
+        String::from_utf8(out).unwrap()
+    }
+
+    #[test]
+    fn it_writes_an_array_assignment() {
+        assert_eq!(written("lines", &["a", "b c"]), "lines=( 'a' 'b c' )\n");
+    }
+
+    #[test]
+    fn it_writes_an_empty_array() {
+        assert_eq!(written("lines", &[]), "lines=( )\n");
+    }
+
+    #[test]
+    fn it_escapes_single_quotes() {
+        assert_eq!(shell_quote("it's"), "'it'\\''s'");
+        assert_eq!(shell_quote("$HOME `ls` \\n"), "'$HOME `ls` \\n'");
+        assert_eq!(shell_quote(""), "''");
+    }
+
+    #[test]
+    fn it_checks_variable_names() {
+        for name in ["lines", "_x", "A1_b"] {
+            assert!(is_shell_variable_name(name), "name = {:?}", name);
+        }
+
+        for name in ["", "1a", "a-b", "a b", "a=b", "é"] {
+            assert!(!is_shell_variable_name(name), "name = {:?}", name);
+        }
+    }
+}
+
+#[cfg(test)]
+mod trim_to_tests {
+    use super::*;
+
+    #[test]
+    fn it_leaves_short_lines_alone() {
+        assert_eq!(trim_to("hello", 5), "hello");
+        assert_eq!(trim_to("hello", 10), "hello");
+        assert_eq!(trim_to("", 1), "");
+    }
+
+    #[test]
+    fn it_trims_long_lines_with_an_ellipsis() {
+        assert_eq!(trim_to("hello world", 6), "hello…");
+        assert_eq!(trim_to("hello", 4), "hel…");
+        assert_eq!(trim_to("hello", 1), "…");
+    }
+
+    #[test]
+    fn it_counts_characters_not_bytes() {
+        assert_eq!(trim_to("café au lait", 5), "café…");
+        assert_eq!(trim_to("日本語のテキスト", 4), "日本語…");
+        assert_eq!(trim_to("日本語", 3), "日本語");
+    }
+}
+
+#[cfg(test)]
+mod select_columns_tests {
+    use super::*;
+
+    #[test]
+    fn it_parses_a_list_of_columns() {
+        assert_eq!(parse_columns("2"), Some(vec![2]));
+        assert_eq!(parse_columns("3,1,3"), Some(vec![3, 1, 3]));
+        assert_eq!(parse_columns("2, 3"), Some(vec![2, 3]));
+
+        for list in ["", "0", "a", "1,,2", "-1", "1.5"] {
+            assert_eq!(parse_columns(list), None, "list = {:?}", list);
+        }
+    }
+
+    #[test]
+    fn it_picks_the_listed_fields() {
+        assert_eq!(select_columns("1\tapple\tred", &[2]), "apple");
+        assert_eq!(select_columns("1\tapple\tred", &[3, 2]), "red\tapple");
+        assert_eq!(select_columns("1\tapple\tred", &[1, 1]), "1\t1");
+    }
+
+    #[test]
+    fn it_leaves_out_missing_fields() {
+        assert_eq!(select_columns("1\tapple", &[2, 3]), "apple");
+        assert_eq!(select_columns("apple", &[2]), "");
+    }
+}
+
+#[cfg(test)]
+mod line_stream_tests {
+    use super::*;
+
+    fn streamed(lines: &[&str], separator: &str, terminator: &str) -> String {
+        let mut out: Vec<u8> = Vec::new();
+        let mut stream = LineStream::new(&mut out, separator, terminator, None, false);
+
+        for line in lines {
+            stream.push(line.to_string()).unwrap();
+        }
+
+        stream.finish().unwrap();
+        String::from_utf8(out).unwrap()
+    }
+
+    // The output is the same as if we'd written all the lines at once.
+    #[test]
+    fn it_matches_write_lines() {
+        for (separator, terminator) in [("\n", "\n"), (",", "\n"), ("\0", "\0")] {
+            for lines in [vec![], vec!["a"], vec!["a", "b", "c"]] {
+                let lines_owned: Vec<String> = lines.iter().map(|s| s.to_string()).collect();
+                let mut expected: Vec<u8> = Vec::new();
+                write_lines(&mut expected, &lines_owned, separator, terminator).unwrap();
+
+                assert_eq!(
+                    streamed(&lines, separator, terminator),
+                    String::from_utf8(expected).unwrap(),
+                    "lines = {:?}, separator = {:?}",
+                    lines,
+                    separator
+                );
+            }
+        }
+    }
+}
@@ -0,0 +1,139 @@
+use indicatif::{ProgressBar, ProgressStyle};
+use std::io::IsTerminal;
+use std::sync::Mutex;
+use std::thread;
+use std::time::Duration;
+
+use crate::diagnostics;
+use crate::input;
+
+/// How often we update the progress bar with the number of bytes read.
+const TICK_INTERVAL: Duration = Duration::from_millis(100);
+
+/// The progress bar we're showing, if any.  This is a static so we can
+/// clear it from anywhere, including when we exit with an error.
+static BAR: Mutex<Option<ProgressBar>> = Mutex::new(None);
+
+/// A progress bar on stderr, which shows how much of the input we've
+/// read so far.  It's cleared when this is dropped, or when you call
+/// `clear()`, whichever comes first.
+pub struct Progress;
+
+impl Drop for Progress {
+    fn drop(&mut self) {
+        clear();
+    }
+}
+
+/// Clear the progress bar, if we're showing one, e.g. because we've
+/// finished reading and we're about to print something.  It stays
+/// cleared.
+pub fn clear() {
+    if let Some(bar) = BAR.lock().unwrap_or_else(|e| e.into_inner()).take() {
+        bar.finish_and_clear();
+    }
+}
+
+/// Start showing a progress bar for reading `files`, or return `None`
+/// if stderr isn't a terminal, e.g. because it's redirected to a file.
+///
+/// If we know how big the input is, we show a bar with the number of
+/// bytes read so far out of the total; otherwise we show a spinner with
+/// the number of bytes read.
+pub fn start(files: &[String]) -> Option<Progress> {
+    if !std::io::stderr().is_terminal() {
+        return None;
+    }
+
+    let bar = match total_size(files) {
+        Some(total) => ProgressBar::new(total).with_style(
+            ProgressStyle::with_template(
+                "{spinner} [{elapsed_precise}] {wide_bar} {bytes}/{total_bytes} ({eta})",
+            )
+            .expect("the progress bar template is valid"),
+        ),
+        None => ProgressBar::new_spinner().with_style(
+            ProgressStyle::with_template("{spinner} [{elapsed_precise}] {bytes} read")
+                .expect("the spinner template is valid"),
+        ),
+    };
+
+    // The input might be read on another thread (e.g. with `--timeout`),
+    // so we poll the shared count of bytes read, rather than updating
+    // the bar as we read.
+    let ticker = bar.clone();
+    thread::spawn(move || {
+        while !ticker.is_finished() {
+            ticker.set_position(input::bytes_read());
+            ticker.tick();
+            thread::sleep(TICK_INTERVAL);
+        }
+    });
+
+    *BAR.lock().unwrap_or_else(|e| e.into_inner()) = Some(bar);
+    diagnostics::before_exit(clear);
+
+    Some(Progress)
+}
+
+/// The total size of the input in bytes, if we can know it before we
+/// read it -- that is, if every input is an uncompressed regular file.
+///
+/// We don't know the size of stdin or a pipe, and we count the bytes
+/// of compressed files after decompression, so we can't compare them
+/// to the size of the file.
+fn total_size(files: &[String]) -> Option<u64> {
+    if files.is_empty() {
+        return None;
+    }
+
+    files
+        .iter()
+        .map(|path| {
+            if path == "-" || input::is_compressed(path) {
+                return None;
+            }
+
+            std::fs::metadata(path)
+                .ok()
+                .filter(|m| m.is_file())
+                .map(|m| m.len())
+        })
+        .sum()
+}
+
+#[cfg(test)]
+mod progress_tests {
+    use super::*;
+
+    #[test]
+    fn it_adds_up_the_size_of_regular_files() {
+        let dir = tempfile::tempdir().unwrap();
+        let a = dir.path().join("a.txt");
+        let b = dir.path().join("b.txt");
+        std::fs::write(&a, "hello\n").unwrap();
+        std::fs::write(&b, "world!\n").unwrap();
+
+        let files = vec![
+            a.to_str().unwrap().to_string(),
+            b.to_str().unwrap().to_string(),
+        ];
+
+        assert_eq!(total_size(&files), Some(13));
+    }
+
+    #[test]
+    fn it_doesnt_know_the_size_of_stdin_or_compressed_files() {
+        let dir = tempfile::tempdir().unwrap();
+        let gz = dir.path().join("a.txt.gz");
+        std::fs::write(&gz, "").unwrap();
+
+        assert_eq!(total_size(&[]), None);
+        assert_eq!(total_size(&["-".to_string()]), None);
+        assert_eq!(total_size(&[gz.to_str().unwrap().to_string()]), None);
+        assert_eq!(
+            total_size(&[dir.path().to_str().unwrap().to_string()]),
+            None
+        );
+    }
+}
@@ -0,0 +1,118 @@
+use crate::confidence;
+
+/// How many lines should we pick?
+#[derive(Clone, Debug, PartialEq)]
+pub enum SampleSize {
+    /// Pick exactly this many lines (or every line, if there are fewer).
+    Count(usize),
+
+    /// Pick this fraction of the input, where 0 < fraction <= 1.
+    Fraction(f64),
+
+    /// Pick enough lines to estimate a proportion with this z-score and
+    /// margin of error, based on the size of the input.
+    Confidence { z: f64, margin: f64 },
+}
+
+impl SampleSize {
+    /// How many lines should we pick from a population of this size?
+    pub fn resolve(&self, population: usize) -> usize {
+        match self {
+            SampleSize::Count(k) => *k,
+            SampleSize::Fraction(fraction) => (fraction * population as f64).round() as usize,
+            SampleSize::Confidence { z, margin } => {
+                confidence::required_sample_size(population, *z, *margin)
+            }
+        }
+    }
+
+    /// Describe the sample size for `--echo-args`: a count is a number,
+    /// and the other sizes are an object which says what they are.
+    pub fn to_json(&self) -> serde_json::Value {
+        match self {
+            SampleSize::Count(k) => serde_json::json!(k),
+            SampleSize::Fraction(fraction) => serde_json::json!({ "fraction": fraction }),
+            SampleSize::Confidence { z, margin } => {
+                serde_json::json!({ "z": z, "margin": margin })
+            }
+        }
+    }
+}
+
+/// Parse the `k` argument.
+///
+///     "5"   => Count(5)
+///     "1.0" => Count(1)
+///     "10%" => Fraction(0.1)
+///     "0.1" => Fraction(0.1)
+///
+/// Numbers less than 1 are fractions; numbers greater than or equal to
+/// 1 are counts, and have to be whole numbers.  Returns `None` if the
+/// argument isn't a valid sample size, including zero.
+pub fn parse_sample_size(arg: &str) -> Option<SampleSize> {
+    if let Some(percentage) = arg.strip_suffix('%') {
+        return match percentage.parse::<f64>() {
+            Ok(p) if p > 0.0 && p <= 100.0 => Some(SampleSize::Fraction(p / 100.0)),
+            _ => None,
+        };
+    }
+
+    if let Ok(count) = arg.parse::<usize>() {
+        return if count > 0 {
+            Some(SampleSize::Count(count))
+        } else {
+            None
+        };
+    }
+
+    match arg.parse::<f64>() {
+        Ok(f) if f > 0.0 && f < 1.0 => Some(SampleSize::Fraction(f)),
+        Ok(f) if f >= 1.0 && f.fract() == 0.0 && f <= usize::MAX as f64 => {
+            Some(SampleSize::Count(f as usize))
+        }
+        _ => None,
+    }
+}
+
+/// Parse a proportion strictly between 0 and 1, either as a percentage
+/// (`95%`) or a decimal (`0.95`).
+pub fn parse_proportion(arg: &str) -> Option<f64> {
+    let p = match arg.strip_suffix('%') {
+        Some(percentage) => percentage.parse::<f64>().ok()? / 100.0,
+        None => arg.parse::<f64>().ok()?,
+    };
+
+    if p > 0.0 && p < 1.0 {
+        Some(p)
+    } else {
+        None
+    }
+}
+
+#[cfg(test)]
+mod sample_size_tests {
+    use super::*;
+
+    #[test]
+    fn it_parses_counts() {
+        assert_eq!(parse_sample_size("5"), Some(SampleSize::Count(5)));
+        assert_eq!(parse_sample_size("1.0"), Some(SampleSize::Count(1)));
+        assert_eq!(parse_sample_size("3.0"), Some(SampleSize::Count(3)));
+    }
+
+    #[test]
+    fn it_parses_fractions() {
+        assert_eq!(parse_sample_size("0.1"), Some(SampleSize::Fraction(0.1)));
+        assert_eq!(parse_sample_size("10%"), Some(SampleSize::Fraction(0.1)));
+        assert_eq!(parse_sample_size("100%"), Some(SampleSize::Fraction(1.0)));
+    }
+
+    #[test]
+    fn it_rejects_invalid_sizes() {
+        for arg in [
+            "0", "0.0", "0%", "-1", "-0.5", "2.5", "101%", "NaN", "inf", "XXX",
+        ] {
+            assert_eq!(parse_sample_size(arg), None, "arg = {:?}", arg);
+        }
+    }
+}
@@ -1,8 +1,23 @@
 use rand::Rng;
 use std::cmp::Ordering;
-use std::collections::BinaryHeap;
+use std::collections::{BinaryHeap, VecDeque};
 use std::ptr;
 
+/// A source of random weights u_i ~ U[0,1).
+///
+/// This is implemented for every random number generator, but tests
+/// can supply their own implementation to choose exactly which weights
+/// each item gets.
+pub trait WeightSource {
+    fn next_weight(&mut self) -> f64;
+}
+
+impl<R: Rng + ?Sized> WeightSource for R {
+    fn next_weight(&mut self) -> f64 {
+        self.random_range(0.0..1.0)
+    }
+}
+
 struct WeightedItem<T> {
     item: T,
     weight: f64,
@@ -42,16 +57,816 @@ impl<T> Ord for WeightedItem<T> {
     }
 }
 
-/// Choose a sample of `k` items from the iterator `items.
+/// Choose a sample of `k` items from the iterator `items`, using
+/// random weights from `rng`.
 ///
 /// Each item has an equal chance of being picked -- that is, there's
 /// a 1/N chance of choosing an item, where N is the length of the iterator.
 ///
-/// This implements "Algorithm L" for reservoir sampling, as described
-/// on the Wikipedia page:
+/// Each item gets a random weight, and we keep the k items with the
+/// smallest weights.  This needs a random number for every item; see
+/// `reservoir_sample_algorithm_l` for a version which skips items.
+///
+pub fn reservoir_sample<T>(
+    items: impl Iterator<Item = T>,
+    k: usize,
+    rng: &mut impl WeightSource,
+) -> Vec<T> {
+    reservoir_sample_with_capacity(items, k, k, rng)
+}
+
+/// Like `reservoir_sample`, but preallocates room for `capacity` items
+/// in the reservoir rather than `k`.
+///
+/// This only changes how much memory we allocate up front, not which
+/// items we pick.  We never allocate room for more than `k` items, because
+/// the reservoir can't get any bigger than that.
+pub fn reservoir_sample_with_capacity<T>(
+    items: impl Iterator<Item = T>,
+    k: usize,
+    capacity: usize,
+    rng: &mut impl WeightSource,
+) -> Vec<T> {
+    sample_smallest_weights(items, k, capacity, |_| rng.next_weight())
+}
+
+/// Like `reservoir_sample_with_capacity`, but returns the items in order
+/// of the random weights they were given, smallest first.
+///
+/// This picks the same items as `reservoir_sample_with_capacity` with
+/// the same weights, and the order depends only on the weights, so it's
+/// the same every time for a given seed.
+pub fn reservoir_sample_in_key_order<T>(
+    items: impl Iterator<Item = T>,
+    k: usize,
+    capacity: usize,
+    rng: &mut impl WeightSource,
+) -> Vec<T> {
+    let mut sample = sample_smallest_weights(
+        items.map(|item| (rng.next_weight(), item)),
+        k,
+        capacity,
+        |(weight, _)| *weight,
+    );
+
+    sample.sort_by(|a, b| a.0.partial_cmp(&b.0).unwrap());
+    sample.into_iter().map(|(_, item)| item).collect()
+}
+
+/// The different implementations of uniform reservoir sampling, so we
+/// can benchmark them and check they agree.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ReservoirImpl {
+    /// Give every item a random weight, and keep the smallest -- this
+    /// is `reservoir_sample`.
+    Naive,
+
+    /// Skip over items with "Algorithm L".
+    AlgorithmL,
+
+    /// Pick a slot to replace for every item with "Algorithm R".
+    AlgorithmR,
+}
+
+impl ReservoirImpl {
+    /// Choose a sample of `k` items from `items` with this implementation.
+    #[cfg(test)]
+    pub fn sample<T>(
+        self,
+        items: impl Iterator<Item = T>,
+        k: usize,
+        rng: &mut impl WeightSource,
+    ) -> Vec<T> {
+        self.sample_with_capacity(items, k, k, rng)
+    }
+
+    /// Choose a sample of `k` items from `items` with this implementation,
+    /// preallocating room for `capacity` items in the reservoir.
+    pub fn sample_with_capacity<T>(
+        self,
+        items: impl Iterator<Item = T>,
+        k: usize,
+        capacity: usize,
+        rng: &mut impl WeightSource,
+    ) -> Vec<T> {
+        match self {
+            ReservoirImpl::Naive => reservoir_sample_with_capacity(items, k, capacity, rng),
+            ReservoirImpl::AlgorithmL => reservoir_sample_algorithm_l(items, k, capacity, rng),
+            ReservoirImpl::AlgorithmR => reservoir_sample_algorithm_r(items, k, capacity, rng),
+        }
+    }
+}
+
+/// Choose a sample of `k` items from `items`, using "Algorithm L" from
+/// Li, "Reservoir-sampling algorithms of time complexity O(n(1 + log(N/n)))"
+/// (1994), as described on the Wikipedia page:
 /// https://en.wikipedia.org/wiki/Reservoir_sampling#Optimal:_Algorithm_L
 ///
-pub fn reservoir_sample<T>(mut items: impl Iterator<Item = T>, k: usize) -> Vec<T> {
+/// Rather than drawing a random number for every item, we draw how many
+/// items to skip before the next one goes into the reservoir, so this
+/// needs O(k log(n/k)) random numbers.
+///
+/// The reservoir starts with room for `capacity` items.
+///
+pub fn reservoir_sample_algorithm_l<T>(
+    mut items: impl Iterator<Item = T>,
+    k: usize,
+    capacity: usize,
+    rng: &mut impl WeightSource,
+) -> Vec<T> {
+    if k == 0 {
+        return vec![];
+    }
+
+    let mut reservoir: Vec<T> = Vec::with_capacity(capacity.min(k));
+    reservoir.extend(items.by_ref().take(k));
+
+    if reservoir.len() < k {
+        return reservoir;
+    }
+
+    // We use 1 - u_i so the argument to ln() is in (0, 1].
+    let mut w = ((1.0 - rng.next_weight()).ln() / k as f64).exp();
+
+    loop {
+        let skip = ((1.0 - rng.next_weight()).ln() / (1.0 - w).ln()).floor();
+
+        // If the skip is too big to fit in a usize, it saturates, and
+        // we run off the end of the input.
+        match items.nth(skip as usize) {
+            Some(item) => reservoir[random_index(k, rng)] = item,
+            None => break,
+        }
+
+        w *= ((1.0 - rng.next_weight()).ln() / k as f64).exp();
+    }
+
+    reservoir
+}
+
+/// Choose a sample of `k` items from `items`, using "Algorithm R" from
+/// Vitter, "Random sampling with a reservoir" (1985).
+///
+/// This is the classic algorithm: the i'th item replaces a random item
+/// in the reservoir with probability k/i.  It needs a random number for
+/// every item, like `reservoir_sample`.
+///
+/// The reservoir starts with room for `capacity` items.
+///
+pub fn reservoir_sample_algorithm_r<T>(
+    items: impl Iterator<Item = T>,
+    k: usize,
+    capacity: usize,
+    rng: &mut impl WeightSource,
+) -> Vec<T> {
+    if k == 0 {
+        return vec![];
+    }
+
+    let mut reservoir: Vec<T> = Vec::with_capacity(capacity.min(k));
+
+    for (i, item) in items.enumerate() {
+        if i < k {
+            reservoir.push(item);
+        } else {
+            let j = random_index(i + 1, rng);
+
+            if j < k {
+                reservoir[j] = item;
+            }
+        }
+    }
+
+    reservoir
+}
+
+/// Choose `n` independent samples of `k` items from the iterator `items`,
+/// in a single pass.
+///
+/// This is the same as calling `reservoir_sample` `n` times on copies
+/// of the input, but only reads the input once.  Each sample has its own
+/// reservoir, and each item gets a separate random weight for each
+/// reservoir, so the samples are independent -- the same item can appear
+/// in more than one sample.
+///
+pub fn multi_reservoir_sample<T: Clone>(
+    items: impl Iterator<Item = T>,
+    k: usize,
+    n: usize,
+    rng: &mut impl WeightSource,
+) -> Vec<Vec<T>> {
+    let mut reservoirs: Vec<BinaryHeap<WeightedItem<T>>> =
+        (0..n).map(|_| BinaryHeap::with_capacity(k)).collect();
+
+    if k == 0 {
+        return vec![vec![]; n];
+    }
+
+    for item in items {
+        for reservoir in reservoirs.iter_mut() {
+            let weight = rng.next_weight();
+
+            if reservoir.len() < k {
+                reservoir.push(WeightedItem {
+                    weight,
+                    item: item.clone(),
+                });
+            } else if weight < reservoir.peek().unwrap().weight {
+                reservoir.pop();
+                reservoir.push(WeightedItem {
+                    weight,
+                    item: item.clone(),
+                });
+            }
+        }
+    }
+
+    reservoirs
+        .into_iter()
+        .map(|r| r.into_vec().into_iter().map(|w| w.item).collect())
+        .collect()
+}
+
+/// Combine uniform samples from two separate populations into a
+/// uniform sample of `k` items from both of them.
+///
+/// `first` is a sample from a population of `first_population` items,
+/// and `second` is a sample from a population of `second_population`
+/// items.  Each sample has to be a uniform sample without replacement of
+/// at least `min(k, population)` items, e.g. the output of an earlier
+/// `reservoir_sample` with the same k.
+///
+/// We decide how many of the `k` items come from each population by
+/// drawing `k` items without replacement from the combined population,
+/// the way a single pass would, then pick that many items at random
+/// from each sample.
+///
+/// # Panics
+///
+/// Panics if either sample has fewer items than we need from it.
+///
+pub fn combine_samples<T>(
+    first: Vec<T>,
+    first_population: usize,
+    second: Vec<T>,
+    second_population: usize,
+    k: usize,
+    rng: &mut impl WeightSource,
+) -> Vec<T> {
+    let mut remaining = (first_population, second_population);
+    let mut from_first = 0;
+
+    for _ in 0..k.min(first_population + second_population) {
+        if random_index(remaining.0 + remaining.1, rng) < remaining.0 {
+            from_first += 1;
+            remaining.0 -= 1;
+        } else {
+            remaining.1 -= 1;
+        }
+    }
+
+    let from_second = k.min(first_population + second_population) - from_first;
+
+    let mut sample = choose_without_replacement(first, from_first, rng);
+    sample.extend(choose_without_replacement(second, from_second, rng));
+    sample
+}
+
+/// Choose `n` items from `items` without replacement, using a partial
+/// Fisher-Yates shuffle.
+fn choose_without_replacement<T>(
+    mut items: Vec<T>,
+    n: usize,
+    rng: &mut impl WeightSource,
+) -> Vec<T> {
+    assert!(
+        items.len() >= n,
+        "can't choose {} items from a sample of {}",
+        n,
+        items.len()
+    );
+
+    for i in 0..n {
+        let j = i + random_index(items.len() - i, rng);
+        items.swap(i, j);
+    }
+
+    items.truncate(n);
+    items
+}
+
+/// Choose a sample of `k` items from the iterator `items`, and also
+/// return the items which weren't picked.
+///
+/// Returns `(selected, rejected)`, which between them contain every
+/// item exactly once.  Each item has the same chance of being picked
+/// as in `reservoir_sample`.  The rejected items are in the same order
+/// as the input.
+///
+/// Unlike `reservoir_sample`, this keeps every item in memory, because
+/// every item ends up in one of the two vectors.
+///
+pub fn reservoir_sample_with_rejects<T>(
+    items: impl Iterator<Item = T>,
+    k: usize,
+    rng: &mut impl WeightSource,
+) -> (Vec<T>, Vec<T>) {
+    let mut keyed: Vec<(f64, usize, T)> = items
+        .enumerate()
+        .map(|(i, item)| (rng.next_weight(), i, item))
+        .collect();
+
+    keyed.sort_by(|a, b| a.0.partial_cmp(&b.0).unwrap());
+
+    let mut rejected = keyed.split_off(k.min(keyed.len()));
+    rejected.sort_by_key(|(_, i, _)| *i);
+
+    (
+        keyed.into_iter().map(|(_, _, item)| item).collect(),
+        rejected.into_iter().map(|(_, _, item)| item).collect(),
+    )
+}
+
+/// Choose a weighted sample of `k` items from the iterator `items`,
+/// where each item is a pair `(weight, item)`.
+///
+/// An item's chance of being picked is proportional to its weight.
+/// An item with weight 0 is only picked if there are `k` or fewer
+/// items in total.  If every item has the same weight, this is the same
+/// as a uniform `reservoir_sample`.
+///
+/// This picks items with the same probabilities as "Algorithm A-Res"
+/// from Efraimidis and Spirakis, "Weighted random sampling with a
+/// reservoir" (2006), but implements "Algorithm A-ExpJ" from the same
+/// paper, which uses exponential jumps.  Once the reservoir is full,
+/// rather than drawing a key for every item, we draw the total weight
+/// we can skip before the next item that goes into the reservoir.  That
+/// needs O(k log(n/k)) random numbers rather than O(n), which is much
+/// faster for large inputs.
+///
+/// We use keys -ln(u_i)/w_i and keep the k smallest.  If T is the
+/// largest key in the reservoir, the weight we can skip is exponentially
+/// distributed with rate T, and the item we land on gets a key drawn
+/// from [0, T).
+///
+/// # Panics
+///
+/// Panics if any weight is negative, infinite, or NaN.  There's no
+/// sensible way to sample with those weights, and the caller should
+/// validate them first -- in the CLI, that's `weights::parse_weight`.
+///
+pub fn weighted_reservoir_sample<T>(
+    items: impl Iterator<Item = (f64, T)>,
+    k: usize,
+    rng: &mut impl WeightSource,
+) -> Vec<T> {
+    weighted_reservoir_sample_expj_with_capacity(items, k, k, rng)
+}
+
+/// Choose a weighted sample with "Algorithm A-Res", which gives each
+/// item a random key u_i^(1/w_i), and keeps the items with the k largest
+/// keys.  To reuse the same reservoir as `reservoir_sample`, we use the
+/// equivalent key -ln(u_i)/w_i and keep the k smallest.
+///
+/// This was the original implementation of `weighted_reservoir_sample`;
+/// we keep it to check A-ExpJ against it.
+#[cfg(test)]
+fn a_res_reservoir_sample<T>(
+    items: impl Iterator<Item = (f64, T)>,
+    k: usize,
+    rng: &mut impl WeightSource,
+) -> Vec<T> {
+    sample_smallest_weights(items, k, k, |(w, _)| exponential_key(*w, rng))
+        .into_iter()
+        .map(|(_, item)| item)
+        .collect()
+}
+
+/// Like `weighted_reservoir_sample`, but preallocates room for
+/// `capacity` items in the reservoir rather than `k`.
+pub fn weighted_reservoir_sample_expj_with_capacity<T>(
+    items: impl Iterator<Item = (f64, T)>,
+    k: usize,
+    capacity: usize,
+    rng: &mut impl WeightSource,
+) -> Vec<T> {
+    expj_reservoir(items, k, capacity, rng)
+        .into_vec()
+        .into_iter()
+        .map(|r| r.item)
+        .collect()
+}
+
+/// Like `weighted_reservoir_sample_expj_with_capacity`, but returns the
+/// items in order of their random keys, smallest first.
+pub fn weighted_reservoir_sample_expj_in_key_order<T>(
+    items: impl Iterator<Item = (f64, T)>,
+    k: usize,
+    capacity: usize,
+    rng: &mut impl WeightSource,
+) -> Vec<T> {
+    expj_reservoir(items, k, capacity, rng)
+        .into_sorted_vec()
+        .into_iter()
+        .map(|r| r.item)
+        .collect()
+}
+
+/// Run A-ExpJ, and return the reservoir with the key of every item.
+fn expj_reservoir<T>(
+    items: impl Iterator<Item = (f64, T)>,
+    k: usize,
+    capacity: usize,
+    rng: &mut impl WeightSource,
+) -> BinaryHeap<WeightedItem<T>> {
+    if k == 0 {
+        return BinaryHeap::new();
+    }
+
+    let mut items = items;
+    let mut reservoir = BinaryHeap::with_capacity(capacity.min(k));
+
+    // Fill the reservoir with the first k items, same as A-Res.
+    for (w, item) in items.by_ref() {
+        let weight = exponential_key(w, rng);
+        reservoir.push(WeightedItem { item, weight });
+
+        if reservoir.len() == k {
+            break;
+        }
+    }
+
+    if reservoir.len() < k {
+        return reservoir;
+    }
+
+    let mut threshold = reservoir.peek().unwrap().weight;
+    let mut jump = exponential_jump(threshold, rng);
+
+    for (w, item) in items {
+        assert!(
+            w.is_finite() && w >= 0.0,
+            "invalid weight {}: weights must be finite and non-negative",
+            w
+        );
+
+        // Items with weight 0 never take up any of the jump, so they
+        // can't go into a full reservoir.
+        jump -= w;
+        if jump > 0.0 || w == 0.0 {
+            continue;
+        }
+
+        // This item goes into the reservoir, and needs a key smaller than
+        // the threshold, i.e. u_i in (e^(-T * w_i), 1].
+        let floor = (-threshold * w).exp();
+        let u = floor + (1.0 - rng.next_weight()) * (1.0 - floor);
+        let weight = -u.ln() / w;
+
+        reservoir.pop();
+        reservoir.push(WeightedItem { item, weight });
+
+        threshold = reservoir.peek().unwrap().weight;
+        jump = exponential_jump(threshold, rng);
+    }
+
+    reservoir
+}
+
+/// Choose `k` items from `items` with replacement, where each item
+/// is a pair `(weight, item)`.
+///
+/// Each draw is independent, and picks an item with probability
+/// proportional to its weight, so the same item can be picked more than
+/// once, and we always pick `k` items (unless there's nothing to pick).
+/// Weights should be finite and non-negative; if the total weight is 0,
+/// there's nothing we can pick, and we return an empty sample.
+///
+/// This uses an `AliasTable`, which is O(n) setup and O(1) per draw.
+///
+pub fn weighted_sample_with_replacement<T: Clone>(
+    items: &[(f64, T)],
+    k: usize,
+    rng: &mut impl WeightSource,
+) -> Vec<T> {
+    let weights: Vec<f64> = items.iter().map(|(w, _)| *w).collect();
+
+    match AliasTable::new(&weights) {
+        Some(table) => (0..k).map(|_| items[table.sample(rng)].1.clone()).collect(),
+        None => vec![],
+    }
+}
+
+/// Choose as many items as fit in a budget of `budget` bytes, taking
+/// them in a random order.
+///
+/// Each item takes up `size(item)` bytes, plus the bytes that go after
+/// it when it's printed: `delimiters` is the length of the separator
+/// between items, and of the terminator after the last one.  We stop at
+/// the first item that doesn't fit, even if a shorter item later on
+/// would have, so short items aren't favoured over long ones.
+///
+/// We shuffle by giving each item a random key and sorting on it, so
+/// this needs every item in memory.
+///
+pub fn byte_budget_sample<T>(
+    items: Vec<T>,
+    budget: usize,
+    size: impl Fn(&T) -> usize,
+    delimiters: (usize, usize),
+    rng: &mut impl WeightSource,
+) -> Vec<T> {
+    let (separator, terminator) = delimiters;
+
+    let mut keyed: Vec<(f64, T)> = items
+        .into_iter()
+        .map(|item| (rng.next_weight(), item))
+        .collect();
+
+    keyed.sort_by(|(a, _), (b, _)| a.partial_cmp(b).unwrap());
+
+    // The first item is followed by the terminator, and every item after
+    // it adds a separator.
+    let mut used = 0;
+
+    keyed
+        .into_iter()
+        .enumerate()
+        .take_while(|(i, (_, item))| {
+            used += size(item) + if *i == 0 { terminator } else { separator };
+            used <= budget
+        })
+        .map(|(_, (_, item))| item)
+        .collect()
+}
+
+/// Decide independently whether to keep each item, where each item
+/// is a pair `(probability, item)`.
+///
+/// Unlike the other sampling functions, there's no fixed sample size --
+/// on average, we keep the sum of the probabilities.  Probabilities
+/// should be between 0 and 1.
+///
+pub fn bernoulli_sample<T>(
+    items: impl Iterator<Item = (f64, T)>,
+    rng: &mut impl WeightSource,
+) -> Vec<T> {
+    items
+        .filter(|(p, _)| rng.next_weight() < *p)
+        .map(|(_, item)| item)
+        .collect()
+}
+
+/// Choose `k` items with replacement by binary searching a table of
+/// cumulative weights.  That's O(n) setup and O(log n) per draw.
+///
+/// This was the original implementation of `weighted_sample_with_replacement`;
+/// we keep it to check the alias method against it, and to benchmark them.
+#[cfg(test)]
+fn cumulative_sample_with_replacement<T: Clone>(
+    items: &[(f64, T)],
+    k: usize,
+    rng: &mut impl WeightSource,
+) -> Vec<T> {
+    let cumulative_weights: Vec<f64> = items
+        .iter()
+        .scan(0.0, |total, (w, _)| {
+            *total += w;
+            Some(*total)
+        })
+        .collect();
+
+    let total_weight = match cumulative_weights.last() {
+        Some(w) if *w > 0.0 => *w,
+        _ => return vec![],
+    };
+
+    // If rounding means we land on the very end of the table, pick the
+    // last item with a non-zero weight.
+    let last_positive = items.iter().rposition(|(w, _)| *w > 0.0).unwrap();
+
+    (0..k)
+        .map(|_| {
+            let target = rng.next_weight() * total_weight;
+            let index = cumulative_weights.partition_point(|c| *c <= target);
+
+            items[index.min(last_positive)].1.clone()
+        })
+        .collect()
+}
+
+/// A table for making repeated weighted draws in O(1) time, using
+/// Walker's alias method.
+///
+/// We split the total probability into n equal-sized columns, one per
+/// item.  Each column is shared by at most two items: the item it
+/// belongs to, which gets the first `probabilities[i]` of the column,
+/// and an "alias" item, which gets the rest.  To make a draw, we pick
+/// a random column, then a random point in that column.
+///
+/// This uses Vose's algorithm to build the table, as described in
+/// https://www.keithschwarz.com/darts-dice-coins/
+///
+pub struct AliasTable {
+    probabilities: Vec<f64>,
+    aliases: Vec<usize>,
+}
+
+impl AliasTable {
+    /// Build an alias table for the given weights.
+    ///
+    /// Returns `None` if there are no weights, or they sum to zero.
+    pub fn new(weights: &[f64]) -> Option<AliasTable> {
+        let n = weights.len();
+        let max_weight = weights.iter().copied().fold(0.0, f64::max);
+
+        if n == 0 || max_weight <= 0.0 {
+            return None;
+        }
+
+        // Divide by the biggest weight before we add them up, so the total
+        // can't overflow even if the weights are very big.
+        let total_weight: f64 = weights.iter().map(|w| w / max_weight).sum();
+
+        // Scale the weights so the average is 1, then sort them into
+        // columns which are under-full (<1) and over-full (>=1).
+        let mut scaled: Vec<f64> = weights
+            .iter()
+            .map(|w| w / max_weight * n as f64 / total_weight)
+            .collect();
+
+        let (mut small, mut large): (Vec<usize>, Vec<usize>) =
+            (0..n).partition(|i| scaled[*i] < 1.0);
+
+        let mut probabilities = vec![1.0; n];
+        let mut aliases: Vec<usize> = (0..n).collect();
+
+        // Fill each under-full column with the excess from an over-full
+        // one.  That takes some weight away from the over-full column,
+        // which might leave it under-full.
+        while let (Some(s), Some(l)) = (small.pop(), large.pop()) {
+            probabilities[s] = scaled[s];
+            aliases[s] = l;
+
+            scaled[l] += scaled[s] - 1.0;
+
+            if scaled[l] < 1.0 {
+                small.push(l);
+            } else {
+                large.push(l);
+            }
+        }
+
+        // Anything left over should be exactly full, but might be a tiny
+        // bit off because of floating-point error.  They keep their
+        // default probability of 1.
+        Some(AliasTable {
+            probabilities,
+            aliases,
+        })
+    }
+
+    /// Draw the index of a single item.
+    pub fn sample(&self, rng: &mut impl WeightSource) -> usize {
+        let n = self.probabilities.len();
+
+        // We use a single random number to pick both the column (the
+        // integer part) and the point within the column (the fractional
+        // part).
+        let u = rng.next_weight() * n as f64;
+        let column = (u as usize).min(n - 1);
+
+        if u - (column as f64) < self.probabilities[column] {
+            column
+        } else {
+            self.aliases[column]
+        }
+    }
+}
+
+/// Choose a sample of `k` items from the iterator `items`, where each
+/// item is a pair `(timestamp, item)`, and only items from the last
+/// `window` seconds are eligible.
+///
+/// The window ends at the latest timestamp we've seen, so an item is
+/// eligible if its timestamp is at least `latest - window`.  Timestamps
+/// don't have to be in order, but items that arrive after they've
+/// already left the window are discarded.
+///
+/// Like `reservoir_sample`, each item gets a random weight and we
+/// pick the k items in the window with the smallest weights.  We can't
+/// throw away an item just because k other items have smaller weights --
+/// those items might expire first -- but once k items with smaller
+/// weights and timestamps at least as late have arrived, it can never be
+/// picked again, because they'll stay in the window at least as long.
+/// In expectation we only keep O(k log n) candidates, where n is the
+/// number of items in the window.
+///
+pub fn windowed_reservoir_sample<T>(
+    items: impl Iterator<Item = (f64, T)>,
+    k: usize,
+    window: f64,
+    rng: &mut impl WeightSource,
+) -> Vec<T> {
+    if k == 0 {
+        return vec![];
+    }
+
+    struct Candidate<T> {
+        timestamp: f64,
+        weight: f64,
+
+        // How many items with a smaller weight and a timestamp at least
+        // as late as this one have we seen?
+        beaten_by: usize,
+
+        item: T,
+    }
+
+    let mut candidates: VecDeque<Candidate<T>> = VecDeque::new();
+    let mut latest = f64::NEG_INFINITY;
+
+    for (timestamp, item) in items {
+        // Evict any candidates which have left the window.  The window
+        // only moves when we see a new latest timestamp.
+        if timestamp > latest {
+            latest = timestamp;
+            candidates.retain(|c| c.timestamp >= latest - window);
+        }
+
+        // Discard this item if it arrived after it left the window.
+        if timestamp < latest - window {
+            continue;
+        }
+
+        let weight = rng.next_weight();
+
+        // Any candidates with a bigger weight and an earlier (or equal)
+        // timestamp have been beaten by this item; if they've been beaten
+        // k times, they can never be in the sample again.
+        //
+        // A candidate with a later timestamp isn't beaten, because this
+        // item will leave the window first.
+        let mut any_dropped = false;
+
+        for c in candidates.iter_mut() {
+            if c.weight > weight && c.timestamp <= timestamp {
+                c.beaten_by += 1;
+                any_dropped |= c.beaten_by >= k;
+            }
+        }
+
+        if any_dropped {
+            candidates.retain(|c| c.beaten_by < k);
+        }
+
+        candidates.push_back(Candidate {
+            timestamp,
+            weight,
+            beaten_by: 0,
+            item,
+        });
+    }
+
+    let mut candidates: Vec<Candidate<T>> = candidates.into();
+    candidates.sort_by(|a, b| a.weight.partial_cmp(&b.weight).unwrap());
+    candidates.truncate(k);
+
+    candidates.into_iter().map(|c| c.item).collect()
+}
+
+/// Choose the `k` items with the smallest weights, where `pick_weight`
+/// chooses a random weight for each item.
+///
+/// For very small k, we keep the reservoir in a sorted `Vec`; otherwise,
+/// we use a `BinaryHeap`.  They pick the same items, but might return
+/// them in a different order.
+fn sample_smallest_weights<T>(
+    items: impl Iterator<Item = T>,
+    k: usize,
+    capacity: usize,
+    pick_weight: impl FnMut(&T) -> f64,
+) -> Vec<T> {
+    if k <= SORTED_VEC_MAX_K {
+        sample_smallest_weights_in::<SortedVec<T>, T>(items, k, capacity, pick_weight)
+    } else {
+        sample_smallest_weights_in::<BinaryHeap<WeightedItem<T>>, T>(
+            items,
+            k,
+            capacity,
+            pick_weight,
+        )
+    }
+}
+
+/// Choose the `k` items with the smallest weights, using a reservoir
+/// of type `R` that starts with room for `capacity` items.
+fn sample_smallest_weights_in<R: Reservoir<T>, T>(
+    mut items: impl Iterator<Item = T>,
+    k: usize,
+    capacity: usize,
+    mut pick_weight: impl FnMut(&T) -> f64,
+) -> Vec<T> {
     // Taking a sample with k=0 doesn't make much sense in practice,
     // but we include this to avoid problems downstream.
     if k == 0 {
@@ -59,17 +874,17 @@ pub fn reservoir_sample<T>(mut items: impl Iterator<Item = T>, k: usize) -> Vec<
     }
 
     // Create an empty reservoir.
-    let mut reservoir: BinaryHeap<WeightedItem<T>> = BinaryHeap::with_capacity(k);
+    let mut reservoir = R::with_capacity(capacity.min(k));
 
     // Fill the reservoir with the first k items.  If there are less
     // than n items, we can exit immediately.
     for _ in 1..=k {
         match items.next() {
             Some(this_item) => reservoir.push(WeightedItem {
+                weight: pick_weight(&this_item),
                 item: this_item,
-                weight: pick_weight(),
             }),
-            None => return reservoir.into_vec().into_iter().map(|r| r.item).collect(),
+            None => return reservoir.into_items(),
         };
     }
 
@@ -79,12 +894,12 @@ pub fn reservoir_sample<T>(mut items: impl Iterator<Item = T>, k: usize) -> Vec<
     // contains at least one item.  Either `items` was non-empty, or if itwas
     // was empty, then we'd already have returned when trying to fill the
     // reservoir with the first k items.
-    let mut max_weight: f64 = reservoir.peek().unwrap().weight;
+    let mut max_weight: f64 = reservoir.max_weight().unwrap();
 
     // Now go through the remaining items.
     for this_item in items {
         // Choose a weight for this item.
-        let this_weight = pick_weight();
+        let this_weight = pick_weight(&this_item);
 
         // If this is greater than the weights seen so far, we can ignore
         // this item and move on to the next one.
@@ -94,67 +909,240 @@ pub fn reservoir_sample<T>(mut items: impl Iterator<Item = T>, k: usize) -> Vec<
 
         // Otherwise, this item has a lower weight than the current item
         // with max weight -- so we'll replace that item.
-        assert!(reservoir.pop().is_some());
+        reservoir.pop_max();
         reservoir.push(WeightedItem {
             item: this_item,
             weight: this_weight,
         });
 
         // Recalculate the max weight for the new sample.
-        max_weight = reservoir.peek().unwrap().weight;
+        max_weight = reservoir.max_weight().unwrap();
     }
 
-    let sample: Vec<T> = reservoir.into_vec().into_iter().map(|r| r.item).collect();
+    let sample: Vec<T> = reservoir.into_items();
     assert!(sample.len() == k);
     sample
 }
 
-/// Create a random weight u_i ~ U[0,1]
-fn pick_weight() -> f64 {
-    rand::rng().random_range(0.0..1.0)
-}
+/// Up to this k, a sorted `Vec` is faster than a `BinaryHeap` for
+/// holding the reservoir.
+///
+/// This comes from `bench_heap_vs_sorted_vec`: when most items go into
+/// the reservoir, the sorted vec is about twice as fast for k=1 (the
+/// default), about the same for k=2, and slower for anything bigger.
+const SORTED_VEC_MAX_K: usize = 1;
 
-#[cfg(test)]
-mod reservoir_sample_tests {
-    use super::*;
-    use std::collections::HashMap;
+/// A reservoir which holds the items with the smallest weights we've
+/// seen so far, so we can swap out the item with the largest weight.
+trait Reservoir<T> {
+    fn with_capacity(capacity: usize) -> Self;
 
-    // If there are no items, then the sample is empty.
-    #[test]
-    fn it_returns_an_empty_sample_for_an_empty_input() {
-        let items: Vec<usize> = vec![];
-        let sample = reservoir_sample(items.into_iter(), 5);
+    /// Add an item to the reservoir.
+    fn push(&mut self, item: WeightedItem<T>);
 
-        assert_eq!(sample.len(), 0);
-    }
+    /// Remove the item with the largest weight.
+    fn pop_max(&mut self);
 
-    // If there are less items than the sample size, then the sample is
-    // the complete set.
-    #[test]
-    fn it_returns_complete_sample_if_less_items_than_sample_size() {
-        let items = vec!["a", "b", "c"];
-        let sample = reservoir_sample(items.into_iter(), 5);
+    /// The largest weight in the reservoir, or `None` if it's empty.
+    fn max_weight(&self) -> Option<f64>;
 
-        assert!(equivalent_items(sample, vec!["a", "b", "c"]));
+    fn into_items(self) -> Vec<T>;
+}
+
+impl<T> Reservoir<T> for BinaryHeap<WeightedItem<T>> {
+    fn with_capacity(capacity: usize) -> Self {
+        BinaryHeap::with_capacity(capacity)
     }
 
-    // If there's an equal number of items to the sample size, then the
-    // sample is the complete set.
-    #[test]
-    fn it_returns_complete_sample_if_item_count_equal_to_sample_size() {
-        let items = vec!["a", "b", "c"];
-        let sample = reservoir_sample(items.into_iter(), 3);
+    fn push(&mut self, item: WeightedItem<T>) {
+        BinaryHeap::push(self, item)
+    }
 
-        assert!(equivalent_items(sample, vec!["a", "b", "c"]));
+    fn pop_max(&mut self) {
+        assert!(self.pop().is_some());
     }
 
-    // If k=0, then it returns an empty sample.
-    #[test]
-    fn it_returns_an_empty_sample_if_k_zero() {
-        let items = vec!["a", "b", "c"];
-        let sample = reservoir_sample(items.into_iter(), 0);
+    fn max_weight(&self) -> Option<f64> {
+        self.peek().map(|r| r.weight)
+    }
 
-        assert_eq!(sample.len(), 0);
+    fn into_items(self) -> Vec<T> {
+        self.into_vec().into_iter().map(|r| r.item).collect()
+    }
+}
+
+/// A reservoir which keeps its items sorted by weight, smallest first.
+///
+/// Adding an item is O(k), because we have to shift the bigger items
+/// along, but for small k that's cheaper than rebalancing a heap.
+struct SortedVec<T>(Vec<WeightedItem<T>>);
+
+impl<T> Reservoir<T> for SortedVec<T> {
+    fn with_capacity(capacity: usize) -> Self {
+        SortedVec(Vec::with_capacity(capacity))
+    }
+
+    fn push(&mut self, item: WeightedItem<T>) {
+        let index = self.0.partition_point(|r| r.weight <= item.weight);
+        self.0.insert(index, item);
+    }
+
+    fn pop_max(&mut self) {
+        assert!(self.0.pop().is_some());
+    }
+
+    fn max_weight(&self) -> Option<f64> {
+        self.0.last().map(|r| r.weight)
+    }
+
+    fn into_items(self) -> Vec<T> {
+        self.0.into_iter().map(|r| r.item).collect()
+    }
+}
+
+/// Pick a random index in 0..n.
+fn random_index(n: usize, rng: &mut impl WeightSource) -> usize {
+    // u_i is in [0, 1), so this is less than n -- but we clamp it in case
+    // rounding takes us to n.
+    ((rng.next_weight() * n as f64) as usize).min(n - 1)
+}
+
+/// Create a random key -ln(u_i)/w_i for an item with weight w_i.
+///
+/// We use 1 - u_i so the argument to ln() is in (0, 1], and we never
+/// get ln(0).  Items with weight 0 get an infinite key -- we return
+/// that directly, because if u_i = 0 we'd be dividing 0 by 0.
+fn exponential_key(weight: f64, rng: &mut impl WeightSource) -> f64 {
+    assert!(
+        weight.is_finite() && weight >= 0.0,
+        "invalid weight {}: weights must be finite and non-negative",
+        weight
+    );
+
+    if weight == 0.0 {
+        return f64::INFINITY;
+    }
+
+    -(1.0 - rng.next_weight()).ln() / weight
+}
+
+/// Draw how much weight we can skip before the next item goes into
+/// the reservoir, when the largest key in the reservoir is `threshold`.
+///
+/// If the threshold is infinite (there's a weight-0 item in the reservoir),
+/// the jump is 0, and the next item with a positive weight replaces it.
+fn exponential_jump(threshold: f64, rng: &mut impl WeightSource) -> f64 {
+    -(1.0 - rng.next_weight()).ln() / threshold
+}
+
+#[cfg(test)]
+mod reservoir_sample_tests {
+    use super::*;
+    use std::collections::HashMap;
+
+    // If there are no items, then the sample is empty.
+    #[test]
+    fn it_returns_an_empty_sample_for_an_empty_input() {
+        let items: Vec<usize> = vec![];
+        let sample = reservoir_sample(items.into_iter(), 5, &mut rand::rng());
+
+        assert_eq!(sample.len(), 0);
+    }
+
+    // If there are less items than the sample size, then the sample is
+    // the complete set.
+    #[test]
+    fn it_returns_complete_sample_if_less_items_than_sample_size() {
+        let items = vec!["a", "b", "c"];
+        let sample = reservoir_sample(items.into_iter(), 5, &mut rand::rng());
+
+        assert!(equivalent_items(sample, vec!["a", "b", "c"]));
+    }
+
+    // If there's an equal number of items to the sample size, then the
+    // sample is the complete set.
+    #[test]
+    fn it_returns_complete_sample_if_item_count_equal_to_sample_size() {
+        let items = vec!["a", "b", "c"];
+        let sample = reservoir_sample(items.into_iter(), 3, &mut rand::rng());
+
+        assert!(equivalent_items(sample, vec!["a", "b", "c"]));
+    }
+
+    // If k=0, then it returns an empty sample.
+    #[test]
+    fn it_returns_an_empty_sample_if_k_zero() {
+        let items = vec!["a", "b", "c"];
+        let sample = reservoir_sample(items.into_iter(), 0, &mut rand::rng());
+
+        assert_eq!(sample.len(), 0);
+    }
+
+    // With several reservoirs, we get n samples of k items each.
+    #[test]
+    fn it_picks_n_samples_of_k_items() {
+        let items: Vec<usize> = (0..100).collect();
+        let samples = multi_reservoir_sample(items.into_iter(), 5, 3, &mut rand::rng());
+
+        assert_eq!(samples.len(), 3);
+
+        for sample in samples {
+            assert_eq!(sample.len(), 5);
+
+            let mut unique = sample.clone();
+            unique.sort();
+            unique.dedup();
+            assert_eq!(unique.len(), 5);
+        }
+    }
+
+    // Each reservoir gets its own weights, so the samples are different.
+    #[test]
+    fn it_picks_independent_samples() {
+        let items = vec!["a", "b", "c"];
+        let mut rng = ScriptedWeights::new(vec![0.1, 0.9, 0.9, 0.1, 0.5, 0.5]);
+        let samples = multi_reservoir_sample(items.into_iter(), 1, 2, &mut rng);
+
+        assert_eq!(samples, vec![vec!["a"], vec!["b"]]);
+    }
+
+    // The selected and rejected items partition the input: every item
+    // is in exactly one of them.
+    #[test]
+    fn it_splits_the_input_into_selected_and_rejected() {
+        let items: Vec<usize> = (0..100).collect();
+        let (selected, rejected) =
+            reservoir_sample_with_rejects(items.into_iter(), 10, &mut rand::rng());
+
+        assert_eq!(selected.len(), 10);
+        assert_eq!(rejected.len(), 90);
+
+        let mut all: Vec<usize> = selected.into_iter().chain(rejected).collect();
+        all.sort();
+        assert_eq!(all, (0..100).collect::<Vec<usize>>());
+    }
+
+    // The items with the smallest weights are selected, and the rest are
+    // rejected in their original order.
+    #[test]
+    fn it_picks_the_smallest_weights_and_rejects_the_rest() {
+        let items = vec!["a", "b", "c", "d"];
+        let mut rng = ScriptedWeights::new(vec![0.4, 0.1, 0.3, 0.2]);
+        let (selected, rejected) = reservoir_sample_with_rejects(items.into_iter(), 2, &mut rng);
+
+        assert_eq!(selected, vec!["b", "d"]);
+        assert_eq!(rejected, vec!["a", "c"]);
+    }
+
+    // If k is bigger than the input, nothing is rejected.
+    #[test]
+    fn it_rejects_nothing_if_k_is_bigger_than_the_input() {
+        let items = vec!["a", "b"];
+        let (selected, rejected) =
+            reservoir_sample_with_rejects(items.into_iter(), 5, &mut rand::rng());
+
+        assert!(equivalent_items(selected, vec!["a", "b"]));
+        assert_eq!(rejected, Vec::<&str>::new());
     }
 
     // It chooses items with a uniform distribution -- every item has
@@ -175,7 +1163,7 @@ mod reservoir_sample_tests {
         // times each integer was picked.
         for _ in 0..iterations {
             let items = 0..n;
-            let sample = reservoir_sample(items, k);
+            let sample = reservoir_sample(items, k, &mut rand::rng());
 
             for s in sample.into_iter() {
                 *counts.entry(s).or_insert(0) += 1;
@@ -200,6 +1188,921 @@ mod reservoir_sample_tests {
         }
     }
 
+    // All the implementations of uniform sampling pick every item with the
+    // same probability, so they agree with each other.
+    //
+    // We run each of them many times on the integers 0..n, and check each
+    // integer is picked about as often as we expect.
+    #[test]
+    fn test_reservoir_impls_distribution() {
+        let k = 10;
+        let n = 100;
+        let iterations = 10000;
+
+        for reservoir_impl in [
+            ReservoirImpl::Naive,
+            ReservoirImpl::AlgorithmL,
+            ReservoirImpl::AlgorithmR,
+        ] {
+            let mut counts: HashMap<usize, usize> = HashMap::new();
+
+            for _ in 0..iterations {
+                let sample = reservoir_impl.sample(0..n, k, &mut rand::rng());
+                assert_eq!(sample.len(), k);
+
+                for s in sample.into_iter() {
+                    *counts.entry(s).or_insert(0) += 1;
+                }
+            }
+
+            let expected = (iterations * k) as f64 / n as f64;
+
+            for item in 0..n {
+                let item_count = *counts.get(&item).unwrap_or(&0);
+
+                let ratio = (item_count as f64) / expected;
+                assert!(
+                    ratio > 0.8 && ratio < 1.2,
+                    "Distribution appears skewed: impl={:?}, item={}, count={}, expected={}",
+                    reservoir_impl,
+                    item,
+                    item_count,
+                    expected
+                );
+            }
+        }
+    }
+
+    // Every implementation returns everything if k is bigger than the
+    // input, and nothing if k is 0.
+    #[test]
+    fn test_reservoir_impls_small_inputs() {
+        for reservoir_impl in [
+            ReservoirImpl::Naive,
+            ReservoirImpl::AlgorithmL,
+            ReservoirImpl::AlgorithmR,
+        ] {
+            let sample = reservoir_impl.sample(0..3, 5, &mut rand::rng());
+            assert!(equivalent_items(sample, vec![0, 1, 2]));
+
+            let sample = reservoir_impl.sample(0..3, 0, &mut rand::rng());
+            assert_eq!(sample, Vec::<usize>::new());
+        }
+    }
+
+    // The capacity only changes how much memory we allocate up front,
+    // so a too-small or too-big capacity picks the same sample -- even one
+    // that's far too big to allocate.
+    #[test]
+    fn the_capacity_doesnt_change_the_sample() {
+        use rand::{rngs::StdRng, SeedableRng};
+
+        for reservoir_impl in [
+            ReservoirImpl::Naive,
+            ReservoirImpl::AlgorithmL,
+            ReservoirImpl::AlgorithmR,
+        ] {
+            let samples: Vec<Vec<usize>> = [0, 1, 10, 1000, usize::MAX]
+                .into_iter()
+                .map(|capacity| {
+                    let mut rng = StdRng::seed_from_u64(42);
+                    reservoir_impl.sample_with_capacity(0..100, 10, capacity, &mut rng)
+                })
+                .collect();
+
+            assert!(samples.iter().all(|s| *s == samples[0]));
+        }
+
+        let samples: Vec<Vec<usize>> = [0, 1, 10, 1000, usize::MAX]
+            .into_iter()
+            .map(|capacity| {
+                let mut rng = StdRng::seed_from_u64(42);
+                let items = (0..100).map(|i| (i as f64, i));
+                weighted_reservoir_sample_expj_with_capacity(items, 10, capacity, &mut rng)
+            })
+            .collect();
+
+        assert!(samples.iter().all(|s| *s == samples[0]));
+    }
+
+    // Combining samples from two halves of the input picks every item
+    // with the same probability as sampling the whole input in one pass.
+    #[test]
+    fn combining_samples_is_like_a_single_pass() {
+        let k = 5;
+        let iterations = 20000;
+        let mut counts: HashMap<usize, usize> = HashMap::new();
+
+        for _ in 0..iterations {
+            let first = reservoir_sample(0..10, k, &mut rand::rng());
+            let second = reservoir_sample(10..40, k, &mut rand::rng());
+
+            let sample = combine_samples(first, 10, second, 30, k, &mut rand::rng());
+            assert_eq!(sample.len(), k);
+
+            for s in sample {
+                *counts.entry(s).or_insert(0) += 1;
+            }
+        }
+
+        let expected = (iterations * k) as f64 / 40.0;
+
+        for item in 0..40 {
+            let ratio = *counts.get(&item).unwrap_or(&0) as f64 / expected;
+            assert!(
+                ratio > 0.9 && ratio < 1.1,
+                "Distribution appears skewed: item={}, ratio={}",
+                item,
+                ratio
+            );
+        }
+    }
+
+    // If k is bigger than both populations, we keep everything.
+    #[test]
+    fn combining_small_samples_keeps_everything() {
+        let sample = combine_samples(vec![1, 2], 2, vec![3], 1, 5, &mut rand::rng());
+        assert!(equivalent_items(sample, vec![1, 2, 3]));
+
+        let sample = combine_samples(vec![1, 2], 2, vec![], 0, 5, &mut rand::rng());
+        assert!(equivalent_items(sample, vec![1, 2]));
+    }
+
+    // If a sample is smaller than it claims, we can't combine it.
+    #[test]
+    #[should_panic(expected = "can't choose 3 items from a sample of 1")]
+    fn combining_panics_if_a_sample_is_too_small() {
+        combine_samples(vec![1], 100, vec![], 0, 3, &mut rand::rng());
+    }
+
+    // In Algorithm R, the i'th item replaces a random slot if the random
+    // index lands in the reservoir.
+    //
+    // Here item "c" gets index floor(0.5 * 3) = 1, so it replaces "b";
+    // item "d" gets index floor(0.9 * 4) = 3, so it's dropped.
+    #[test]
+    fn it_replaces_a_random_slot_in_algorithm_r() {
+        let items = vec!["a", "b", "c", "d"];
+        let mut rng = ScriptedWeights::new(vec![0.5, 0.9]);
+        let sample = reservoir_sample_algorithm_r(items.into_iter(), 2, 2, &mut rng);
+
+        assert_eq!(sample, vec!["a", "c"]);
+    }
+
+    // In a weighted sample, items are picked in proportion to their weight.
+    //
+    // We take a large number of single-item samples from three items
+    // with weights 1, 2 and 7, and check each is picked about as often
+    // as we expect.
+    #[test]
+    fn test_weighted_distribution() {
+        let weights = [1.0, 2.0, 7.0];
+        let iterations = 20000;
+
+        let mut counts: HashMap<usize, usize> = HashMap::new();
+
+        for _ in 0..iterations {
+            let items = weights.iter().enumerate().map(|(i, w)| (*w, i));
+            let sample = weighted_reservoir_sample(items, 1, &mut rand::rng());
+
+            for s in sample.into_iter() {
+                *counts.entry(s).or_insert(0) += 1;
+            }
+        }
+
+        let total_weight: f64 = weights.iter().sum();
+
+        for (i, w) in weights.iter().enumerate() {
+            let expected = iterations as f64 * w / total_weight;
+            let item_count = *counts.get(&i).unwrap_or(&0);
+
+            let ratio = (item_count as f64) / expected;
+            assert!(
+                ratio > 0.9 && ratio < 1.1,
+                "Distribution appears skewed: item={}, count={}, expected={}",
+                i,
+                item_count,
+                expected
+            );
+        }
+    }
+
+    // If every item has the same weight, a weighted sample is the same
+    // as a uniform sample.
+    #[test]
+    fn test_equal_weights_distribution() {
+        let k = 20;
+        let n = 100;
+        let iterations = 10000;
+
+        let mut counts: HashMap<i32, usize> = HashMap::new();
+
+        for _ in 0..iterations {
+            let items = (0..n).map(|i| (3.0, i));
+            let sample = weighted_reservoir_sample(items, k, &mut rand::rng());
+
+            for s in sample.into_iter() {
+                *counts.entry(s).or_insert(0) += 1;
+            }
+        }
+
+        let expected = (iterations * k) as f64 / n as f64;
+
+        for item in 0..n {
+            let item_count = *counts.get(&item).unwrap_or(&0);
+
+            let ratio = (item_count as f64) / expected;
+            assert!(
+                ratio > 0.8 && ratio < 1.2,
+                "Distribution appears skewed: count={}, expected={}",
+                item_count,
+                expected
+            );
+        }
+    }
+
+    // If all the weights are equal, the scripted random weights decide
+    // the sample, just like in a uniform sample.
+    #[test]
+    fn it_picks_the_same_items_as_uniform_if_weights_equal() {
+        let weights = vec![0.5, 0.1, 0.9, 0.2, 0.7];
+
+        let items = vec![(2.0, "a"), (2.0, "b"), (2.0, "c"), (2.0, "d"), (2.0, "e")];
+        let mut rng = ScriptedWeights::new(weights.clone());
+        let weighted = a_res_reservoir_sample(items.into_iter(), 2, &mut rng);
+
+        let items = vec!["a", "b", "c", "d", "e"];
+        let mut rng = ScriptedWeights::new(weights);
+        let uniform = reservoir_sample(items.into_iter(), 2, &mut rng);
+
+        assert!(equivalent_items(weighted, uniform));
+    }
+
+    // Negative or NaN weights are a programming error, so we panic.
+    #[test]
+    #[should_panic(expected = "weights must be finite and non-negative")]
+    fn it_panics_on_negative_weights() {
+        let items = vec![(1.0, "a"), (-1.0, "b")];
+        weighted_reservoir_sample(items.into_iter(), 1, &mut rand::rng());
+    }
+
+    #[test]
+    #[should_panic(expected = "weights must be finite and non-negative")]
+    fn it_panics_on_nan_weights() {
+        let items = vec![(f64::NAN, "a"), (1.0, "b")];
+        weighted_reservoir_sample(items.into_iter(), 5, &mut rand::rng());
+    }
+
+    // An item with weight 0 is never picked if there are enough other
+    // items to fill the sample, even if it gets the "best" random weight.
+    #[test]
+    fn it_never_picks_zero_weight_items() {
+        let items = vec![(1.0, "a"), (0.0, "b"), (1.0, "c")];
+        let mut rng = ScriptedWeights::new(vec![0.5, 0.0, 0.5]);
+        let sample = a_res_reservoir_sample(items.into_iter(), 2, &mut rng);
+
+        assert!(equivalent_items(sample, vec!["a", "c"]));
+    }
+
+    // The sample is the k items with the smallest weights.
+    #[test]
+    fn it_picks_the_items_with_the_smallest_weights() {
+        let items = vec!["a", "b", "c", "d", "e"];
+        let mut rng = ScriptedWeights::new(vec![0.5, 0.1, 0.9, 0.2, 0.7]);
+        let sample = reservoir_sample(items.into_iter(), 2, &mut rng);
+
+        assert!(equivalent_items(sample, vec!["b", "d"]));
+    }
+
+    // In a weighted sample, a heavy item can beat a lighter item, even if
+    // the lighter item gets a better random weight.
+    //
+    // Here both items get u_i = 0.5, so their keys are -ln(0.5)/w_i, and
+    // the item with the larger weight has the smaller key.
+    #[test]
+    fn it_prefers_heavier_items() {
+        let items = vec![(1.0, "light"), (2.0, "heavy")];
+        let mut rng = ScriptedWeights::new(vec![0.5, 0.5]);
+        let sample = a_res_reservoir_sample(items.into_iter(), 1, &mut rng);
+
+        assert_eq!(sample, vec!["heavy"]);
+    }
+
+    // A-ExpJ picks items in proportion to their weight, the same as A-Res.
+    //
+    // We take a large number of samples from a long run of items, so most
+    // of them are reached by a jump rather than filling the reservoir.
+    #[test]
+    fn test_expj_weighted_distribution() {
+        let weights: Vec<f64> = (0..50).map(|i| (i % 5 + 1) as f64).collect();
+        let k = 5;
+        let iterations = 20000;
+
+        let mut counts: HashMap<usize, usize> = HashMap::new();
+
+        for _ in 0..iterations {
+            let items = weights.iter().enumerate().map(|(i, w)| (*w, i));
+            let sample = weighted_reservoir_sample(items, k, &mut rand::rng());
+            assert_eq!(sample.len(), k);
+
+            for s in sample.into_iter() {
+                *counts.entry(s).or_insert(0) += 1;
+            }
+        }
+
+        // Group the items by weight, and compare A-ExpJ to A-Res -- the
+        // inclusion probabilities for k > 1 aren't simply proportional
+        // to the weights, so we use A-Res as the reference.
+        let mut reference: HashMap<usize, usize> = HashMap::new();
+
+        for _ in 0..iterations {
+            let items = weights.iter().enumerate().map(|(i, w)| (*w, i));
+            for s in a_res_reservoir_sample(items, k, &mut rand::rng()) {
+                *reference.entry(s).or_insert(0) += 1;
+            }
+        }
+
+        for w in 1..=5 {
+            let count_for = |counts: &HashMap<usize, usize>| -> usize {
+                (0..weights.len())
+                    .filter(|i| weights[*i] == w as f64)
+                    .map(|i| *counts.get(&i).unwrap_or(&0))
+                    .sum()
+            };
+
+            let ratio = count_for(&counts) as f64 / count_for(&reference) as f64;
+            assert!(
+                ratio > 0.9 && ratio < 1.1,
+                "Distribution appears skewed: weight={}, ratio={}",
+                w,
+                ratio
+            );
+        }
+    }
+
+    // A-ExpJ skips over items without drawing a random number for them,
+    // so it needs far fewer random numbers than there are items.
+    #[test]
+    fn it_skips_items_with_exponential_jumps() {
+        struct CountingWeights {
+            calls: usize,
+        }
+
+        impl WeightSource for CountingWeights {
+            fn next_weight(&mut self) -> f64 {
+                self.calls += 1;
+                rand::rng().next_weight()
+            }
+        }
+
+        let n = 100_000;
+        let k = 10;
+
+        let mut rng = CountingWeights { calls: 0 };
+        let sample = weighted_reservoir_sample((0..n).map(|i| (1.0, i)), k, &mut rng);
+
+        assert_eq!(sample.len(), k);
+        assert!(rng.calls < n / 100, "calls = {}", rng.calls);
+    }
+
+    // Until the reservoir is full, A-ExpJ gives items the same keys as
+    // A-Res, so with scripted weights it picks the same items.
+    #[test]
+    fn it_fills_the_reservoir_like_a_res() {
+        let items = vec![(1.0, "light"), (2.0, "heavy")];
+        let mut rng = ScriptedWeights::new(vec![0.5, 0.5]);
+        let sample = weighted_reservoir_sample(items.into_iter(), 5, &mut rng);
+
+        assert!(equivalent_items(sample, vec!["heavy", "light"]));
+    }
+
+    // An item with weight 0 in a full reservoir is replaced by the next
+    // item with a positive weight, and is never jumped to.
+    #[test]
+    fn it_never_jumps_to_zero_weight_items() {
+        let items = vec![(0.0, "a"), (0.0, "b"), (1.0, "c"), (0.0, "d")];
+        let sample = weighted_reservoir_sample(items.into_iter(), 1, &mut rand::rng());
+
+        assert_eq!(sample, vec!["c"]);
+    }
+
+    #[test]
+    #[should_panic(expected = "weights must be finite and non-negative")]
+    fn it_panics_on_negative_weights_after_the_reservoir_is_full() {
+        let items = vec![(1.0, "a"), (-1.0, "b")];
+        weighted_reservoir_sample(items.into_iter(), 1, &mut rand::rng());
+    }
+
+    // In a windowed sample, items that have left the window are never
+    // picked, even if there's room in the sample.
+    #[test]
+    fn it_only_picks_items_in_the_window() {
+        let items = (0..100).map(|t| (t as f64, t));
+        let sample = windowed_reservoir_sample(items, 50, 10.0, &mut rand::rng());
+
+        assert!(equivalent_items(sample, (89..100).collect()));
+    }
+
+    // An item that's been beaten by k later items is dropped, but the
+    // items that beat it are still picked.
+    #[test]
+    fn it_picks_the_smallest_weights_in_the_window() {
+        let items = vec![(0.0, "a"), (1.0, "b"), (2.0, "c"), (3.0, "d")];
+        let mut rng = ScriptedWeights::new(vec![0.1, 0.9, 0.5, 0.3]);
+        let sample = windowed_reservoir_sample(items.into_iter(), 2, 2.0, &mut rng);
+
+        // "a" has the smallest weight, but it's left the window
+        assert!(equivalent_items(sample, vec!["c", "d"]));
+    }
+
+    // If timestamps are out of order, an item with a smaller weight but
+    // an earlier timestamp doesn't knock out a candidate, because it
+    // leaves the window first -- here "b" beats "a", but then expires,
+    // and "a" is still the smallest weight in the window.
+    #[test]
+    fn it_keeps_candidates_beaten_by_earlier_timestamps() {
+        let items = vec![(100.0, "a"), (95.0, "b"), (106.0, "c")];
+        let mut rng = ScriptedWeights::new(vec![0.5, 0.1, 0.9]);
+        let sample = windowed_reservoir_sample(items.into_iter(), 1, 10.0, &mut rng);
+
+        assert_eq!(sample, vec!["a"]);
+    }
+
+    // With timestamps in any order, we pick the same items as checking
+    // every item in the final window.
+    #[test]
+    fn it_matches_a_brute_force_sample_with_out_of_order_timestamps() {
+        use rand::{rngs::StdRng, Rng, SeedableRng};
+
+        for seed in 0..200 {
+            let mut rng = StdRng::seed_from_u64(seed);
+            let timestamps: Vec<f64> = (0..50)
+                .map(|i| i as f64 + rng.random_range(-8.0..8.0))
+                .collect();
+            let weights: Vec<f64> = (0..50).map(|_| rng.next_weight()).collect();
+
+            let window = 10.0;
+
+            // Items which arrive after they've left the window are
+            // discarded without drawing a weight, so we only give weights
+            // to the items which arrive on time.
+            let mut latest = f64::NEG_INFINITY;
+            let mut on_time: Vec<(f64, f64, usize)> = Vec::new();
+
+            for (i, t) in timestamps.iter().enumerate() {
+                latest = latest.max(*t);
+
+                if *t >= latest - window {
+                    on_time.push((*t, weights[i], i));
+                }
+            }
+
+            // The sample is the 3 smallest weights in the final window.
+            let mut expected: Vec<(f64, usize)> = on_time
+                .iter()
+                .filter(|(t, _, _)| *t >= latest - window)
+                .map(|(_, w, i)| (*w, *i))
+                .collect();
+            expected.sort_by(|a, b| a.0.partial_cmp(&b.0).unwrap());
+            let expected: Vec<usize> = expected.into_iter().take(3).map(|(_, i)| i).collect();
+
+            let items = timestamps.iter().cloned().zip(0..);
+            let mut scripted = ScriptedWeights::new(on_time.iter().map(|(_, w, _)| *w).collect());
+            let sample = windowed_reservoir_sample(items, 3, window, &mut scripted);
+
+            assert!(equivalent_items(sample, expected), "seed = {}", seed);
+        }
+    }
+
+    // Items that arrive late, after they've already left the window,
+    // are discarded.
+    #[test]
+    fn it_discards_items_which_arrive_late() {
+        let items = vec![(10.0, "a"), (0.0, "b"), (9.0, "c")];
+        let sample = windowed_reservoir_sample(items.into_iter(), 5, 5.0, &mut rand::rng());
+
+        assert!(equivalent_items(sample, vec!["a", "c"]));
+    }
+
+    // Items in the window are picked uniformly.
+    #[test]
+    fn test_windowed_distribution() {
+        let k = 5;
+        let iterations = 10000;
+
+        let mut counts: HashMap<i32, usize> = HashMap::new();
+
+        for _ in 0..iterations {
+            let items = (0..100).map(|t| (t as f64, t));
+            let sample = windowed_reservoir_sample(items, k, 19.0, &mut rand::rng());
+
+            for s in sample.into_iter() {
+                *counts.entry(s).or_insert(0) += 1;
+            }
+        }
+
+        let expected = (iterations * k) as f64 / 20.0;
+
+        for item in 80..100 {
+            let item_count = *counts.get(&item).unwrap_or(&0);
+
+            let ratio = (item_count as f64) / expected;
+            assert!(
+                ratio > 0.8 && ratio < 1.2,
+                "Distribution appears skewed: count={}, expected={}",
+                item_count,
+                expected
+            );
+        }
+    }
+
+    // When sampling with replacement, we always pick k items, even if
+    // that's more than there are in the input.
+    #[test]
+    fn it_picks_k_items_with_replacement() {
+        let items = vec![(1.0, "a"), (1.0, "b")];
+        let sample = weighted_sample_with_replacement(&items, 10, &mut rand::rng());
+
+        assert_eq!(sample.len(), 10);
+        assert!(sample.iter().all(|s| *s == "a" || *s == "b"));
+    }
+
+    // With a cumulative weight table, each draw lands on the item whose
+    // range of cumulative weight contains it, and zero-weight items
+    // have an empty range.
+    #[test]
+    fn it_picks_items_by_cumulative_weight() {
+        let items = vec![(1.0, "a"), (0.0, "b"), (3.0, "c")];
+        let mut rng = ScriptedWeights::new(vec![0.0, 0.2, 0.25, 0.99]);
+        let sample = cumulative_sample_with_replacement(&items, 4, &mut rng);
+
+        assert_eq!(sample, vec!["a", "a", "c", "c"]);
+    }
+
+    // If the total weight is zero, there's nothing to pick.
+    #[test]
+    fn it_returns_an_empty_sample_if_total_weight_zero() {
+        let items = vec![(0.0, "a"), (0.0, "b")];
+        let sample = weighted_sample_with_replacement(&items, 5, &mut rand::rng());
+
+        assert_eq!(sample.len(), 0);
+    }
+
+    // When sampling with replacement, items are picked in proportion
+    // to their weight.
+    #[test]
+    fn test_weighted_with_replacement_distribution() {
+        let items = vec![(1.0, 0), (2.0, 1), (7.0, 2)];
+        let draws = 50000;
+
+        let sample = weighted_sample_with_replacement(&items, draws, &mut rand::rng());
+
+        for (w, i) in items {
+            let expected = draws as f64 * w / 10.0;
+            let item_count = sample.iter().filter(|s| **s == i).count();
+
+            let ratio = (item_count as f64) / expected;
+            assert!(
+                ratio > 0.9 && ratio < 1.1,
+                "Distribution appears skewed: item={}, count={}, expected={}",
+                i,
+                item_count,
+                expected
+            );
+        }
+    }
+
+    // An alias table gives each item exactly the right share of the
+    // total probability.
+    //
+    // Each column holds 1/n of the total probability; item i gets
+    // probabilities[i] of its own column, plus the remainder of every
+    // column where it's the alias.
+    #[test]
+    fn it_builds_an_alias_table_with_the_right_probabilities() {
+        let weights = vec![1.0, 2.0, 0.0, 7.0, 5.0, 5.0];
+        let table = AliasTable::new(&weights).unwrap();
+        let n = weights.len() as f64;
+        let total_weight: f64 = weights.iter().sum();
+
+        for (i, w) in weights.iter().enumerate() {
+            let mut probability = table.probabilities[i] / n;
+
+            for (j, alias) in table.aliases.iter().enumerate() {
+                if *alias == i && j != i {
+                    probability += (1.0 - table.probabilities[j]) / n;
+                }
+            }
+
+            let expected = w / total_weight;
+            assert!(
+                (probability - expected).abs() < 1e-9,
+                "item={}, probability={}, expected={}",
+                i,
+                probability,
+                expected
+            );
+        }
+    }
+
+    // An alias table picks a column with the integer part of the random
+    // number, and the item or its alias with the fractional part.
+    #[test]
+    fn it_draws_from_an_alias_table() {
+        // Scaled weights are [0.5, 1.5], so column 0 is half item 0 and
+        // half item 1, and column 1 is all item 1.
+        let table = AliasTable::new(&[1.0, 3.0]).unwrap();
+        let mut rng = ScriptedWeights::new(vec![0.1, 0.3, 0.6, 0.9]);
+
+        let draws: Vec<usize> = (0..4).map(|_| table.sample(&mut rng)).collect();
+
+        assert_eq!(draws, vec![0, 1, 1, 1]);
+    }
+
+    // Weights whose total is bigger than the biggest float still get
+    // the right probabilities.
+    #[test]
+    fn it_builds_an_alias_table_for_weights_whose_total_overflows() {
+        let table = AliasTable::new(&[1e308, 1e308, 0.0]).unwrap();
+
+        assert_eq!(table.probabilities[2], 0.0);
+        assert_ne!(table.aliases[2], 2);
+
+        let mut rng = ScriptedWeights::new(vec![0.1, 0.5, 0.9]);
+        let draws: Vec<usize> = (0..3).map(|_| table.sample(&mut rng)).collect();
+
+        assert!(!draws.contains(&2));
+    }
+
+    // There's no alias table for an empty list of weights, or if all
+    // the weights are zero.
+    #[test]
+    fn it_cant_build_an_alias_table_without_weight() {
+        assert!(AliasTable::new(&[]).is_none());
+        assert!(AliasTable::new(&[0.0, 0.0]).is_none());
+    }
+
+    // Compare the speed of the alias method and a cumulative weight table
+    // for lots of weighted draws.  This is ignored by default; run it with
+    //
+    //     cargo test --release -- --ignored --nocapture bench_
+    //
+    #[test]
+    #[ignore]
+    fn bench_alias_vs_cumulative_search() {
+        use std::time::Instant;
+
+        let items: Vec<(f64, usize)> = (0..100_000).map(|i| ((i % 100) as f64, i)).collect();
+        let k = 1_000_000;
+
+        let start = Instant::now();
+        let cumulative = cumulative_sample_with_replacement(&items, k, &mut rand::rng());
+        let cumulative_time = start.elapsed();
+
+        let start = Instant::now();
+        let alias = weighted_sample_with_replacement(&items, k, &mut rand::rng());
+        let alias_time = start.elapsed();
+
+        assert_eq!(cumulative.len(), alias.len());
+
+        println!(
+            "n={}, k={}: cumulative search = {:?}, alias = {:?}",
+            items.len(),
+            k,
+            cumulative_time,
+            alias_time
+        );
+    }
+
+    // The heap and the sorted vec are two ways to hold the reservoir, so
+    // with the same random weights, they should pick the same items.
+    #[test]
+    fn the_reservoir_backends_agree() {
+        for k in [0, 1, 2, 5, 10, 100, 1000] {
+            let weights: Vec<f64> = (0..500).map(|_| rand::random()).collect();
+
+            let from_heap = sample_smallest_weights_in::<BinaryHeap<WeightedItem<usize>>, usize>(
+                0..500,
+                k,
+                k,
+                |i| weights[*i],
+            );
+            let from_sorted_vec =
+                sample_smallest_weights_in::<SortedVec<usize>, usize>(0..500, k, k, |i| {
+                    weights[*i]
+                });
+
+            assert!(equivalent_items(from_heap, from_sorted_vec), "k = {}", k);
+        }
+    }
+
+    // Compare the speed of the two reservoir backends for different k,
+    // to find where the heap starts to win.  This is ignored by default;
+    // run it with
+    //
+    //     cargo test --release -- --ignored --nocapture bench_
+    //
+    #[test]
+    #[ignore]
+    fn bench_heap_vs_sorted_vec() {
+        use std::time::Instant;
+
+        // Most items don't go into the reservoir, so we also try weights
+        // that get smaller and smaller, where every item does.
+        let n = 1_000_000;
+        let random_weights: Vec<f64> = (0..n).map(|_| rand::random()).collect();
+        let shrinking_weights: Vec<f64> = (0..n).map(|i| 1.0 - i as f64 / n as f64).collect();
+
+        for (k, weights) in [1, 2, 4, 16, 64, 256, 1024, 4096]
+            .into_iter()
+            .flat_map(|k| [(k, &random_weights), (k, &shrinking_weights)])
+        {
+            let start = Instant::now();
+            let from_heap = sample_smallest_weights_in::<BinaryHeap<WeightedItem<usize>>, usize>(
+                0..n,
+                k,
+                k,
+                |i| weights[*i],
+            );
+            let heap_time = start.elapsed();
+
+            let start = Instant::now();
+            let from_sorted_vec =
+                sample_smallest_weights_in::<SortedVec<usize>, usize>(0..n, k, k, |i| weights[*i]);
+            let sorted_vec_time = start.elapsed();
+
+            assert_eq!(from_heap.len(), from_sorted_vec.len());
+
+            println!(
+                "n={}, k={}, {}: heap = {:?}, sorted vec = {:?}",
+                n,
+                k,
+                if weights == &random_weights {
+                    "random"
+                } else {
+                    "shrinking"
+                },
+                heap_time,
+                sorted_vec_time
+            );
+        }
+    }
+
+    // With a byte budget, we take items in a random order until the
+    // next one doesn't fit, counting a newline after each item.
+    #[test]
+    fn it_picks_items_until_the_budget_is_spent() {
+        let items = vec!["aaa", "b", "cc", "dddd"];
+        let mut rng = ScriptedWeights::new(vec![0.3, 0.1, 0.2, 0.4]);
+        let sample = byte_budget_sample(items, 9, |s| s.len(), (1, 1), &mut rng);
+
+        // "b\n" + "cc\n" + "aaa\n" is 9 bytes, and "dddd\n" doesn't fit
+        assert_eq!(sample, vec!["b", "cc", "aaa"]);
+    }
+
+    // We stop at the first item that doesn't fit, even if a later
+    // item would.
+    #[test]
+    fn it_stops_at_the_first_item_that_doesnt_fit() {
+        let items = vec!["aaaa", "b"];
+        let mut rng = ScriptedWeights::new(vec![0.1, 0.2]);
+        let sample = byte_budget_sample(items, 4, |s| s.len(), (1, 1), &mut rng);
+
+        assert_eq!(sample, Vec::<&str>::new());
+    }
+
+    // The sample never goes over the budget.
+    #[test]
+    fn it_stays_within_the_budget() {
+        let items: Vec<String> = (0..1000).map(|i| "x".repeat(i % 17)).collect();
+
+        for budget in [0, 1, 10, 100, 1000] {
+            let sample =
+                byte_budget_sample(items.clone(), budget, |s| s.len(), (1, 1), &mut rand::rng());
+            let size: usize = sample.iter().map(|s| s.len() + 1).sum();
+
+            assert!(size <= budget, "size = {}, budget = {}", size, budget);
+        }
+    }
+
+    // The separator goes between items, and the terminator after the
+    // last one, so "a, b\r\n" is 6 bytes.
+    #[test]
+    fn it_counts_the_separator_and_terminator() {
+        let items = vec!["a", "b", "c"];
+
+        for (budget, expected) in [
+            (2, vec![]),
+            (3, vec!["a"]),
+            (5, vec!["a"]),
+            (6, vec!["a", "b"]),
+        ] {
+            let mut rng = ScriptedWeights::new(vec![0.1, 0.2, 0.3]);
+            let sample = byte_budget_sample(items.clone(), budget, |s| s.len(), (2, 2), &mut rng);
+
+            assert_eq!(sample, expected, "budget = {}", budget);
+        }
+    }
+
+    // With Bernoulli sampling, each item is kept if its random weight
+    // is less than its probability.
+    #[test]
+    fn it_keeps_items_below_their_probability() {
+        let items = vec![(0.5, "a"), (0.5, "b"), (0.0, "c"), (1.0, "d")];
+        let mut rng = ScriptedWeights::new(vec![0.4, 0.6, 0.0, 0.99]);
+        let sample = bernoulli_sample(items.into_iter(), &mut rng);
+
+        assert_eq!(sample, vec!["a", "d"]);
+    }
+
+    // Over lots of items, we keep each item about as often as its
+    // probability says we should.
+    #[test]
+    fn test_bernoulli_distribution() {
+        let n = 100_000;
+        let probabilities = [0.1, 0.5, 0.9];
+
+        let items = (0..n).map(|i| (probabilities[i % 3], i % 3));
+        let sample = bernoulli_sample(items, &mut rand::rng());
+
+        for (bucket, p) in probabilities.iter().enumerate() {
+            let kept = sample.iter().filter(|b| **b == bucket).count();
+            let expected = p * (n / 3) as f64;
+
+            let ratio = kept as f64 / expected;
+            assert!(
+                ratio > 0.95 && ratio < 1.05,
+                "Distribution appears skewed: p={}, kept={}, expected={}",
+                p,
+                kept,
+                expected
+            );
+        }
+    }
+
+    // In key order, the items come out sorted by the weights they were
+    // given, rather than in the order of the reservoir.
+    #[test]
+    fn it_returns_the_sample_in_key_order() {
+        let mut rng = ScriptedWeights::new(vec![0.4, 0.1, 0.9, 0.3, 0.2]);
+        let sample =
+            reservoir_sample_in_key_order(["a", "b", "c", "d", "e"].into_iter(), 3, 3, &mut rng);
+        assert_eq!(sample, vec!["b", "e", "d"]);
+
+        let mut rng = ScriptedWeights::new(vec![0.4, 0.1, 0.9, 0.3, 0.2]);
+        let unordered = reservoir_sample(["a", "b", "c", "d", "e"].into_iter(), 3, &mut rng);
+        assert!(equivalent_items(sample, unordered));
+    }
+
+    #[test]
+    fn it_returns_a_weighted_sample_in_key_order() {
+        use rand::{rngs::StdRng, SeedableRng};
+
+        let items = vec![(1.0, "a"), (2.0, "b"), (4.0, "c"), (8.0, "d")];
+
+        for seed in 0..100 {
+            let mut rng = StdRng::seed_from_u64(seed);
+            let sample = weighted_reservoir_sample_expj_in_key_order(
+                items.clone().into_iter(),
+                4,
+                4,
+                &mut rng,
+            );
+
+            // With a full sample, every item gets a key from a single
+            // draw, so we can work out the keys ourselves.
+            let mut rng = StdRng::seed_from_u64(seed);
+            let mut keyed: Vec<(f64, &str)> = items
+                .iter()
+                .map(|(w, item)| (exponential_key(*w, &mut rng), *item))
+                .collect();
+            keyed.sort_by(|a, b| a.0.partial_cmp(&b.0).unwrap());
+
+            let expected: Vec<&str> = keyed.into_iter().map(|(_, item)| item).collect();
+            assert_eq!(sample, expected, "seed = {}", seed);
+        }
+    }
+
+    /// A weight source that returns a fixed sequence of weights, so
+    /// tests can choose exactly which items get picked.
+    struct ScriptedWeights {
+        weights: std::vec::IntoIter<f64>,
+    }
+
+    impl ScriptedWeights {
+        fn new(weights: Vec<f64>) -> ScriptedWeights {
+            ScriptedWeights {
+                weights: weights.into_iter(),
+            }
+        }
+    }
+
+    impl WeightSource for ScriptedWeights {
+        fn next_weight(&mut self) -> f64 {
+            self.weights.next().expect("ran out of scripted weights")
+        }
+    }
+
     /// Returns true if two vectors contain the same items (but potentially
     /// in a different order), false otherwise.
     ///
@@ -219,3 +2122,116 @@ mod reservoir_sample_tests {
         vec1 == vec2
     }
 }
+
+// These check invariants that should hold for any input and any k,
+// rather than for a handful of hand-picked examples.  Each item is
+// tagged with its position, so we can tell apart items with the same
+// value, and check we never pick the same position twice.
+#[cfg(test)]
+mod reservoir_sample_proptests {
+    use super::*;
+    use proptest::prelude::*;
+    use rand::rngs::StdRng;
+    use rand::SeedableRng;
+    use std::collections::HashSet;
+
+    const IMPLS: [ReservoirImpl; 3] = [
+        ReservoirImpl::Naive,
+        ReservoirImpl::AlgorithmL,
+        ReservoirImpl::AlgorithmR,
+    ];
+
+    /// Check that `sample` is made of items from `items`, and never
+    /// has the same position twice.
+    fn assert_subset_of(sample: &[(usize, u8)], items: &[u8]) {
+        let mut positions = HashSet::new();
+
+        for (i, value) in sample {
+            assert_eq!(items.get(*i), Some(value), "item {} isn't in the input", i);
+            assert!(positions.insert(*i), "item {} was picked twice", i);
+        }
+    }
+
+    proptest! {
+        #[test]
+        fn it_picks_min_k_n_items(items: Vec<u8>, k in 0..50usize, seed: u64) {
+            for reservoir_impl in IMPLS {
+                let mut rng = StdRng::seed_from_u64(seed);
+                let sample = reservoir_impl.sample(items.iter().enumerate(), k, &mut rng);
+
+                prop_assert_eq!(sample.len(), k.min(items.len()), "impl = {:?}", reservoir_impl);
+            }
+        }
+
+        #[test]
+        fn it_picks_distinct_items_from_the_input(items: Vec<u8>, k in 0..50usize, seed: u64) {
+            for reservoir_impl in IMPLS {
+                let mut rng = StdRng::seed_from_u64(seed);
+                let sample: Vec<(usize, u8)> = reservoir_impl.sample(
+                    items.iter().copied().enumerate(),
+                    k,
+                    &mut rng,
+                );
+
+                assert_subset_of(&sample, &items);
+            }
+        }
+
+        #[test]
+        fn it_picks_min_k_n_distinct_weighted_items(
+            items: Vec<(u8, u8)>,
+            k in 0..50usize,
+            seed: u64,
+        ) {
+            let mut rng = StdRng::seed_from_u64(seed);
+            let sample: Vec<(usize, u8)> = weighted_reservoir_sample(
+                items.iter().enumerate().map(|(i, (w, v))| (*w as f64 + 1.0, (i, *v))),
+                k,
+                &mut rng,
+            );
+
+            let values: Vec<u8> = items.iter().map(|(_, v)| *v).collect();
+
+            prop_assert_eq!(sample.len(), k.min(items.len()));
+            assert_subset_of(&sample, &values);
+        }
+
+        #[test]
+        fn it_splits_every_item_into_selected_or_rejected(
+            items: Vec<u8>,
+            k in 0..50usize,
+            seed: u64,
+        ) {
+            let mut rng = StdRng::seed_from_u64(seed);
+            let (selected, rejected) = reservoir_sample_with_rejects(
+                items.iter().copied().enumerate(),
+                k,
+                &mut rng,
+            );
+
+            prop_assert_eq!(selected.len(), k.min(items.len()));
+            prop_assert!(rejected.windows(2).all(|w| w[0].0 < w[1].0));
+
+            let mut everything = selected;
+            everything.extend(rejected);
+            everything.sort();
+
+            prop_assert_eq!(everything, items.into_iter().enumerate().collect::<Vec<_>>());
+        }
+
+        #[test]
+        fn it_always_picks_k_items_with_replacement(
+            items: Vec<u8>,
+            k in 0..50usize,
+            seed: u64,
+        ) {
+            let weighted: Vec<(f64, u8)> = items.iter().map(|v| (1.0, *v)).collect();
+
+            let mut rng = StdRng::seed_from_u64(seed);
+            let sample = weighted_sample_with_replacement(&weighted, k, &mut rng);
+
+            prop_assert_eq!(sample.len(), if items.is_empty() { 0 } else { k });
+            prop_assert!(sample.iter().all(|v| items.contains(v)));
+        }
+    }
+}
@@ -0,0 +1,95 @@
+use crate::fields::nth_field;
+
+/// How we turn a score into the probability of keeping a line.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ScoreTransform {
+    /// A logistic curve: 50% at the threshold, rising smoothly towards
+    /// 100% for higher scores and falling towards 0% for lower scores.
+    /// The scale controls how steep the curve is.
+    Sigmoid,
+
+    /// A straight line: 0% at the threshold, rising to 100% at
+    /// `threshold + scale`, and clamped outside that range.
+    Linear,
+}
+
+/// The parameters for turning a score into a keep probability.
+#[derive(Clone, Copy, Debug)]
+pub struct ScoreOptions {
+    pub transform: ScoreTransform,
+    pub threshold: f64,
+    pub scale: f64,
+}
+
+/// Parse the score of a line, which is its `field`-th tab-separated
+/// field (counting from 1).  Returns `None` if the line doesn't have
+/// that field, or it isn't a finite number.
+///
+///     parse_score("apple\t0.75\tred", 2)  => Some(0.75)
+///     parse_score("apple\tred", 2)        => None
+///
+pub fn parse_score(line: &str, field: usize) -> Option<f64> {
+    let field = nth_field(line, field)?;
+
+    match field.trim().parse::<f64>() {
+        Ok(s) if s.is_finite() => Some(s),
+        _ => None,
+    }
+}
+
+/// Turn a score into the probability of keeping the line, between
+/// 0 and 1 inclusive.
+///
+/// The scale has to be positive, which we check when parsing args.
+pub fn keep_probability(score: f64, options: ScoreOptions) -> f64 {
+    let x = (score - options.threshold) / options.scale;
+
+    match options.transform {
+        ScoreTransform::Sigmoid => 1.0 / (1.0 + (-x).exp()),
+        ScoreTransform::Linear => x.clamp(0.0, 1.0),
+    }
+}
+
+#[cfg(test)]
+mod scores_tests {
+    use super::*;
+
+    #[test]
+    fn it_parses_the_score_field() {
+        assert_eq!(parse_score("apple\t0.75\tred", 2), Some(0.75));
+        assert_eq!(parse_score("3\tapple", 1), Some(3.0));
+        assert_eq!(parse_score("apple\tred", 2), None);
+        assert_eq!(parse_score("apple", 2), None);
+        assert_eq!(parse_score("apple\tNaN", 2), None);
+        assert_eq!(parse_score("1\tapple", 0), None);
+    }
+
+    #[test]
+    fn it_applies_a_sigmoid() {
+        let options = ScoreOptions {
+            transform: ScoreTransform::Sigmoid,
+            threshold: 0.5,
+            scale: 0.1,
+        };
+
+        assert_eq!(keep_probability(0.5, options), 0.5);
+        assert!(keep_probability(1.0, options) > 0.99);
+        assert!(keep_probability(0.0, options) < 0.01);
+        assert!(keep_probability(0.6, options) > keep_probability(0.55, options));
+    }
+
+    #[test]
+    fn it_applies_a_linear_transform() {
+        let options = ScoreOptions {
+            transform: ScoreTransform::Linear,
+            threshold: 10.0,
+            scale: 20.0,
+        };
+
+        assert_eq!(keep_probability(5.0, options), 0.0);
+        assert_eq!(keep_probability(10.0, options), 0.0);
+        assert_eq!(keep_probability(15.0, options), 0.25);
+        assert_eq!(keep_probability(30.0, options), 1.0);
+        assert_eq!(keep_probability(100.0, options), 1.0);
+    }
+}
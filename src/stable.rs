@@ -0,0 +1,134 @@
+use sha2::{Digest, Sha256};
+
+/// The hash functions we can use to decide which IDs to keep.
+///
+/// The default is FNV-1a, but you can pin a well-known hash function
+/// with `--hash-algo`, so you can reproduce a selection with another
+/// tool, or be sure it won't change if the default does.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum HashAlgorithm {
+    /// FNV-1a with the SplitMix64 finalizer -- see `stable_hash`.
+    Fnv,
+
+    /// XXH64, with the seed as its seed.
+    XxHash,
+
+    /// The first 8 bytes of the SHA-256 of the seed, then the ID.
+    Sha256,
+}
+
+impl HashAlgorithm {
+    /// Hash an ID with a seed.
+    fn hash(self, id: &str, seed: u64) -> u64 {
+        match self {
+            HashAlgorithm::Fnv => stable_hash(id, seed),
+            HashAlgorithm::XxHash => xxhash_rust::xxh64::xxh64(id.as_bytes(), seed),
+            HashAlgorithm::Sha256 => {
+                let digest = Sha256::new()
+                    .chain_update(seed.to_le_bytes())
+                    .chain_update(id.as_bytes())
+                    .finalize();
+
+                u64::from_be_bytes(digest[..8].try_into().unwrap())
+            }
+        }
+    }
+}
+
+/// Decide whether to keep every line with this ID, so that the same ID
+/// always gets the same decision with the same seed.
+///
+/// We hash the ID with the seed, and keep it if the hash falls in the
+/// first `fraction` of the possible hashes.  On average, that keeps
+/// `fraction` of the distinct IDs.
+pub fn is_selected(id: &str, seed: u64, fraction: f64, algorithm: HashAlgorithm) -> bool {
+    // Converting the hash to f64 can round it up to u64::MAX, so we
+    // special-case keeping everything.
+    fraction >= 1.0 || (algorithm.hash(id, seed) as f64) < fraction * (u64::MAX as f64)
+}
+
+/// Hash an ID with a seed.
+///
+/// This has to give the same answer on every platform and with every
+/// version of Rust, so people can reproduce a selection later -- which
+/// rules out `DefaultHasher`.  We use FNV-1a to hash the bytes, then
+/// the SplitMix64 finalizer to spread the bits out, so similar IDs
+/// get very different hashes.
+fn stable_hash(id: &str, seed: u64) -> u64 {
+    const FNV_OFFSET_BASIS: u64 = 0xcbf29ce484222325;
+    const FNV_PRIME: u64 = 0x100000001b3;
+
+    let mut hash = FNV_OFFSET_BASIS;
+
+    for byte in seed.to_le_bytes().iter().chain(id.as_bytes()) {
+        hash ^= *byte as u64;
+        hash = hash.wrapping_mul(FNV_PRIME);
+    }
+
+    hash = (hash ^ (hash >> 30)).wrapping_mul(0xbf58476d1ce4e5b9);
+    hash = (hash ^ (hash >> 27)).wrapping_mul(0x94d049bb133111eb);
+    hash ^ (hash >> 31)
+}
+
+#[cfg(test)]
+mod stable_tests {
+    use super::*;
+
+    // The hash is part of the interface -- if it changes, people get
+    // a different selection from the same seed.  These values should
+    // never change.
+    #[test]
+    fn the_hash_is_stable() {
+        assert_eq!(stable_hash("user-1", 0), 0x624c32e939b8f587);
+        assert_eq!(stable_hash("user-1", 1), 0xd4b762c8db590940);
+    }
+
+    #[test]
+    fn the_seed_changes_the_hash() {
+        assert_ne!(stable_hash("user-1", 0), stable_hash("user-1", 1));
+    }
+
+    // Like FNV, the other hash functions should never change.
+    #[test]
+    fn every_hash_algorithm_is_stable() {
+        assert_eq!(
+            HashAlgorithm::Fnv.hash("user-1", 0),
+            stable_hash("user-1", 0)
+        );
+
+        // These are the standard test vectors for the empty string.
+        assert_eq!(HashAlgorithm::XxHash.hash("", 0), 0xef46db3751d8e999);
+        assert_eq!(
+            HashAlgorithm::Sha256.hash("", 0),
+            u64::from_be_bytes(Sha256::digest([0; 8])[..8].try_into().unwrap())
+        );
+
+        assert_eq!(HashAlgorithm::XxHash.hash("user-1", 1), 0xa55238cc636c8c35);
+        assert_eq!(HashAlgorithm::Sha256.hash("user-1", 1), 0x40676abac6457948);
+    }
+
+    // Over lots of IDs, we keep about `fraction` of them.
+    #[test]
+    fn it_selects_about_the_right_fraction() {
+        let selected = (0..10000)
+            .filter(|i| is_selected(&format!("user-{}", i), 42, 0.25, HashAlgorithm::Fnv))
+            .count();
+
+        assert!((2300..2700).contains(&selected), "selected = {}", selected);
+    }
+
+    #[test]
+    fn it_selects_everything_or_nothing() {
+        for algorithm in [
+            HashAlgorithm::Fnv,
+            HashAlgorithm::XxHash,
+            HashAlgorithm::Sha256,
+        ] {
+            for i in 0..100 {
+                let id = format!("user-{}", i);
+                assert!(is_selected(&id, 0, 1.0, algorithm));
+                assert!(!is_selected(&id, 0, 0.0, algorithm));
+            }
+        }
+    }
+}
@@ -0,0 +1,189 @@
+use std::time::Duration;
+
+/// Build the `--stats` report for a sample, as a JSON object.
+///
+/// Line lengths are in bytes.  If the sample is empty, the min, max and
+/// mean length are `null`.
+///
+///     {
+///       "lines_read": 100,
+///       "sample_size": 2,
+///       "min_line_length": 3,
+///       "max_line_length": 5,
+///       "mean_line_length": 4.0,
+///       "elapsed_seconds": 0.012
+///     }
+///
+pub fn report<'a>(
+    lines_read: usize,
+    sample: impl Iterator<Item = &'a str>,
+    elapsed: Duration,
+) -> serde_json::Value {
+    let lengths: Vec<usize> = sample.map(|line| line.len()).collect();
+
+    let mean = if lengths.is_empty() {
+        None
+    } else {
+        Some(lengths.iter().sum::<usize>() as f64 / lengths.len() as f64)
+    };
+
+    serde_json::json!({
+        "lines_read": lines_read,
+        "sample_size": lengths.len(),
+        "min_line_length": lengths.iter().min(),
+        "max_line_length": lengths.iter().max(),
+        "mean_line_length": mean,
+        "elapsed_seconds": elapsed.as_secs_f64(),
+    })
+}
+
+/// The running mean and variance of a stream of numbers, so we can
+/// describe the whole population without keeping every value.
+///
+/// This uses Welford's algorithm, which is more accurate than summing
+/// the values and their squares.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct RunningStats {
+    count: usize,
+    mean: f64,
+    m2: f64,
+}
+
+impl RunningStats {
+    pub fn push(&mut self, x: f64) {
+        self.count += 1;
+        let delta = x - self.mean;
+        self.mean += delta / self.count as f64;
+        self.m2 += delta * (x - self.mean);
+    }
+
+    pub fn count(&self) -> usize {
+        self.count
+    }
+
+    pub fn mean(&self) -> f64 {
+        self.mean
+    }
+
+    /// The variance of the values, treating them as the whole
+    /// population rather than a sample of it.
+    pub fn variance(&self) -> f64 {
+        if self.count == 0 {
+            0.0
+        } else {
+            self.m2 / self.count as f64
+        }
+    }
+}
+
+/// How many standard errors the sample mean is from the population
+/// mean, for a simple random sample.
+///
+/// If we sampled without replacement, we use the finite population
+/// correction, because a sample that covers most of the population
+/// can't be far from its mean.
+///
+/// Returns `None` if it's undefined: if the sample is empty, every value
+/// is the same, or the sample is the whole population.
+pub fn z_score(
+    population: &RunningStats,
+    sample: &RunningStats,
+    with_replacement: bool,
+) -> Option<f64> {
+    let n = sample.count() as f64;
+    let big_n = population.count() as f64;
+
+    let correction = if with_replacement || population.count() < 2 {
+        1.0
+    } else {
+        (big_n - n) / (big_n - 1.0)
+    };
+
+    let standard_error = (population.variance() / n * correction).sqrt();
+
+    if sample.count() == 0 || standard_error == 0.0 || !standard_error.is_finite() {
+        None
+    } else {
+        Some((sample.mean() - population.mean()) / standard_error)
+    }
+}
+
+#[cfg(test)]
+mod report_tests {
+    use super::*;
+
+    #[test]
+    fn it_reports_the_line_lengths() {
+        let sample = ["abc", "abcde", "abcd"];
+        let stats = report(10, sample.into_iter(), Duration::from_millis(1500));
+
+        assert_eq!(
+            stats,
+            serde_json::json!({
+                "lines_read": 10,
+                "sample_size": 3,
+                "min_line_length": 3,
+                "max_line_length": 5,
+                "mean_line_length": 4.0,
+                "elapsed_seconds": 1.5,
+            })
+        );
+    }
+
+    #[test]
+    fn it_reports_an_empty_sample() {
+        let stats = report(0, std::iter::empty(), Duration::ZERO);
+
+        assert_eq!(stats["sample_size"], 0);
+        assert_eq!(stats["min_line_length"], serde_json::Value::Null);
+        assert_eq!(stats["mean_line_length"], serde_json::Value::Null);
+    }
+}
+
+#[cfg(test)]
+mod z_score_tests {
+    use super::*;
+
+    fn stats(values: &[f64]) -> RunningStats {
+        let mut stats = RunningStats::default();
+        for &x in values {
+            stats.push(x);
+        }
+        stats
+    }
+
+    #[test]
+    fn it_computes_the_mean_and_variance() {
+        let s = stats(&[2.0, 4.0, 4.0, 4.0, 5.0, 5.0, 7.0, 9.0]);
+
+        assert_eq!(s.count(), 8);
+        assert_eq!(s.mean(), 5.0);
+        assert_eq!(s.variance(), 4.0);
+    }
+
+    #[test]
+    fn it_computes_the_z_score() {
+        // The population has mean 5 and variance 4, so a sample of 2
+        // with replacement has a standard error of sqrt(4 / 2) = sqrt(2).
+        let population = stats(&[2.0, 4.0, 4.0, 4.0, 5.0, 5.0, 7.0, 9.0]);
+        let sample = stats(&[7.0, 9.0]);
+
+        let z = z_score(&population, &sample, true).unwrap();
+        assert!((z - 3.0 / 2.0_f64.sqrt()).abs() < 1e-9, "z = {}", z);
+
+        // Without replacement, the finite population correction is
+        // sqrt((8 - 2) / (8 - 1)), which makes the standard error smaller.
+        let z = z_score(&population, &sample, false).unwrap();
+        let expected = 3.0 / (2.0 * 6.0 / 7.0_f64).sqrt();
+        assert!((z - expected).abs() < 1e-9, "z = {}", z);
+    }
+
+    #[test]
+    fn it_has_no_z_score_if_its_undefined() {
+        let population = stats(&[1.0, 2.0, 3.0]);
+
+        assert_eq!(z_score(&population, &stats(&[]), false), None);
+        assert_eq!(z_score(&population, &stats(&[1.0, 2.0, 3.0]), false), None);
+        assert_eq!(z_score(&stats(&[1.0, 1.0]), &stats(&[1.0]), false), None);
+    }
+}
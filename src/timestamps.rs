@@ -0,0 +1,47 @@
+/// Parse the timestamp at the start of a line, for `--window`.
+///
+/// The timestamp is the first whitespace-separated field of the line,
+/// and has to be a Unix timestamp in seconds, optionally with a
+/// fractional part.  Returns `None` if the line doesn't start with
+/// a valid timestamp.
+///
+///     parse_timestamp("1736600000 started server")   => Some(1736600000.0)
+///     parse_timestamp("1736600000.25\tstarted")      => Some(1736600000.25)
+///     parse_timestamp("started server")              => None
+///
+pub fn parse_timestamp(line: &str) -> Option<f64> {
+    let field = line.split_whitespace().next()?;
+
+    match field.parse::<f64>() {
+        Ok(t) if t.is_finite() => Some(t),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod parse_timestamp_tests {
+    use super::*;
+
+    #[test]
+    fn it_parses_the_first_field() {
+        assert_eq!(parse_timestamp("1736600000 started"), Some(1736600000.0));
+        assert_eq!(
+            parse_timestamp("1736600000.25\tstarted"),
+            Some(1736600000.25)
+        );
+        assert_eq!(parse_timestamp("  42"), Some(42.0));
+    }
+
+    #[test]
+    fn it_rejects_invalid_timestamps() {
+        for line in [
+            "",
+            "started",
+            "2025-01-11T12:00:00Z started",
+            "NaN x",
+            "inf x",
+        ] {
+            assert_eq!(parse_timestamp(line), None, "line = {:?}", line);
+        }
+    }
+}
@@ -0,0 +1,323 @@
+use regex::Regex;
+use std::collections::HashMap;
+
+use crate::diagnostics;
+use crate::fields::nth_field;
+use crate::input::Record;
+
+/// Parse the weight of a line in weighted mode.
+///
+/// The weight is the `field`-th tab-separated field of the line, counting
+/// from 1 -- usually the first, unless you pass `--weight-field`.  It has
+/// to be a finite, non-negative number.  Returns `None` if the line
+/// doesn't have a valid weight.
+///
+///     parse_weight("2.5\tapple", 1)  => Some(2.5)
+///     parse_weight("3", 1)           => Some(3.0)
+///     parse_weight("apple", 1)       => None
+///     parse_weight("-1\tapple", 1)   => None
+///     parse_weight("apple\t2.5", 2)  => Some(2.5)
+///
+pub fn parse_weight(line: &str, field: usize) -> Option<f64> {
+    weight_text(line, None, field).and_then(parse_field)
+}
+
+/// Parse the weight of a line using a regex, for lines where the weight
+/// isn't in a fixed column.
+///
+/// The weight is the first capture group of the first match, and has
+/// the same rules as `parse_weight`.  Returns `None` if the regex doesn't
+/// match, or the captured text isn't a valid weight.
+///
+///     pattern = "took ([0-9.]+)ms"
+///
+///     parse_weight_with_regex("GET / took 12.5ms (ok)", pattern) => Some(12.5)
+///     parse_weight_with_regex("GET / failed", pattern)           => None
+///
+pub fn parse_weight_with_regex(line: &str, pattern: &Regex) -> Option<f64> {
+    weight_text(line, Some(pattern), 1).and_then(parse_field)
+}
+
+/// Find the text of a line's weight, before we parse it: the first
+/// capture group of `pattern`, or if there's no pattern, the `field`-th
+/// tab-separated field, counting from 1.
+///
+/// Returns `None` if the line doesn't have a weight at all -- the field
+/// is empty or missing, or the regex doesn't match.  This lets us tell
+/// the difference between a missing weight and a malformed one.
+///
+///     weight_text("2.5\tapple", None, 1)  => Some("2.5")
+///     weight_text("abc\tapple", None, 1)  => Some("abc")
+///     weight_text("\tapple", None, 1)     => None
+///     weight_text("2.5\tapple", None, 3)  => None
+///
+pub fn weight_text<'a>(line: &'a str, pattern: Option<&Regex>, field: usize) -> Option<&'a str> {
+    let text = match pattern {
+        Some(pattern) => pattern.captures(line)?.get(1)?.as_str(),
+        None => nth_field(line, field).unwrap_or(""),
+    };
+
+    if text.trim().is_empty() {
+        None
+    } else {
+        Some(text)
+    }
+}
+
+/// Attach a weight to each record from a separate weights file, which
+/// has one weight per line, lined up with the input by position.
+///
+/// Invalid weights are left as `None`, the same as a missing weight
+/// in the input -- unless `fail_fast` is set, in which case we exit with
+/// an error on the first line which has a malformed weight.  Blank lines
+/// are always treated as a missing weight.
+///
+/// Exits with an error if the weights file and the input have
+/// a different number of lines.
+pub fn attach_weights(
+    records: impl Iterator<Item = Record>,
+    weights: impl Iterator<Item = Record>,
+    weights_path: String,
+    fail_fast: bool,
+) -> impl Iterator<Item = Record> {
+    let mut records = records.fuse();
+    let mut weights = weights.fuse();
+    let mut count = 0;
+
+    std::iter::from_fn(move || match (records.next(), weights.next()) {
+        (Some(mut record), Some(weight)) => {
+            count += 1;
+            record.weight = parse_field(&weight.line);
+
+            if fail_fast && record.weight.is_none() && !weight.line.trim().is_empty() {
+                diagnostics::exit_with_error(format!(
+                    "Invalid weight {:?} on line {} of {}",
+                    weight.line, weight.line_number, weight.source
+                ));
+            }
+
+            Some(record)
+        }
+        (None, None) => None,
+        (Some(_), None) => diagnostics::exit_with_error(format!(
+            "The weights file {} has {} line(s), but the input has more",
+            weights_path, count
+        )),
+        (None, Some(_)) => diagnostics::exit_with_error(format!(
+            "The input has {} line(s), but the weights file {} has more",
+            count, weights_path
+        )),
+    })
+}
+
+/// Replace the weights of `items` with their softmax at `temperature`,
+/// i.e. each weight w becomes e^(w/t), scaled so they sum to 1.
+///
+/// A low temperature concentrates nearly all the weight on the largest
+/// weights, and a high temperature spreads it evenly.  Every item gets
+/// a positive weight, even if its original weight was 0.
+///
+/// We subtract the largest weight before we exponentiate, which doesn't
+/// change the result, but stops e^(w/t) overflowing when t is small.
+///
+///     softmax([(1, "a"), (1, "b")], 1.0)    => [(0.5, "a"), (0.5, "b")]
+///     softmax([(1, "a"), (2, "b")], 0.01)   => [(~0, "a"), (~1, "b")]
+///
+pub fn softmax<T>(items: Vec<(f64, T)>, temperature: f64) -> Vec<(f64, T)> {
+    let max_weight = items
+        .iter()
+        .map(|(w, _)| *w)
+        .fold(f64::NEG_INFINITY, f64::max);
+
+    let items: Vec<(f64, T)> = items
+        .into_iter()
+        .map(|(w, item)| (((w - max_weight) / temperature).exp(), item))
+        .collect();
+
+    let total: f64 = items.iter().map(|(w, _)| w).sum();
+
+    items
+        .into_iter()
+        .map(|(w, item)| (w / total, item))
+        .collect()
+}
+
+/// Weight each line by the inverse of how often its key appears, so
+/// every key has the same total weight, and lines with a rare key are
+/// more likely to be picked than lines with a common one.
+///
+/// The key is the first capture group of `pattern`, or the whole match
+/// if it doesn't have any groups.  Lines where the pattern doesn't match
+/// all share a single key.
+///
+///     pattern = "^(\\w+)"
+///
+///     balance_weights(["a 1", "a 2", "a 3", "b 1"], pattern)
+///         => [1/3, 1/3, 1/3, 1]
+///
+pub fn balance_weights<'a>(lines: impl Iterator<Item = &'a str>, pattern: &Regex) -> Vec<f64> {
+    let keys: Vec<Option<&str>> = lines
+        .map(|line| {
+            let captures = pattern.captures(line)?;
+            captures.get(1).or(captures.get(0)).map(|m| m.as_str())
+        })
+        .collect();
+
+    let mut counts: HashMap<Option<&str>, usize> = HashMap::new();
+
+    for key in &keys {
+        *counts.entry(*key).or_insert(0) += 1;
+    }
+
+    keys.iter().map(|key| 1.0 / counts[key] as f64).collect()
+}
+
+fn parse_field(field: &str) -> Option<f64> {
+    match field.trim().parse::<f64>() {
+        Ok(w) if w.is_finite() && w >= 0.0 => Some(w),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod parse_weight_tests {
+    use super::*;
+
+    #[test]
+    fn it_parses_the_first_field() {
+        assert_eq!(parse_weight("2.5\tapple", 1), Some(2.5));
+        assert_eq!(parse_weight("0\tapple\tbanana", 1), Some(0.0));
+        assert_eq!(parse_weight("3", 1), Some(3.0));
+    }
+
+    #[test]
+    fn it_rejects_invalid_weights() {
+        for line in [
+            "",
+            "apple",
+            "\tapple",
+            "-1\tapple",
+            "NaN\tapple",
+            "inf\tapple",
+        ] {
+            assert_eq!(parse_weight(line, 1), None, "line = {:?}", line);
+        }
+    }
+
+    #[test]
+    fn it_parses_the_first_capture_group() {
+        let pattern = Regex::new("took ([0-9.]+)ms").unwrap();
+
+        assert_eq!(
+            parse_weight_with_regex("GET / took 12.5ms (ok)", &pattern),
+            Some(12.5)
+        );
+        assert_eq!(
+            parse_weight_with_regex("took 1ms, then took 2ms", &pattern),
+            Some(1.0)
+        );
+    }
+
+    #[test]
+    fn it_rejects_lines_without_a_regex_match() {
+        let pattern = Regex::new("took ([0-9.]+)ms").unwrap();
+
+        for line in ["", "GET / failed", "took ms", "took 1.2.3ms"] {
+            assert_eq!(
+                parse_weight_with_regex(line, &pattern),
+                None,
+                "line = {:?}",
+                line
+            );
+        }
+    }
+
+    #[test]
+    fn it_finds_the_weight_text() {
+        let pattern = Regex::new("w=([^ ]*)").unwrap();
+
+        assert_eq!(weight_text("2.5\tapple", None, 1), Some("2.5"));
+        assert_eq!(weight_text("abc\tapple", None, 1), Some("abc"));
+        assert_eq!(weight_text("apple", None, 1), Some("apple"));
+        assert_eq!(weight_text("a w=xyz b", Some(&pattern), 1), Some("xyz"));
+    }
+
+    #[test]
+    fn it_parses_other_fields() {
+        assert_eq!(parse_weight("apple\t2.5", 2), Some(2.5));
+        assert_eq!(parse_weight("apple\t2.5\tred", 2), Some(2.5));
+        assert_eq!(parse_weight("2.5\tapple", 1), Some(2.5));
+        assert_eq!(parse_weight("apple\t2.5", 3), None);
+        assert_eq!(parse_weight("apple\t\tred", 2), None);
+    }
+
+    #[test]
+    fn it_distinguishes_missing_weights() {
+        let pattern = Regex::new("w=([^ ]*)").unwrap();
+
+        assert_eq!(weight_text("", None, 1), None);
+        assert_eq!(weight_text("\tapple", None, 1), None);
+        assert_eq!(weight_text(" \tapple", None, 1), None);
+        assert_eq!(weight_text("a b", Some(&pattern), 1), None);
+        assert_eq!(weight_text("a w= b", Some(&pattern), 1), None);
+    }
+}
+
+#[cfg(test)]
+mod softmax_tests {
+    use super::*;
+
+    #[test]
+    fn it_gives_equal_weights_the_same_probability() {
+        let weights = softmax(vec![(3.0, "a"), (3.0, "b")], 1.0);
+        assert_eq!(weights, vec![(0.5, "a"), (0.5, "b")]);
+    }
+
+    #[test]
+    fn it_concentrates_weight_at_low_temperature() {
+        let weights = softmax(vec![(1.0, "a"), (2.0, "b"), (0.0, "c")], 0.01);
+
+        assert_eq!(weights[1], (1.0, "b"));
+        assert!(weights[0].0 < 1e-10 && weights[2].0 < 1e-10);
+    }
+
+    #[test]
+    fn it_spreads_weight_at_high_temperature() {
+        let weights = softmax(vec![(1.0, "a"), (100.0, "b")], 1e6);
+
+        for (w, _) in weights {
+            assert!((w - 0.5).abs() < 1e-3, "w = {}", w);
+        }
+    }
+
+    #[test]
+    fn it_handles_an_empty_input() {
+        assert_eq!(softmax(Vec::<(f64, &str)>::new(), 1.0), vec![]);
+    }
+}
+
+#[cfg(test)]
+mod balance_weights_tests {
+    use super::*;
+
+    #[test]
+    fn it_weights_by_the_inverse_frequency_of_the_key() {
+        let pattern = Regex::new(r"^(\w+)").unwrap();
+        let weights = balance_weights(["a 1", "a 2", "b 1", "a 3"].into_iter(), &pattern);
+        assert_eq!(weights, vec![1.0 / 3.0, 1.0 / 3.0, 1.0, 1.0 / 3.0]);
+    }
+
+    #[test]
+    fn it_uses_the_whole_match_if_there_are_no_groups() {
+        let pattern = Regex::new(r"[a-z]+").unwrap();
+        let weights = balance_weights(["1 cat", "2 dog", "3 cat"].into_iter(), &pattern);
+        assert_eq!(weights, vec![0.5, 1.0, 0.5]);
+    }
+
+    #[test]
+    fn it_puts_lines_without_a_match_in_one_group() {
+        let pattern = Regex::new(r"^key=(\w+)").unwrap();
+        let weights = balance_weights(["key=a", "no key", "also none"].into_iter(), &pattern);
+        assert_eq!(weights, vec![1.0, 0.5, 0.5]);
+    }
+}